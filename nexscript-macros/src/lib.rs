@@ -0,0 +1,247 @@
+//! NexScript Macros - Compile NexScript inside Rust source
+//!
+//! `nexscript! { ... }` takes a script inline and `include_nx!("path.nx")`
+//! reads one from a file relative to the crate root. Both check and transpile
+//! at compile time and expand to the generated Bevy items:
+//!
+//! ```ignore
+//! mod coin {
+//!     nexscript_macros::nexscript! {
+//!         entity Coin:
+//!             let value = 10
+//!
+//!             fn on_ready():
+//!                 print("Coin spawned!")
+//!     }
+//! }
+//! // app.add_plugins(coin::CoinPlugin);
+//! ```
+//!
+//! Rust tokenizes the macro input, losing the whitespace NexScript's
+//! indentation depends on, so the script is laid back out from each token's
+//! line and column. `#` comments only survive if they are valid Rust tokens
+//! (no apostrophes, balanced brackets); a single string literal holding the
+//! whole script is accepted for anything else.
+
+use nexscript::diagnostics;
+use proc_macro2::{Delimiter, LineColumn, Span, TokenStream, TokenTree};
+use quote::{quote, quote_spanned};
+use std::fs;
+use std::path::Path;
+use syn::LitStr;
+
+/// Compile an inline script to Rust items
+#[proc_macro]
+pub fn nexscript(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand_inline(input.into()).into()
+}
+
+/// Compile a `.nx` file, relative to `CARGO_MANIFEST_DIR`, to Rust items
+#[proc_macro]
+pub fn include_nx(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    match syn::parse2::<LitStr>(input.into()) {
+        Ok(path) => expand_file(&path),
+        Err(e) => e.to_compile_error(),
+    }
+    .into()
+}
+
+fn expand_inline(input: TokenStream) -> TokenStream {
+    if let Ok(script) = syn::parse2::<LitStr>(input.clone()) {
+        return compile(&script.value(), |line, column, message| {
+            let message = format!("{} (script line {}, column {})", message, line, column);
+            (script.span(), message)
+        });
+    }
+
+    let layout = Layout::new(input);
+    compile(&layout.source, |line, column, message| {
+        (layout.span_at(line, column), message.to_string())
+    })
+}
+
+fn expand_file(path: &LitStr) -> TokenStream {
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let full_path = Path::new(&root).join(path.value());
+    let source = match fs::read_to_string(&full_path) {
+        Ok(source) => source,
+        Err(e) => {
+            let message = format!("couldn't read {}: {}", full_path.display(), e);
+            return quote_spanned!(path.span()=> compile_error!(#message););
+        }
+    };
+
+    let items = compile(&source, |line, column, message| {
+        let message = format!("{}:{}:{}: {}", path.value(), line, column, message);
+        (path.span(), message)
+    });
+
+    // Depend on the file so editing it recompiles the crate
+    let full_path = full_path.to_string_lossy().into_owned();
+    quote! {
+        const _: &str = include_str!(#full_path);
+        #items
+    }
+}
+
+/// Check and transpile `source`. `locate` turns each error's line, column
+/// and message into the span and text of a `compile_error!`.
+fn compile(source: &str, locate: impl Fn(usize, usize, &str) -> (Span, String)) -> TokenStream {
+    let (program, diagnostics) = diagnostics::check(source);
+    let errors: TokenStream = diagnostics
+        .iter()
+        .filter(|d| d.is_error())
+        .map(|d| {
+            let mut message = format!("{} [{}]", d.message, d.code);
+            for fix in &d.fixes {
+                message.push_str(&format!("\nhelp: {}", fix.message));
+            }
+            let (span, message) = locate(d.location.line, d.location.column, &message);
+            quote_spanned!(span=> compile_error!(#message);)
+        })
+        .collect();
+    let Some(program) = program.filter(|_| errors.is_empty()) else {
+        return errors;
+    };
+
+    match nexscript::transpile(&program).parse() {
+        Ok(items) => items,
+        Err(e) => {
+            let message = format!("NexScript generated invalid Rust: {}", e);
+            quote!(compile_error!(#message);)
+        }
+    }
+}
+
+/// Script text rebuilt from macro input tokens
+struct Layout {
+    source: String,
+    /// Where each token landed in `source` (1-based line and column) and
+    /// where it came from
+    tokens: Vec<(usize, usize, Span)>,
+}
+
+impl Layout {
+    fn new(input: TokenStream) -> Self {
+        let mut leaves = Vec::new();
+        flatten(input, &mut leaves);
+
+        // The least indented line is the script's top level
+        let base = leaves
+            .iter()
+            .map(|(_, span)| span.start().column)
+            .min()
+            .unwrap_or(0);
+
+        let mut layout = Layout {
+            source: String::new(),
+            tokens: Vec::new(),
+        };
+        let (mut line, mut line_start) = (1, 0);
+        let mut cursor: Option<LineColumn> = None;
+
+        for (text, span) in leaves {
+            let start = span.start();
+            match cursor {
+                Some(end) if end.line >= start.line => {
+                    let gap = start.column.saturating_sub(end.column);
+                    layout.source.push_str(&" ".repeat(gap));
+                }
+                Some(end) => {
+                    layout.source.push_str(&"\n".repeat(start.line - end.line));
+                    layout.source.push_str(&" ".repeat(start.column - base));
+                    line += start.line - end.line;
+                    line_start = layout.source.len() - (start.column - base);
+                }
+                None => layout.source.push_str(&" ".repeat(start.column - base)),
+            }
+
+            let column = layout.source[line_start..].chars().count() + 1;
+            layout.tokens.push((line, column, span));
+            layout.source.push_str(&text);
+
+            // Multi-line string literals move the line along too
+            if let Some(newline) = text.rfind('\n') {
+                line += text.matches('\n').count();
+                line_start = layout.source.len() - text.len() + newline + 1;
+            }
+            cursor = Some(span.end());
+        }
+        layout
+    }
+
+    /// The token a script error points at: the last one starting at or
+    /// before the error's position
+    fn span_at(&self, line: usize, column: usize) -> Span {
+        self.tokens
+            .iter()
+            .take_while(|(l, c, _)| (*l, *c) <= (line, column))
+            .last()
+            .or(self.tokens.first())
+            .map_or_else(Span::call_site, |(_, _, span)| *span)
+    }
+}
+
+/// Leaf tokens in order, with delimiters as tokens of their own
+fn flatten(input: TokenStream, out: &mut Vec<(String, Span)>) {
+    for tree in input {
+        match tree {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => {
+                        flatten(group.stream(), out);
+                        continue;
+                    }
+                };
+                out.push((open.to_string(), group.span_open()));
+                flatten(group.stream(), out);
+                out.push((close.to_string(), group.span_close()));
+            }
+            tree => out.push((tree.to_string(), tree.span())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "entity Coin:\n    let value = 10\n\n    fn on_ready():\n        print(\"Coin spawned!\")\n";
+
+    fn tokens(source: &str) -> TokenStream {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn test_layout_restores_indentation() {
+        // Indented as it would be inside a module
+        let indented: String = SCRIPT
+            .lines()
+            .map(|line| format!("        {}\n", line))
+            .collect();
+        let layout = Layout::new(tokens(&indented));
+        assert_eq!(
+            layout.source.lines().map(str::trim_end).collect::<Vec<_>>(),
+            SCRIPT.lines().collect::<Vec<_>>()
+        );
+
+        let items = expand_inline(tokens(&indented)).to_string();
+        assert!(items.contains("CoinPlugin"));
+        assert_eq!(items, expand_inline(quote!(#SCRIPT)).to_string());
+    }
+
+    #[test]
+    fn test_error_points_at_token() {
+        let input = tokens("entity Coin:\n    let value = 10\n    let broken =\n");
+        let layout = Layout::new(input.clone());
+        let (_, found) = diagnostics::check(&layout.source);
+        let span = layout.span_at(found[0].location.line, found[0].location.column);
+        assert_eq!((span.start().line, span.start().column), (3, 15));
+
+        let expanded = expand_inline(input).to_string();
+        assert!(expanded.starts_with("compile_error !"));
+    }
+}
@@ -0,0 +1,28 @@
+// NexScript VS Code extension entry point
+// Starts `nexc lsp` and connects it to .nx documents
+
+const { workspace } = require("vscode");
+const { LanguageClient } = require("vscode-languageclient/node");
+
+let client;
+
+function activate() {
+    const command = workspace.getConfiguration("nexscript").get("server.path") || "nexc";
+
+    const serverOptions = {
+        command,
+        args: ["lsp"],
+    };
+    const clientOptions = {
+        documentSelector: [{ scheme: "file", language: "nexscript" }],
+    };
+
+    client = new LanguageClient("nexscript", "NexScript Language Server", serverOptions, clientOptions);
+    client.start();
+}
+
+function deactivate() {
+    return client ? client.stop() : undefined;
+}
+
+module.exports = { activate, deactivate };
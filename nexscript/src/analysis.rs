@@ -0,0 +1,1128 @@
+//! Analysis - Editor queries over a single NexScript document
+//!
+//! This backs `nexc lsp`. Declarations are found from the token stream and
+//! indentation rather than the AST, so outline, navigation and completion
+//! keep working while the file is mid-edit and does not parse. Types come
+//! from the type checker whenever the document parses.
+
+use crate::diagnostics::{self, Severity};
+use crate::inheritance::Bases;
+use crate::lexer::{self, is_keyword, Token, TokenKind, KEYWORDS};
+use crate::modules::Imports;
+use crate::type_checker::{infer_type_in, prelude_fn, TypeEnv, PRELUDE};
+use crate::{EntityDef, FnDef, Program, Span, Statement, SystemDef, TypeExpr};
+
+/// Zero-based line and UTF-16 column, matching the LSP convention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    pub fn contains(&self, pos: Position) -> bool {
+        self.start <= pos && pos <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Entity,
+    Component,
+    Struct,
+    Enum,
+    Variant,
+    Interface,
+    Field,
+    Variable,
+    Function,
+    Signal,
+    StateMachine,
+    State,
+}
+
+/// A declaration in the document, nested the way it is indented
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The whole declaration, including its indented body
+    pub range: Range,
+    /// Just the declared name
+    pub selection_range: Range,
+    /// Header line as written, e.g. `fn take_damage(amount: int)`
+    pub detail: String,
+    pub children: Vec<Symbol>,
+}
+
+/// A problem to show in the editor
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Function,
+    Signal,
+    Variable,
+    Component,
+    Field,
+    Entity,
+    State,
+    /// A struct or enum
+    Type,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: Option<String>,
+}
+
+/// Replace the text in `range` with `new_text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+/// A parameter, loop variable or `let` inside a function body
+#[derive(Debug, Clone)]
+struct Local {
+    name: String,
+    selection_range: Range,
+    /// Range of the function (or block) the local is visible in
+    scope: Range,
+    detail: String,
+}
+
+/// What a name under the cursor resolved to
+#[derive(Debug, Clone)]
+enum Resolved {
+    Symbol(Symbol),
+    Local(Local),
+    Prelude(&'static str),
+}
+
+/// A parsed and indexed NexScript document
+pub struct Document {
+    source: String,
+    line_starts: Vec<usize>,
+    program: Option<Program>,
+    diagnostics: Vec<Diagnostic>,
+    symbols: Vec<Symbol>,
+    locals: Vec<Local>,
+}
+
+impl Document {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+
+        let mut doc = Document {
+            source: source.to_string(),
+            line_starts,
+            program: None,
+            diagnostics: Vec::new(),
+            symbols: Vec::new(),
+            locals: Vec::new(),
+        };
+
+        let (program, found) = diagnostics::check(source);
+        doc.program = program;
+        doc.diagnostics = found
+            .into_iter()
+            .map(|d| Diagnostic {
+                range: doc.diagnostic_range(d.location.span),
+                severity: d.severity,
+                code: d.code,
+                message: d.message,
+            })
+            .collect();
+
+        doc.index();
+        doc
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The AST, if the document parsed
+    pub fn program(&self) -> Option<&Program> {
+        self.program.as_ref()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Top-level declarations with their members nested inside
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Where the name under the cursor is declared
+    pub fn definition(&self, pos: Position) -> Option<Range> {
+        match self.resolve(pos)? {
+            Resolved::Symbol(symbol) => Some(symbol.selection_range),
+            Resolved::Local(local) => Some(local.selection_range),
+            Resolved::Prelude(_) => None,
+        }
+    }
+
+    /// Every use of the name under the cursor, optionally including its declaration
+    pub fn references(&self, pos: Position, include_declaration: bool) -> Vec<Range> {
+        let Some(resolved) = self.resolve(pos) else {
+            return Vec::new();
+        };
+        let (name, decl, scope, member_of) = match &resolved {
+            Resolved::Symbol(symbol) => {
+                let member_of = match symbol.kind {
+                    SymbolKind::Field => self.parent_of(&symbol.selection_range),
+                    _ => None,
+                };
+                (
+                    symbol.name.clone(),
+                    symbol.selection_range,
+                    self.scope_of(symbol),
+                    member_of,
+                )
+            }
+            Resolved::Local(local) => (
+                local.name.clone(),
+                local.selection_range,
+                Some(local.scope),
+                None,
+            ),
+            Resolved::Prelude(_) => return Vec::new(),
+        };
+
+        let tokens = lexer::tokenize(&self.source);
+        let significant: Vec<&Token> = tokens.iter().filter(|t| !t.is_trivia()).collect();
+        let mut ranges = Vec::new();
+
+        for (i, token) in significant.iter().enumerate() {
+            if token.kind != TokenKind::Ident || token.text != name {
+                continue;
+            }
+            let range = self.token_range(token);
+            if range == decl {
+                if include_declaration {
+                    ranges.push(range);
+                }
+                continue;
+            }
+            if let Some(scope) = scope {
+                if !scope.contains(range.start) {
+                    continue;
+                }
+            }
+
+            let after_dot = i >= 1 && significant[i - 1].text == ".";
+            match &member_of {
+                // Fields are only referenced as `Component.field`
+                Some(component) => {
+                    if !(after_dot && i >= 2 && significant[i - 2].text == component.as_str()) {
+                        continue;
+                    }
+                }
+                None => {
+                    if after_dot {
+                        continue;
+                    }
+                }
+            }
+
+            // Don't mistake a local of the same name for this symbol
+            if matches!(resolved, Resolved::Symbol(_))
+                && self.local_at(&name, range.start).is_some()
+            {
+                continue;
+            }
+
+            ranges.push(range);
+        }
+
+        ranges
+    }
+
+    /// Edits renaming the symbol under the cursor, or an error message
+    pub fn rename(&self, pos: Position, new_name: &str) -> Result<Vec<TextEdit>, String> {
+        let valid = new_name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && new_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || is_keyword(new_name) {
+            return Err(format!("'{}' is not a valid identifier", new_name));
+        }
+
+        match self.resolve(pos) {
+            Some(Resolved::Prelude(name)) => Err(format!("cannot rename built-in '{}'", name)),
+            Some(_) => Ok(self
+                .references(pos, true)
+                .into_iter()
+                .map(|range| TextEdit {
+                    range,
+                    new_text: new_name.to_string(),
+                })
+                .collect()),
+            None => Err("no symbol at this position".to_string()),
+        }
+    }
+
+    /// Markdown describing the name under the cursor
+    pub fn hover(&self, pos: Position) -> Option<String> {
+        let text = match self.resolve(pos)? {
+            Resolved::Prelude(name) => {
+                let prelude = prelude_fn(name)?;
+                format!("{}\n```\n\n{}", prelude.signature(), prelude.doc)
+            }
+            Resolved::Local(local) => match self.type_of(&local.name, local.scope.start) {
+                Some(t) => format!("{}: {}\n```", local.name, t),
+                None => format!("{}\n```", local.detail),
+            },
+            Resolved::Symbol(symbol) => match symbol.kind {
+                SymbolKind::Variable => match self.type_of(&symbol.name, symbol.range.start) {
+                    Some(t) => format!("let {}: {}\n```", symbol.name, t),
+                    None => format!("{}\n```", symbol.detail),
+                },
+                SymbolKind::Field => {
+                    let component = self.parent_of(&symbol.selection_range)?;
+                    let path = format!("{}.{}", component, symbol.name);
+                    match self.type_of(&path, symbol.range.start) {
+                        Some(t) => format!("{}: {}\n```", path, t),
+                        None => format!("{}\n```", path),
+                    }
+                }
+                SymbolKind::Component
+                | SymbolKind::Struct
+                | SymbolKind::Enum
+                | SymbolKind::Interface => {
+                    let fields: Vec<String> = symbol
+                        .children
+                        .iter()
+                        .map(|f| format!("    {}", f.detail))
+                        .collect();
+                    if fields.is_empty() {
+                        format!("{}\n```", symbol.detail)
+                    } else {
+                        format!("{}:\n{}\n```", symbol.detail, fields.join("\n"))
+                    }
+                }
+                _ => format!("{}\n```", symbol.detail),
+            },
+        };
+        Some(format!("```nexscript\n{}", text))
+    }
+
+    /// Completion candidates at the cursor. The client filters them by the
+    /// word being typed, so everything valid in this context is returned.
+    pub fn completions(&self, pos: Position) -> Vec<Completion> {
+        let offset = self.offset(pos);
+        let tokens = lexer::tokenize(&self.source[..offset]);
+        let mut before: Vec<&Token> = tokens.iter().filter(|t| !t.is_trivia()).collect();
+
+        // Ignore the partial word under the cursor
+        if before
+            .last()
+            .is_some_and(|t| t.kind == TokenKind::Ident && t.end() == offset)
+        {
+            before.pop();
+        }
+
+        let mut items = Vec::new();
+        match before.as_slice() {
+            [.., base, dot] if dot.text == "." => {
+                self.member_completions(base.text, pos, &mut items);
+            }
+            [.., emit] if emit.text == "emit" => {
+                for symbol in self.all_symbols() {
+                    if symbol.kind == SymbolKind::Signal {
+                        items.push(symbol_completion(symbol));
+                    }
+                }
+            }
+            _ => self.scope_completions(pos, &mut items),
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        items.retain(|item| seen.insert(item.label.clone()));
+        items
+    }
+
+    // ------------------------------------------------------------------------
+    // Completion helpers
+    // ------------------------------------------------------------------------
+
+    fn member_completions(&self, base: &str, pos: Position, items: &mut Vec<Completion>) {
+        let component = self
+            .enclosing(pos)
+            .into_iter()
+            .rev()
+            .flat_map(|s| s.children.iter())
+            .chain(self.all_symbols())
+            .find(|s| s.kind == SymbolKind::Component && s.name == base);
+
+        if let Some(component) = component {
+            for field in &component.children {
+                let path = format!("{}.{}", component.name, field.name);
+                items.push(Completion {
+                    label: field.name.clone(),
+                    kind: CompletionKind::Field,
+                    detail: self.type_of(&path, pos).map(|t| t.to_string()),
+                });
+            }
+            return;
+        }
+
+        let axes: &[&str] = match self.type_of(base, pos) {
+            Some(TypeExpr::Simple(name)) if name == "Vec2" => &["x", "y"],
+            Some(TypeExpr::Simple(name)) if name == "Vec3" => &["x", "y", "z"],
+            _ => &[],
+        };
+        for axis in axes {
+            items.push(Completion {
+                label: axis.to_string(),
+                kind: CompletionKind::Field,
+                detail: Some("float".to_string()),
+            });
+        }
+    }
+
+    fn scope_completions(&self, pos: Position, items: &mut Vec<Completion>) {
+        for local in &self.locals {
+            if local.scope.contains(pos) && local.selection_range.start <= pos {
+                items.push(Completion {
+                    label: local.name.clone(),
+                    kind: CompletionKind::Variable,
+                    detail: Some(local.detail.clone()),
+                });
+            }
+        }
+
+        // Members of the enclosing entity (or state machine), then top level
+        for container in self.enclosing(pos).into_iter().rev() {
+            for member in &container.children {
+                items.push(symbol_completion(member));
+            }
+        }
+        for symbol in &self.symbols {
+            items.push(symbol_completion(symbol));
+        }
+
+        for prelude in PRELUDE {
+            items.push(Completion {
+                label: prelude.name.to_string(),
+                kind: CompletionKind::Function,
+                detail: Some(prelude.signature()),
+            });
+        }
+        for keyword in KEYWORDS {
+            items.push(Completion {
+                label: keyword.to_string(),
+                kind: CompletionKind::Keyword,
+                detail: None,
+            });
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Name resolution
+    // ------------------------------------------------------------------------
+
+    fn resolve(&self, pos: Position) -> Option<Resolved> {
+        let offset = self.offset(pos);
+        let tokens = lexer::tokenize(&self.source);
+        let significant: Vec<&Token> = tokens.iter().filter(|t| !t.is_trivia()).collect();
+        let index = significant
+            .iter()
+            .position(|t| t.kind == TokenKind::Ident && t.offset <= offset && offset <= t.end())?;
+        let token = significant[index];
+        if is_keyword(token.text) {
+            return None;
+        }
+        let name = token.text;
+        let token_range = self.token_range(token);
+        let token_pos = token_range.start;
+
+        // On a declaration itself
+        if let Some(symbol) = self
+            .all_symbols()
+            .find(|s| s.selection_range == token_range)
+        {
+            return Some(Resolved::Symbol(symbol.clone()));
+        }
+
+        // `Component.field`
+        if index >= 2 && significant[index - 1].text == "." {
+            let base = significant[index - 2].text;
+            return self
+                .all_symbols()
+                .filter(|s| s.kind == SymbolKind::Component && s.name == base)
+                .flat_map(|s| s.children.iter())
+                .find(|f| f.name == name)
+                .cloned()
+                .map(Resolved::Symbol);
+        }
+
+        if let Some(local) = self.local_at(name, token_pos) {
+            return Some(Resolved::Local(local.clone()));
+        }
+
+        // Innermost container first, then top level, then anywhere
+        let found = self
+            .enclosing(token_pos)
+            .into_iter()
+            .rev()
+            .flat_map(|s| s.children.iter())
+            .chain(self.symbols.iter())
+            .chain(self.all_symbols())
+            .find(|s| s.name == name && s.kind != SymbolKind::Field);
+        if let Some(symbol) = found {
+            return Some(Resolved::Symbol(symbol.clone()));
+        }
+
+        prelude_fn(name).map(|p| Resolved::Prelude(p.name))
+    }
+
+    fn local_at(&self, name: &str, pos: Position) -> Option<&Local> {
+        self.locals
+            .iter()
+            .rfind(|l| l.name == name && l.scope.contains(pos))
+    }
+
+    /// Symbols containing `pos`, outermost first
+    fn enclosing(&self, pos: Position) -> Vec<&Symbol> {
+        let mut chain = Vec::new();
+        let mut level = &self.symbols;
+        while let Some(symbol) = level.iter().find(|s| s.range.contains(pos)) {
+            chain.push(symbol);
+            level = &symbol.children;
+        }
+        chain
+    }
+
+    /// Name of the symbol directly containing the one declared at `selection`
+    fn parent_of(&self, selection: &Range) -> Option<String> {
+        let chain = self.enclosing(selection.start);
+        let index = chain.iter().position(|s| s.selection_range == *selection)?;
+        index.checked_sub(1).map(|i| chain[i].name.clone())
+    }
+
+    /// Range references to a symbol are limited to (`None` for the whole file)
+    fn scope_of(&self, symbol: &Symbol) -> Option<Range> {
+        match symbol.kind {
+            SymbolKind::Variable => {
+                let chain = self.enclosing(symbol.selection_range.start);
+                chain
+                    .iter()
+                    .rev()
+                    .find(|s| s.kind == SymbolKind::Entity)
+                    .map(|s| s.range)
+            }
+            _ => None,
+        }
+    }
+
+    fn all_symbols(&self) -> impl Iterator<Item = &Symbol> {
+        fn walk<'a>(symbols: &'a [Symbol], out: &mut Vec<&'a Symbol>) {
+            for symbol in symbols {
+                out.push(symbol);
+                walk(&symbol.children, out);
+            }
+        }
+        let mut out = Vec::new();
+        walk(&self.symbols, &mut out);
+        out.into_iter()
+    }
+
+    // ------------------------------------------------------------------------
+    // Types
+    // ------------------------------------------------------------------------
+
+    /// Type of a name (or `Component.field` path) as seen from `pos`
+    fn type_of(&self, name: &str, pos: Position) -> Option<TypeExpr> {
+        let program = self.program.as_ref()?;
+        let chain = self.enclosing(pos);
+        let mut env = TypeEnv::new();
+
+        let mut functions: Vec<&FnDef> = Vec::new();
+        let mut systems: Vec<&SystemDef> = Vec::new();
+        for stmt in &program.statements {
+            match stmt {
+                Statement::FnDef(func) => {
+                    env.declare_fn(func);
+                    functions.push(func);
+                }
+                Statement::SystemDef(system) => systems.push(system),
+                Statement::StructDef(def) => env.declare_struct(def),
+                Statement::EnumDef(def) => env.declare_enum(def),
+                _ => {}
+            }
+        }
+
+        let entity = chain
+            .iter()
+            .find(|s| s.kind == SymbolKind::Entity)
+            .and_then(|symbol| find_entity(program, &symbol.name));
+        if let Some(entity) = entity {
+            // With what it inherits from the file's own entities
+            let imports = Imports::default();
+            match Bases::new(program, &imports).flatten(entity) {
+                Ok(flattened) => env.declare_entity(&flattened),
+                Err(_) => env.declare_entity(entity),
+            }
+            functions.extend(entity.functions.iter());
+        }
+
+        let symbol = chain.iter().rev().find(|s| s.kind == SymbolKind::Function);
+        let func = symbol.and_then(|symbol| {
+            if let Some(func) = functions.iter().find(|f| f.name == symbol.name) {
+                return Some((&func.params, &func.body));
+            }
+            let system = systems.iter().find(|s| s.name == symbol.name)?;
+            Some((&system.params, &system.body))
+        });
+        if let Some((params, body)) = func {
+            for param in params {
+                env.declare(&param.name, param.type_expr.clone());
+            }
+            declare_locals(body, &mut env);
+        }
+
+        env.lookup(name).cloned()
+    }
+
+    // ------------------------------------------------------------------------
+    // Indexing
+    // ------------------------------------------------------------------------
+
+    /// Find declarations by walking lines and their indentation
+    fn index(&mut self) {
+        let tokens = lexer::tokenize(&self.source);
+
+        // Significant tokens grouped by line, with each line's indentation
+        let mut lines: Vec<(usize, Vec<&Token>)> = Vec::new();
+        let mut current: Vec<&Token> = Vec::new();
+        let mut indent = 0;
+        let mut at_line_start = true;
+        for token in &tokens {
+            match token.kind {
+                TokenKind::Newline => {
+                    if !current.is_empty() {
+                        lines.push((indent, std::mem::take(&mut current)));
+                    }
+                    indent = 0;
+                    at_line_start = true;
+                }
+                TokenKind::Whitespace if at_line_start => indent = token.text.len(),
+                TokenKind::Whitespace | TokenKind::Comment => {}
+                _ => {
+                    at_line_start = false;
+                    current.push(token);
+                }
+            }
+        }
+        if !current.is_empty() {
+            lines.push((indent, current));
+        }
+
+        // Flat list of (indent, symbol) in source order; locals kept aside
+        let mut flat: Vec<(usize, Symbol)> = Vec::new();
+        let mut open: Vec<(usize, SymbolKind)> = Vec::new();
+        let mut pending_locals: Vec<(usize, Local)> = Vec::new();
+
+        for (line_index, (indent, line)) in lines.iter().enumerate() {
+            while open.last().is_some_and(|(i, _)| *i >= *indent) {
+                open.pop();
+            }
+            let parent = open.last().map(|(_, kind)| *kind);
+            let range = self.block_range(&lines, line_index);
+
+            let mut words = line.iter().map(|t| t.text).peekable();
+            if words.peek() == Some(&"async") {
+                words.next();
+            }
+            let keyword = words.next().unwrap_or_default();
+            let name_token = line
+                .iter()
+                .skip_while(|t| t.text != keyword)
+                .nth(1)
+                .filter(|t| t.kind == TokenKind::Ident);
+
+            let kind = match (keyword, parent) {
+                ("entity", _) => Some(SymbolKind::Entity),
+                ("component", _) => Some(SymbolKind::Component),
+                ("struct", _) => Some(SymbolKind::Struct),
+                ("enum", _) => Some(SymbolKind::Enum),
+                ("interface", _) => Some(SymbolKind::Interface),
+                ("fn" | "system", _) => Some(SymbolKind::Function),
+                ("signal", _) => Some(SymbolKind::Signal),
+                ("state_machine", _) => Some(SymbolKind::StateMachine),
+                ("state", _) => Some(SymbolKind::State),
+                ("let", Some(SymbolKind::Function | SymbolKind::State)) => None,
+                ("let", _) => Some(SymbolKind::Variable),
+                _ => None,
+            };
+
+            // Component fields are `name = value` lines in a component body,
+            // struct fields `name: type` lines in a struct body
+            let field = match parent {
+                Some(SymbolKind::Component) => Some("="),
+                Some(SymbolKind::Struct) => Some(":"),
+                _ => None,
+            };
+            if field.is_some_and(|separator| {
+                line.len() >= 2 && line[0].kind == TokenKind::Ident && line[1].text == separator
+            }) {
+                flat.push((
+                    *indent,
+                    Symbol {
+                        name: line[0].text.to_string(),
+                        kind: SymbolKind::Field,
+                        range,
+                        selection_range: self.token_range(line[0]),
+                        detail: self.line_text(line),
+                        children: Vec::new(),
+                    },
+                ));
+                continue;
+            }
+
+            if parent == Some(SymbolKind::Enum) && line[0].kind == TokenKind::Ident {
+                flat.push((
+                    *indent,
+                    Symbol {
+                        name: line[0].text.to_string(),
+                        kind: SymbolKind::Variant,
+                        range,
+                        selection_range: self.token_range(line[0]),
+                        detail: self.line_text(line),
+                        children: Vec::new(),
+                    },
+                ));
+                continue;
+            }
+
+            let function_scope = open
+                .iter()
+                .any(|(_, k)| matches!(k, SymbolKind::Function | SymbolKind::State));
+
+            match (kind, name_token) {
+                (Some(kind), Some(name_token)) => {
+                    if kind == SymbolKind::Function {
+                        // Parameters are `name: type` pairs inside the parens
+                        for window in line.windows(2) {
+                            if window[0].kind == TokenKind::Ident
+                                && window[1].text == ":"
+                                && window[0].offset > name_token.offset
+                            {
+                                pending_locals.push((
+                                    *indent,
+                                    Local {
+                                        name: window[0].text.to_string(),
+                                        selection_range: self.token_range(window[0]),
+                                        scope: range,
+                                        detail: self.param_text(line, window[0]),
+                                    },
+                                ));
+                            }
+                        }
+                    }
+                    if kind.has_body() {
+                        open.push((*indent, kind));
+                    }
+                    flat.push((
+                        *indent,
+                        Symbol {
+                            name: name_token.text.to_string(),
+                            kind,
+                            range,
+                            selection_range: self.token_range(name_token),
+                            detail: self.line_text(line),
+                            children: Vec::new(),
+                        },
+                    ));
+                }
+                _ if function_scope && matches!(keyword, "let" | "for") => {
+                    // `for i, item in ...` declares every name before `in`
+                    let names: Vec<&Token> = if keyword == "for" {
+                        line[1..]
+                            .iter()
+                            .take_while(|t| t.text != "in")
+                            .filter(|t| t.kind == TokenKind::Ident)
+                            .copied()
+                            .collect()
+                    } else {
+                        name_token.into_iter().copied().collect()
+                    };
+                    let scope = self.local_scope(&lines, line_index, keyword == "for");
+                    for name_token in names {
+                        pending_locals.push((
+                            *indent,
+                            Local {
+                                name: name_token.text.to_string(),
+                                selection_range: self.token_range(name_token),
+                                scope,
+                                detail: self.line_text(line),
+                            },
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.locals = pending_locals.into_iter().map(|(_, l)| l).collect();
+        self.symbols = nest(flat);
+    }
+
+    /// Range from the line's first token to the end of its indented body
+    fn block_range(&self, lines: &[(usize, Vec<&Token>)], index: usize) -> Range {
+        let (indent, line) = &lines[index];
+        let mut last = index;
+        for (i, (next_indent, _)) in lines.iter().enumerate().skip(index + 1) {
+            if next_indent <= indent {
+                break;
+            }
+            last = i;
+        }
+        Range {
+            start: self.position(line[0].offset),
+            end: self.position(lines[last].1.last().unwrap().end()),
+        }
+    }
+
+    /// Where a local declared on `index` is visible: the rest of the enclosing
+    /// block, or the loop body for `for` variables
+    fn local_scope(&self, lines: &[(usize, Vec<&Token>)], index: usize, is_loop: bool) -> Range {
+        if is_loop {
+            return self.block_range(lines, index);
+        }
+        let (indent, line) = &lines[index];
+        let mut last = index;
+        for (i, (next_indent, _)) in lines.iter().enumerate().skip(index + 1) {
+            if next_indent < indent {
+                break;
+            }
+            last = i;
+        }
+        Range {
+            start: self.position(line[0].offset),
+            end: self.position(lines[last].1.last().unwrap().end()),
+        }
+    }
+
+    /// Source text of a line without its trailing block colon
+    fn line_text(&self, line: &[&Token]) -> String {
+        let start = line[0].offset;
+        let end = line.last().unwrap().end();
+        let text = self.source[start..end].trim();
+        text.strip_suffix(':')
+            .unwrap_or(text)
+            .trim_end()
+            .to_string()
+    }
+
+    /// `name: type` text for the parameter starting at `token`
+    fn param_text(&self, line: &[&Token], token: &Token) -> String {
+        let end = line
+            .iter()
+            .skip_while(|t| t.offset <= token.offset)
+            .find(|t| t.text == "," || t.text == ")")
+            .map(|t| t.offset)
+            .unwrap_or(token.end());
+        self.source[token.offset..end].trim().to_string()
+    }
+
+    /// Range to underline; an empty span (e.g. a line that ended too soon)
+    /// underlines its whole line so it stays visible
+    fn diagnostic_range(&self, span: Span) -> Range {
+        if span.start < span.end {
+            return Range {
+                start: self.position(span.start),
+                end: self.position(span.end),
+            };
+        }
+        let line = self.position(span.start).line as usize;
+        let line_start = self.line_starts[line];
+        let line_text = self.source[line_start..].lines().next().unwrap_or_default();
+        Range {
+            start: self.position(line_start + (line_text.len() - line_text.trim_start().len())),
+            end: self.position(line_start + line_text.trim_end().len()),
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Positions
+    // ------------------------------------------------------------------------
+
+    fn token_range(&self, token: &Token) -> Range {
+        Range {
+            start: self.position(token.offset),
+            end: self.position(token.end()),
+        }
+    }
+
+    /// Convert a byte offset to a line and UTF-16 column
+    pub fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_start = self.line_starts[line];
+        let character: usize = self.source[line_start..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        Position {
+            line: line as u32,
+            character: character as u32,
+        }
+    }
+
+    /// Convert a line and UTF-16 column to a byte offset, clamped to the line
+    pub fn offset(&self, pos: Position) -> usize {
+        let Some(&line_start) = self.line_starts.get(pos.line as usize) else {
+            return self.source.len();
+        };
+        let line = self.source[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or_default();
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= pos.character as usize {
+                return line_start + i;
+            }
+            units += c.len_utf16();
+        }
+        line_start + line.len()
+    }
+}
+
+impl SymbolKind {
+    /// Whether declarations of this kind own an indented block
+    fn has_body(self) -> bool {
+        matches!(
+            self,
+            SymbolKind::Entity
+                | SymbolKind::Component
+                | SymbolKind::Struct
+                | SymbolKind::Enum
+                | SymbolKind::Interface
+                | SymbolKind::Function
+                | SymbolKind::StateMachine
+                | SymbolKind::State
+        )
+    }
+}
+
+/// Turn an indentation-ordered list into a tree
+fn nest(flat: Vec<(usize, Symbol)>) -> Vec<Symbol> {
+    fn close(stack: &mut Vec<(usize, Symbol)>, roots: &mut Vec<Symbol>) {
+        let (_, done) = stack.pop().unwrap();
+        match stack.last_mut() {
+            Some((_, parent)) => parent.children.push(done),
+            None => roots.push(done),
+        }
+    }
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<(usize, Symbol)> = Vec::new();
+    for (indent, symbol) in flat {
+        while stack.last().is_some_and(|(i, _)| *i >= indent) {
+            close(&mut stack, &mut roots);
+        }
+        stack.push((indent, symbol));
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+    roots
+}
+
+fn symbol_completion(symbol: &Symbol) -> Completion {
+    let kind = match symbol.kind {
+        SymbolKind::Entity => CompletionKind::Entity,
+        SymbolKind::Component => CompletionKind::Component,
+        SymbolKind::Field | SymbolKind::Variable => CompletionKind::Variable,
+        SymbolKind::Function => CompletionKind::Function,
+        SymbolKind::Signal => CompletionKind::Signal,
+        SymbolKind::StateMachine | SymbolKind::State | SymbolKind::Variant => CompletionKind::State,
+        SymbolKind::Struct | SymbolKind::Enum | SymbolKind::Interface => CompletionKind::Type,
+    };
+    Completion {
+        label: symbol.name.clone(),
+        kind,
+        detail: Some(symbol.detail.clone()),
+    }
+}
+
+fn find_entity<'a>(program: &'a Program, name: &str) -> Option<&'a EntityDef> {
+    program.statements.iter().find_map(|stmt| match stmt {
+        Statement::EntityDef(entity) if entity.name == name => Some(entity),
+        _ => None,
+    })
+}
+
+/// Declare every `let` in a function body (including nested blocks)
+fn declare_locals(body: &[Statement], env: &mut TypeEnv) {
+    for stmt in body {
+        match stmt {
+            Statement::VarDecl(var) => {
+                let type_expr = var
+                    .type_expr
+                    .clone()
+                    .or_else(|| infer_type_in(&var.value, env));
+                if let Some(t) = type_expr {
+                    env.declare(&var.name, t);
+                }
+            }
+            Statement::If(if_stmt) => {
+                declare_locals(&if_stmt.then_body, env);
+                for (_, body) in &if_stmt.elif_clauses {
+                    declare_locals(body, env);
+                }
+                if let Some(body) = &if_stmt.else_body {
+                    declare_locals(body, env);
+                }
+            }
+            Statement::Match(match_stmt) => {
+                let subject = infer_type_in(&match_stmt.subject, env);
+                for arm in &match_stmt.arms {
+                    env.declare_pattern(&arm.pattern, subject.as_ref());
+                    declare_locals(&arm.body, env);
+                }
+            }
+            Statement::While(while_stmt) => declare_locals(&while_stmt.body, env),
+            Statement::For(for_stmt) => {
+                env.declare_loop(for_stmt);
+                declare_locals(&for_stmt.body, env);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: &str = "entity Player:
+    component Health:
+        current = 100
+
+    let speed = 200.0
+
+    signal died()
+
+    fn take_damage(amount: int):
+        let old = Health.current
+        Health.current = old - amount
+        if Health.current <= 0:
+            emit died()
+";
+
+    fn pos(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn test_symbols_nest_by_indentation() {
+        let doc = Document::new(PLAYER);
+        assert!(doc.diagnostics().is_empty());
+        let player = &doc.symbols()[0];
+        assert_eq!(player.name, "Player");
+        let members: Vec<&str> = player.children.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(members, vec!["Health", "speed", "died", "take_damage"]);
+        assert_eq!(player.children[0].children[0].name, "current");
+    }
+
+    #[test]
+    fn test_definition_and_references() {
+        let doc = Document::new(PLAYER);
+        // `died` in `emit died()`
+        let def = doc.definition(pos(12, 19)).unwrap();
+        assert_eq!(def.start, pos(6, 11));
+        assert_eq!(doc.references(pos(6, 12), true).len(), 2);
+
+        // `Health.current` resolves to the component field
+        let field = doc.definition(pos(9, 27)).unwrap();
+        assert_eq!(field.start, pos(2, 8));
+        assert_eq!(doc.references(pos(2, 8), false).len(), 3);
+    }
+
+    #[test]
+    fn test_hover_and_completion() {
+        let doc = Document::new(PLAYER);
+        let hover = doc.hover(pos(9, 12)).unwrap();
+        assert!(hover.contains("old: int"), "{}", hover);
+
+        let labels: Vec<String> = doc
+            .completions(pos(12, 17))
+            .into_iter()
+            .map(|c| c.label)
+            .collect();
+        assert_eq!(labels, vec!["died"]);
+
+        // Loop names over a query are its components
+        let doc = Document::new(
+            "system gravity(q: Query<Transform, Velocity, with Player>):\n    for t, v in q:\n        v.y -= 9.8\n",
+        );
+        let hover = doc.hover(pos(2, 8)).unwrap();
+        assert!(hover.contains("v: Velocity"), "{}", hover);
+    }
+
+    #[test]
+    fn test_rename_and_parse_error() {
+        let doc = Document::new(PLAYER);
+        let edits = doc.rename(pos(8, 8), "hurt").unwrap();
+        assert_eq!(edits.len(), 1);
+        assert!(doc.rename(pos(8, 8), "if").is_err());
+
+        let broken = Document::new("entity Player:\n    let = 1\n");
+        assert_eq!(broken.diagnostics().len(), 1);
+        assert_eq!(broken.diagnostics()[0].range.start.line, 1);
+        assert_eq!(broken.symbols()[0].name, "Player");
+    }
+
+    #[test]
+    fn test_parse_error_ranges() {
+        let range = |source: &str| {
+            let doc = Document::new(source);
+            let d = &doc.diagnostics()[0];
+            let (start, end) = (d.range.start, d.range.end);
+            (
+                d.message.clone(),
+                (start.line, start.character, end.line, end.character),
+            )
+        };
+
+        let (message, at) =
+            range("system s(q: Query<A, B>):\n    for (t, v) in q:\n        pass\n");
+        assert_eq!(
+            message,
+            "expected a name: `for` takes its names without parentheses"
+        );
+        assert_eq!(at, (1, 8, 1, 14));
+
+        // A wrapped line is joined before parsing; the error is still on the
+        // line it was written on, in UTF-16 columns
+        let (_, at) = range("fn f():\n    for t in [\"a\",\n        \"é😀\"] x:\n        pass\n");
+        assert_eq!(at, (2, 16, 2, 17));
+    }
+}
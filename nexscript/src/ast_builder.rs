@@ -1,77 +1,99 @@
-//! AST Builder - Constructs AST from pest parse tree
+//! AST Builder - Constructs AST from the concrete syntax tree
 
+use crate::cst::SyntaxNode;
 use crate::{
-    Arg, AssignOp, Assignment, BinaryOp, ComponentDef, EmitStmt, EntityDef, Expr, FnDef, ForStmt,
-    IfStmt, LValue, Param, Program, Rule, SignalDef, StateDef, StateMachine, Statement, TypeExpr,
-    UnaryOp, VarDecl, WhileStmt,
+    Annotation, Arg, AssignOp, Assignment, BinaryOp, ComponentDef, EmitStmt, EntityDef, EnumDef,
+    Expr, FieldDef, FnDef, ForStmt, IfStmt, ImportStmt, InterfaceDef, LValue, LambdaParam,
+    MatchArm, MatchStmt, Param, Pattern, Program, Rule, SignalDef, StateDef, StateMachine,
+    Statement, StructDef, SystemDef, TypeExpr, UnaryOp, VarDecl, VariantDef, WhileStmt,
 };
-use pest::iterators::{Pair, Pairs};
-use std::collections::HashMap;
-
-/// Build AST from parsed pairs
-pub fn build_ast(pairs: Pairs<Rule>) -> Program {
-    let mut statements = Vec::new();
-
-    for pair in pairs {
-        match pair.as_rule() {
-            Rule::program => {
-                for inner in pair.into_inner() {
-                    if let Some(stmt) = build_statement(inner) {
-                        statements.push(stmt);
-                    }
-                }
-            }
-            Rule::EOI => {}
-            _ => {
-                if let Some(stmt) = build_statement(pair) {
-                    statements.push(stmt);
-                }
-            }
-        }
-    }
 
+/// Build AST from the syntax tree of a program
+pub fn build_ast(root: &SyntaxNode) -> Program {
+    let statements = root.nodes().filter_map(build_statement).collect();
     Program { statements }
 }
 
-fn build_statement(pair: Pair<Rule>) -> Option<Statement> {
-    match pair.as_rule() {
+fn build_statement(pair: &SyntaxNode) -> Option<Statement> {
+    match pair.kind {
+        Rule::import_stmt | Rule::from_import_stmt => Some(Statement::Import(build_import(pair))),
         Rule::entity_def => Some(Statement::EntityDef(build_entity(pair))),
+        Rule::struct_def => Some(Statement::StructDef(build_struct(pair))),
+        Rule::enum_def => Some(Statement::EnumDef(build_enum(pair))),
+        Rule::interface_def => Some(Statement::InterfaceDef(build_interface(pair))),
+        Rule::annotated_def => build_annotated(pair),
         Rule::fn_def => Some(Statement::FnDef(build_function(pair))),
+        Rule::system_def => Some(Statement::SystemDef(build_system(pair))),
         Rule::signal_def => Some(Statement::SignalDef(build_signal(pair))),
         Rule::state_machine_def => Some(Statement::StateMachine(build_state_machine(pair))),
         Rule::variable_decl => Some(Statement::VarDecl(build_var_decl(pair))),
         Rule::assignment => Some(Statement::Assignment(build_assignment(pair))),
         Rule::if_stmt => Some(Statement::If(build_if(pair))),
+        Rule::match_stmt => Some(Statement::Match(build_match(pair))),
         Rule::while_stmt => Some(Statement::While(build_while(pair))),
         Rule::for_stmt => Some(Statement::For(build_for(pair))),
         Rule::return_stmt => Some(build_return(pair)),
+        Rule::break_stmt => Some(Statement::Break { span: pair.span }),
+        Rule::continue_stmt => Some(Statement::Continue { span: pair.span }),
+        Rule::pass_stmt => Some(Statement::Pass),
         Rule::emit_stmt => Some(Statement::Emit(build_emit(pair))),
-        Rule::expression => Some(Statement::Expr(build_expression(pair))),
+        Rule::expression => Some(Statement::Expr {
+            expr: build_expression(pair),
+            span: pair.span,
+        }),
         Rule::NEWLINE | Rule::INDENT | Rule::DEDENT | Rule::EOI => None,
         _ => None,
     }
 }
 
-fn build_entity(pair: Pair<Rule>) -> EntityDef {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
+fn build_import(pair: &SyntaxNode) -> ImportStmt {
+    let mut inner = pair
+        .nodes()
+        .filter(|node| !matches!(node.kind, Rule::import_keyword | Rule::from_keyword));
+    let module = inner
+        .next()
+        .unwrap()
+        .nodes()
+        .map(|part| part.text())
+        .collect();
+    let names = inner.map(|name| name.text()).collect();
+    ImportStmt {
+        module,
+        names,
+        span: pair.span,
+    }
+}
+
+fn build_entity(pair: &SyntaxNode) -> EntityDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
 
+    let mut extends = None;
+    let mut implements = Vec::new();
     let mut components = Vec::new();
     let mut functions = Vec::new();
     let mut signals = Vec::new();
     let mut variables = Vec::new();
 
     for member in inner {
-        match member.as_rule() {
+        match member.kind {
             Rule::component_def => components.push(build_component(member)),
             Rule::fn_def => functions.push(build_function(member)),
+            Rule::annotated_fn => functions.extend(build_annotated_fn(member)),
             Rule::signal_def => signals.push(build_signal(member)),
             Rule::variable_decl => variables.push(build_var_decl(member)),
+            Rule::extends_clause => {
+                extends = member.nodes().next().map(|name| name.text());
+            }
+            Rule::implements_clause => {
+                implements = member.nodes().map(|name| name.text()).collect();
+            }
             Rule::entity_body => {
-                for body_member in member.into_inner() {
-                    match body_member.as_rule() {
+                for body_member in member.nodes() {
+                    match body_member.kind {
                         Rule::component_def => components.push(build_component(body_member)),
                         Rule::fn_def => functions.push(build_function(body_member)),
+                        Rule::annotated_fn => functions.extend(build_annotated_fn(body_member)),
                         Rule::signal_def => signals.push(build_signal(body_member)),
                         Rule::variable_decl => variables.push(build_var_decl(body_member)),
                         _ => {}
@@ -84,45 +106,131 @@ fn build_entity(pair: Pair<Rule>) -> EntityDef {
 
     EntityDef {
         name,
+        extends,
+        implements,
         components,
         functions,
         signals,
         variables,
+        span: pair.span,
     }
 }
 
-fn build_component(pair: Pair<Rule>) -> ComponentDef {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
-    let mut fields = HashMap::new();
+fn build_component(pair: &SyntaxNode) -> ComponentDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
+    let mut fields = Vec::new();
 
     for field_pair in inner {
-        if field_pair.as_rule() == Rule::component_body {
-            for field in field_pair.into_inner() {
-                if field.as_rule() == Rule::component_field {
-                    let mut field_inner = field.into_inner();
-                    let field_name = field_inner.next().unwrap().as_str().to_string();
+        if field_pair.kind == Rule::component_body {
+            for field in field_pair.nodes() {
+                if field.kind == Rule::component_field {
+                    let mut field_inner = field.nodes();
+                    let field_name = field_inner.next().unwrap().text();
                     let field_value = build_expression(field_inner.next().unwrap());
-                    fields.insert(field_name, field_value);
+                    fields.push((field_name, field_value));
                 }
             }
         }
     }
 
-    ComponentDef { name, fields }
+    ComponentDef {
+        name,
+        fields,
+        span: pair.span,
+    }
+}
+
+fn build_interface(pair: &SyntaxNode) -> InterfaceDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
+
+    let mut functions = Vec::new();
+    let mut signals = Vec::new();
+    for member in inner.flat_map(|body| body.nodes()) {
+        match member.kind {
+            Rule::fn_signature => functions.push(build_function(member)),
+            Rule::signal_def => signals.push(build_signal(member)),
+            _ => {}
+        }
+    }
+
+    InterfaceDef {
+        name,
+        functions,
+        signals,
+        span: pair.span,
+    }
 }
 
-fn build_function(pair: Pair<Rule>) -> FnDef {
-    let mut inner = pair.into_inner();
+fn build_struct(pair: &SyntaxNode) -> StructDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
+
+    let mut fields = Vec::new();
+    for body in inner.filter(|item| item.kind == Rule::struct_body) {
+        for field in body.nodes().filter(|f| f.kind == Rule::struct_field) {
+            let mut field_inner = field.nodes();
+            let field_name = field_inner.next().unwrap().text();
+            let type_expr = build_type(field_inner.next().unwrap());
+            let default = field_inner.next().map(build_expression);
+            fields.push(FieldDef {
+                name: field_name,
+                type_expr,
+                default,
+                span: field.span,
+            });
+        }
+    }
+
+    StructDef {
+        name,
+        fields,
+        span: pair.span,
+    }
+}
+
+fn build_enum(pair: &SyntaxNode) -> EnumDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
+
+    let mut variants = Vec::new();
+    for body in inner.filter(|item| item.kind == Rule::enum_body) {
+        for variant in body.nodes().filter(|v| v.kind == Rule::enum_variant) {
+            let mut variant_inner = variant.nodes();
+            let variant_name = variant_inner.next().unwrap().text();
+            let fields = variant_inner
+                .filter(|item| item.kind == Rule::param_list)
+                .flat_map(|list| list.nodes())
+                .filter(|param| param.kind == Rule::param)
+                .map(build_param)
+                .collect();
+            variants.push(VariantDef {
+                name: variant_name,
+                fields,
+                span: variant.span,
+            });
+        }
+    }
+
+    EnumDef {
+        name,
+        variants,
+        span: pair.span,
+    }
+}
+
+fn build_function(pair: &SyntaxNode) -> FnDef {
+    let mut inner = pair.nodes();
     let mut is_async = false;
 
     // Check for async keyword
     let first = inner.next().unwrap();
-    let name = if first.as_rule() == Rule::async_keyword {
+    let name = if first.kind == Rule::async_keyword {
         is_async = true;
-        inner.next().unwrap().as_str().to_string()
+        inner.next().unwrap().text()
     } else {
-        first.as_str().to_string()
+        first.text()
     };
 
     let mut params = Vec::new();
@@ -130,20 +238,20 @@ fn build_function(pair: Pair<Rule>) -> FnDef {
     let mut body = Vec::new();
 
     for item in inner {
-        match item.as_rule() {
+        match item.kind {
             Rule::param_list => {
-                for param in item.into_inner() {
-                    if param.as_rule() == Rule::param {
+                for param in item.nodes() {
+                    if param.kind == Rule::param {
                         params.push(build_param(param));
                     }
                 }
             }
             Rule::return_type => {
-                let type_pair = item.into_inner().next().unwrap();
+                let type_pair = item.nodes().next().unwrap();
                 return_type = Some(build_type(type_pair));
             }
             Rule::block => {
-                for stmt in item.into_inner() {
+                for stmt in item.nodes() {
                     if let Some(s) = build_statement(stmt) {
                         body.push(s);
                     }
@@ -159,66 +267,143 @@ fn build_function(pair: Pair<Rule>) -> FnDef {
         params,
         return_type,
         body,
+        annotations: Vec::new(),
+        span: pair.span,
     }
 }
 
-fn build_param(pair: Pair<Rule>) -> Param {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
+/// A function or system with the annotations above it
+fn build_annotated(pair: &SyntaxNode) -> Option<Statement> {
+    let mut annotations = Vec::new();
+    for item in pair.nodes() {
+        match item.kind {
+            Rule::annotation => annotations.push(build_annotation(item)),
+            Rule::fn_def => {
+                let func = build_function(item);
+                return Some(Statement::FnDef(FnDef {
+                    annotations,
+                    ..func
+                }));
+            }
+            Rule::system_def => {
+                let system = build_system(item);
+                return Some(Statement::SystemDef(SystemDef {
+                    annotations,
+                    ..system
+                }));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn build_annotated_fn(pair: &SyntaxNode) -> Option<FnDef> {
+    match build_annotated(pair)? {
+        Statement::FnDef(func) => Some(func),
+        _ => None,
+    }
+}
+
+fn build_annotation(pair: &SyntaxNode) -> Annotation {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
+    Annotation {
+        name,
+        args: inner.map(build_expression).collect(),
+        span: pair.span,
+    }
+}
+
+/// A system has a function's name, parameters and body
+fn build_system(pair: &SyntaxNode) -> SystemDef {
+    let func = build_function(pair);
+    SystemDef {
+        name: func.name,
+        params: func.params,
+        body: func.body,
+        annotations: Vec::new(),
+        span: func.span,
+    }
+}
+
+fn build_param(pair: &SyntaxNode) -> Param {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
     let type_expr = build_type(inner.next().unwrap());
-    Param { name, type_expr }
+    Param {
+        name,
+        type_expr,
+        span: pair.span,
+    }
 }
 
-fn build_type(pair: Pair<Rule>) -> TypeExpr {
-    match pair.as_rule() {
-        Rule::type_expr => build_type(pair.into_inner().next().unwrap()),
-        Rule::simple_type => TypeExpr::Simple(pair.as_str().to_string()),
+fn build_type(pair: &SyntaxNode) -> TypeExpr {
+    match pair.kind {
+        Rule::type_expr => build_type(pair.nodes().next().unwrap()),
+        Rule::simple_type => TypeExpr::Simple(pair.text()),
         Rule::generic_type => {
-            let mut inner = pair.into_inner();
-            let name = inner.next().unwrap().as_str().to_string();
+            let mut inner = pair.nodes();
+            let name = inner.next().unwrap().text();
             let params: Vec<TypeExpr> = inner
-                .filter(|p| p.as_rule() == Rule::type_list)
-                .flat_map(|p| p.into_inner())
+                .filter(|p| p.kind == Rule::type_list)
+                .flat_map(|p| p.nodes())
                 .map(build_type)
                 .collect();
             TypeExpr::Generic { name, params }
         }
-        Rule::identifier => TypeExpr::Simple(pair.as_str().to_string()),
-        _ => TypeExpr::Simple(pair.as_str().to_string()),
+        // `with Player` is `With<Player>`
+        Rule::query_filter => {
+            let mut inner = pair.nodes();
+            let name = match inner.next().unwrap().text().as_str() {
+                "with" => "With",
+                _ => "Without",
+            };
+            TypeExpr::Generic {
+                name: name.to_string(),
+                params: vec![build_type(inner.next().unwrap())],
+            }
+        }
+        Rule::identifier => TypeExpr::Simple(pair.text()),
+        _ => TypeExpr::Simple(pair.text()),
     }
 }
 
-fn build_signal(pair: Pair<Rule>) -> SignalDef {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
+fn build_signal(pair: &SyntaxNode) -> SignalDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
 
     let mut params = Vec::new();
     for item in inner {
-        if item.as_rule() == Rule::param_list {
-            for param in item.into_inner() {
-                if param.as_rule() == Rule::param {
+        if item.kind == Rule::param_list {
+            for param in item.nodes() {
+                if param.kind == Rule::param {
                     params.push(build_param(param));
                 }
             }
         }
     }
 
-    SignalDef { name, params }
+    SignalDef {
+        name,
+        params,
+        span: pair.span,
+    }
 }
 
-fn build_state_machine(pair: Pair<Rule>) -> StateMachine {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
+fn build_state_machine(pair: &SyntaxNode) -> StateMachine {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
 
     let mut initial_state = None;
     let mut states = Vec::new();
 
     for item in inner {
-        match item.as_rule() {
+        match item.kind {
             Rule::state_machine_body => {
-                for body_item in item.into_inner() {
-                    match body_item.as_rule() {
-                        Rule::identifier => initial_state = Some(body_item.as_str().to_string()),
+                for body_item in item.nodes() {
+                    match body_item.kind {
+                        Rule::identifier => initial_state = Some(body_item.text()),
                         Rule::state_def => states.push(build_state(body_item)),
                         _ => {}
                     }
@@ -233,17 +418,18 @@ fn build_state_machine(pair: Pair<Rule>) -> StateMachine {
         name,
         initial_state,
         states,
+        span: pair.span,
     }
 }
 
-fn build_state(pair: Pair<Rule>) -> StateDef {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
+fn build_state(pair: &SyntaxNode) -> StateDef {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
 
     let mut body = Vec::new();
     for item in inner {
-        if item.as_rule() == Rule::block {
-            for stmt in item.into_inner() {
+        if item.kind == Rule::block {
+            for stmt in item.nodes() {
                 if let Some(s) = build_statement(stmt) {
                     body.push(s);
                 }
@@ -251,18 +437,22 @@ fn build_state(pair: Pair<Rule>) -> StateDef {
         }
     }
 
-    StateDef { name, body }
+    StateDef {
+        name,
+        body,
+        span: pair.span,
+    }
 }
 
-fn build_var_decl(pair: Pair<Rule>) -> VarDecl {
-    let mut inner = pair.into_inner();
-    let name = inner.next().unwrap().as_str().to_string();
+fn build_var_decl(pair: &SyntaxNode) -> VarDecl {
+    let mut inner = pair.nodes();
+    let name = inner.next().unwrap().text();
 
     let mut type_expr = None;
     let mut value = Expr::Int(0); // Default
 
     for item in inner {
-        match item.as_rule() {
+        match item.kind {
             Rule::type_expr => type_expr = Some(build_type(item)),
             Rule::expression
             | Rule::or_expr
@@ -291,22 +481,20 @@ fn build_var_decl(pair: Pair<Rule>) -> VarDecl {
         name,
         type_expr,
         value,
+        span: pair.span,
     }
 }
 
-fn build_assignment(pair: Pair<Rule>) -> Assignment {
-    let mut inner = pair.into_inner();
+fn build_assignment(pair: &SyntaxNode) -> Assignment {
+    let mut inner = pair.nodes();
 
     let lvalue_pair = inner.next().unwrap();
     let target = LValue {
-        parts: lvalue_pair
-            .into_inner()
-            .map(|p| p.as_str().to_string())
-            .collect(),
+        parts: lvalue_pair.nodes().map(|p| p.text()).collect(),
     };
 
     let op_pair = inner.next().unwrap();
-    let op = match op_pair.as_str() {
+    let op = match op_pair.text().as_str() {
         "=" => AssignOp::Assign,
         "+=" => AssignOp::AddAssign,
         "-=" => AssignOp::SubAssign,
@@ -317,11 +505,16 @@ fn build_assignment(pair: Pair<Rule>) -> Assignment {
 
     let value = build_expression(inner.next().unwrap());
 
-    Assignment { target, op, value }
+    Assignment {
+        target,
+        op,
+        value,
+        span: pair.span,
+    }
 }
 
-fn build_if(pair: Pair<Rule>) -> IfStmt {
-    let mut inner = pair.into_inner();
+fn build_if(pair: &SyntaxNode) -> IfStmt {
+    let mut inner = pair.nodes();
 
     let condition = build_expression(inner.next().unwrap());
     let mut then_body = Vec::new();
@@ -329,10 +522,10 @@ fn build_if(pair: Pair<Rule>) -> IfStmt {
     let mut else_body = None;
 
     for item in inner {
-        match item.as_rule() {
+        match item.kind {
             Rule::block => {
                 if then_body.is_empty() {
-                    for stmt in item.into_inner() {
+                    for stmt in item.nodes() {
                         if let Some(s) = build_statement(stmt) {
                             then_body.push(s);
                         }
@@ -340,11 +533,11 @@ fn build_if(pair: Pair<Rule>) -> IfStmt {
                 }
             }
             Rule::elif_clause => {
-                let mut elif_inner = item.into_inner();
+                let mut elif_inner = item.nodes();
                 let elif_cond = build_expression(elif_inner.next().unwrap());
                 let mut elif_body = Vec::new();
-                if let Some(block) = elif_inner.next() {
-                    for stmt in block.into_inner() {
+                if let Some(block) = elif_inner.find(|p| p.kind == Rule::block) {
+                    for stmt in block.nodes() {
                         if let Some(s) = build_statement(stmt) {
                             elif_body.push(s);
                         }
@@ -354,9 +547,9 @@ fn build_if(pair: Pair<Rule>) -> IfStmt {
             }
             Rule::else_clause => {
                 let mut else_stmts = Vec::new();
-                for block in item.into_inner() {
-                    if block.as_rule() == Rule::block {
-                        for stmt in block.into_inner() {
+                for block in item.nodes() {
+                    if block.kind == Rule::block {
+                        for stmt in block.nodes() {
                             if let Some(s) = build_statement(stmt) {
                                 else_stmts.push(s);
                             }
@@ -374,17 +567,96 @@ fn build_if(pair: Pair<Rule>) -> IfStmt {
         then_body,
         elif_clauses,
         else_body,
+        span: pair.span,
+    }
+}
+
+fn build_match(pair: &SyntaxNode) -> MatchStmt {
+    let mut inner = pair.nodes();
+    let subject = build_expression(inner.next().unwrap());
+    let arms = inner
+        .filter(|p| p.kind == Rule::match_arm)
+        .map(build_arm)
+        .collect();
+    MatchStmt {
+        subject,
+        arms,
+        span: pair.span,
+    }
+}
+
+fn build_arm(pair: &SyntaxNode) -> MatchArm {
+    let mut inner = pair.nodes();
+    let pattern = build_pattern(inner.next().unwrap());
+
+    let mut guard = None;
+    let mut body = Vec::new();
+    for item in inner {
+        match item.kind {
+            Rule::match_guard => guard = Some(build_expression(item.nodes().next().unwrap())),
+            Rule::block => body.extend(item.nodes().filter_map(build_statement)),
+            _ => {}
+        }
+    }
+
+    MatchArm {
+        pattern,
+        guard,
+        body,
+        span: pair.span,
+    }
+}
+
+fn build_pattern(pair: &SyntaxNode) -> Pattern {
+    match pair.kind {
+        Rule::binding_pattern => Pattern::Binding(pair.nodes().next().unwrap().text()),
+        Rule::literal_pattern => {
+            let mut inner = pair.nodes();
+            let first = inner.next().unwrap();
+            if first.kind == Rule::negative {
+                let value = build_expression(inner.next().unwrap());
+                Pattern::Literal(Expr::UnaryOp(UnaryOp::Neg, Box::new(value)))
+            } else {
+                Pattern::Literal(build_expression(first))
+            }
+        }
+        Rule::range_pattern => {
+            let inner: Vec<&SyntaxNode> = pair.nodes().collect();
+            let bound = |pattern: &SyntaxNode| match build_pattern(pattern) {
+                Pattern::Literal(value) => value,
+                _ => Expr::Int(0),
+            };
+            Pattern::Range {
+                start: bound(inner[0]),
+                end: bound(inner[2]),
+                inclusive: inner[1].text() == "..=",
+            }
+        }
+        Rule::tuple_pattern => Pattern::Tuple(pair.nodes().map(build_pattern).collect()),
+        Rule::variant_pattern => {
+            let mut path = Vec::new();
+            let mut fields = Vec::new();
+            for item in pair.nodes() {
+                if item.kind == Rule::identifier {
+                    path.push(item.text());
+                } else {
+                    fields.push(build_pattern(item));
+                }
+            }
+            Pattern::Variant { path, fields }
+        }
+        _ => Pattern::Wildcard,
     }
 }
 
-fn build_while(pair: Pair<Rule>) -> WhileStmt {
-    let mut inner = pair.into_inner();
+fn build_while(pair: &SyntaxNode) -> WhileStmt {
+    let mut inner = pair.nodes();
     let condition = build_expression(inner.next().unwrap());
 
     let mut body = Vec::new();
     for item in inner {
-        if item.as_rule() == Rule::block {
-            for stmt in item.into_inner() {
+        if item.kind == Rule::block {
+            for stmt in item.nodes() {
                 if let Some(s) = build_statement(stmt) {
                     body.push(s);
                 }
@@ -392,18 +664,24 @@ fn build_while(pair: Pair<Rule>) -> WhileStmt {
         }
     }
 
-    WhileStmt { condition, body }
+    WhileStmt {
+        condition,
+        body,
+        span: pair.span,
+    }
 }
 
-fn build_for(pair: Pair<Rule>) -> ForStmt {
-    let mut inner = pair.into_inner();
-    let var_name = inner.next().unwrap().as_str().to_string();
-    let iterable = build_expression(inner.next().unwrap());
-
+fn build_for(pair: &SyntaxNode) -> ForStmt {
+    let mut names = Vec::new();
+    let mut iterable = None;
     let mut body = Vec::new();
-    for item in inner {
-        if item.as_rule() == Rule::block {
-            for stmt in item.into_inner() {
+    for item in pair.nodes() {
+        if item.kind == Rule::identifier {
+            names.push(item.text());
+        } else if iterable.is_none() {
+            iterable = Some(build_expression(item));
+        } else if item.kind == Rule::block {
+            for stmt in item.nodes() {
                 if let Some(s) = build_statement(stmt) {
                     body.push(s);
                 }
@@ -412,47 +690,103 @@ fn build_for(pair: Pair<Rule>) -> ForStmt {
     }
 
     ForStmt {
-        var_name,
-        iterable,
+        names,
+        iterable: iterable.unwrap(),
         body,
+        span: pair.span,
     }
 }
 
-fn build_return(pair: Pair<Rule>) -> Statement {
-    let expr = pair.into_inner().next().map(build_expression);
+fn build_return(pair: &SyntaxNode) -> Statement {
+    let expr = pair.nodes().next().map(build_expression);
     Statement::Return(expr)
 }
 
-fn build_emit(pair: Pair<Rule>) -> EmitStmt {
-    let mut inner = pair.into_inner();
-    let signal_name = inner.next().unwrap().as_str().to_string();
+fn build_emit(pair: &SyntaxNode) -> EmitStmt {
+    let mut inner = pair.nodes();
+    let signal_name = inner.next().unwrap().text();
 
     let mut args = Vec::new();
     for item in inner {
-        if item.as_rule() == Rule::arg_list {
-            for arg in item.into_inner() {
-                if arg.as_rule() == Rule::arg {
-                    args.push(build_expression(arg.into_inner().last().unwrap()));
+        if item.kind == Rule::arg_list {
+            for arg in item.nodes() {
+                if arg.kind == Rule::arg {
+                    args.push(build_expression(arg.nodes().last().unwrap()));
                 }
             }
         }
     }
 
-    EmitStmt { signal_name, args }
+    EmitStmt {
+        signal_name,
+        args,
+        span: pair.span,
+    }
 }
 
-fn build_expression(pair: Pair<Rule>) -> Expr {
-    match pair.as_rule() {
+fn build_expression(pair: &SyntaxNode) -> Expr {
+    match pair.kind {
+        // The "not" keyword is not captured as a pair, so detect it by the
+        // operand starting after the rule itself
+        Rule::not_expr => {
+            let mut inner = pair.nodes();
+            let first = inner.next().unwrap();
+            match inner.next() {
+                Some(operand) if first.kind == Rule::not_keyword => {
+                    Expr::UnaryOp(UnaryOp::Not, Box::new(build_expression(operand)))
+                }
+                _ => build_expression(first),
+            }
+        }
+
+        // Calls, indexing and member access chain left to right onto the primary
+        Rule::postfix_expr => {
+            let mut inner = pair.nodes();
+            let mut result = build_expression(inner.next().unwrap());
+            for postfix in inner {
+                result = match postfix.kind {
+                    Rule::call => match build_expression(postfix) {
+                        Expr::Call { args, .. } => Expr::Call {
+                            callee: Box::new(result),
+                            args,
+                        },
+                        other => other,
+                    },
+                    Rule::index => {
+                        let index = build_expression(postfix.nodes().next().unwrap());
+                        Expr::Index(Box::new(result), Box::new(index))
+                    }
+                    Rule::member_access => {
+                        let member = postfix.nodes().next().unwrap().text();
+                        Expr::MemberAccess(Box::new(result), member)
+                    }
+                    _ => result,
+                };
+            }
+            result
+        }
+
+        // The "and" / "or" keywords are not captured either, so every pair
+        // after the first is another operand
+        Rule::or_expr | Rule::and_expr => {
+            let op = if pair.kind == Rule::or_expr {
+                BinaryOp::Or
+            } else {
+                BinaryOp::And
+            };
+            let mut operands = pair.nodes().map(build_expression);
+            let first = operands.next().unwrap();
+            operands.fold(first, |left, right| {
+                Expr::BinaryOp(Box::new(left), op, Box::new(right))
+            })
+        }
+
         Rule::expression
-        | Rule::or_expr
-        | Rule::and_expr
-        | Rule::not_expr
         | Rule::comparison
         | Rule::add_expr
         | Rule::mul_expr
-        | Rule::unary_expr
-        | Rule::postfix_expr => {
-            let mut inner: Vec<Pair<Rule>> = pair.into_inner().collect();
+        | Rule::unary_expr => {
+            let mut inner: Vec<&SyntaxNode> = pair.nodes().collect();
 
             if inner.len() == 1 {
                 return build_expression(inner.remove(0));
@@ -461,14 +795,14 @@ fn build_expression(pair: Pair<Rule>) -> Expr {
             // Binary operations
             if inner.len() >= 3 {
                 let left = build_expression(inner.remove(0));
-                let op = parse_binary_op(inner.remove(0).as_str());
+                let op = parse_binary_op(&inner.remove(0).text());
                 let right = build_expression(inner.remove(0));
 
                 let mut result = Expr::BinaryOp(Box::new(left), op, Box::new(right));
 
                 // Handle chained operations
                 while inner.len() >= 2 {
-                    let next_op = parse_binary_op(inner.remove(0).as_str());
+                    let next_op = parse_binary_op(&inner.remove(0).text());
                     let next_right = build_expression(inner.remove(0));
                     result = Expr::BinaryOp(Box::new(result), next_op, Box::new(next_right));
                 }
@@ -477,70 +811,100 @@ fn build_expression(pair: Pair<Rule>) -> Expr {
             }
 
             // Unary operations
-            if inner.len() == 2 {
-                let op_str = inner[0].as_str();
-                if op_str == "-" || op_str == "not" {
-                    let op = if op_str == "-" {
-                        UnaryOp::Neg
-                    } else {
+            if let [op, operand] = inner.as_slice() {
+                if op.kind == Rule::unary_op {
+                    let op = if op.nodes().any(|node| node.kind == Rule::not_keyword) {
                         UnaryOp::Not
+                    } else {
+                        UnaryOp::Neg
                     };
-                    return Expr::UnaryOp(op, Box::new(build_expression(inner.remove(1))));
+                    return Expr::UnaryOp(op, Box::new(build_expression(operand)));
                 }
             }
 
             Expr::Int(0) // Fallback
         }
 
-        Rule::int_literal => Expr::Int(pair.as_str().parse().unwrap_or(0)),
-        Rule::float_literal => Expr::Float(pair.as_str().parse().unwrap_or(0.0)),
+        Rule::int_literal => Expr::Int(pair.text().parse().unwrap_or(0)),
+        Rule::float_literal => Expr::Float(pair.text().parse().unwrap_or(0.0)),
         Rule::string_literal => {
-            let s = pair.as_str();
+            let s = pair.text();
             Expr::String(s[1..s.len() - 1].to_string())
         }
-        Rule::bool_literal => Expr::Bool(pair.as_str() == "true"),
+        Rule::bool_literal => Expr::Bool(pair.text() == "true"),
         Rule::vec2_literal => {
-            let mut inner = pair.into_inner();
+            let mut inner = pair.nodes();
             let x = build_expression(inner.next().unwrap());
             let y = build_expression(inner.next().unwrap());
             Expr::Vec2(Box::new(x), Box::new(y))
         }
         Rule::vec3_literal => {
-            let mut inner = pair.into_inner();
+            let mut inner = pair.nodes();
             let x = build_expression(inner.next().unwrap());
             let y = build_expression(inner.next().unwrap());
             let z = build_expression(inner.next().unwrap());
             Expr::Vec3(Box::new(x), Box::new(y), Box::new(z))
         }
+        Rule::tuple_literal => Expr::Tuple(pair.nodes().map(build_expression).collect()),
+        Rule::lambda => {
+            let mut params = Vec::new();
+            let mut return_type = None;
+            let mut body = Expr::Int(0);
+            for item in pair.nodes() {
+                match item.kind {
+                    Rule::lambda_params => {
+                        params = item
+                            .nodes()
+                            .map(|param| {
+                                let mut inner = param.nodes();
+                                LambdaParam {
+                                    name: inner.next().unwrap().text(),
+                                    type_expr: inner.next().map(build_type),
+                                }
+                            })
+                            .collect();
+                    }
+                    Rule::return_type => {
+                        return_type = Some(build_type(item.nodes().next().unwrap()));
+                    }
+                    _ => body = build_expression(item),
+                }
+            }
+            Expr::Lambda {
+                params,
+                return_type,
+                body: Box::new(body),
+            }
+        }
         Rule::list_literal => {
-            let items: Vec<Expr> = pair.into_inner().map(build_expression).collect();
+            let items: Vec<Expr> = pair.nodes().map(build_expression).collect();
             Expr::List(items)
         }
         Rule::map_literal => {
             let entries: Vec<(String, Expr)> = pair
-                .into_inner()
-                .filter(|p| p.as_rule() == Rule::map_entry)
+                .nodes()
+                .filter(|p| p.kind == Rule::map_entry)
                 .map(|entry| {
-                    let mut inner = entry.into_inner();
-                    let key = inner.next().unwrap().as_str().trim_matches('"').to_string();
+                    let mut inner = entry.nodes();
+                    let key = inner.next().unwrap().text().trim_matches('"').to_string();
                     let value = build_expression(inner.next().unwrap());
                     (key, value)
                 })
                 .collect();
             Expr::Map(entries)
         }
-        Rule::identifier => Expr::Identifier(pair.as_str().to_string()),
+        Rule::identifier => Expr::Identifier(pair.text()),
         Rule::call => {
             let args: Vec<Arg> = pair
-                .into_inner()
-                .filter(|p| p.as_rule() == Rule::arg_list)
-                .flat_map(|al| al.into_inner())
-                .filter(|p| p.as_rule() == Rule::arg)
+                .nodes()
+                .filter(|p| p.kind == Rule::arg_list)
+                .flat_map(|al| al.nodes())
+                .filter(|p| p.kind == Rule::arg)
                 .map(|arg| {
-                    let mut inner: Vec<Pair<Rule>> = arg.into_inner().collect();
+                    let mut inner: Vec<&SyntaxNode> = arg.nodes().collect();
                     if inner.len() == 2 {
                         // Named argument
-                        let name = Some(inner.remove(0).as_str().to_string());
+                        let name = Some(inner.remove(0).text());
                         let value = build_expression(inner.remove(0));
                         Arg { name, value }
                     } else {
@@ -559,7 +923,7 @@ fn build_expression(pair: Pair<Rule>) -> Expr {
         }
         _ => {
             // Try to recurse into first child
-            if let Some(child) = pair.into_inner().next() {
+            if let Some(child) = pair.nodes().next() {
                 return build_expression(child);
             }
             Expr::Int(0)
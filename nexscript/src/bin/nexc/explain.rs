@@ -0,0 +1,125 @@
+//! `nexc explain-rustc` - Point rustc diagnostics at `.nx` sources
+//!
+//! Reads cargo's `--message-format=json` output, or plain text such as a
+//! panic message, and for every location inside a generated file that has a
+//! source map next to it, shows the `.nx` line it was generated from.
+//! Everything else passes through unchanged. Paths are resolved from the
+//! current directory, so run it where cargo ran.
+
+use nexscript::source_map::SourceMap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+/// Source maps by generated file, loaded on first use
+#[derive(Default)]
+struct Maps(HashMap<PathBuf, Option<SourceMap>>);
+
+impl Maps {
+    /// `.nx` location of a line in a generated file
+    fn remap(&mut self, file: &str, line: usize) -> Option<String> {
+        let map = self
+            .0
+            .entry(PathBuf::from(file))
+            .or_insert_with(|| SourceMap::load(Path::new(file)))
+            .as_ref()?;
+        let mapping = map.lookup(line)?;
+        Some(format!(
+            "{}:{}:{}",
+            map.source, mapping.line, mapping.column
+        ))
+    }
+}
+
+pub fn run(reader: impl BufRead) -> io::Result<()> {
+    let mut maps = Maps::default();
+    for line in reader.lines() {
+        let line = line?;
+        match serde_json::from_str::<Value>(&line) {
+            Ok(message) if message.is_object() => {
+                // Artifact and build-finished messages are left out
+                if message["reason"] == "compiler-message" {
+                    explain(&message["message"], &mut maps);
+                }
+            }
+            _ => println!("{}", remap_text(&line, &mut maps)),
+        }
+    }
+    Ok(())
+}
+
+fn explain(diagnostic: &Value, maps: &mut Maps) {
+    let spans: Vec<(String, usize, usize, Option<&str>)> = diagnostic["spans"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|span| {
+            Some((
+                span["file_name"].as_str()?.to_string(),
+                span["line_start"].as_u64()? as usize,
+                span["column_start"].as_u64()? as usize,
+                span["label"].as_str(),
+            ))
+        })
+        .collect();
+    let remapped: Vec<Option<String>> = spans
+        .iter()
+        .map(|(file, line, _, _)| maps.remap(file, *line))
+        .collect();
+
+    if remapped.iter().all(Option::is_none) {
+        if let Some(rendered) = diagnostic["rendered"].as_str() {
+            print!("{}", rendered);
+        }
+        return;
+    }
+
+    let level = diagnostic["level"].as_str().unwrap_or("error");
+    let message = diagnostic["message"].as_str().unwrap_or_default();
+    match diagnostic["code"]["code"].as_str() {
+        Some(code) => println!("{}[{}]: {}", level, code, message),
+        None => println!("{}: {}", level, message),
+    }
+    for ((file, line, column, label), nx) in spans.iter().zip(&remapped) {
+        let generated = format!("{}:{}:{}", file, line, column);
+        match nx {
+            Some(nx) => println!("  --> {} (generated {})", nx, generated),
+            None => println!("  --> {}", generated),
+        }
+        if let Some(label) = label {
+            println!("      {}", label);
+        }
+    }
+    for child in diagnostic["children"].as_array().into_iter().flatten() {
+        let level = child["level"].as_str().unwrap_or("note");
+        let message = child["message"].as_str().unwrap_or_default();
+        println!("   = {}: {}", level, message);
+    }
+    println!();
+}
+
+/// Annotate every `file.rs:line:column` with its `.nx` location
+fn remap_text(text: &str, maps: &mut Maps) -> String {
+    text.split(' ')
+        .map(|word| {
+            let location = word.trim_matches(|c: char| "'\"`(),:".contains(c));
+            let mut parts = location.rsplitn(3, ':');
+            let (Some(_column), Some(line), Some(file)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return word.to_string();
+            };
+            let nx = line
+                .parse()
+                .ok()
+                .filter(|_| file.ends_with(".rs"))
+                .and_then(|line| maps.remap(file, line));
+            match nx {
+                Some(nx) => format!("{} [{}]", word, nx),
+                None => word.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
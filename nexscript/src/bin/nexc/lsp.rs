@@ -0,0 +1,361 @@
+//! Language server for `nexc lsp`
+//!
+//! Speaks the Language Server Protocol over stdio. All language knowledge
+//! lives in `nexscript::analysis`; this module only translates between the
+//! protocol types and the analysis types.
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Rename,
+    Request as RequestTrait,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString, OneOf, Position,
+    PublishDiagnosticsParams, Range, ReferenceParams, RenameParams, ServerCapabilities, SymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+use nexscript::analysis::{self, Document};
+use nexscript::diagnostics::Severity;
+use nexscript::manifest::{Lints, Manifest};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+type LspResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+/// Run the server until the client asks it to shut down
+pub fn run() -> LspResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_string()]),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    Server {
+        connection: &connection,
+        documents: HashMap::new(),
+        lints: HashMap::new(),
+    }
+    .main_loop()?;
+
+    // The writer thread only finishes once every sender is gone
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: HashMap<Url, Document>,
+    /// Lint levels by manifest path, with the manifest's modification time
+    /// when they were loaded
+    lints: HashMap<PathBuf, (Option<SystemTime>, Lints)>,
+}
+
+impl Server<'_> {
+    fn main_loop(&mut self) -> LspResult<()> {
+        for msg in &self.connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(req);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(not) => self.handle_notification(not)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, req: Request) -> Response {
+        let id = req.id.clone();
+        let result = match req.method.as_str() {
+            Completion::METHOD => self.completion(req),
+            HoverRequest::METHOD => self.hover(req),
+            GotoDefinition::METHOD => self.definition(req),
+            References::METHOD => self.references(req),
+            DocumentSymbolRequest::METHOD => self.document_symbols(req),
+            Rename::METHOD => self.rename(req),
+            _ => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request: {}", req.method),
+                )
+            }
+        };
+        match result {
+            Ok(response) => response,
+            Err(e) => Response::new_err(id, ErrorCode::RequestFailed as i32, e.to_string()),
+        }
+    }
+
+    fn handle_notification(&mut self, not: Notification) -> LspResult<()> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+                self.update(params.text_document.uri, &params.text_document.text)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+                // Full sync: the last change holds the whole document
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.update(params.text_document.uri, &change.text)?;
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish_diagnostics(params.text_document.uri, Vec::new())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, uri: Url, text: &str) -> LspResult<()> {
+        let doc = Document::new(text);
+        let lints = self.lints_for(&uri);
+        let diagnostics = doc
+            .diagnostics()
+            .iter()
+            .filter_map(|d| Some((d, lints.severity(&d.code, d.severity)?)))
+            .map(|(d, severity)| Diagnostic {
+                range: to_lsp_range(d.range),
+                severity: Some(match severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: Some(NumberOrString::String(d.code.clone())),
+                source: Some("nexscript".to_string()),
+                message: d.message.clone(),
+                ..Default::default()
+            })
+            .collect();
+        self.documents.insert(uri.clone(), doc);
+        self.publish_diagnostics(uri, diagnostics)
+    }
+
+    /// Lint levels from the manifest of the project a document is in.
+    /// Documents outside a project, or in one whose manifest doesn't load, use
+    /// the defaults. The manifest is only parsed again once it changes.
+    fn lints_for(&mut self, uri: &Url) -> Lints {
+        let Some(path) = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| Manifest::find(path.parent()?))
+        else {
+            return Lints::default();
+        };
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        match self.lints.get(&path) {
+            Some((loaded, lints)) if *loaded == modified => lints.clone(),
+            _ => {
+                let lints = Manifest::load(&path)
+                    .map(|manifest| manifest.lints)
+                    .unwrap_or_default();
+                self.lints.insert(path, (modified, lints.clone()));
+                lints
+            }
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: Url, diagnostics: Vec<Diagnostic>) -> LspResult<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                PublishDiagnostics::METHOD.to_string(),
+                params,
+            )))?;
+        Ok(())
+    }
+
+    fn document(&self, uri: &Url) -> LspResult<&Document> {
+        self.documents
+            .get(uri)
+            .ok_or_else(|| format!("document not open: {}", uri).into())
+    }
+
+    fn completion(&self, req: Request) -> LspResult<Response> {
+        let (id, params) = extract::<CompletionParams>(req, Completion::METHOD)?;
+        let position = params.text_document_position;
+        let doc = self.document(&position.text_document.uri)?;
+
+        let items: Vec<CompletionItem> = doc
+            .completions(from_lsp_position(position.position))
+            .into_iter()
+            .map(|c| CompletionItem {
+                label: c.label,
+                kind: Some(completion_kind(c.kind)),
+                detail: c.detail,
+                ..Default::default()
+            })
+            .collect();
+        Ok(Response::new_ok(id, CompletionResponse::Array(items)))
+    }
+
+    fn hover(&self, req: Request) -> LspResult<Response> {
+        let (id, params) = extract::<HoverParams>(req, HoverRequest::METHOD)?;
+        let position = params.text_document_position_params;
+        let doc = self.document(&position.text_document.uri)?;
+
+        let hover = doc
+            .hover(from_lsp_position(position.position))
+            .map(|value| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value,
+                }),
+                range: None,
+            });
+        Ok(Response::new_ok(id, hover))
+    }
+
+    fn definition(&self, req: Request) -> LspResult<Response> {
+        let (id, params) = extract::<GotoDefinitionParams>(req, GotoDefinition::METHOD)?;
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let doc = self.document(&uri)?;
+
+        let location = doc
+            .definition(from_lsp_position(position.position))
+            .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri, to_lsp_range(range))));
+        Ok(Response::new_ok(id, location))
+    }
+
+    fn references(&self, req: Request) -> LspResult<Response> {
+        let (id, params) = extract::<ReferenceParams>(req, References::METHOD)?;
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let doc = self.document(&uri)?;
+
+        let locations: Vec<Location> = doc
+            .references(
+                from_lsp_position(position.position),
+                params.context.include_declaration,
+            )
+            .into_iter()
+            .map(|range| Location::new(uri.clone(), to_lsp_range(range)))
+            .collect();
+        Ok(Response::new_ok(id, locations))
+    }
+
+    fn document_symbols(&self, req: Request) -> LspResult<Response> {
+        let (id, params) = extract::<DocumentSymbolParams>(req, DocumentSymbolRequest::METHOD)?;
+        let doc = self.document(&params.text_document.uri)?;
+
+        let symbols = doc.symbols().iter().map(to_lsp_symbol).collect();
+        Ok(Response::new_ok(
+            id,
+            DocumentSymbolResponse::Nested(symbols),
+        ))
+    }
+
+    fn rename(&self, req: Request) -> LspResult<Response> {
+        let (id, params) = extract::<RenameParams>(req, Rename::METHOD)?;
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let doc = self.document(&uri)?;
+
+        let edits: Vec<TextEdit> = doc
+            .rename(from_lsp_position(position.position), &params.new_name)?
+            .into_iter()
+            .map(|edit| TextEdit::new(to_lsp_range(edit.range), edit.new_text))
+            .collect();
+        let changes = HashMap::from([(uri, edits)]);
+        Ok(Response::new_ok(id, WorkspaceEdit::new(changes)))
+    }
+}
+
+fn extract<P: serde::de::DeserializeOwned>(
+    req: Request,
+    method: &str,
+) -> LspResult<(RequestId, P)> {
+    req.extract(method)
+        .map_err(|e| format!("invalid {} request: {:?}", method, e).into())
+}
+
+fn from_lsp_position(pos: Position) -> analysis::Position {
+    analysis::Position {
+        line: pos.line,
+        character: pos.character,
+    }
+}
+
+fn to_lsp_range(range: analysis::Range) -> Range {
+    Range::new(
+        Position::new(range.start.line, range.start.character),
+        Position::new(range.end.line, range.end.character),
+    )
+}
+
+fn completion_kind(kind: analysis::CompletionKind) -> CompletionItemKind {
+    match kind {
+        analysis::CompletionKind::Keyword => CompletionItemKind::KEYWORD,
+        analysis::CompletionKind::Function => CompletionItemKind::FUNCTION,
+        analysis::CompletionKind::Signal => CompletionItemKind::EVENT,
+        analysis::CompletionKind::Variable => CompletionItemKind::VARIABLE,
+        analysis::CompletionKind::Component => CompletionItemKind::STRUCT,
+        analysis::CompletionKind::Field => CompletionItemKind::FIELD,
+        analysis::CompletionKind::Entity => CompletionItemKind::CLASS,
+        analysis::CompletionKind::State => CompletionItemKind::ENUM_MEMBER,
+        analysis::CompletionKind::Type => CompletionItemKind::STRUCT,
+    }
+}
+
+#[allow(deprecated)] // `DocumentSymbol::deprecated` must still be set
+fn to_lsp_symbol(symbol: &analysis::Symbol) -> DocumentSymbol {
+    let kind = match symbol.kind {
+        analysis::SymbolKind::Entity => SymbolKind::CLASS,
+        analysis::SymbolKind::Component => SymbolKind::STRUCT,
+        analysis::SymbolKind::Struct => SymbolKind::STRUCT,
+        analysis::SymbolKind::Enum => SymbolKind::ENUM,
+        analysis::SymbolKind::Variant => SymbolKind::ENUM_MEMBER,
+        analysis::SymbolKind::Interface => SymbolKind::INTERFACE,
+        analysis::SymbolKind::Field => SymbolKind::FIELD,
+        analysis::SymbolKind::Variable => SymbolKind::VARIABLE,
+        analysis::SymbolKind::Function => SymbolKind::FUNCTION,
+        analysis::SymbolKind::Signal => SymbolKind::EVENT,
+        analysis::SymbolKind::StateMachine => SymbolKind::ENUM,
+        analysis::SymbolKind::State => SymbolKind::ENUM_MEMBER,
+    };
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: Some(symbol.detail.clone()),
+        kind,
+        tags: None,
+        deprecated: None,
+        range: to_lsp_range(symbol.range),
+        selection_range: to_lsp_range(symbol.selection_range),
+        children: Some(symbol.children.iter().map(to_lsp_symbol).collect()),
+    }
+}
@@ -0,0 +1,645 @@
+//! NexScript CLI Tool
+//!
+//! Every subcommand looks for a `nexscript.toml` in the current directory
+//! and its parents and uses its settings; options given on the command line
+//! take precedence.
+//!
+//! Usage:
+//!   nexc build [--input <DIR>] [--output <DIR>] [--watch] [--message-format <FORMAT>]
+//!   nexc check <FILE> [--root <DIR>] [--message-format <FORMAT>]
+//!   nexc fmt [PATHS]... [--check]
+//!   nexc new <NAME>
+//!   nexc repl [--load <FILE>]
+//!   nexc lsp
+//!   nexc explain <CODE>
+//!   nexc explain-rustc [FILE]
+
+use clap::{Parser, Subcommand};
+use glob::glob;
+use nexscript::build::Builder;
+use nexscript::diagnostics;
+use nexscript::formatter::format_source;
+use nexscript::interpreter::{Interpreter, Value};
+use nexscript::manifest::Manifest;
+use nexscript::modules::ModuleGraph;
+use nexscript::{infer_type_in, parse, Statement, TypeEnv};
+use report::{MessageFormat, Reporter};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+mod explain;
+mod lsp;
+mod report;
+mod watch;
+
+#[derive(Parser)]
+#[command(name = "nexc")]
+#[command(about = "NexScript Compiler CLI", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Build all NexScript files in a directory
+    Build {
+        /// Input directory containing .nx files (default: the manifest's
+        /// sources, or ./)
+        #[arg(short, long)]
+        input: Option<String>,
+
+        /// Output directory for Rust files (default: the manifest's output,
+        /// or ./src/generated/)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Keep running and rebuild whenever a .nx file changes
+        #[arg(short, long)]
+        watch: bool,
+
+        /// How to print diagnostics
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Check a single file for errors, exiting with 1 if there are any
+    Check {
+        /// File to check
+        file: String,
+
+        /// Directory imports are resolved from (default: the manifest's source
+        /// directory holding the file, or the file's directory)
+        #[arg(long)]
+        root: Option<String>,
+
+        /// How to print diagnostics
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Format .nx files in place
+    Fmt {
+        /// Files or directories to format (default: the manifest's sources,
+        /// or ./)
+        paths: Vec<String>,
+
+        /// Only report files that are not formatted, exiting with 1 if any
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Create a new component
+    New {
+        /// Name of the new entity/component
+        name: String,
+    },
+
+    /// Start an interactive session
+    Repl {
+        /// Script to load before the first prompt
+        #[arg(short, long)]
+        load: Option<String>,
+    },
+
+    /// Run the language server over stdio (used by nexscript-vscode)
+    Lsp,
+
+    /// Describe a diagnostic code such as NX0004 in detail
+    Explain {
+        /// Code to explain; lists every code when left out
+        code: Option<String>,
+    },
+
+    /// Map rustc errors in generated code back to .nx lines
+    ExplainRustc {
+        /// Output of `cargo build --message-format=json` (default: stdin)
+        file: Option<String>,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let manifest = manifest();
+    let manifest = manifest.as_ref();
+
+    match &cli.command {
+        Commands::Build {
+            input,
+            output,
+            watch,
+            message_format,
+        } => {
+            let format = *message_format;
+            let inputs = match (input, manifest) {
+                (Some(input), _) => vec![PathBuf::from(input)],
+                (None, Some(manifest)) => manifest.sources(),
+                (None, None) => vec![PathBuf::from("./")],
+            };
+            let output = match (output, manifest) {
+                (Some(output), _) => PathBuf::from(output),
+                (None, Some(manifest)) => manifest.output(),
+                (None, None) => PathBuf::from("./src/generated/"),
+            };
+            let mut builder = inputs
+                .iter()
+                .fold(Builder::new(), |builder, input| builder.input(input))
+                .output(&output);
+            if let Some(manifest) = manifest {
+                builder = builder.settings(manifest);
+            }
+
+            let ok = build(&builder, &inputs, &output, format);
+            if *watch {
+                let prelude = manifest.map(Manifest::prelude).unwrap_or_default();
+                let result = watch::run(&inputs, &prelude, |affected| {
+                    if format == MessageFormat::Human {
+                        println!();
+                        for path in &affected.changed {
+                            println!("🔄 Changed {}", path.display());
+                        }
+                        for path in &affected.dependents {
+                            println!("🔗 Imports a changed file: {}", path.display());
+                        }
+                    }
+                    build_once(&builder, format);
+                });
+                if let Err(e) = result {
+                    eprintln!("❌ Failed to watch: {}", e);
+                    std::process::exit(1);
+                }
+            } else if !ok {
+                std::process::exit(1);
+            }
+        }
+        Commands::Check {
+            file,
+            root,
+            message_format,
+        } => {
+            if !check(file, root.as_deref(), manifest, *message_format) {
+                std::process::exit(1);
+            }
+        }
+        Commands::Fmt { paths, check } => {
+            let paths: Vec<PathBuf> = match (paths.is_empty(), manifest) {
+                (false, _) => paths.iter().map(PathBuf::from).collect(),
+                (true, Some(manifest)) => manifest.sources(),
+                (true, None) => vec![PathBuf::from("./")],
+            };
+            if !fmt(&paths, *check) {
+                std::process::exit(1);
+            }
+        }
+        Commands::New { name } => {
+            let dir = manifest
+                .and_then(|manifest| manifest.sources().into_iter().next())
+                .unwrap_or_default();
+            create_new(name, &dir);
+        }
+        Commands::Repl { load } => {
+            repl(load.as_deref());
+        }
+        Commands::Lsp => {
+            if let Err(e) = lsp::run() {
+                eprintln!("❌ Language server failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Explain { code } => {
+            if !explain_code(code.as_deref()) {
+                std::process::exit(1);
+            }
+        }
+        Commands::ExplainRustc { file } => {
+            let result = match file {
+                Some(file) => {
+                    fs::File::open(file).and_then(|f| explain::run(io::BufReader::new(f)))
+                }
+                None => explain::run(io::stdin().lock()),
+            };
+            if let Err(e) = result {
+                eprintln!("❌ Failed to read diagnostics: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// The project's manifest, if the current directory is in one. A manifest
+/// that doesn't load is reported and ends the run.
+fn manifest() -> Option<Manifest> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    match Manifest::discover(&cwd) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns false if the build had errors
+fn build(builder: &Builder, inputs: &[PathBuf], output: &Path, format: MessageFormat) -> bool {
+    if format == MessageFormat::Human {
+        println!("📦 Building NexScript project...");
+        for input in inputs {
+            println!("   Input: {}", input.display());
+        }
+        println!("   Output: {}", output.display());
+    }
+
+    build_once(builder, format)
+}
+
+/// Compile every out of date file. Errors are reported but never abort, so
+/// watch mode keeps running. Returns false if there were any.
+fn build_once(builder: &Builder, format: MessageFormat) -> bool {
+    let report = match builder.run() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("❌ Build failed: {}", e);
+            return false;
+        }
+    };
+
+    let mut reporter = Reporter::new(format);
+    if reporter.is_human() {
+        for path in &report.compiled {
+            let filename = path.file_stem().unwrap_or_default().to_string_lossy();
+            println!("   Compiling {}...", filename);
+        }
+        for output in &report.removed {
+            println!("   Removed {} (its source was deleted)", output);
+        }
+    }
+    for diagnostic in &report.diagnostics {
+        reporter.report(diagnostic);
+    }
+
+    if reporter.is_human() {
+        if report.failed.is_empty() {
+            println!(
+                "✨ Built {} files successfully! ({} unchanged)",
+                report.compiled.len(),
+                report.unchanged
+            );
+        } else {
+            println!(
+                "❌ {} of {} files failed to build",
+                report.failed.len(),
+                report.sources.len()
+            );
+        }
+    }
+    let ok = !reporter.has_errors();
+    reporter.finish();
+    ok
+}
+
+/// Returns false if the file has errors
+fn check(
+    file: &str,
+    root: Option<&str>,
+    manifest: Option<&Manifest>,
+    format: MessageFormat,
+) -> bool {
+    let mut reporter = Reporter::new(format);
+    if reporter.is_human() {
+        println!("🔍 Checking {}...", file);
+    }
+
+    let path = Path::new(file);
+    let root = root
+        .map(PathBuf::from)
+        .or_else(|| manifest?.source_root(path))
+        .unwrap_or_else(|| path.parent().unwrap_or(Path::new("")).to_path_buf());
+    let mut graph = ModuleGraph::new(root);
+    if let Some(manifest) = manifest {
+        graph = graph.with_prelude(&manifest.prelude());
+    }
+    graph.load(path);
+    let found = match manifest {
+        Some(manifest) => manifest.lints.apply(graph.check(path)),
+        None => graph.check(path),
+    };
+    for diagnostic in &found {
+        reporter.report(diagnostic);
+    }
+
+    let ok = !reporter.has_errors();
+    if ok && reporter.is_human() {
+        println!("✅ No problems found");
+    }
+    reporter.finish();
+    ok
+}
+
+/// Print a code's explanation, or list every code. Returns false if the
+/// code is unknown.
+fn explain_code(code: Option<&str>) -> bool {
+    let Some(code) = code else {
+        for code in diagnostics::CODES {
+            println!("{}  {}", code.code, code.summary);
+        }
+        println!("\nRun `nexc explain <CODE>` for details.");
+        return true;
+    };
+    match diagnostics::explain(code) {
+        Some(code) => {
+            println!("{}: {}\n", code.code, code.summary);
+            print!("{}", code.explanation);
+            true
+        }
+        None => {
+            eprintln!("❌ Unknown diagnostic code: {}", code);
+            false
+        }
+    }
+}
+
+/// Returns false if any file failed to format or, with `check`, needs formatting
+fn fmt(paths: &[PathBuf], check: bool) -> bool {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let pattern = format!("{}/**/*.nx", path.display());
+            match glob(&pattern) {
+                Ok(entries) => files.extend(entries.flatten()),
+                Err(e) => eprintln!("❌ Invalid pattern: {}", e),
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut ok = true;
+    let mut changed = 0;
+    let mut failed = 0;
+    for file in &files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("❌ Failed to read {}: {}", file.display(), e);
+                failed += 1;
+                ok = false;
+                continue;
+            }
+        };
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("❌ {}: {}", file.display(), e);
+                failed += 1;
+                ok = false;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("Would reformat {}", file.display());
+            changed += 1;
+            ok = false;
+        } else if let Err(e) = fs::write(file, formatted) {
+            eprintln!("❌ Failed to write {}: {}", file.display(), e);
+            failed += 1;
+            ok = false;
+        } else {
+            println!("   Formatted {}", file.display());
+            changed += 1;
+        }
+    }
+
+    if !check {
+        println!("✨ Formatted {} of {} files", changed, files.len());
+    }
+    if failed > 0 {
+        println!("❌ {} of {} files could not be formatted", failed, files.len());
+    } else if check && ok {
+        println!("✅ {} files already formatted", files.len());
+    }
+    ok
+}
+
+/// Create `<name>.nx` in `dir`
+fn create_new(name: &str, dir: &Path) {
+    let content = format!(
+        r#"# {}.nx
+entity {}:
+    component Transform:
+        position = Vec2(0, 0)
+
+    fn on_ready():
+        print("{} ready!")
+
+    fn on_update(delta: float):
+        pass
+"#,
+        name, name, name
+    );
+
+    let path = dir.join(format!("{}.nx", name.to_lowercase()));
+    if let Err(e) = fs::write(&path, content) {
+        eprintln!("❌ Failed to create file: {}", e);
+    } else {
+        println!("✨ Created {}", path.display());
+    }
+}
+
+fn repl(load: Option<&str>) {
+    println!("NexScript REPL - type :help for commands, :quit to exit");
+
+    let mut env = TypeEnv::new();
+    let mut interpreter = Interpreter::new();
+    if let Some(file) = load {
+        repl_load(&mut env, &mut interpreter, file);
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    while let Some(line) = repl_read_line(&mut lines, ">>> ") {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if let Some(command) = trimmed.strip_prefix(':') {
+            let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+            match name {
+                "quit" | "q" => break,
+                "help" | "h" => repl_help(),
+                "type" | "t" => repl_type(&env, arg.trim()),
+                "ast" => repl_ast(arg.trim()),
+                "load" => repl_load(&mut env, &mut interpreter, arg.trim()),
+                _ => println!("❌ Unknown command :{} (try :help)", name),
+            }
+            continue;
+        }
+
+        // A trailing colon opens a block; keep reading until a blank line
+        let mut source = format!("{}\n", line);
+        if trimmed.ends_with(':') {
+            while let Some(next) = repl_read_line(&mut lines, "... ") {
+                if next.trim().is_empty() {
+                    break;
+                }
+                source.push_str(&next);
+                source.push('\n');
+            }
+        }
+
+        repl_eval(&mut env, &mut interpreter, &source);
+    }
+}
+
+fn repl_read_line(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    prompt: &str,
+) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok()?;
+    lines.next()?.ok()
+}
+
+fn repl_help() {
+    println!("  <statement>     evaluate it, keeping variables and definitions for later lines");
+    println!("  :type <expr>    show the inferred type of an expression");
+    println!("  :ast <code>     dump the AST as JSON");
+    println!("  :load <file>    load a .nx file, with a mock instance of each entity in it");
+    println!("  :quit           exit");
+}
+
+/// Evaluate each statement, printing what it prints and the value of an
+/// expression; the first error stops the rest
+fn repl_eval(env: &mut TypeEnv, interpreter: &mut Interpreter, source: &str) {
+    let program = match parse(source) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("❌ Error: {}", e);
+            return;
+        }
+    };
+
+    for stmt in &program.statements {
+        // Keep the types in step for `:type`
+        match stmt {
+            Statement::VarDecl(var) => {
+                if let Some(t) = var
+                    .type_expr
+                    .clone()
+                    .or_else(|| infer_type_in(&var.value, env))
+                {
+                    env.declare(&var.name, t);
+                }
+            }
+            Statement::FnDef(func) => env.declare_fn(func),
+            Statement::EntityDef(entity) => env.declare_entity(entity),
+            Statement::StructDef(def) => env.declare_struct(def),
+            Statement::EnumDef(def) => env.declare_enum(def),
+            _ => {}
+        }
+
+        let result = interpreter.run_top(stmt);
+        for line in interpreter.take_output() {
+            println!("{}", line);
+        }
+        match result {
+            Ok(Some(Value::None)) | Ok(None) => {}
+            Ok(Some(value)) => println!("{}", value),
+            Err(e) => {
+                println!("❌ Error: {}", e);
+                return;
+            }
+        }
+        match stmt {
+            Statement::FnDef(func) => println!("{}", func.signature()),
+            Statement::EntityDef(entity) => println!("entity {}", entity.name),
+            Statement::StructDef(def) => println!("struct {}", def.name),
+            Statement::EnumDef(def) => println!("enum {}", def.name),
+            _ => {}
+        }
+    }
+}
+
+fn repl_type(env: &TypeEnv, source: &str) {
+    match parse(&format!("{}\n", source)) {
+        Ok(program) => match program.statements.as_slice() {
+            [Statement::Expr { expr, .. }] => match infer_type_in(expr, env) {
+                Some(t) => println!("{}", t),
+                None => println!("?"),
+            },
+            _ => println!("❌ Error: :type expects a single expression"),
+        },
+        Err(e) => println!("❌ Error: {}", e),
+    }
+}
+
+fn repl_ast(source: &str) {
+    match parse(&format!("{}\n", source)) {
+        Ok(program) => {
+            let json = match program.statements.as_slice() {
+                [Statement::Expr { expr, .. }] => serde_json::to_string_pretty(expr),
+                [stmt] => serde_json::to_string_pretty(stmt),
+                stmts => serde_json::to_string_pretty(stmts),
+            };
+            match json {
+                Ok(json) => println!("{}", json),
+                Err(e) => println!("❌ Error: {}", e),
+            }
+        }
+        Err(e) => println!("❌ Error: {}", e),
+    }
+}
+
+/// Load a script and act as a mock instance of each entity in it, so its
+/// variables, components and functions resolve in later lines
+fn repl_load(env: &mut TypeEnv, interpreter: &mut Interpreter, file: &str) {
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("❌ Failed to read file: {}", e);
+            return;
+        }
+    };
+    let program = match parse(&source) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("❌ Error: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = interpreter.load(&program) {
+        println!("❌ Error: {}", e);
+        return;
+    }
+
+    for stmt in &program.statements {
+        match stmt {
+            Statement::EntityDef(entity) => {
+                env.declare_entity(entity);
+                println!("Loaded entity {}", entity.name);
+                for func in &entity.functions {
+                    println!("  {}", func.signature());
+                }
+            }
+            Statement::FnDef(func) => {
+                env.declare_fn(func);
+                println!("Loaded {}", func.signature());
+            }
+            Statement::StructDef(def) => {
+                env.declare_struct(def);
+                println!("Loaded struct {}", def.name);
+            }
+            Statement::EnumDef(def) => {
+                env.declare_enum(def);
+                println!("Loaded enum {}", def.name);
+            }
+            _ => {}
+        }
+    }
+}
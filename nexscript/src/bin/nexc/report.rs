@@ -0,0 +1,196 @@
+//! Diagnostic output for `nexc check` and `nexc build`
+//!
+//! `human` is for people reading a terminal: each diagnostic is shown with
+//! the source lines it points at, in colour unless stderr is not a terminal
+//! or `NO_COLOR` is set. `json` prints each diagnostic as
+//! one JSON object per line as soon as it is found, like cargo's
+//! `--message-format=json`. `sarif` prints a single SARIF 2.1.0 log once the
+//! command is done, for code scanning tools. With the machine formats,
+//! stdout carries nothing but diagnostics.
+
+use clap::ValueEnum;
+use nexscript::diagnostics::{Diagnostic, Location, CODES};
+use nexscript::render::render;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::IsTerminal;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+    Sarif,
+}
+
+pub struct Reporter {
+    format: MessageFormat,
+    /// Held back for the SARIF log
+    collected: Vec<Diagnostic>,
+    errors: usize,
+    /// File contents for the human snippets, read once per file
+    sources: HashMap<String, Option<String>>,
+    color: bool,
+}
+
+impl Reporter {
+    pub fn new(format: MessageFormat) -> Self {
+        Reporter {
+            format,
+            collected: Vec::new(),
+            errors: 0,
+            sources: HashMap::new(),
+            color: std::io::stderr().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        }
+    }
+
+    /// Whether progress messages should be printed
+    pub fn is_human(&self) -> bool {
+        self.format == MessageFormat::Human
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    pub fn report(&mut self, diagnostic: &Diagnostic) {
+        if diagnostic.is_error() {
+            self.errors += 1;
+        }
+        match self.format {
+            MessageFormat::Human => {
+                let source = self
+                    .sources
+                    .entry(diagnostic.file.clone())
+                    .or_insert_with(|| fs::read_to_string(&diagnostic.file).ok());
+                eprintln!("{}", render(diagnostic, source.as_deref(), self.color));
+            }
+            MessageFormat::Json => match serde_json::to_string(diagnostic) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("❌ Failed to serialize diagnostic: {}", e),
+            },
+            MessageFormat::Sarif => self.collected.push(diagnostic.clone()),
+        }
+    }
+
+    /// Print anything held back until the end
+    pub fn finish(self) {
+        if self.format == MessageFormat::Sarif {
+            match serde_json::to_string_pretty(&sarif(&self.collected)) {
+                Ok(log) => println!("{}", log),
+                Err(e) => eprintln!("❌ Failed to serialize SARIF log: {}", e),
+            }
+        }
+    }
+}
+
+fn sarif(diagnostics: &[Diagnostic]) -> Value {
+    let rules: Vec<Value> = CODES
+        .iter()
+        .map(|code| {
+            json!({
+                "id": code.code,
+                "shortDescription": { "text": code.summary },
+                "fullDescription": { "text": code.explanation },
+            })
+        })
+        .collect();
+    let results: Vec<Value> = diagnostics.iter().map(sarif_result).collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "nexc",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+fn sarif_result(diagnostic: &Diagnostic) -> Value {
+    let uri = diagnostic.file.replace('\\', "/");
+    let mut text = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        text.push_str(&format!("\nnote: {}", note));
+    }
+
+    let related: Vec<Value> = diagnostic
+        .related
+        .iter()
+        .enumerate()
+        .map(|(id, related)| {
+            json!({
+                "id": id,
+                "message": { "text": related.message },
+                "physicalLocation": physical_location(
+                    &related_uri(&uri, &related.file),
+                    &related.location
+                ),
+            })
+        })
+        .collect();
+    let fixes: Vec<Value> = diagnostic
+        .fixes
+        .iter()
+        .map(|fix| {
+            json!({
+                "description": { "text": fix.message },
+                "artifactChanges": [{
+                    "artifactLocation": { "uri": uri },
+                    "replacements": [{
+                        "deletedRegion": region(&fix.location),
+                        "insertedContent": { "text": fix.replacement },
+                    }],
+                }],
+            })
+        })
+        .collect();
+
+    json!({
+        "ruleId": diagnostic.code,
+        "level": diagnostic.severity.to_string(),
+        "message": { "text": text },
+        "locations": [{ "physicalLocation": physical_location(&uri, &diagnostic.location) }],
+        "relatedLocations": related,
+        "fixes": fixes,
+    })
+}
+
+/// Related locations without a file are in the diagnostic's own
+fn related_uri(uri: &str, file: &str) -> String {
+    if file.is_empty() {
+        uri.to_string()
+    } else {
+        file.replace('\\', "/")
+    }
+}
+
+fn physical_location(uri: &str, location: &Location) -> Value {
+    // Errors without a position, such as an unreadable file, point at the file
+    if location.line == 0 {
+        return json!({ "artifactLocation": { "uri": uri } });
+    }
+    json!({
+        "artifactLocation": { "uri": uri },
+        "region": region(location),
+    })
+}
+
+fn region(location: &Location) -> Value {
+    json!({
+        "startLine": location.line,
+        "startColumn": location.column,
+        "endLine": location.end_line,
+        "endColumn": location.end_column,
+        "byteOffset": location.span.start,
+        "byteLength": location.span.end - location.span.start,
+    })
+}
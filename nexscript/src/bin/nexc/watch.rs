@@ -0,0 +1,253 @@
+//! Watch mode for `nexc build --watch`
+//!
+//! Editors often write a file several times per save, so file system events
+//! are debounced and handed over as one batch. Events also fire when files
+//! are merely read (including by the build itself), so a `.nx` file only
+//! counts as changed once its contents differ from the last time we saw it.
+//! A change also affects every file importing the changed one, directly or
+//! not. Rebuilds go through the build cache, so only those files are
+//! recompiled.
+
+use glob::glob;
+use nexscript::build;
+use nexscript::modules::ModuleGraph;
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long the input tree must be quiet before a rebuild starts
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Files a batch of changes affects
+#[derive(Debug, Default, PartialEq)]
+pub struct Affected {
+    /// `.nx` files whose contents changed, or that were deleted
+    pub changed: Vec<PathBuf>,
+    /// Files that import a changed one, directly or not
+    pub dependents: Vec<PathBuf>,
+}
+
+/// Watch the input directories until interrupted, calling `rebuild` with
+/// the files affected by each burst of changes. Watch errors are reported
+/// and watching goes on.
+pub fn run(
+    inputs: &[PathBuf],
+    prelude: &[Vec<String>],
+    mut rebuild: impl FnMut(&Affected),
+) -> Result<(), notify_debouncer_mini::notify::Error> {
+    let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+    let mut debouncer = new_debouncer(DEBOUNCE, tx)?;
+    for input in inputs {
+        debouncer.watcher().watch(input, RecursiveMode::Recursive)?;
+    }
+    let mut watch = Watch::new(inputs, prelude);
+
+    let names: Vec<String> = inputs.iter().map(|dir| dir.display().to_string()).collect();
+    println!(
+        "👀 Watching {} for changes (Ctrl+C to stop)",
+        names.join(", ")
+    );
+
+    for result in rx {
+        if let Some(affected) = watch.handle(result) {
+            rebuild(&affected);
+        }
+    }
+
+    Ok(())
+}
+
+/// What is known about the input trees between batches of events
+struct Watch {
+    inputs: Vec<PathBuf>,
+    prelude: Vec<Vec<String>>,
+    /// Content hash of each `.nx` file, `None` once it is deleted
+    seen: HashMap<PathBuf, Option<String>>,
+    /// Every file each `.nx` file imports, directly or not
+    dependencies: HashMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl Watch {
+    fn new(inputs: &[PathBuf], prelude: &[Vec<String>]) -> Self {
+        let mut watch = Watch {
+            inputs: inputs.to_vec(),
+            prelude: prelude.to_vec(),
+            seen: HashMap::new(),
+            dependencies: HashMap::new(),
+        };
+        for path in watch.sources() {
+            let hash = fingerprint(&path);
+            watch.seen.insert(path, hash);
+        }
+        watch.dependencies = watch.scan_dependencies();
+        watch
+    }
+
+    /// The files a batch of events affects, if any `.nx` file changed
+    fn handle(&mut self, result: DebounceEventResult) -> Option<Affected> {
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("❌ Watch error: {}", e);
+                return None;
+            }
+        };
+
+        let mut changed: Vec<PathBuf> = events
+            .into_iter()
+            .map(|event| canonical(&event.path))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "nx"))
+            .collect();
+        changed.sort();
+        changed.dedup();
+        changed.retain(|path| {
+            let hash = fingerprint(path);
+            self.seen.insert(path.clone(), hash.clone()) != Some(hash)
+        });
+        if changed.is_empty() {
+            return None;
+        }
+
+        // A deleted or renamed module is only in the imports from before
+        let mut dependencies = self.scan_dependencies();
+        for (path, imports) in std::mem::take(&mut self.dependencies) {
+            dependencies.entry(path).or_default().extend(imports);
+        }
+        let affected = affected(changed, &dependencies);
+        self.dependencies = self.scan_dependencies();
+        Some(affected)
+    }
+
+    /// Every `.nx` file under the inputs
+    fn sources(&self) -> Vec<PathBuf> {
+        self.inputs
+            .iter()
+            .flat_map(|input| glob(&format!("{}/**/*.nx", input.display())).into_iter())
+            .flatten()
+            .flatten()
+            .map(|path| canonical(&path))
+            .collect()
+    }
+
+    /// What each file imports now, resolved like the build resolves it
+    fn scan_dependencies(&self) -> HashMap<PathBuf, BTreeSet<PathBuf>> {
+        let mut found = HashMap::new();
+        for input in &self.inputs {
+            let mut graph = ModuleGraph::new(canonical(input)).with_prelude(&self.prelude);
+            let pattern = format!("{}/**/*.nx", input.display());
+            for path in glob(&pattern).into_iter().flatten().flatten() {
+                let path = canonical(&path);
+                graph.load(&path);
+                let imports = graph
+                    .dependencies(&path)
+                    .iter()
+                    .map(|p| canonical(p))
+                    .collect();
+                found.insert(path, imports);
+            }
+        }
+        found
+    }
+}
+
+/// The changed files and every file that imports one of them
+fn affected(changed: Vec<PathBuf>, dependencies: &HashMap<PathBuf, BTreeSet<PathBuf>>) -> Affected {
+    let mut dependents: Vec<PathBuf> = dependencies
+        .iter()
+        .filter(|(path, imports)| {
+            !changed.contains(path) && changed.iter().any(|c| imports.contains(c))
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    dependents.sort();
+    Affected {
+        changed,
+        dependents,
+    }
+}
+
+/// The absolute form of `path`, matching what notify reports. Deleted files
+/// can't be canonicalized, so this goes through their directory.
+fn canonical(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => fs::canonicalize(dir)
+            .map(|dir| dir.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Content hash of a file, `None` once it is deleted
+fn fingerprint(path: &Path) -> Option<String> {
+    fs::read(path).ok().map(build::hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify_debouncer_mini::notify;
+    use notify_debouncer_mini::{DebouncedEvent, DebouncedEventKind};
+
+    #[test]
+    fn test_affected_includes_importers() {
+        let path = PathBuf::from;
+        let dependencies = HashMap::from([
+            (path("util.nx"), BTreeSet::new()),
+            (path("player.nx"), BTreeSet::from([path("util.nx")])),
+            (
+                path("boss.nx"),
+                BTreeSet::from([path("player.nx"), path("util.nx")]),
+            ),
+            (path("coin.nx"), BTreeSet::new()),
+        ]);
+
+        let found = affected(vec![path("util.nx")], &dependencies);
+        assert_eq!(found.changed, vec![path("util.nx")]);
+        assert_eq!(found.dependents, vec![path("boss.nx"), path("player.nx")]);
+
+        // A changed file isn't also listed as a dependent
+        let found = affected(vec![path("player.nx"), path("util.nx")], &dependencies);
+        assert_eq!(found.dependents, vec![path("boss.nx")]);
+    }
+
+    #[test]
+    fn test_watch_rebuilds_importers_and_survives_errors() {
+        let root = std::env::temp_dir().join(format!("nexscript-watch-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let root = canonical(&root);
+        let (util, player) = (root.join("util.nx"), root.join("player.nx"));
+        fs::write(&util, "fn bonus() -> int:\n    return 1\n").unwrap();
+        fs::write(&player, "import util\n\nentity Player:\n    let hp = 3\n").unwrap();
+        fs::write(root.join("coin.nx"), "entity Coin:\n    let value = 1\n").unwrap();
+        let mut watch = Watch::new(std::slice::from_ref(&root), &[]);
+        let event = |path: &Path| {
+            Ok(vec![DebouncedEvent::new(
+                path.to_path_buf(),
+                DebouncedEventKind::Any,
+            )])
+        };
+
+        assert_eq!(watch.handle(Err(notify::Error::generic("lost"))), None);
+        // Read but not changed
+        assert_eq!(watch.handle(event(&util)), None);
+
+        fs::write(&util, "fn bonus() -> int:\n    return 2\n").unwrap();
+        let found = watch.handle(event(&util)).unwrap();
+        assert_eq!(found.changed, vec![util.clone()]);
+        assert_eq!(found.dependents, vec![player.clone()]);
+
+        // Its importers still rebuild once it is gone
+        fs::remove_file(&util).unwrap();
+        let found = watch.handle(event(&util)).unwrap();
+        assert_eq!(found.dependents, vec![player]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,688 @@
+//! Build - Compile a tree of `.nx` files to Rust
+//!
+//! This drives `nexc build` and can be called from the `main` of a `build.rs`:
+//!
+//! ```no_run
+//! nexscript::build::Builder::new()
+//!     .input("scripts")
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! and then in the crate:
+//!
+//! ```ignore
+//! mod scripts {
+//!     include!(concat!(env!("OUT_DIR"), "/nexscript/mod.rs"));
+//! }
+//! // app.add_plugins(scripts::NexScriptPlugin);
+//! ```
+//!
+//! Builds are incremental: a manifest in the output directory records, for
+//! each source file, the hash of its contents and of the Rust it produced.
+//! Each output gets a source map next to it (see [`crate::source_map`]).
+//! A file is only rebuilt when either no longer matches, when a file it
+//! imports changed, or when the compiler version or build options changed
+//! since the manifest was written. Imports are resolved from the file's
+//! input directory (see [`crate::modules`]). Each file is generated as a
+//! module named after it, and a file whose module name is already taken
+//! fails to build.
+//!
+//! [`Builder::from_manifest`] takes the inputs, output and settings from a
+//! project's `nexscript.toml` (see [`crate::manifest`]).
+
+use crate::diagnostics::{Diagnostic, Location, Related};
+use crate::manifest::{Lints, Manifest, Target};
+use crate::modules::{module_name, ModuleGraph};
+use crate::source_map::map_path;
+use crate::{transpile_mod, CodegenOptions, ModuleExports, NexScriptError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Manifest file name inside the output directory
+pub const MANIFEST: &str = ".nexc-cache.json";
+
+/// Subdirectory of `OUT_DIR` used when no output directory is set
+const OUT_DIR_SUBDIR: &str = "nexscript";
+
+/// Compiles every `.nx` file under a set of input directories
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    target: Target,
+    options: CodegenOptions,
+    lints: Lints,
+    prelude: Vec<Vec<String>>,
+}
+
+/// What a build did
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// Every source file found, compiled or not
+    pub sources: Vec<PathBuf>,
+    pub compiled: Vec<PathBuf>,
+    pub unchanged: usize,
+    /// Outputs deleted because their source is gone
+    pub removed: Vec<String>,
+    /// Scripts with errors; their previous output, if any, is kept
+    pub failed: Vec<PathBuf>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// What happened to one source file
+enum Outcome {
+    Compiled,
+    Unchanged,
+    Failed,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a project as its manifest describes
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        let builder = manifest
+            .sources()
+            .into_iter()
+            .fold(Builder::new(), Builder::input);
+        builder.output(manifest.output()).settings(manifest)
+    }
+
+    /// Use a manifest's target, code generation options, lint levels and
+    /// prelude, but not its directories
+    pub fn settings(mut self, manifest: &Manifest) -> Self {
+        self.target = manifest.build.target;
+        self.options = manifest.codegen_options();
+        self.lints = manifest.lints.clone();
+        self.prelude = manifest.prelude();
+        self
+    }
+
+    /// Add a directory to search for `.nx` files
+    pub fn input(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.inputs.push(dir.into());
+        self
+    }
+
+    /// Where to write the generated Rust; defaults to `$OUT_DIR/nexscript`
+    pub fn output(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.output = Some(dir.into());
+        self
+    }
+
+    /// Build from a Cargo build script: tell Cargo which files to watch and
+    /// report diagnostics as warnings with their file and line. Fails if
+    /// any script failed to compile, which fails the Cargo build.
+    pub fn build(&self) -> crate::Result<BuildReport> {
+        let report = self.run()?;
+
+        for input in &self.inputs {
+            println!("cargo:rerun-if-changed={}", input.display());
+        }
+        for source in &report.sources {
+            println!("cargo:rerun-if-changed={}", source.display());
+        }
+        for diagnostic in &report.diagnostics {
+            println!("cargo:warning={}", diagnostic);
+        }
+
+        match report.failed.len() {
+            0 => Ok(report),
+            n => Err(NexScriptError::BuildFailed(n)),
+        }
+    }
+
+    /// Compile every out of date file. Diagnostics are collected in the
+    /// report rather than stopping the build; only I/O failures on the output
+    /// directory are returned as errors.
+    pub fn run(&self) -> crate::Result<BuildReport> {
+        if self.target != Target::Bevy {
+            return Err(NexScriptError::UnsupportedTarget(self.target.to_string()));
+        }
+        let (output, include_dir) = match (&self.output, std::env::var_os("OUT_DIR")) {
+            (Some(output), _) => (output.clone(), None),
+            (None, Some(out_dir)) => (
+                PathBuf::from(out_dir).join(OUT_DIR_SUBDIR),
+                Some(OUT_DIR_SUBDIR),
+            ),
+            (None, None) => {
+                return Err(NexScriptError::IoError(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no output directory set and OUT_DIR is not defined",
+                )))
+            }
+        };
+        fs::create_dir_all(&output)?;
+
+        // Cache keys are relative to the input directory, so pointing the same
+        // output directory at other inputs invalidates the cache, as does
+        // changing any setting that affects what is generated
+        let mut options: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|dir| {
+                let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.clone());
+                format!("input={}", dir.display())
+            })
+            .collect();
+        options.push(format!("codegen={:?}", self.options));
+        options.push(format!("lints={:?}", self.lints));
+        options.push(format!("prelude={:?}", self.prelude));
+        let mut cache = BuildCache::load(&output, &hash(options.join("\n")));
+
+        let mut report = BuildReport::default();
+        let mut present = HashSet::new();
+        // Every module is written to the same directory, so the first file
+        // generated as a name keeps it
+        let mut generated: HashMap<String, PathBuf> = HashMap::new();
+
+        for input in &self.inputs {
+            let pattern = format!("{}/**/*.nx", input.display());
+            let paths: Vec<PathBuf> = glob::glob(&pattern)
+                .map_err(|e| {
+                    NexScriptError::IoError(io::Error::new(io::ErrorKind::InvalidInput, e))
+                })?
+                .flatten()
+                .collect();
+
+            // Every file's hash up front, to tell whether a file's imports changed
+            let mut sources = Sources {
+                input,
+                hashes: HashMap::new(),
+                graph: ModuleGraph::new(input).with_prelude(&self.prelude),
+                options: &self.options,
+                lints: &self.lints,
+            };
+            for path in &paths {
+                if let Ok(source) = fs::read_to_string(path) {
+                    sources.hashes.insert(sources.key(path), hash(source));
+                }
+            }
+
+            for path in paths {
+                let key = sources.key(&path);
+                present.insert(key.clone());
+
+                let module = module_name(&path);
+                if let Some(first) = generated.get(&module) {
+                    report.diagnostics.push(collision(&path, first, &module));
+                    // Its output, if any, is now the other file's
+                    cache.files.remove(&key);
+                    report.failed.push(path.clone());
+                    report.sources.push(path);
+                    continue;
+                }
+                generated.insert(module, path.clone());
+
+                match compile_file(
+                    &path,
+                    &output,
+                    &key,
+                    &mut sources,
+                    &mut cache,
+                    &mut report.diagnostics,
+                )? {
+                    Outcome::Compiled => report.compiled.push(path.clone()),
+                    Outcome::Unchanged => report.unchanged += 1,
+                    Outcome::Failed => {
+                        // Retry the file even if the source doesn't change, but
+                        // keep its last output tracked so it can be cleaned up
+                        cache.invalidate(&key);
+                        report.failed.push(path.clone());
+                    }
+                }
+                report.sources.push(path);
+            }
+        }
+
+        // Remove outputs whose source was deleted, unless a file that is
+        // still there, such as the same file moved, now generates them
+        for key in cache.stale(&present) {
+            if let Some(entry) = cache.remove(&key) {
+                let module = entry.output.trim_end_matches(".rs");
+                if generated.contains_key(module)
+                    || cache
+                        .files
+                        .values()
+                        .any(|other| other.output == entry.output)
+                {
+                    continue;
+                }
+                let out_path = output.join(&entry.output);
+                remove_if_exists(&map_path(&out_path))?;
+                if remove_if_exists(&out_path)? {
+                    report.removed.push(entry.output);
+                }
+            }
+        }
+
+        // Declare every module and register it in the aggregate plugin
+        let modules: Vec<(String, ModuleExports)> = cache
+            .files
+            .values()
+            .map(|entry| {
+                let module = entry.output.trim_end_matches(".rs").to_string();
+                (module, entry.exports.clone())
+            })
+            .collect();
+        write_if_changed(
+            &output.join("mod.rs"),
+            &transpile_mod(&modules, include_dir),
+        )?;
+
+        cache.save(&output)?;
+        Ok(report)
+    }
+}
+
+/// The files in one input directory
+struct Sources<'a> {
+    input: &'a Path,
+    /// Hash of each readable file's contents, by key
+    hashes: HashMap<String, String>,
+    /// Files loaded so far, with their imports
+    graph: ModuleGraph,
+    options: &'a CodegenOptions,
+    lints: &'a Lints,
+}
+
+impl Sources<'_> {
+    /// Cache key: the path relative to the input directory
+    fn key(&self, path: &Path) -> String {
+        path.strip_prefix(self.input)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+/// Compile one file unless the cache says it is up to date, adding its
+/// diagnostics to `diagnostics`
+fn compile_file(
+    path: &Path,
+    output_dir: &Path,
+    key: &str,
+    sources: &mut Sources,
+    cache: &mut BuildCache,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> io::Result<Outcome> {
+    let file = path.display().to_string();
+    let Some(source_hash) = sources.hashes.get(key).cloned() else {
+        let error = fs::read_to_string(path).err().map_or_else(
+            || "file changed while building".to_string(),
+            |e| e.to_string(),
+        );
+        let diagnostic = Diagnostic::error("NX0002", error, Location::default());
+        diagnostics.push(diagnostic.in_file(file));
+        return Ok(Outcome::Failed);
+    };
+    if cache.is_fresh(key, &source_hash, &sources.hashes, output_dir) {
+        return Ok(Outcome::Unchanged);
+    }
+
+    sources.graph.load(path);
+    let found = sources.lints.apply(sources.graph.check(path));
+    let failed = found.iter().any(Diagnostic::is_error);
+    diagnostics.extend(found);
+    if failed {
+        return Ok(Outcome::Failed);
+    }
+    let (Some(exports), Some((rust_code, source_map))) = (
+        sources.graph.exports(path),
+        sources.graph.transpile(path, sources.options),
+    ) else {
+        return Ok(Outcome::Failed);
+    };
+
+    let output = format!("{}.rs", module_name(path));
+    let out_path = output_dir.join(&output);
+    write_if_changed(&out_path, &rust_code)?;
+    write_if_changed(&map_path(&out_path), &source_map.to_json()?)?;
+
+    let dependencies = sources
+        .graph
+        .dependencies(path)
+        .iter()
+        .map(|dependency| {
+            let key = sources.key(dependency);
+            let hash = sources.hashes.get(&key).cloned().unwrap_or_default();
+            (key, hash)
+        })
+        .collect();
+    cache.files.insert(
+        key.to_string(),
+        CacheEntry {
+            source_hash,
+            output,
+            output_hash: hash(&rust_code),
+            exports,
+            dependencies,
+        },
+    );
+    Ok(Outcome::Compiled)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BuildCache {
+    compiler_version: String,
+    options_hash: String,
+    /// Keyed by source path relative to its input directory
+    files: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    source_hash: String,
+    /// Output file name relative to the output directory
+    output: String,
+    output_hash: String,
+    /// What the module contributes to the generated `mod.rs`
+    #[serde(default)]
+    exports: ModuleExports,
+    /// Hash of every file it imports, directly or not, by key
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
+}
+
+impl BuildCache {
+    /// Load the manifest from `output_dir`. Entries written by another
+    /// compiler version or with other options are never fresh, but are kept
+    /// so their outputs can still be cleaned up.
+    fn load(output_dir: &Path, options_hash: &str) -> Self {
+        let mut cache: BuildCache = fs::read_to_string(output_dir.join(MANIFEST))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+
+        let version = env!("CARGO_PKG_VERSION");
+        if cache.compiler_version != version || cache.options_hash != options_hash {
+            for entry in cache.files.values_mut() {
+                entry.source_hash.clear();
+            }
+            cache.compiler_version = version.to_string();
+            cache.options_hash = options_hash.to_string();
+        }
+        cache
+    }
+
+    fn save(&self, output_dir: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_if_changed(&output_dir.join(MANIFEST), &json)?;
+        Ok(())
+    }
+
+    /// Whether `key` was built from this exact source and the same versions
+    /// of its imports, and its output is untouched, with its source map
+    /// alongside
+    fn is_fresh(
+        &self,
+        key: &str,
+        source_hash: &str,
+        hashes: &HashMap<String, String>,
+        output_dir: &Path,
+    ) -> bool {
+        let Some(entry) = self.files.get(key) else {
+            return false;
+        };
+        let out_path = output_dir.join(&entry.output);
+        entry.source_hash == source_hash
+            && entry
+                .dependencies
+                .iter()
+                .all(|(dependency, hash)| hashes.get(dependency) == Some(hash))
+            && fs::read(&out_path).is_ok_and(|output| hash(output) == entry.output_hash)
+            && map_path(&out_path).exists()
+    }
+
+    fn invalidate(&mut self, key: &str) {
+        if let Some(entry) = self.files.get_mut(key) {
+            entry.source_hash.clear();
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        self.files.remove(key)
+    }
+
+    /// Keys whose source file no longer exists
+    fn stale(&self, present: &HashSet<String>) -> Vec<String> {
+        self.files
+            .keys()
+            .filter(|key| !present.contains(*key))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Returns whether there was a file to remove
+fn remove_if_exists(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// `path` would be generated as the same module as `first`
+fn collision(path: &Path, first: &Path, module: &str) -> Diagnostic {
+    let message = format!("generated as `{}.rs`, like `{}`", module, first.display());
+    let mut diagnostic = Diagnostic::error("NX0027", message, Location::default());
+    diagnostic.notes.push("rename one of the files".to_string());
+    diagnostic.related.push(Related {
+        message: "also generated here".to_string(),
+        file: first.display().to_string(),
+        location: Location::default(),
+    });
+    diagnostic.in_file(path.display().to_string())
+}
+
+/// Hex-encoded SHA-256 of `bytes`
+pub fn hash(bytes: impl AsRef<[u8]>) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Write `contents` unless the file already holds exactly that, so Cargo
+/// doesn't see a new modification time. Returns whether the file was written.
+fn write_if_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    if fs::read(path).is_ok_and(|existing| existing == contents.as_bytes()) {
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_is_incremental() {
+        let root = std::env::temp_dir().join(format!("nexscript-build-{}", std::process::id()));
+        let (input, output) = (root.join("scripts"), root.join("generated"));
+        fs::create_dir_all(&input).unwrap();
+        fs::write(
+            input.join("player.nx"),
+            "entity Player:\n    let speed = 1.0\n",
+        )
+        .unwrap();
+        fs::write(input.join("enemy.nx"), "entity Enemy:\n    let hp = 3\n").unwrap();
+        let builder = Builder::new().input(&input).output(&output);
+
+        let report = builder.run().unwrap();
+        assert_eq!(report.compiled.len(), 2);
+        let module = fs::read_to_string(output.join("mod.rs")).unwrap();
+        assert!(module.contains("pub mod enemy;\npub mod player;\n"));
+        assert!(output.join("player.rs.map").exists());
+
+        let report = builder.run().unwrap();
+        assert!(report.compiled.is_empty());
+        assert_eq!(report.unchanged, 2);
+
+        fs::write(input.join("enemy.nx"), "entity Enemy:\n    let hp =\n").unwrap();
+        let report = builder.run().unwrap();
+        assert_eq!(report.failed, vec![input.join("enemy.nx")]);
+        assert!(report.diagnostics[0]
+            .to_string()
+            .ends_with("enemy.nx:2:13: error[NX0001]: expected an expression"));
+
+        fs::remove_file(input.join("enemy.nx")).unwrap();
+        let report = builder.run().unwrap();
+        assert_eq!(report.removed, vec!["enemy.rs"]);
+        assert!(!output.join("enemy.rs").exists());
+        assert!(!output.join("enemy.rs.map").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_cached_build_leaves_outputs_untouched() {
+        let root = std::env::temp_dir().join(format!("nexscript-cached-{}", std::process::id()));
+        let (input, output) = (root.join("scripts"), root.join("generated"));
+        fs::create_dir_all(&input).unwrap();
+        fs::write(
+            input.join("player.nx"),
+            "entity Player:\n    let speed = 1.0\n",
+        )
+        .unwrap();
+        fs::write(input.join("enemy.nx"), "entity Enemy:\n    let hp = 3\n").unwrap();
+        let builder = Builder::new().input(&input).output(&output);
+        let modified = |name: &str| fs::metadata(output.join(name)).unwrap().modified().unwrap();
+        let outputs = ["player.rs", "player.rs.map", "enemy.rs", "mod.rs"];
+
+        builder.run().unwrap();
+        let before: Vec<_> = outputs.iter().map(|name| modified(name)).collect();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let report = builder.run().unwrap();
+        assert!(report.compiled.is_empty());
+        let after: Vec<_> = outputs.iter().map(|name| modified(name)).collect();
+        assert_eq!(after, before);
+
+        fs::remove_file(input.join("enemy.nx")).unwrap();
+        let report = builder.run().unwrap();
+        assert_eq!(report.removed, vec!["enemy.rs"]);
+        assert!(!output.join("enemy.rs").exists());
+        assert_eq!(modified("player.rs"), before[0]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_builder_keeps_output_of_moved_file() {
+        let root = std::env::temp_dir().join(format!("nexscript-moved-{}", std::process::id()));
+        let (input, output) = (root.join("scripts"), root.join("generated"));
+        fs::create_dir_all(input.join("sub")).unwrap();
+        fs::write(input.join("enemy.nx"), "entity Enemy:\n    let hp = 3\n").unwrap();
+        let builder = Builder::new().input(&input).output(&output);
+        builder.run().unwrap();
+
+        fs::rename(input.join("enemy.nx"), input.join("sub/enemy.nx")).unwrap();
+        let report = builder.run().unwrap();
+        assert_eq!(report.compiled, vec![input.join("sub/enemy.nx")]);
+        assert!(report.removed.is_empty());
+        assert!(output.join("enemy.rs").exists());
+        assert!(output.join("enemy.rs.map").exists());
+        let module = fs::read_to_string(output.join("mod.rs")).unwrap();
+        assert!(module.contains("pub mod enemy;\n"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_module_names_are_rust_identifiers() {
+        let root = std::env::temp_dir().join(format!("nexscript-names-{}", std::process::id()));
+        let (input, output) = (root.join("scripts"), root.join("generated"));
+        fs::create_dir_all(input.join("a")).unwrap();
+        fs::create_dir_all(input.join("b")).unwrap();
+        fs::write(
+            input.join("a/goblin.nx"),
+            "entity Goblin:\n    let hp = 3\n",
+        )
+        .unwrap();
+        fs::write(
+            input.join("b/goblin.nx"),
+            "entity Hobgoblin:\n    let hp = 5\n",
+        )
+        .unwrap();
+        fs::write(input.join("my-enemy.nx"), "entity Enemy:\n    let hp = 1\n").unwrap();
+        fs::write(input.join("type.nx"), "entity Kind:\n    let hp = 1\n").unwrap();
+        let builder = Builder::new().input(&input).output(&output);
+
+        let report = builder.run().unwrap();
+        assert_eq!(report.compiled.len(), 3);
+        assert_eq!(report.failed, vec![input.join("b/goblin.nx")]);
+        assert_eq!(report.diagnostics[0].code, "NX0027");
+        assert!(report.diagnostics[0]
+            .message
+            .starts_with("generated as `goblin.rs`, like `"));
+        let module = fs::read_to_string(output.join("mod.rs")).unwrap();
+        assert!(module.contains("pub mod goblin;\npub mod my_enemy;\npub mod type_;\n"));
+        assert!(fs::read_to_string(output.join("goblin.rs"))
+            .unwrap()
+            .contains("pub struct Goblin"));
+
+        // The collision is reported again rather than the first file's
+        // output being taken as fresh
+        let report = builder.run().unwrap();
+        assert_eq!(report.unchanged, 3);
+        assert_eq!(report.failed, vec![input.join("b/goblin.nx")]);
+
+        // Resolving it by deleting the first file hands the output over
+        fs::remove_file(input.join("a/goblin.nx")).unwrap();
+        let report = builder.run().unwrap();
+        assert_eq!(report.compiled, vec![input.join("b/goblin.nx")]);
+        assert!(report.removed.is_empty());
+        assert!(fs::read_to_string(output.join("goblin.rs"))
+            .unwrap()
+            .contains("pub struct Hobgoblin"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_importers_rebuild_when_imports_change() {
+        let root = std::env::temp_dir().join(format!("nexscript-imports-{}", std::process::id()));
+        let (input, output) = (root.join("scripts"), root.join("generated"));
+        fs::create_dir_all(input.join("util")).unwrap();
+        fs::write(
+            input.join("util/combat.nx"),
+            "fn damage(base: int) -> int:\n    return base\n",
+        )
+        .unwrap();
+        fs::write(
+            input.join("player.nx"),
+            "from util.combat import damage\n\nfn attack() -> int:\n    return damage(1)\n",
+        )
+        .unwrap();
+        fs::write(input.join("enemy.nx"), "entity Enemy:\n    let hp = 3\n").unwrap();
+        let builder = Builder::new().input(&input).output(&output);
+
+        let report = builder.run().unwrap();
+        assert_eq!(report.compiled.len(), 3);
+        let player = fs::read_to_string(output.join("player.rs")).unwrap();
+        assert!(player.contains("use super::combat::damage;"));
+
+        // Only the changed module and the one importing it are rebuilt
+        fs::write(
+            input.join("util/combat.nx"),
+            "fn damage(base: int, scale: float) -> int:\n    return base\n",
+        )
+        .unwrap();
+        let report = builder.run().unwrap();
+        assert_eq!(report.compiled, vec![input.join("util/combat.nx")]);
+        assert_eq!(report.failed, vec![input.join("player.nx")]);
+        assert_eq!(report.diagnostics[0].code, "NX0009");
+        assert_eq!(report.unchanged, 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
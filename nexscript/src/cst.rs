@@ -0,0 +1,335 @@
+//! CST - Lossless concrete syntax tree
+//!
+//! pest skips whitespace and comments and runs over the preprocessed text, so
+//! its parse tree can't reproduce the source. The CST keeps pest's rule
+//! structure, maps every node back onto the original source and attaches each
+//! lexer token (comments and blank lines included) to the innermost node that
+//! covers it. Concatenating the tokens gives back the source byte for byte.
+//! The AST is built from this tree, and AST spans select nodes in it.
+
+use crate::lexer::{self, Token, TokenKind};
+use crate::{Result, Rule, Span};
+use pest::iterators::Pair;
+use std::iter::Peekable;
+
+/// A parsed source file
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    root: SyntaxNode,
+}
+
+/// A grammar rule and everything it covers
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: Rule,
+    pub span: Span,
+    pub children: Vec<SyntaxElement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+/// A lexer token, including trivia
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub span: Span,
+    pub text: String,
+}
+
+impl SyntaxTree {
+    pub fn parse(source: &str) -> Result<Self> {
+        let (preprocessed, map) = crate::preprocess_indentation(source);
+        let pairs = crate::parse_preprocessed(source, &preprocessed, &map)?;
+
+        let mut root = SyntaxNode {
+            kind: Rule::program,
+            span: Span::default(),
+            children: Vec::new(),
+        };
+        for pair in pairs {
+            if let Some(node) = skeleton(pair, source, &map) {
+                root = node;
+            }
+        }
+
+        // The root owns everything, including trivia before and after the program
+        root.span = Span {
+            start: 0,
+            end: source.len(),
+        };
+        let mut tokens = lexer::tokenize(source).into_iter().peekable();
+        attach_tokens(&mut root, &mut tokens);
+
+        Ok(SyntaxTree { root })
+    }
+
+    pub fn root(&self) -> &SyntaxNode {
+        &self.root
+    }
+
+    /// The source text, byte for byte
+    pub fn text(&self) -> String {
+        self.root.text()
+    }
+
+    /// The outermost node an AST span was taken from
+    pub fn node(&self, span: Span) -> Option<&SyntaxNode> {
+        self.root.find(span)
+    }
+
+    pub fn comments(&self) -> Vec<&SyntaxToken> {
+        self.root
+            .tokens()
+            .into_iter()
+            .filter(|t| t.kind == TokenKind::Comment)
+            .collect()
+    }
+
+    /// Text of the `#` comment lines directly above a node, if any
+    pub fn doc_comment(&self, span: Span) -> Option<String> {
+        let tokens = self.root.tokens();
+        let is_ws = |i: usize| tokens[i].kind == TokenKind::Whitespace;
+        let is_kind = |i: usize, kind| tokens[i].kind == kind;
+
+        let mut docs = Vec::new();
+        let mut i = tokens.partition_point(|t| t.span.start < span.start);
+        loop {
+            // Walk back over `<comment> <newline> [indent]` to the comment
+            while i > 0 && is_ws(i - 1) {
+                i -= 1;
+            }
+            if i == 0 || !is_kind(i - 1, TokenKind::Newline) {
+                break;
+            }
+            let mut comment = i - 1;
+            while comment > 0 && is_ws(comment - 1) {
+                comment -= 1;
+            }
+            if comment == 0 || !is_kind(comment - 1, TokenKind::Comment) {
+                break;
+            }
+            comment -= 1;
+
+            // Only whole-line comments count, not trailing ones
+            let mut line_start = comment;
+            while line_start > 0 && is_ws(line_start - 1) {
+                line_start -= 1;
+            }
+            if line_start > 0 && !is_kind(line_start - 1, TokenKind::Newline) {
+                break;
+            }
+
+            let text = tokens[comment].text.trim_start_matches('#');
+            docs.push(text.strip_prefix(' ').unwrap_or(text).trim_end());
+            i = line_start;
+        }
+
+        if docs.is_empty() {
+            return None;
+        }
+        docs.reverse();
+        Some(docs.join("\n"))
+    }
+}
+
+impl SyntaxNode {
+    /// Source text of the node, including any comments inside it
+    pub fn text(&self) -> String {
+        self.tokens().iter().map(|t| t.text.as_str()).collect()
+    }
+
+    /// Child nodes, skipping tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> + Clone {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// All tokens under the node in source order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    fn find(&self, span: Span) -> Option<&SyntaxNode> {
+        if self.span == span {
+            return Some(self);
+        }
+        self.nodes()
+            .filter(|n| n.span.start <= span.start && span.end <= n.span.end)
+            .find_map(|n| n.find(span))
+    }
+}
+
+/// Offsets of text copied from the source into the preprocessed string
+#[derive(Debug, Default)]
+pub(crate) struct OffsetMap {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    processed: usize,
+    source: usize,
+    len: usize,
+}
+
+impl OffsetMap {
+    pub(crate) fn push(&mut self, processed: usize, source: usize, len: usize) {
+        if len > 0 {
+            self.segments.push(Segment {
+                processed,
+                source,
+                len,
+            });
+        }
+    }
+
+    /// Source offset where a node starting at `offset` begins. Markers and
+    /// line joins between segments belong to the text that follows them.
+    pub(crate) fn start(&self, offset: usize) -> usize {
+        let i = self
+            .segments
+            .partition_point(|s| s.processed + s.len <= offset);
+        match self.segments.get(i) {
+            Some(s) if s.processed <= offset => s.source + offset - s.processed,
+            Some(s) => s.source,
+            None => self.segments.last().map_or(0, |s| s.source + s.len),
+        }
+    }
+
+    /// Source offset where a node ending at `offset` ends, leaving out any
+    /// trailing newlines and markers
+    fn end(&self, offset: usize) -> usize {
+        let i = self.segments.partition_point(|s| s.processed < offset);
+        match i.checked_sub(1).map(|i| self.segments[i]) {
+            Some(s) => s.source + (offset - s.processed).min(s.len),
+            None => self.segments.first().map_or(0, |s| s.source),
+        }
+    }
+}
+
+/// Copy the pest tree, mapping spans onto the source. Indentation markers
+/// have no source text, so they are left out.
+fn skeleton(pair: Pair<Rule>, source: &str, map: &OffsetMap) -> Option<SyntaxNode> {
+    let kind = pair.as_rule();
+    if matches!(kind, Rule::INDENT | Rule::DEDENT | Rule::EOI) {
+        return None;
+    }
+
+    // pest can end a rule after skipping a trailing comment; leave it outside
+    let start = map.start(pair.as_span().start());
+    let end = map.end(pair.as_span().end()).max(start);
+    let end = lexer::tokenize(&source[start..end])
+        .iter()
+        .rfind(|t| !t.is_trivia())
+        .map_or(start, |t| start + t.end());
+    let children = pair
+        .into_inner()
+        .filter_map(|child| skeleton(child, source, map))
+        .map(SyntaxElement::Node)
+        .collect();
+
+    Some(SyntaxNode {
+        kind,
+        span: Span { start, end },
+        children,
+    })
+}
+
+/// Hand out tokens in order: each goes to the deepest node that contains it
+fn attach_tokens<'a, I>(node: &mut SyntaxNode, tokens: &mut Peekable<I>)
+where
+    I: Iterator<Item = Token<'a>>,
+{
+    for child in std::mem::take(&mut node.children) {
+        let SyntaxElement::Node(mut child) = child else {
+            continue;
+        };
+        take_tokens(&mut node.children, tokens, child.span.start);
+        attach_tokens(&mut child, tokens);
+        node.children.push(SyntaxElement::Node(child));
+    }
+    take_tokens(&mut node.children, tokens, node.span.end);
+}
+
+fn take_tokens<'a, I>(out: &mut Vec<SyntaxElement>, tokens: &mut Peekable<I>, until: usize)
+where
+    I: Iterator<Item = Token<'a>>,
+{
+    while let Some(token) = tokens.next_if(|t| t.end() <= until) {
+        out.push(SyntaxElement::Token(SyntaxToken {
+            kind: token.kind,
+            span: Span {
+                start: token.offset,
+                end: token.end(),
+            },
+            text: token.text.to_string(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Statement};
+
+    const SOURCE: &str = "# Player script\r\nentity Player:\r\n    # Movement speed\r\n    let speed = 200.0  # px/s\r\n\r\n    fn on_update(delta: float):\r\n        move(speed *\r\n             delta)  # wrapped\r\n";
+
+    #[test]
+    fn test_cst_is_lossless() {
+        let tree = SyntaxTree::parse(SOURCE).unwrap();
+        assert_eq!(tree.text(), SOURCE);
+        assert_eq!(tree.root().kind, Rule::program);
+
+        let comments: Vec<&str> = tree.comments().iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(
+            comments,
+            vec!["# Player script", "# Movement speed", "# px/s", "# wrapped"]
+        );
+
+        let example = include_str!("../examples/player.nx");
+        assert_eq!(SyntaxTree::parse(example).unwrap().text(), example);
+    }
+
+    #[test]
+    fn test_ast_spans_point_into_cst() {
+        let tree = SyntaxTree::parse(SOURCE).unwrap();
+        let program = parse(SOURCE).unwrap();
+        let Statement::EntityDef(entity) = &program.statements[0] else {
+            panic!("expected entity");
+        };
+
+        let var = tree.node(entity.variables[0].span).unwrap();
+        assert_eq!(var.kind, Rule::variable_decl);
+        assert_eq!(var.text(), "let speed = 200.0");
+        assert_eq!(
+            tree.doc_comment(entity.variables[0].span).as_deref(),
+            Some("Movement speed")
+        );
+
+        let func = &entity.functions[0];
+        assert_eq!(tree.node(func.span).unwrap().kind, Rule::fn_def);
+        assert!(SOURCE[func.span.start..func.span.end].ends_with("delta)"));
+        assert_eq!(tree.doc_comment(func.span), None);
+        assert_eq!(
+            tree.doc_comment(entity.span).as_deref(),
+            Some("Player script")
+        );
+    }
+}
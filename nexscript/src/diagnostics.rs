@@ -0,0 +1,3388 @@
+//! Diagnostics - Problems found in a NexScript file
+//!
+//! `check` parses a file and runs the semantic checks over it, collecting
+//! every problem rather than stopping at the first. Each diagnostic carries
+//! a stable code (`NX0001`, ...) that tools and docs can refer to; codes are
+//! never renumbered or reused.
+
+use crate::exhaustiveness::{self, Enums};
+use crate::inheritance::{self, Bases};
+use crate::lexer::{self, TokenKind};
+use crate::modules::{self, Imported, Imports, Item};
+use crate::queries::{self, Query};
+use crate::schedules::{self, Plan, Problem};
+use crate::source_map::line_col;
+use crate::type_checker::{infer_type, infer_type_in, member_path, TypeEnv};
+use crate::{
+    is_filter, parse, strip_comment, Annotation, Arg, BinaryOp, EmitStmt, EntityDef, EnumDef, Expr,
+    FnDef, ForStmt, ImportStmt, InterfaceDef, MatchStmt, NexScriptError, Param, Pattern, Program,
+    SignalDef, Span, Statement, StructDef, SystemDef, TypeExpr, UnaryOp,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A diagnostic code, as listed by `nexc explain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    /// One line, used as the rule name in SARIF logs
+    pub summary: &'static str,
+    /// What causes it and how to fix it, with examples
+    pub explanation: &'static str,
+}
+
+/// Every diagnostic code
+pub const CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "NX0001",
+        summary: "syntax error",
+        explanation: r#"The file does not follow NexScript's grammar, so nothing else in it
+could be checked.
+
+The most common causes are a block header without its trailing `:` and
+indentation that doesn't line up with any enclosing block:
+
+    entity Coin                 # error: missing `:`
+        let value = 10
+
+    fn collect():
+        emit scored(value)
+          print("done")         # error: unexpected indent
+
+Every line of a block must be indented by the same amount, and blocks are
+opened by `entity`, `component`, `struct`, `enum`, `fn`, `state_machine`,
+`state`, `if`, `elif`, `else`, `match`, `case`, `while` and `for` headers
+ending in `:`.
+"#,
+    },
+    ErrorCode {
+        code: "NX0002",
+        summary: "file could not be read",
+        explanation: r#"A `.nx` file could not be read from disk, for example because it was
+deleted during the build, its permissions forbid reading it or it is not
+valid UTF-8.
+
+NexScript sources must be UTF-8 text. Check that the file exists and is
+readable by the user running `nexc`.
+"#,
+    },
+    ErrorCode {
+        code: "NX0003",
+        summary: "duplicate definition",
+        explanation: r#"A name is defined twice in the same scope. Top-level entities,
+functions, signals and state machines share one scope; inside an entity its
+components, variables, signals and functions share another, and the states
+of a state machine share a third.
+
+    entity Coin:
+        let value = 1
+        let value = 2           # error: `value` is defined more than once
+
+Rename one of the definitions, or remove the one that isn't needed. The
+generated Rust would otherwise contain two items with the same name.
+"#,
+    },
+    ErrorCode {
+        code: "NX0004",
+        summary: "unknown signal",
+        explanation: r#"An `emit` statement names a signal that is not declared, neither at the
+top level nor in the enclosing entity.
+
+    signal scored(points: int)
+
+    entity Coin:
+        fn collect():
+            emit scord(10)      # error: unknown signal `scord`
+
+Declare the signal with `signal name(params)` or fix the spelling; when a
+declared signal has a similar name, it is suggested as a fix.
+"#,
+    },
+    ErrorCode {
+        code: "NX0005",
+        summary: "wrong number of signal arguments",
+        explanation: r#"An `emit` statement passes a different number of arguments than the
+signal declares parameters. Every parameter becomes a field of the
+generated event, so all of them must be given.
+
+    signal scored(points: int)
+
+    entity Coin:
+        fn collect():
+            emit scored()       # error: `scored` takes 1 argument but 0 were given
+
+Pass one argument per parameter, in order, or change the signal's
+declaration.
+"#,
+    },
+    ErrorCode {
+        code: "NX0006",
+        summary: "unresolved module",
+        explanation: r#"An `import` names a module that has no file. Module paths are relative to
+the project root, with a dot between directories, so `util.damage` is the
+file `util/damage.nx`:
+
+    import util.damge           # error: unresolved module `util.damge`
+
+Check the spelling and that the file is inside the project, and note that
+paths are not relative to the importing file.
+"#,
+    },
+    ErrorCode {
+        code: "NX0007",
+        summary: "import cycle",
+        explanation: r#"Modules import each other, directly or through other modules:
+
+    # a.nx
+    import b
+
+    # b.nx
+    import a                    # error: import cycle: b -> a -> b
+
+Move what both modules need into a third module that neither imports from,
+and import it from both.
+"#,
+    },
+    ErrorCode {
+        code: "NX0008",
+        summary: "unknown imported name",
+        explanation: r#"A `from ... import`, or a `module.name` after a plain `import`, names
+something the module doesn't define. Only the module's top-level entities,
+functions, signals and state machines can be imported; members of an entity
+can't.
+
+    # combat.nx
+    fn damage(base: int) -> int:
+        return base
+
+    # player.nx
+    from combat import dammage  # error: module `combat` has no `dammage`
+    import combat
+    combat.dammage(1)           # error: module `combat` has no `dammage`
+
+Fix the spelling, or add the definition to the module.
+"#,
+    },
+    ErrorCode {
+        code: "NX0009",
+        summary: "wrong number of function arguments",
+        explanation: r#"A call passes a different number of arguments than the function declares
+parameters. This is checked for the file's own top-level functions, its
+entity's functions, imported functions and the `range` and `enumerate`
+built-ins. Constructing a struct or enum
+variant with more positional arguments than it has fields, or matching a
+variant with a different number of field patterns, is reported the same
+way, as is a lambda passed where a `Fn` with a different number of
+parameters is expected.
+
+    fn damage(base: int, scale: float) -> int:
+        return base
+
+    entity Player:
+        fn attack():
+            let d = damage(10)  # error: `damage` takes 2 arguments but 1 was given
+
+Pass one argument per parameter, in order.
+"#,
+    },
+    ErrorCode {
+        code: "NX0010",
+        summary: "mismatched argument type",
+        explanation: r#"An argument's type is known and is not what the parameter declares. An
+`int` can be passed where a `float` is expected; other built-in types
+must match exactly. Arguments to struct and enum constructors, and field
+defaults, are checked against the fields' types, and a lambda passed as a
+`Fn<params..., return>` must take and return those types.
+
+    fn heal(amount: int):
+        print(amount)
+
+    entity Player:
+        fn rest():
+            heal("a lot")       # error: expected `int`, found `str`
+
+Convert the value, or pass one of the declared type.
+"#,
+    },
+    ErrorCode {
+        code: "NX0011",
+        summary: "unknown field",
+        explanation: r#"A struct or enum variant is constructed with a named argument that isn't
+one of its fields.
+
+    struct Weapon:
+        name: str
+        damage: int = 1
+
+    entity Player:
+        fn equip():
+            let w = Weapon("sword", dammage: 5)  # error: `Weapon` has no field `dammage`
+
+Fix the spelling, or add the field to the declaration.
+"#,
+    },
+    ErrorCode {
+        code: "NX0012",
+        summary: "missing field",
+        explanation: r#"A struct or enum variant is constructed without a value for a field that
+has no default.
+
+    struct Weapon:
+        name: str
+        damage: int = 1
+
+    entity Player:
+        fn equip():
+            let w = Weapon(damage: 5)   # error: missing field `name` in `Weapon`
+
+Pass the field, by position or by name, or give it a default in the
+declaration.
+"#,
+    },
+    ErrorCode {
+        code: "NX0013",
+        summary: "unknown enum variant",
+        explanation: r#"An enum is used with a variant it doesn't declare.
+
+    enum Shape:
+        Circle(radius: float)
+        Square(side: float)
+
+    entity Player:
+        fn build():
+            let s = Shape.Triangle(1.0)  # error: `Shape` has no variant `Triangle`
+
+Fix the spelling, or add the variant to the enum. Variants named in `case`
+patterns are checked the same way.
+"#,
+    },
+    ErrorCode {
+        code: "NX0014",
+        summary: "non-exhaustive match",
+        explanation: r#"Some value of a `match` subject is matched by none of its arms. Enums
+and `bool` are covered by naming every variant or value; ints, floats and
+strings have too many values, so they also need a `_` or binding arm. Arms
+with an `if` guard may not match, so they don't count.
+
+    enum Shape:
+        Circle(radius: float)
+        Square(side: float)
+
+    fn area(s: Shape) -> float:
+        match s:                # error: `match` doesn't cover `Shape.Square(_)`
+            case Shape.Circle(r):
+                return 3.14 * r * r
+
+Add an arm for the missing value, or a `case _:` arm for everything else.
+"#,
+    },
+    ErrorCode {
+        code: "NX0015",
+        summary: "unreachable match arm",
+        explanation: r#"A `case` can never run, because the arms before it (without guards)
+match every value it matches. This is a warning by default.
+
+    fn describe(n: int) -> str:
+        match n:
+            case 0..10:
+                return "small"
+            case 5:             # warning: unreachable `case`
+                return "five"
+            case _:
+                return "large"
+
+Move the more specific arm before the general one, or remove it.
+"#,
+    },
+    ErrorCode {
+        code: "NX0016",
+        summary: "`break` or `continue` outside of a loop",
+        explanation: r#"`break` and `continue` only make sense inside the body of a `while` or
+`for` loop.
+
+    fn on_update(delta: float):
+        if health <= 0:
+            break               # error: `break` outside of a loop
+
+Use `return` to leave the function instead.
+"#,
+    },
+    ErrorCode {
+        code: "NX0017",
+        summary: "unknown interface",
+        explanation: r#"An entity `implements` a name that isn't an interface declared in the file
+or imported into it.
+
+    interface Damageable:
+        fn take_damage(amount: int)
+
+    entity Crate implements Damagable:  # error: unknown interface `Damagable`
+        fn take_damage(amount: int):
+            pass
+
+Fix the spelling, or import the interface from the module declaring it.
+"#,
+    },
+    ErrorCode {
+        code: "NX0018",
+        summary: "entity doesn't implement its interface",
+        explanation: r#"An entity that `implements` an interface must declare every function and
+signal the interface lists, with the same parameter types and return type.
+
+    interface Damageable:
+        signal died()
+        fn take_damage(amount: int)
+
+    entity Crate implements Damageable:  # error: `Crate` is missing `signal died()`
+        fn take_damage(amount: float):   # error: doesn't match `fn take_damage(amount: int)`
+            pass
+
+Add the missing declarations, or change them to match the interface.
+"#,
+    },
+    ErrorCode {
+        code: "NX0019",
+        summary: "invalid base entity",
+        explanation: r#"An entity `extends` a name that isn't an entity declared in the file or
+imported into it, or its bases lead back to itself.
+
+    entity Enemy:
+        let speed = 1.0
+
+    entity Boss extends Enemey:     # error: unknown base entity `Enemey`
+        let speed = 2.0
+
+    entity A extends B:             # error: `A` extends itself
+        let x = 1
+    entity B extends A:             # error: `B` extends itself
+        let x = 2
+
+Fix the spelling, import the base from the module declaring it, or break
+the cycle.
+"#,
+    },
+    ErrorCode {
+        code: "NX0020",
+        summary: "override doesn't match its base",
+        explanation: r#"A function or signal that overrides one of its base entity's must keep the
+parameter types and return type, so code written against the base still
+works with the derived entity.
+
+    entity Enemy:
+        fn take_damage(amount: int):
+            pass
+
+    entity Boss extends Enemy:
+        fn take_damage(amount: float):  # error: doesn't match `fn take_damage(amount: int)`
+            pass
+
+Change the override to match, or give it a different name.
+"#,
+    },
+    ErrorCode {
+        code: "NX0021",
+        summary: "invalid `super` call",
+        explanation: r#"`super.name()` calls the base entity's version of a function, so it can
+only be used in an entity that `extends` another, and only for functions
+the base has.
+
+    entity Enemy:
+        fn take_damage(amount: int):
+            pass
+
+    entity Boss extends Enemy:
+        fn take_damage(amount: int):
+            super.take_damag(amount / 2)  # error: `Enemy` has no function `take_damag`
+
+    entity Crate:
+        fn open():
+            super.open()                  # error: `super` outside of an entity that extends another
+"#,
+    },
+    ErrorCode {
+        code: "NX0022",
+        summary: "invalid query",
+        explanation: r#"A `Query` lists the components it yields, then any `with` or `without`
+filters. A loop over it names one variable per component, in order.
+Filters can't be used outside of a `Query`.
+
+    system gravity(q: Query<Transform, Velocity, with Player>):
+        for v in q:                     # error: `q` yields 2 components but the loop has 1 name
+            v.y -= 9.8
+
+    fn count(players: List<with Player>) -> int:  # error: `with Player` is only allowed in a `Query`
+        pass
+
+Name every component in the loop (`for t, v in q:`), or remove the ones
+the system doesn't use from the query.
+"#,
+    },
+    ErrorCode {
+        code: "NX0023",
+        summary: "invalid system annotation",
+        explanation: r#"Annotations on the lines above a system or an entity's `on_update` say
+when it runs: `@schedule` picks the Bevy schedule (`Update` if not given),
+`@before` and `@after` order it against other systems or sets of the same
+file, `@set` puts it in a set, and `@run_if` skips it unless a state is
+active or a function returns `true`.
+
+    @schedule(FixedUpdate)
+    @after(spawn)                     # error: unknown system or set `spawn`
+    @run_if(in_state(Playing))
+    system gravity(q: Query<Velocity>):
+        pass
+
+    @before(gravity)                  # error: `@before` can only annotate a system or `on_update`
+    fn helper():
+        pass
+
+Systems can only be ordered against others in the same schedule. A run
+condition is `in_state(State)`, or a function that takes no parameters and
+returns `bool`.
+"#,
+    },
+    ErrorCode {
+        code: "NX0024",
+        summary: "system ordering cycle",
+        explanation: r#"`@before` and `@after` lead from a system back to itself, directly or
+through the sets it is in, so no order satisfies them all.
+
+    @after(collide)                   # error: `gravity` is ordered to run before itself
+    system gravity(q: Query<Velocity>):
+        pass
+
+    @after(gravity)
+    system collide(q: Query<Velocity>):
+        pass
+
+Remove one of the orderings in the cycle.
+"#,
+    },
+    ErrorCode {
+        code: "NX0025",
+        summary: "invalid range",
+        explanation: r#"A range whose start isn't below its end contains no values, so a `case`
+with it never matches. A `range()` can't step by 0, and with a negative
+step it counts down, so its start must be above its end.
+
+    match n:
+        case 10..0:             # error: empty range pattern
+            pass
+        case 5..5:              # error: empty range pattern
+            pass
+        case 5..=5:             # matches 5
+            pass
+
+    for i in range(0, 10, 0):   # error: `range` can't step by 0
+        pass
+    for i in range(0, 10, -1):  # error: `range` counts down but 0 is below 10
+        pass
+
+Swap the bounds, use `..=` to include the end, or fix the step.
+"#,
+    },
+    ErrorCode {
+        code: "NX0026",
+        summary: "stored function",
+        explanation: r#"A function type such as `Fn<int, bool>` can be a function's parameter or
+return type, but not the type of a struct or enum field, an entity
+variable or a signal parameter. Those become types that are cloned,
+printed and saved, which a function can't be.
+
+    struct Button:
+        on_click: Fn<None>          # error: `Fn<None>` can't be stored in a struct field
+
+    fn apply(f: Fn<int, int>, x: int) -> int:
+        return f(x)
+
+Store what the function needs instead, and pass the function where it's
+called.
+"#,
+    },
+    ErrorCode {
+        code: "NX0027",
+        summary: "module name collision",
+        explanation: r#"Every `.nx` file is generated as a Rust module named after the file, in
+one output directory, so two files with the same name in different
+directories, or names that only differ in case or punctuation, would
+overwrite each other.
+
+    scripts/a/goblin.nx
+    scripts/b/goblin.nx         # error: generated as `goblin.rs`, like `scripts/a/goblin.nx`
+
+Rename one of the files.
+"#,
+    },
+    ErrorCode {
+        code: "NX0028",
+        summary: "wrong number of loop names",
+        explanation: r#"A loop over `range()` names one variable for the number, and a loop over
+`enumerate()` names two, for the index and the item.
+
+    for i, j in range(10):              # error: `range` yields a number but the loop has 2 names
+        pass
+    for i, item, extra in enumerate(items):  # error: `enumerate` yields an index and an item but the loop has 3 names
+        pass
+
+    for i, item in enumerate(items):
+        pass
+"#,
+    },
+    ErrorCode {
+        code: "NX0029",
+        summary: "mismatched operand types",
+        explanation: r#"An arithmetic operator is applied to values whose types don't go
+together, such as a number and a `str`. Only `+` works on strings, and
+only on two of them. Operands are checked in the bodies of lambdas passed
+where a `Fn` is expected, where the parameters' types are known.
+
+    fn labels(items: List<int>) -> List<str>:
+        return items.map(|x| x + "a")   # error: can't apply `+` to `int` and `str`
+
+Convert one side so both have the same type.
+"#,
+    },
+    ErrorCode {
+        code: "NX0030",
+        summary: "pattern doesn't match the subject's type",
+        explanation: r#"A `case` pattern must have the type of the value being matched: a tuple
+pattern needs a tuple of as many items, a literal or range a value of its
+type, and a variant its enum. Otherwise the arm could never run, and the
+generated Rust doesn't compile. Subjects are checked when their type is
+known, such as a parameter or a variable with a literal value.
+
+    fn describe(hp: int) -> str:
+        match hp:
+            case (0, true):         # error: expected `int`, found a tuple of 2 items
+                return "dead"
+            case _:
+                return "alive"
+
+Match on a value of the pattern's type, or change the pattern.
+"#,
+    },
+];
+
+/// Look up a code such as `NX0004`, ignoring case
+pub fn explain(code: &str) -> Option<&'static ErrorCode> {
+    CODES.iter().find(|c| c.code.eq_ignore_ascii_case(code))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem in a source file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    /// File the problem is in; empty until the caller fills it in
+    pub file: String,
+    pub location: Location,
+    pub notes: Vec<String>,
+    /// Other places that explain the problem, e.g. an earlier definition
+    pub related: Vec<Related>,
+    pub fixes: Vec<Fix>,
+}
+
+/// A span with its 1-based start and end line and column
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Location {
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Related {
+    pub message: String,
+    /// File the location is in, when it isn't the diagnostic's own
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub file: String,
+    pub location: Location,
+}
+
+/// Replace the text at `location` with `replacement`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Fix {
+    pub message: String,
+    pub location: Location,
+    pub replacement: String,
+}
+
+impl Location {
+    pub fn new(source: &str, span: Span) -> Self {
+        let (line, column) = line_col(source, span.start);
+        let (end_line, end_column) = line_col(source, span.end);
+        Location {
+            span,
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn error(code: &str, message: impl Into<String>, location: Location) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: code.to_string(),
+            message: message.into(),
+            file: String::new(),
+            location,
+            notes: Vec::new(),
+            related: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &str, message: impl Into<String>, location: Location) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, location)
+        }
+    }
+
+    /// A parse or I/O error as a diagnostic
+    pub fn from_error(error: &NexScriptError, source: &str) -> Self {
+        match error {
+            NexScriptError::ParseError {
+                line,
+                column,
+                message,
+            } => syntax_error(source, *line, *column, message),
+            NexScriptError::IoError(e) => {
+                Diagnostic::error("NX0002", e.to_string(), Location::default())
+            }
+            error => Diagnostic::error("NX0001", error.to_string(), Location::default()),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn in_file(mut self, file: impl Into<String>) -> Self {
+        self.file = file.into();
+        self
+    }
+}
+
+/// `file:line:column: error[NX0001]: message`
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+            if self.location.line > 0 {
+                write!(f, "{}:{}:", self.location.line, self.location.column)?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+/// Parse and check a file, returning the program if it parsed
+pub fn check(source: &str) -> (Option<Program>, Vec<Diagnostic>) {
+    match parse(source) {
+        Ok(program) => {
+            let diagnostics = check_program(&program, source);
+            (Some(program), diagnostics)
+        }
+        Err(e) => (None, vec![Diagnostic::from_error(&e, source)]),
+    }
+}
+
+/// Semantic checks over a parsed program
+pub fn check_program(program: &Program, source: &str) -> Vec<Diagnostic> {
+    check_program_with(program, source, &Imports::default())
+}
+
+/// Semantic checks over a parsed program, with what its imports bring into
+/// scope. Names imported from modules missing from `imports` are assumed
+/// to be valid, so a file can still be checked on its own.
+pub fn check_program_with(program: &Program, source: &str, imports: &Imports) -> Vec<Diagnostic> {
+    let bases = Bases::new(program, imports);
+    let inherited = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::EntityDef(entity) => {
+                let base = bases.base(entity).transpose()?;
+                Some((entity.name.clone(), base))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut checker = Checker {
+        source,
+        imports,
+        bases,
+        inherited: &inherited,
+        unresolved: HashSet::new(),
+        modules: modules::plain_imports(program),
+        diagnostics: Vec::new(),
+        scope: Span::default(),
+        calls: HashMap::new(),
+        loops: 0,
+        locals: TypeEnv::new(),
+    };
+    checker.program(program);
+    checker.diagnostics
+}
+
+/// Block headers that must end with `:`
+const BLOCK_KEYWORDS: &[&str] = &[
+    "entity",
+    "component",
+    "struct",
+    "enum",
+    "interface",
+    "fn",
+    "system",
+    "async",
+    "state_machine",
+    "state",
+    "if",
+    "match",
+    "case",
+    "elif",
+    "else",
+    "while",
+    "for",
+];
+
+fn syntax_error(source: &str, line: usize, column: usize, message: &str) -> Diagnostic {
+    let line_start: usize = source
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let line_text = source[line_start..].lines().next().unwrap_or_default();
+    let content_start = line_start + (line_text.len() - line_text.trim_start().len());
+    let content_end = line_start + line_text.trim_end().len();
+
+    // From the error to the end of the line, or empty when the line ended
+    // too soon; `column` counts characters
+    let column_offset = line_text
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or(line_text.len(), |(i, _)| i);
+    let start = (line_start + column_offset).clamp(content_start, content_end);
+    let location = Location::new(
+        source,
+        Span {
+            start,
+            end: content_end,
+        },
+    );
+
+    let mut diagnostic = Diagnostic::error("NX0001", message, location);
+    if let Some(end) = missing_colon(source, line_start) {
+        // What pest expected is rarely meaningful here, e.g. a map entry
+        // after the indent marker it took for `{`
+        diagnostic.message = "expected `:` at the end of the block header".to_string();
+        diagnostic.location = Location::new(source, Span { start: end, end });
+        diagnostic.fixes.push(Fix {
+            message: "add `:` to start the block".to_string(),
+            location: diagnostic.location,
+            replacement: ":".to_string(),
+        });
+    } else if let Some((span, names)) = parenthesized_for(source, line_start) {
+        // pest reads `for (t, v)` as a call and only stops at the `:`
+        diagnostic.message =
+            "expected a name: `for` takes its names without parentheses".to_string();
+        diagnostic.location = Location::new(source, span);
+        diagnostic.fixes.push(Fix {
+            message: "remove the parentheses".to_string(),
+            location: diagnostic.location,
+            replacement: names.trim().to_string(),
+        });
+    }
+    diagnostic
+}
+
+/// Where a `for` on the error line has its names in parentheses, as in
+/// `for (t, v) in q:`: the span of the parentheses and the names in them
+fn parenthesized_for(source: &str, line_start: usize) -> Option<(Span, &str)> {
+    let line = source[line_start..].lines().next().unwrap_or_default();
+    let tokens: Vec<_> = lexer::tokenize(line)
+        .into_iter()
+        .filter(|t| !t.is_trivia())
+        .collect();
+    let [keyword, open, ..] = tokens.as_slice() else {
+        return None;
+    };
+    if keyword.text != "for" || open.text != "(" {
+        return None;
+    }
+    let close = tokens.iter().position(|t| t.text == ")")?;
+    let names = &tokens[2..close];
+    let listed = names.iter().enumerate().all(|(i, t)| match i % 2 {
+        0 => t.kind == TokenKind::Ident,
+        _ => t.text == ",",
+    });
+    (listed && names.len() % 2 == 1).then(|| {
+        let span = Span {
+            start: line_start + open.offset,
+            end: line_start + tokens[close].end(),
+        };
+        (span, &line[open.end()..tokens[close].offset])
+    })
+}
+
+/// Where a block header without its `:` ends, if the error is on that
+/// header or on the first line of its body
+fn missing_colon(source: &str, error_line_start: usize) -> Option<usize> {
+    let previous = source[..error_line_start]
+        .trim_end()
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    [error_line_start, previous].into_iter().find_map(|start| {
+        let line = source[start..].lines().next().unwrap_or_default();
+        let code = strip_comment(line).trim();
+        let keyword = code
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()?;
+        // A header wrapped onto more lines ends on a later one
+        let wrapped = crate::bracket_depth(code) > 0;
+        (BLOCK_KEYWORDS.contains(&keyword) && !code.ends_with(':') && !wrapped)
+            .then(|| start + (line.len() - line.trim_start().len()) + code.len())
+    })
+}
+
+struct Checker<'a> {
+    source: &'a str,
+    imports: &'a Imports<'a>,
+    bases: Bases<'a>,
+    /// The flattened base of each entity that extends another, by entity
+    inherited: &'a HashMap<String, Result<EntityDef, inheritance::Error>>,
+    /// Names imported from modules that weren't loaded; nothing is known
+    /// about them
+    unresolved: HashSet<&'a str>,
+    /// Modules imported with a plain `import`, by the names they can be used
+    /// under (see [`modules::plain_imports`])
+    modules: HashMap<String, &'a str>,
+    diagnostics: Vec<Diagnostic>,
+    /// Span of the function being checked, and how many calls to each name
+    /// have been seen in it so far, to find the next one in the source
+    scope: Span,
+    calls: HashMap<String, usize>,
+    /// How many loops the statement being checked is inside
+    loops: usize,
+    /// Types of the parameters and variables of the function being checked
+    locals: TypeEnv,
+}
+
+/// A signal, function or type that can be used by name, with where it is
+/// declared
+struct Visible<'a, T> {
+    def: &'a T,
+    /// Empty when declared in the file being checked
+    file: &'a str,
+    location: Location,
+}
+
+impl<T> Clone for Visible<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Visible<'_, T> {}
+
+impl<T> Visible<'_, T> {
+    fn declared_here(&self, what: &str) -> Related {
+        Related {
+            message: format!("{} declared here", what),
+            file: self.file.to_string(),
+            location: self.location,
+        }
+    }
+}
+
+/// Signals, functions and types visible in a body
+#[derive(Clone, Default)]
+struct Scope<'a> {
+    signals: Vec<Visible<'a, SignalDef>>,
+    functions: Vec<Visible<'a, FnDef>>,
+    structs: Vec<Visible<'a, StructDef>>,
+    enums: Vec<Visible<'a, EnumDef>>,
+    interfaces: Vec<Visible<'a, InterfaceDef>>,
+    /// Whether the body belongs to an entity that extends another, and the
+    /// base with its functions when it was found
+    derived: bool,
+    base: Option<Visible<'a, EntityDef>>,
+    supers: Vec<Visible<'a, FnDef>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Make an imported signal, function or type visible
+    fn add(&mut self, imported: &Imported<'a>) {
+        match imported.item {
+            Item::Struct(def) => self.structs.push(Visible {
+                def,
+                file: imported.file,
+                location: imported.location,
+            }),
+            Item::Enum(def) => self.enums.push(Visible {
+                def,
+                file: imported.file,
+                location: imported.location,
+            }),
+            Item::Signal(signal) => self.signals.push(Visible {
+                def: signal,
+                file: imported.file,
+                location: imported.location,
+            }),
+            Item::Function(func) => self.functions.push(Visible {
+                def: func,
+                file: imported.file,
+                location: imported.location,
+            }),
+            Item::Interface(def) => self.interfaces.push(Visible {
+                def,
+                file: imported.file,
+                location: imported.location,
+            }),
+            _ => {}
+        }
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn program(&mut self, program: &'a Program) {
+        let mut names = Definitions::default();
+        let mut scope = Scope::default();
+        let mut entities = Vec::new();
+        for stmt in &program.statements {
+            match stmt {
+                Statement::Import(import) => self.import(import, &mut names, &mut scope),
+                Statement::EntityDef(entity) => {
+                    names.define(self, &entity.name, entity.span);
+                    entities.push(entity.name.as_str());
+                }
+                Statement::SystemDef(system) => names.define(self, &system.name, system.span),
+                Statement::FnDef(func) => {
+                    names.define(self, &func.name, func.span);
+                    scope
+                        .functions
+                        .push(self.local(func, &func.name, func.span));
+                }
+                Statement::SignalDef(signal) => {
+                    names.define(self, &signal.name, signal.span);
+                    for param in &signal.params {
+                        self.stored(&param.type_expr, param.span, "a signal");
+                    }
+                    scope
+                        .signals
+                        .push(self.local(signal, &signal.name, signal.span));
+                }
+                Statement::StateMachine(machine) => {
+                    names.define(self, &machine.name, machine.span);
+                    let mut states = Definitions::default();
+                    for state in &machine.states {
+                        states.define(self, &state.name, state.span);
+                    }
+                }
+                Statement::StructDef(def) => {
+                    names.define(self, &def.name, def.span);
+                    let mut fields = Definitions::default();
+                    for field in &def.fields {
+                        fields.define(self, &field.name, field.span);
+                        self.stored(&field.type_expr, field.span, "a struct field");
+                    }
+                    scope.structs.push(self.local(def, &def.name, def.span));
+                }
+                Statement::EnumDef(def) => {
+                    names.define(self, &def.name, def.span);
+                    let mut variants = Definitions::default();
+                    for variant in &def.variants {
+                        variants.define(self, &variant.name, variant.span);
+                        let mut fields = Definitions::default();
+                        for field in &variant.fields {
+                            fields.define(self, &field.name, variant.span);
+                            self.stored(&field.type_expr, variant.span, "an enum variant");
+                        }
+                    }
+                    scope.enums.push(self.local(def, &def.name, def.span));
+                }
+                Statement::InterfaceDef(def) => {
+                    names.define(self, &def.name, def.span);
+                    let mut members = Definitions::default();
+                    for signal in &def.signals {
+                        members.define(self, &signal.name, signal.span);
+                    }
+                    for func in &def.functions {
+                        members.define(self, &func.name, func.span);
+                    }
+                    scope.interfaces.push(self.local(def, &def.name, def.span));
+                }
+                _ => {}
+            }
+        }
+        // After the file's own definitions, which shadow them
+        for imported in self.imports.prelude.values() {
+            scope.add(imported);
+        }
+        for (name, imported) in self.imports.names.iter().chain(&self.imports.prelude) {
+            if let Item::Entity(_) = imported.item {
+                entities.push(name.as_str());
+            }
+        }
+
+        for stmt in &program.statements {
+            match stmt {
+                Statement::EntityDef(entity) => {
+                    if let Some(Err(error)) = self.inherited.get(&entity.name) {
+                        self.extends(entity, error, &entities);
+                    }
+                    let base = self.base(entity);
+                    if let Some(base) = base {
+                        self.overrides(entity, base);
+                    }
+                    // Interfaces can be satisfied by inherited members
+                    let flattened = base.map(|base| inheritance::inherit(entity, base.def.clone()));
+                    let mut interfaces = Definitions::default();
+                    for (name, span) in self.interfaces(entity) {
+                        interfaces.define(self, name, span);
+                    }
+                    for name in &entity.implements {
+                        let members = flattened.as_ref().unwrap_or(entity);
+                        self.implements(entity, members, name, &scope);
+                    }
+                    let mut members = Definitions::default();
+                    for component in &entity.components {
+                        members.define(self, &component.name, component.span);
+                    }
+                    for var in &entity.variables {
+                        members.define(self, &var.name, var.span);
+                        let declared = var.type_expr.clone().or_else(|| infer_type(&var.value));
+                        if let Some(type_expr) = declared {
+                            self.stored(
+                                &type_expr,
+                                self.header(var.span).span,
+                                "an entity variable",
+                            );
+                        }
+                    }
+                    for signal in &entity.signals {
+                        members.define(self, &signal.name, signal.span);
+                        for param in &signal.params {
+                            self.stored(&param.type_expr, param.span, "a signal");
+                        }
+                    }
+                    for func in &entity.functions {
+                        members.define(self, &func.name, func.span);
+                    }
+
+                    // Entity functions can use their own signals and
+                    // functions as well as global ones
+                    let mut visible = scope.clone();
+                    for signal in &entity.signals {
+                        visible
+                            .signals
+                            .push(self.local(signal, &signal.name, signal.span));
+                    }
+                    for func in &entity.functions {
+                        visible
+                            .functions
+                            .push(self.local(func, &func.name, func.span));
+                    }
+                    // and those they inherit, but only their own bodies are
+                    // checked here
+                    visible.derived = entity.extends.is_some();
+                    if let Some(base) = base {
+                        for signal in &base.def.signals {
+                            if !entity.signals.iter().any(|s| s.name == signal.name) {
+                                visible.signals.push(self.member(
+                                    base,
+                                    signal,
+                                    &signal.name,
+                                    signal.span,
+                                ));
+                            }
+                        }
+                        for func in &base.def.functions {
+                            let inherited = self.member(base, func, &func.name, func.span);
+                            if !entity.functions.iter().any(|f| f.name == func.name) {
+                                visible.functions.push(inherited);
+                            }
+                            visible.supers.push(inherited);
+                        }
+                        visible.base = Some(base);
+                    }
+                    for func in &entity.functions {
+                        self.enter(func.span);
+                        self.locals.declare_entity(entity);
+                        self.params(&func.params);
+                        self.body(&func.body, &visible);
+                    }
+                }
+                Statement::FnDef(func) => {
+                    self.enter(func.span);
+                    self.params(&func.params);
+                    self.body(&func.body, &scope);
+                }
+                Statement::SystemDef(system) => {
+                    self.enter(system.span);
+                    self.params(&system.params);
+                    self.queries(system);
+                    self.body(&system.body, &scope);
+                }
+                Statement::StructDef(def) => self.defaults(def),
+                Statement::Import(_) | Statement::EnumDef(_) | Statement::InterfaceDef(_) => {}
+                stmt => {
+                    self.enter(Span {
+                        start: 0,
+                        end: self.source.len(),
+                    });
+                    self.body(std::slice::from_ref(stmt), &scope);
+                }
+            }
+        }
+        self.schedules(program);
+    }
+
+    /// Check the annotations saying when systems run, including those
+    /// entities inherit
+    fn schedules(&mut self, program: &Program) {
+        let flattened: HashMap<&str, EntityDef> = program
+            .statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::EntityDef(entity) => {
+                    let Some(Ok(base)) = self.inherited.get(&entity.name) else {
+                        return None;
+                    };
+                    let flattened = inheritance::inherit(entity, base.clone());
+                    Some((entity.name.as_str(), flattened))
+                }
+                _ => None,
+            })
+            .collect();
+        let plan = Plan::new(program, self.imports, |entity| {
+            flattened.get(entity.name.as_str()).unwrap_or(entity)
+        });
+
+        // Inherited annotations are reported once, where they are written
+        let mut reported = HashSet::new();
+        for error in plan.errors {
+            if self.unresolved.contains(problem_name(&error.problem)) {
+                continue;
+            }
+            let diagnostic = self.annotation(error.annotation, error.problem);
+            let span = diagnostic.location.span;
+            if reported.insert((span.start, span.end, diagnostic.message.clone())) {
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    fn annotation(&self, annotation: &Annotation, problem: Problem) -> Diagnostic {
+        let at = self.header(annotation.span);
+        let written = |name: &str| {
+            let text = &self.source[annotation.span.start..annotation.span.end];
+            let start = text.rfind(name).map_or(annotation.span.start, |offset| {
+                annotation.span.start + offset
+            });
+            Location::new(
+                self.source,
+                Span {
+                    start,
+                    end: start + name.len(),
+                },
+            )
+        };
+        let suggest = |diagnostic: &mut Diagnostic, name: &str, known: &[String]| {
+            if let Some(similar) = known
+                .iter()
+                .filter(|other| edit_distance(other, name) <= 2)
+                .min_by_key(|other| edit_distance(other, name))
+            {
+                diagnostic.fixes.push(Fix {
+                    message: format!("did you mean `{}`?", similar),
+                    location: diagnostic.location,
+                    replacement: similar.clone(),
+                });
+            }
+        };
+        let name = &annotation.name;
+        match problem {
+            Problem::NotASystem(func) => {
+                let message = format!("`@{}` can only annotate a system or `on_update`", name);
+                let mut diagnostic = Diagnostic::error("NX0023", message, at);
+                diagnostic
+                    .notes
+                    .push(format!("`{}` runs when it is called", func));
+                diagnostic
+            }
+            Problem::UnknownAnnotation => {
+                let location = self.name_location(annotation.span, name);
+                let message = format!("unknown annotation `@{}`", name);
+                let mut diagnostic = Diagnostic::error("NX0023", message, location);
+                let known: Vec<String> = ["schedule", "before", "after", "set", "run_if"]
+                    .map(String::from)
+                    .to_vec();
+                suggest(&mut diagnostic, name, &known);
+                diagnostic.notes.push(
+                    "systems take `@schedule`, `@before`, `@after`, `@set` and `@run_if`"
+                        .to_string(),
+                );
+                diagnostic
+            }
+            Problem::Arguments(expected) => {
+                let message = format!("`@{}` takes {}", name, expected);
+                Diagnostic::error("NX0023", message, at)
+            }
+            Problem::UnknownSchedule(schedule) => {
+                let message = format!("unknown schedule `{}`", schedule);
+                let mut diagnostic = Diagnostic::error("NX0023", message, written(&schedule));
+                let known: Vec<String> =
+                    schedules::SCHEDULES.iter().map(|s| s.to_string()).collect();
+                suggest(&mut diagnostic, &schedule, &known);
+                diagnostic
+            }
+            Problem::UnknownSystem(system, known) => {
+                let message = format!("unknown system or set `{}`", system);
+                let mut diagnostic = Diagnostic::error("NX0023", message, written(&system));
+                suggest(&mut diagnostic, &system, &known);
+                diagnostic.notes.push(
+                    "systems can be ordered against the systems and sets of their own file"
+                        .to_string(),
+                );
+                diagnostic
+            }
+            Problem::UnknownState(state, known) => {
+                let message = format!("unknown state `{}`", state);
+                let mut diagnostic = Diagnostic::error("NX0023", message, written(&state));
+                suggest(&mut diagnostic, &state, &known);
+                diagnostic
+            }
+            Problem::UnknownCondition(condition, known) => {
+                let message = format!("unknown run condition `{}`", condition);
+                let mut diagnostic = Diagnostic::error("NX0023", message, written(&condition));
+                suggest(&mut diagnostic, &condition, &known);
+                diagnostic
+            }
+            Problem::NotACondition(condition) => {
+                let message = format!("`{}` can't be a run condition", condition);
+                let mut diagnostic = Diagnostic::error("NX0023", message, written(&condition));
+                diagnostic
+                    .notes
+                    .push("run conditions take no parameters and return `bool`".to_string());
+                diagnostic
+            }
+            Problem::Schedules(system, other, schedule, other_schedule) => {
+                let message = format!(
+                    "`{}` runs in `{}` but `{}` runs in `{}`",
+                    system, schedule, other, other_schedule
+                );
+                let mut diagnostic = Diagnostic::error("NX0023", message, written(&other));
+                diagnostic.notes.push(
+                    "systems can only be ordered against others in the same schedule".to_string(),
+                );
+                diagnostic
+            }
+            Problem::Cycle(chain) => {
+                let message = format!("`{}` is ordered to run before itself", chain[0]);
+                let mut diagnostic = Diagnostic::error("NX0024", message, at);
+                diagnostic
+                    .notes
+                    .push(format!("each runs before the next: {}", chain.join(" -> ")));
+                diagnostic
+            }
+        }
+    }
+
+    /// Check that an entity declares or inherits everything an interface it
+    /// implements requires. `members` is the entity with what it inherits;
+    /// problems with inherited members are reported on the header.
+    fn implements(&mut self, entity: &EntityDef, members: &EntityDef, name: &str, scope: &Scope) {
+        let location = self.clause_location(entity, " implements ", name);
+        let interface = match name.split_once('.') {
+            Some((module, member)) => {
+                if self.unresolved.contains(module) {
+                    return;
+                }
+                let module = Expr::Identifier(module.to_string());
+                match self.imported(&module, member).map(|imported| imported.item) {
+                    Some(Item::Interface(def)) => self.visible(def, &module, member),
+                    _ => None,
+                }
+            }
+            None => {
+                if self.unresolved.contains(name) {
+                    return;
+                }
+                scope
+                    .interfaces
+                    .iter()
+                    .find(|i| i.def.name == name)
+                    .copied()
+            }
+        };
+        let Some(interface) = interface else {
+            let message = format!("unknown interface `{}`", name);
+            let mut diagnostic = Diagnostic::error("NX0017", message, location);
+            let declared: Vec<&str> = scope
+                .interfaces
+                .iter()
+                .map(|i| i.def.name.as_str())
+                .collect();
+            if let Some(similar) = declared
+                .iter()
+                .filter(|declared| edit_distance(declared, name) <= 2)
+                .min_by_key(|declared| edit_distance(declared, name))
+            {
+                diagnostic.fixes.push(Fix {
+                    message: format!("did you mean `{}`?", similar),
+                    location,
+                    replacement: similar.to_string(),
+                });
+            }
+            self.diagnostics.push(diagnostic);
+            return;
+        };
+
+        let def = interface.def;
+        let mut problems = Vec::new();
+        for required in &def.signals {
+            let expected = signal_signature(required);
+            match members.signals.iter().find(|s| s.name == required.name) {
+                None => problems.push((
+                    location,
+                    format!("`{}` is missing `{}`", entity.name, expected),
+                )),
+                Some(signal) if signal_signature(signal) != expected => {
+                    let location = match entity.signals.iter().any(|s| s.name == signal.name) {
+                        true => self.name_location(signal.span, &signal.name),
+                        false => location,
+                    };
+                    problems.push((
+                        location,
+                        format!("`{}` doesn't match `{}`", signal.name, expected),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        for required in &def.functions {
+            let expected = required.signature();
+            match members.functions.iter().find(|f| f.name == required.name) {
+                None => problems.push((
+                    location,
+                    format!("`{}` is missing `{}`", entity.name, expected),
+                )),
+                Some(func) if !same_signature(func, required) => {
+                    let location = match entity.functions.iter().any(|f| f.name == func.name) {
+                        true => self.name_location(func.span, &func.name),
+                        false => location,
+                    };
+                    problems.push((
+                        location,
+                        format!("`{}` doesn't match `{}`", func.name, expected),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        for (location, message) in problems {
+            let mut diagnostic = Diagnostic::error("NX0018", message, location);
+            diagnostic
+                .notes
+                .push(format!("required by interface `{}`", def.name));
+            diagnostic
+                .related
+                .push(interface.declared_here("interface"));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Declare a function's parameters, and check that query filters
+    /// (`with Player`) are only used directly in a `Query`, and that each
+    /// query yields a component
+    fn params(&mut self, params: &[Param]) {
+        for param in params {
+            self.locals.declare(&param.name, param.type_expr.clone());
+            let location = Location::new(self.source, param.span);
+            let types: Vec<&TypeExpr> = match (&param.type_expr, Query::of(&param.type_expr)) {
+                (TypeExpr::Generic { params, .. }, Some(query)) => {
+                    if query.components.is_empty() {
+                        let message = format!("`{}` doesn't yield any components", param.name);
+                        let mut diagnostic = Diagnostic::error("NX0022", message, location);
+                        diagnostic.notes.push(
+                            "list the components to yield before the filters: `Query<Transform, with Player>`"
+                                .to_string(),
+                        );
+                        self.diagnostics.push(diagnostic);
+                    }
+                    // What filters and components are written with
+                    params
+                        .iter()
+                        .flat_map(|t| match t {
+                            TypeExpr::Generic { name, params } if is_filter(name) => {
+                                params.iter().collect()
+                            }
+                            t => vec![t],
+                        })
+                        .collect()
+                }
+                (type_expr, _) => vec![type_expr],
+            };
+            if let Some(filter) = types.into_iter().find_map(misplaced_filter) {
+                let message = format!("`{}` is only allowed in a `Query`", filter);
+                self.diagnostics
+                    .push(Diagnostic::error("NX0022", message, location));
+            }
+        }
+    }
+
+    /// Check that each loop over one of a system's queries names every
+    /// component the query yields
+    fn queries(&mut self, system: &SystemDef) {
+        for (name, for_stmt) in queries::loops(&system.body) {
+            let Some(query) = system
+                .params
+                .iter()
+                .find(|p| p.name == name)
+                .and_then(|p| Query::of(&p.type_expr))
+            else {
+                continue;
+            };
+            let (yields, names) = (query.components.len(), for_stmt.names.len());
+            if yields == names {
+                continue;
+            }
+            let message = format!(
+                "`{}` yields {} but the loop has {}",
+                name,
+                plural(yields, "component"),
+                plural(names, "name")
+            );
+            let mut diagnostic = Diagnostic::error("NX0022", message, self.header(for_stmt.span));
+            let components: Vec<String> = query.components.iter().map(|c| c.to_string()).collect();
+            diagnostic
+                .notes
+                .push(format!("`{}` yields {}", name, components.join(", ")));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Report a base entity that can't be found or leads back to the entity
+    fn extends(&mut self, entity: &EntityDef, error: &inheritance::Error, entities: &[&str]) {
+        let diagnostic = match error {
+            inheritance::Error::Unknown(name) => {
+                let module = name
+                    .split_once('.')
+                    .map_or(name.as_str(), |(module, _)| module);
+                if self.unresolved.contains(module) {
+                    return;
+                }
+                let location = self.clause_location(entity, " extends ", name);
+                let message = format!("unknown base entity `{}`", name);
+                let mut diagnostic = Diagnostic::error("NX0019", message, location);
+                if let Some(similar) = entities
+                    .iter()
+                    .filter(|other| **other != entity.name)
+                    .filter(|other| edit_distance(other, name) <= 2)
+                    .min_by_key(|other| edit_distance(other, name))
+                {
+                    diagnostic.fixes.push(Fix {
+                        message: format!("did you mean `{}`?", similar),
+                        location,
+                        replacement: similar.to_string(),
+                    });
+                }
+                diagnostic
+            }
+            // Entities whose bases reach a cycle they aren't part of are
+            // only reported through the entities in it
+            inheritance::Error::Cycle(chain) if chain.first() == chain.last() => {
+                let location = self.header(entity.span);
+                let message = format!("`{}` extends itself", entity.name);
+                let mut diagnostic = Diagnostic::error("NX0019", message, location);
+                if chain.len() > 2 {
+                    diagnostic
+                        .notes
+                        .push(format!("inheritance chain: {}", chain.join(" -> ")));
+                }
+                diagnostic
+            }
+            inheritance::Error::Cycle(_) => return,
+        };
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Check that the functions and signals an entity overrides keep the
+    /// signature they have in its base
+    fn overrides(&mut self, entity: &EntityDef, base: Visible<EntityDef>) {
+        let mut problems = Vec::new();
+        for signal in &entity.signals {
+            let Some(inherited) = base.def.signals.iter().find(|s| s.name == signal.name) else {
+                continue;
+            };
+            let expected = signal_signature(inherited);
+            if signal_signature(signal) != expected {
+                problems.push((signal.span, &signal.name, expected));
+            }
+        }
+        for func in &entity.functions {
+            let Some(inherited) = base.def.functions.iter().find(|f| f.name == func.name) else {
+                continue;
+            };
+            if !same_signature(func, inherited) {
+                problems.push((func.span, &func.name, inherited.signature()));
+            }
+        }
+        for (span, name, expected) in problems {
+            let location = self.name_location(span, name);
+            let message = format!(
+                "`{}` doesn't match `{}` from `{}`",
+                name, expected, base.def.name
+            );
+            let mut diagnostic = Diagnostic::error("NX0020", message, location);
+            diagnostic.related.push(base.declared_here("base entity"));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// The flattened base of an entity that extends another, if it was found
+    fn base(&self, entity: &EntityDef) -> Option<Visible<'a, EntityDef>> {
+        let extends = entity.extends.as_deref()?;
+        let Ok(def) = self.inherited.get(&entity.name)? else {
+            return None;
+        };
+        let found = self.bases.get(extends)?;
+        let (file, location) = match found.imported {
+            Some(imported) => (imported.file, imported.location),
+            None => (
+                "",
+                self.name_location(found.entity.span, &found.entity.name),
+            ),
+        };
+        Some(Visible {
+            def,
+            file,
+            location,
+        })
+    }
+
+    /// A member an entity inherits from `base`. Members of imported bases
+    /// are located at the base.
+    fn member<T>(
+        &self,
+        base: Visible<'a, EntityDef>,
+        def: &'a T,
+        name: &str,
+        span: Span,
+    ) -> Visible<'a, T> {
+        match base.file {
+            "" => self.local(def, name, span),
+            file => Visible {
+                def,
+                file,
+                location: base.location,
+            },
+        }
+    }
+
+    /// Where an entity's header names a base or an interface after
+    /// `clause` (` extends `, ` implements `)
+    fn clause_location(&self, entity: &EntityDef, clause: &str, name: &str) -> Location {
+        let header = self.header(entity.span);
+        let text = &self.source[entity.span.start..entity.span.end];
+        let text = text.lines().next().unwrap_or_default();
+        let Some(start) = text
+            .find(clause)
+            .and_then(|clause| Some(clause + text[clause..].find(name)?))
+        else {
+            return header;
+        };
+        let start = entity.span.start + start;
+        Location::new(
+            self.source,
+            Span {
+                start,
+                end: start + name.len(),
+            },
+        )
+    }
+
+    /// Each interface an entity's header names after ` implements `, with
+    /// where it is written
+    fn interfaces<'e>(&self, entity: &'e EntityDef) -> Vec<(&'e str, Span)> {
+        let header = self.header(entity.span).span;
+        let text = &self.source[header.start..header.end];
+        let mut from = text.find(" implements ").unwrap_or(text.len());
+        entity
+            .implements
+            .iter()
+            .map(|name| {
+                let Some(start) = text.get(from..).and_then(|rest| rest.find(name.as_str())) else {
+                    return (name.as_str(), header);
+                };
+                let start = from + start;
+                from = start + name.len();
+                let span = Span {
+                    start: header.start + start,
+                    end: header.start + from,
+                };
+                (name.as_str(), span)
+            })
+            .collect()
+    }
+
+    /// Define what an import brings in, and make imported signals and
+    /// functions visible
+    fn import(&mut self, import: &'a ImportStmt, names: &mut Definitions, scope: &mut Scope<'a>) {
+        if import.names.is_empty() {
+            names.define(self, import.module_name(), import.span);
+            if !self.imports.modules.contains_key(import.module_name()) {
+                self.unresolved.insert(import.module_name());
+            }
+            return;
+        }
+
+        for name in &import.names {
+            names.define(self, name, import.span);
+            match self.imports.names.get(name) {
+                Some(imported) => scope.add(imported),
+                None => {
+                    self.unresolved.insert(name);
+                }
+            }
+        }
+    }
+
+    fn local<T>(&self, def: &'a T, name: &str, span: Span) -> Visible<'a, T> {
+        Visible {
+            def,
+            file: "",
+            location: self.name_location(span, name),
+        }
+    }
+
+    /// Start checking a new function
+    fn enter(&mut self, span: Span) {
+        self.scope = span;
+        self.calls.clear();
+        self.loops = 0;
+        self.locals = TypeEnv::new();
+    }
+
+    fn body(&mut self, body: &[Statement], scope: &Scope) {
+        for stmt in body {
+            match stmt {
+                Statement::Emit(emit) => {
+                    self.emit(emit, scope);
+                    for arg in &emit.args {
+                        self.expr(arg, scope);
+                    }
+                }
+                Statement::VarDecl(var) => {
+                    self.expr(&var.value, scope);
+                    let declared = var
+                        .type_expr
+                        .clone()
+                        .or_else(|| infer_type_in(&var.value, &self.locals));
+                    if let Some(declared) = declared {
+                        self.locals.declare(&var.name, declared);
+                    }
+                }
+                Statement::Assignment(assign) => self.expr(&assign.value, scope),
+                Statement::Return(Some(value)) | Statement::Expr { expr: value, .. } => {
+                    self.expr(value, scope)
+                }
+                Statement::If(if_stmt) => {
+                    self.expr(&if_stmt.condition, scope);
+                    self.body(&if_stmt.then_body, scope);
+                    for (condition, clause) in &if_stmt.elif_clauses {
+                        self.expr(condition, scope);
+                        self.body(clause, scope);
+                    }
+                    if let Some(else_body) = &if_stmt.else_body {
+                        self.body(else_body, scope);
+                    }
+                }
+                Statement::Match(match_stmt) => self.match_stmt(match_stmt, scope),
+                Statement::While(while_stmt) => {
+                    self.expr(&while_stmt.condition, scope);
+                    self.loop_body(&while_stmt.body, scope);
+                }
+                Statement::For(for_stmt) => {
+                    self.expr(&for_stmt.iterable, scope);
+                    self.loop_names(for_stmt);
+                    self.loop_body(&for_stmt.body, scope);
+                }
+                Statement::Break { span } | Statement::Continue { span } if self.loops == 0 => {
+                    let keyword = if matches!(stmt, Statement::Break { .. }) {
+                        "break"
+                    } else {
+                        "continue"
+                    };
+                    let message = format!("`{}` outside of a loop", keyword);
+                    let location = Location::new(self.source, *span);
+                    self.diagnostics
+                        .push(Diagnostic::error("NX0016", message, location));
+                }
+                // Nested functions and states aren't inside the loops around
+                // them; calls are still found in source order
+                Statement::FnDef(func) => {
+                    let loops = std::mem::take(&mut self.loops);
+                    let locals = std::mem::take(&mut self.locals);
+                    self.params(&func.params);
+                    self.body(&func.body, scope);
+                    self.loops = loops;
+                    self.locals = locals;
+                }
+                Statement::StateMachine(machine) => {
+                    let loops = std::mem::take(&mut self.loops);
+                    for state in &machine.states {
+                        self.body(&state.body, scope);
+                    }
+                    self.loops = loops;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn loop_body(&mut self, body: &[Statement], scope: &Scope) {
+        self.loops += 1;
+        self.body(body, scope);
+        self.loops -= 1;
+    }
+
+    /// Check a match's patterns against the enums they name and the
+    /// subject's type, then which of its arms can run and whether every
+    /// value is matched
+    fn match_stmt(&mut self, match_stmt: &MatchStmt, scope: &Scope) {
+        self.expr(&match_stmt.subject, scope);
+        let subject = infer_type_in(&match_stmt.subject, &self.locals);
+        let mut enums = Enums::new();
+        for arm in &match_stmt.arms {
+            self.pattern(&arm.pattern, arm.span, scope, &mut enums);
+            if let Some(subject) = &subject {
+                if let Some(message) = pattern_mismatch(&arm.pattern, subject) {
+                    let location = self.header(arm.span);
+                    let mut diagnostic = Diagnostic::error("NX0030", message, location);
+                    diagnostic
+                        .notes
+                        .push(format!("the `match` subject has type `{}`", subject));
+                    self.diagnostics.push(diagnostic);
+                }
+                self.locals.declare_pattern(&arm.pattern, Some(subject));
+            }
+            if let Some(guard) = &arm.guard {
+                self.expr(guard, scope);
+            }
+            self.body(&arm.body, scope);
+        }
+
+        let arms: Vec<(&Pattern, bool)> = match_stmt
+            .arms
+            .iter()
+            .map(|arm| (&arm.pattern, arm.guard.is_some()))
+            .collect();
+        let coverage = exhaustiveness::check(&arms, &enums);
+        for i in coverage.unreachable {
+            let location = self.header(match_stmt.arms[i].span);
+            let mut diagnostic = Diagnostic::warning("NX0015", "unreachable `case`", location);
+            diagnostic
+                .notes
+                .push("every value it matches is matched by an earlier `case`".to_string());
+            self.diagnostics.push(diagnostic);
+        }
+        if let Some(missing) = coverage.missing {
+            let message = format!("`match` doesn't cover `{}`", missing);
+            let location = self.header(match_stmt.span);
+            let mut diagnostic = Diagnostic::error("NX0014", message, location);
+            let note = match missing.to_string().as_str() {
+                "_" => "add a `case _:` arm to match everything else".to_string(),
+                missing => format!(
+                    "add a `case {}:` arm, or a `case _:` arm to match everything else",
+                    missing
+                ),
+            };
+            diagnostic.notes.push(note);
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Check the variants a pattern names, and record their enums for the
+    /// coverage check
+    fn pattern(&mut self, pattern: &Pattern, span: Span, scope: &Scope, enums: &mut Enums) {
+        match pattern {
+            Pattern::Variant { path, fields } => {
+                for field in fields {
+                    self.pattern(field, span, scope, enums);
+                }
+                let Some((name, enum_path)) = path.split_last() else {
+                    return;
+                };
+                let mut parts = enum_path.iter().cloned();
+                let Some(first) = parts.next() else {
+                    return;
+                };
+                let base = parts.fold(Expr::Identifier(first), |base, part| {
+                    Expr::MemberAccess(Box::new(base), part)
+                });
+                let Some(def) = self.enum_of(&base, scope) else {
+                    return;
+                };
+                enums.entry(enum_path.join(".")).or_insert_with(|| {
+                    def.def
+                        .variants
+                        .iter()
+                        .map(|variant| (variant.name.clone(), variant.fields.len()))
+                        .collect()
+                });
+
+                let location = self.name_location(span, name);
+                match def.def.variants.iter().find(|v| v.name == *name) {
+                    None => self.no_variant(def, name, location),
+                    Some(variant) if variant.fields.len() != fields.len() => {
+                        let message = format!(
+                            "`{}.{}` has {} but the pattern has {}",
+                            def.def.name,
+                            name,
+                            plural(variant.fields.len(), "field"),
+                            fields.len()
+                        );
+                        let mut diagnostic = Diagnostic::error("NX0009", message, location);
+                        diagnostic.related.push(def.declared_here("enum"));
+                        self.diagnostics.push(diagnostic);
+                    }
+                    Some(_) => {}
+                }
+            }
+            Pattern::Tuple(items) => {
+                for item in items {
+                    self.pattern(item, span, scope, enums);
+                }
+            }
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let (Some(start), Some(end)) = (number(start), number(end)) else {
+                    return;
+                };
+                if start < end || (start == end && *inclusive) {
+                    return;
+                }
+                let location = self.header(span);
+                let mut diagnostic = Diagnostic::error("NX0025", "empty range pattern", location);
+                diagnostic.notes.push(
+                    "a range's start must be below its end, or equal to it with `..=`".to_string(),
+                );
+                self.diagnostics.push(diagnostic);
+            }
+            _ => {}
+        }
+    }
+
+    /// The first line of a block statement, without its comment
+    fn header(&self, span: Span) -> Location {
+        let text = &self.source[span.start..span.end];
+        let line = strip_comment(text.lines().next().unwrap_or_default()).trim_end();
+        let span = Span {
+            start: span.start,
+            end: span.start + line.len(),
+        };
+        Location::new(self.source, span)
+    }
+
+    /// Check the calls in an expression, in source order
+    fn expr(&mut self, expr: &Expr, scope: &Scope) {
+        match expr {
+            Expr::Call { callee, args } => {
+                self.call(callee, args, scope);
+                if let Expr::MemberAccess(base, _) = &**callee {
+                    self.expr(base, scope);
+                }
+                for arg in args {
+                    self.expr(&arg.value, scope);
+                }
+            }
+            Expr::MemberAccess(base, name) => {
+                if let Some(def) = self.enum_of(base, scope) {
+                    if !def.def.variants.iter().any(|v| v.name == *name) {
+                        self.unknown_variant(def, base, name);
+                    }
+                }
+                self.module_member(base, name);
+                self.expr(base, scope);
+            }
+            Expr::Index(base, index) => {
+                self.expr(base, scope);
+                self.expr(index, scope);
+            }
+            Expr::BinaryOp(left, _, right) => {
+                self.expr(left, scope);
+                self.expr(right, scope);
+            }
+            Expr::UnaryOp(_, operand) => self.expr(operand, scope),
+            Expr::Vec2(x, y) => {
+                self.expr(x, scope);
+                self.expr(y, scope);
+            }
+            Expr::Vec3(x, y, z) => {
+                self.expr(x, scope);
+                self.expr(y, scope);
+                self.expr(z, scope);
+            }
+            Expr::List(items) | Expr::Tuple(items) => {
+                for item in items {
+                    self.expr(item, scope);
+                }
+            }
+            Expr::Map(entries) => {
+                for (_, value) in entries {
+                    self.expr(value, scope);
+                }
+            }
+            Expr::Lambda { body, .. } => self.expr(body, scope),
+            _ => {}
+        }
+    }
+
+    fn emit(&mut self, emit: &EmitStmt, scope: &Scope) {
+        if self.unresolved.contains(emit.signal_name.as_str()) {
+            return;
+        }
+        let signals = &scope.signals;
+        let name = self.name_location(emit.span, &emit.signal_name);
+        let Some(signal) = signals.iter().find(|s| s.def.name == emit.signal_name) else {
+            let message = format!("unknown signal `{}`", emit.signal_name);
+            let mut diagnostic = Diagnostic::error("NX0004", message, name);
+            if signals.is_empty() {
+                diagnostic
+                    .notes
+                    .push("no signals are declared here".to_string());
+            } else {
+                let declared: Vec<&str> = signals.iter().map(|s| s.def.name.as_str()).collect();
+                diagnostic
+                    .notes
+                    .push(format!("declared signals: {}", declared.join(", ")));
+            }
+            if let Some(similar) = signals
+                .iter()
+                .filter(|s| edit_distance(&s.def.name, &emit.signal_name) <= 2)
+                .min_by_key(|s| edit_distance(&s.def.name, &emit.signal_name))
+            {
+                diagnostic.fixes.push(Fix {
+                    message: format!("did you mean `{}`?", similar.def.name),
+                    location: name,
+                    replacement: similar.def.name.clone(),
+                });
+            }
+            self.diagnostics.push(diagnostic);
+            return;
+        };
+
+        let params = &signal.def.params;
+        if params.len() != emit.args.len() {
+            let message = format!(
+                "signal `{}` takes {} but {} given",
+                signal.def.name,
+                plural(params.len(), "argument"),
+                given(emit.args.len()),
+            );
+            let location = Location::new(self.source, emit.span);
+            let mut diagnostic = Diagnostic::error("NX0005", message, location);
+            diagnostic.related.push(signal.declared_here("signal"));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Check the number of arguments passed to a built-in function
+    fn builtin(&mut self, name: &str, count: usize, location: Location) {
+        let Some(&(_, min, max)) = BUILTINS.iter().find(|(builtin, ..)| *builtin == name) else {
+            return;
+        };
+        if (min..=max).contains(&count) {
+            return;
+        }
+        let expected = if min == max {
+            plural(min, "argument")
+        } else {
+            format!("{} to {} arguments", min, max)
+        };
+        let message = format!("`{}` takes {} but {} given", name, expected, given(count));
+        self.diagnostics
+            .push(Diagnostic::error("NX0009", message, location));
+    }
+
+    /// Check that a loop over a built-in names what it yields
+    fn loop_names(&mut self, for_stmt: &ForStmt) {
+        let Expr::Call { callee, .. } = &for_stmt.iterable else {
+            return;
+        };
+        let Expr::Identifier(name) = &**callee else {
+            return;
+        };
+        let Some(&(_, count, yields, example)) = LOOP_NAMES.iter().find(|(n, ..)| n == name) else {
+            return;
+        };
+        let names = for_stmt.names.len();
+        if names == count {
+            return;
+        }
+        let message = format!(
+            "`{}` yields {} but the loop has {}",
+            name,
+            yields,
+            plural(names, "name")
+        );
+        let mut diagnostic = Diagnostic::error("NX0028", message, self.header(for_stmt.span));
+        diagnostic.notes.push(format!("for example `{}`", example));
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Check that a `range(start, end, step)` with a literal step can count
+    /// from its start to its end
+    fn range_step(&mut self, args: &[Arg], spans: &[Span]) {
+        let ([start, end, step], Some(&span)) = (args, spans.get(2)) else {
+            return;
+        };
+        let Some(step) = number(&step.value) else {
+            return;
+        };
+        let location = Location::new(self.source, span);
+        if step == 0.0 {
+            self.diagnostics.push(Diagnostic::error(
+                "NX0025",
+                "`range` can't step by 0",
+                location,
+            ));
+            return;
+        }
+        let (Some(from), Some(to)) = (number(&start.value), number(&end.value)) else {
+            return;
+        };
+        if step < 0.0 && from <= to {
+            let message = format!("`range` counts down but {} is below {}", from, to);
+            let mut diagnostic = Diagnostic::error("NX0025", message, location);
+            diagnostic
+                .notes
+                .push("a negative step needs a start above the end".to_string());
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Check a call's arguments against the function it calls, if it is
+    /// one of the file's functions or an imported one, or against the
+    /// fields of the struct or enum variant it constructs
+    fn call(&mut self, callee: &Expr, args: &[Arg], scope: &Scope) {
+        if let Some(def) = self.struct_of(callee, scope) {
+            let site = self.call_site(&def.def.name);
+            let fields: Vec<Field> = def
+                .def
+                .fields
+                .iter()
+                .map(|f| (f.name.as_str(), &f.type_expr, f.default.is_some()))
+                .collect();
+            if let Some(site) = site {
+                let declared = def.declared_here("struct");
+                self.construct(&def.def.name, &fields, declared, args, site);
+            }
+            return;
+        }
+        if let Expr::MemberAccess(base, name) = callee {
+            if let Some(def) = self.enum_of(base, scope) {
+                let site = self.call_site(name);
+                let Some(variant) = def.def.variants.iter().find(|v| v.name == *name) else {
+                    self.unknown_variant(def, base, name);
+                    return;
+                };
+                let fields: Vec<Field> = variant
+                    .fields
+                    .iter()
+                    .map(|f| (f.name.as_str(), &f.type_expr, false))
+                    .collect();
+                if let Some(site) = site {
+                    let what = format!("{}.{}", def.def.name, variant.name);
+                    let declared = def.declared_here("enum");
+                    self.construct(&what, &fields, declared, args, site);
+                }
+                return;
+            }
+        }
+
+        let (name, func) = match callee {
+            Expr::Identifier(name) => {
+                let func = scope.functions.iter().find(|f| f.def.name == *name);
+                (name, func.copied())
+            }
+            Expr::MemberAccess(base, name) => {
+                if matches!(&**base, Expr::Identifier(base) if base == "super") {
+                    let func = scope.supers.iter().find(|f| f.def.name == *name).copied();
+                    if func.is_none() {
+                        self.no_super(base, name, scope);
+                    }
+                    let site = self.call_site(name);
+                    if let (Some(func), Some((location, arg_spans))) = (func, site) {
+                        self.arguments(func, args, location, arg_spans);
+                    }
+                    return;
+                }
+                self.module_member(base, name);
+                let func = self
+                    .imported(base, name)
+                    .and_then(|imported| match imported.item {
+                        Item::Function(def) => Some(Visible {
+                            def,
+                            file: imported.file,
+                            location: imported.location,
+                        }),
+                        _ => None,
+                    });
+                (name, func)
+            }
+            _ => return,
+        };
+        // Count every call so the next one with this name is found
+        let site = self.call_site(name);
+        if let (None, Expr::MemberAccess(list, method), [arg], Some((_, spans))) =
+            (func, callee, args, &site)
+        {
+            if let Some(expected) = list_callback(list, method) {
+                self.lambda(&arg.value, &expected, spans[0]);
+            }
+        }
+        if func.is_none() && matches!(callee, Expr::Identifier(_)) {
+            if let Some((location, spans)) = site {
+                self.builtin(name, args.len(), location);
+                if name == "range" {
+                    self.range_step(args, &spans);
+                }
+            }
+            return;
+        }
+        let (Some(func), Some((location, arg_spans))) = (func, site) else {
+            return;
+        };
+        self.arguments(func, args, location, arg_spans);
+    }
+
+    /// Report a `super.name()` call that has no base function to call
+    fn no_super(&mut self, base: &Expr, name: &str, scope: &Scope) {
+        // A base that wasn't found is reported on the entity
+        if scope.derived && scope.base.is_none() {
+            return;
+        }
+        let Some(location) = self.member_location(base, name) else {
+            return;
+        };
+        let Some(base) = scope.base else {
+            let message = "`super` outside of an entity that extends another";
+            self.diagnostics
+                .push(Diagnostic::error("NX0021", message, location));
+            return;
+        };
+        let message = format!("`{}` has no function `{}`", base.def.name, name);
+        let mut diagnostic = Diagnostic::error("NX0021", message, location);
+        if let Some(similar) = scope
+            .supers
+            .iter()
+            .map(|f| f.def.name.as_str())
+            .filter(|other| edit_distance(other, name) <= 2)
+            .min_by_key(|other| edit_distance(other, name))
+        {
+            diagnostic.fixes.push(Fix {
+                message: format!("did you mean `{}`?", similar),
+                location,
+                replacement: similar.to_string(),
+            });
+        }
+        diagnostic.related.push(base.declared_here("base entity"));
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Check a call's arguments against the parameters of the function it
+    /// calls
+    fn arguments(
+        &mut self,
+        func: Visible<FnDef>,
+        args: &[Arg],
+        location: Location,
+        arg_spans: Vec<Span>,
+    ) {
+        let params = &func.def.params;
+        if params.len() != args.len() {
+            let message = format!(
+                "`{}` takes {} but {} given",
+                func.def.name,
+                plural(params.len(), "argument"),
+                given(args.len()),
+            );
+            let mut diagnostic = Diagnostic::error("NX0009", message, location);
+            diagnostic.related.push(func.declared_here("function"));
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+
+        for ((arg, param), span) in args.iter().zip(params).zip(arg_spans) {
+            if let (Expr::Lambda { .. }, TypeExpr::Generic { name, params }) =
+                (&arg.value, &param.type_expr)
+            {
+                if name == "Fn" {
+                    self.lambda(&arg.value, params, span);
+                    continue;
+                }
+            }
+            let Some(found) = infer_type(&arg.value) else {
+                continue;
+            };
+            if accepts(&param.type_expr, &found) {
+                continue;
+            }
+            let message = format!("expected `{}`, found `{}`", param.type_expr, found);
+            let location = Location::new(self.source, span);
+            let mut diagnostic = Diagnostic::error("NX0010", message, location);
+            diagnostic.notes.push(format!(
+                "parameter `{}` of `{}` is `{}`",
+                param.name, func.def.name, param.type_expr
+            ));
+            diagnostic.related.push(func.declared_here("function"));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Check a lambda passed where a `Fn<params..., return>` is expected:
+    /// how many parameters it takes, their types, and its body with the
+    /// parameters typed
+    fn lambda(&mut self, lambda: &Expr, expected: &[TypeExpr], span: Span) {
+        let Expr::Lambda {
+            params,
+            return_type,
+            body,
+        } = lambda
+        else {
+            return;
+        };
+        let Some((returns, takes)) = expected.split_last() else {
+            return;
+        };
+        let location = Location::new(self.source, span);
+        let expected = TypeExpr::Generic {
+            name: "Fn".to_string(),
+            params: expected.to_vec(),
+        };
+        if params.len() != takes.len() {
+            let message = format!(
+                "expected a function taking {}, found one taking {}",
+                plural(takes.len(), "argument"),
+                params.len()
+            );
+            let mut diagnostic = Diagnostic::error("NX0009", message, location);
+            diagnostic.notes.push(format!("expected `{}`", expected));
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+
+        let mut env = TypeEnv::new();
+        for (param, takes) in params.iter().zip(takes) {
+            let Some(declared) = &param.type_expr else {
+                env.declare(&param.name, takes.clone());
+                continue;
+            };
+            env.declare(&param.name, declared.clone());
+            if !accepts(declared, takes) {
+                let message = format!("expected `{}`, found `{}`", takes, declared);
+                let mut diagnostic = Diagnostic::error("NX0010", message, location);
+                diagnostic.notes.push(format!(
+                    "parameter `{}` is passed a `{}` by `{}`",
+                    param.name, takes, expected
+                ));
+                self.diagnostics.push(diagnostic);
+            }
+        }
+
+        if self.operands(body, &env, location) {
+            return;
+        }
+        let Some(found) = return_type.clone().or_else(|| infer_type_in(body, &env)) else {
+            return;
+        };
+        let nothing = matches!(returns, TypeExpr::Simple(t) if t == "None");
+        if !nothing && !accepts(returns, &found) {
+            let message = format!("expected `{}`, found `{}`", returns, found);
+            let mut diagnostic = Diagnostic::error("NX0010", message, location);
+            diagnostic.notes.push(format!(
+                "the function must return `{}` to be a `{}`",
+                returns, expected
+            ));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Report the first arithmetic operator in `expr` applied to types that
+    /// don't go together. Returns whether there was one.
+    fn operands(&mut self, expr: &Expr, env: &TypeEnv, location: Location) -> bool {
+        match expr {
+            Expr::BinaryOp(left, op, right) => {
+                if self.operands(left, env, location) || self.operands(right, env, location) {
+                    return true;
+                }
+                let symbol = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    BinaryOp::Div => "/",
+                    BinaryOp::Mod => "%",
+                    _ => return false,
+                };
+                let (Some(l), Some(r)) = (infer_type_in(left, env), infer_type_in(right, env))
+                else {
+                    return false;
+                };
+                let (TypeExpr::Simple(lname), TypeExpr::Simple(rname)) = (&l, &r) else {
+                    return false;
+                };
+                let strings = (lname == "str") as u8 + (rname == "str") as u8;
+                let mismatched = lname == "bool"
+                    || rname == "bool"
+                    || strings == 1
+                    || (strings == 2 && *op != BinaryOp::Add);
+                let known = |t: &str| BUILTIN_TYPES.contains(&t);
+                if !(mismatched && known(lname) && known(rname)) {
+                    return false;
+                }
+                let message = format!("can't apply `{}` to `{}` and `{}`", symbol, l, r);
+                self.diagnostics
+                    .push(Diagnostic::error("NX0029", message, location));
+                true
+            }
+            Expr::UnaryOp(_, operand) => self.operands(operand, env, location),
+            Expr::Call { args, .. } => args
+                .iter()
+                .any(|arg| self.operands(&arg.value, env, location)),
+            _ => false,
+        }
+    }
+
+    /// Check a constructor's arguments against the fields of `what`:
+    /// named arguments set the field they name, the rest fill the fields in
+    /// order, and fields left out must have a default
+    fn construct(
+        &mut self,
+        what: &str,
+        fields: &[Field],
+        declared: Related,
+        args: &[Arg],
+        (location, arg_spans): (Location, Vec<Span>),
+    ) {
+        let positional = args.iter().filter(|arg| arg.name.is_none()).count();
+        if positional > fields.len() {
+            let message = format!(
+                "`{}` has {} but {} given",
+                what,
+                plural(fields.len(), "field"),
+                given(positional),
+            );
+            let mut diagnostic = Diagnostic::error("NX0009", message, location);
+            diagnostic.related.push(declared);
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+
+        let mut set = vec![false; fields.len()];
+        for (i, (arg, span)) in args.iter().zip(arg_spans).enumerate() {
+            let field = match &arg.name {
+                Some(name) => fields.iter().position(|(field, ..)| field == name),
+                None => Some(i),
+            };
+            let Some(field) = field else {
+                let name = arg.name.as_deref().unwrap_or_default();
+                let message = format!("`{}` has no field `{}`", what, name);
+                let name_location = self.name_location(span, name);
+                let mut diagnostic = Diagnostic::error("NX0011", message, name_location);
+                let names: Vec<&str> = fields.iter().map(|(field, ..)| *field).collect();
+                if names.is_empty() {
+                    diagnostic.notes.push(format!("`{}` has no fields", what));
+                } else {
+                    diagnostic
+                        .notes
+                        .push(format!("fields: {}", names.join(", ")));
+                }
+                if let Some(similar) = names
+                    .iter()
+                    .filter(|field| edit_distance(field, name) <= 2)
+                    .min_by_key(|field| edit_distance(field, name))
+                {
+                    diagnostic.fixes.push(Fix {
+                        message: format!("did you mean `{}`?", similar),
+                        location: name_location,
+                        replacement: similar.to_string(),
+                    });
+                }
+                diagnostic.related.push(declared.clone());
+                self.diagnostics.push(diagnostic);
+                continue;
+            };
+            set[field] = true;
+
+            let (name, expected, _) = fields[field];
+            let Some(found) = infer_type(&arg.value) else {
+                continue;
+            };
+            if accepts(expected, &found) {
+                continue;
+            }
+            let message = format!("expected `{}`, found `{}`", expected, found);
+            let mut diagnostic =
+                Diagnostic::error("NX0010", message, Location::new(self.source, span));
+            diagnostic
+                .notes
+                .push(format!("field `{}` of `{}` is `{}`", name, what, expected));
+            diagnostic.related.push(declared.clone());
+            self.diagnostics.push(diagnostic);
+        }
+
+        let missing: Vec<String> = fields
+            .iter()
+            .zip(set)
+            .filter(|((_, _, default), set)| !default && !set)
+            .map(|((name, ..), _)| format!("`{}`", name))
+            .collect();
+        if !missing.is_empty() {
+            let message = format!(
+                "missing {} {} in `{}`",
+                if missing.len() == 1 {
+                    "field"
+                } else {
+                    "fields"
+                },
+                missing.join(", "),
+                what
+            );
+            let mut diagnostic = Diagnostic::error("NX0012", message, location);
+            diagnostic.related.push(declared);
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Check that a struct's field defaults have the fields' types
+    fn defaults(&mut self, def: &StructDef) {
+        for field in &def.fields {
+            let Some(found) = field.default.as_ref().and_then(infer_type) else {
+                continue;
+            };
+            if accepts(&field.type_expr, &found) {
+                continue;
+            }
+            let message = format!("expected `{}`, found `{}`", field.type_expr, found);
+            let location = Location::new(self.source, field.span);
+            let mut diagnostic = Diagnostic::error("NX0010", message, location);
+            diagnostic.notes.push(format!(
+                "field `{}` of `{}` is `{}`",
+                field.name, def.name, field.type_expr
+            ));
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Report a function type kept in data, which is cloned, printed and
+    /// saved where a function can't be
+    fn stored(&mut self, type_expr: &TypeExpr, span: Span, place: &str) {
+        let Some(function) = function_type(type_expr) else {
+            return;
+        };
+        let message = format!("`{}` can't be stored in {}", function, place);
+        let mut diagnostic = Diagnostic::error("NX0026", message, Location::new(self.source, span));
+        diagnostic
+            .notes
+            .push("functions can only be passed to and returned from `fn`s".to_string());
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// The struct a callee names: `Weapon` or `module.Weapon`
+    fn struct_of<'s>(&self, callee: &Expr, scope: &Scope<'s>) -> Option<Visible<'s, StructDef>>
+    where
+        'a: 's,
+    {
+        match callee {
+            Expr::Identifier(name) => scope.structs.iter().find(|s| s.def.name == *name).copied(),
+            Expr::MemberAccess(base, name) => match self.imported(base, name)?.item {
+                Item::Struct(def) => Some(self.visible(def, base, name)?),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The enum an expression names: `Shape` or `module.Shape`
+    fn enum_of<'s>(&self, expr: &Expr, scope: &Scope<'s>) -> Option<Visible<'s, EnumDef>>
+    where
+        'a: 's,
+    {
+        match expr {
+            Expr::Identifier(name) => scope.enums.iter().find(|e| e.def.name == *name).copied(),
+            Expr::MemberAccess(base, name) => match self.imported(base, name)?.item {
+                Item::Enum(def) => Some(self.visible(def, base, name)?),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The imported module `expr` names, as `combat` or `util.combat`, by
+    /// the name its items are imported under
+    fn module_of(&self, expr: &Expr) -> Option<&'a str> {
+        self.modules.get(&member_path(expr)?).copied()
+    }
+
+    /// The item `module.name` refers to, if `module` is an imported module
+    fn imported(&self, module: &Expr, name: &str) -> Option<&'a Imported<'a>> {
+        self.imports.modules.get(self.module_of(module)?)?.get(name)
+    }
+
+    /// Report `module.name` when the module was loaded but has no `name`.
+    /// Modules that weren't loaded are reported at their import.
+    fn module_member(&mut self, module: &Expr, name: &str) {
+        let Some(items) = self
+            .module_of(module)
+            .and_then(|used| self.imports.modules.get(used))
+        else {
+            return;
+        };
+        if items.contains_key(name) {
+            return;
+        }
+        let Some(location) = self.member_location(module, name) else {
+            return;
+        };
+        let path = member_path(module).unwrap_or_default();
+        let message = format!("module `{}` has no `{}`", path, name);
+        let mut diagnostic = Diagnostic::error("NX0008", message, location);
+        if let Some(similar) = items
+            .keys()
+            .filter(|other| edit_distance(other, name) <= 2)
+            .min_by_key(|other| (edit_distance(other, name), other.as_str()))
+        {
+            diagnostic.fixes.push(Fix {
+                message: format!("did you mean `{}`?", similar),
+                location,
+                replacement: similar.clone(),
+            });
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn visible<T>(&self, def: &'a T, module: &Expr, name: &str) -> Option<Visible<'a, T>> {
+        let imported = self.imported(module, name)?;
+        Some(Visible {
+            def,
+            file: imported.file,
+            location: imported.location,
+        })
+    }
+
+    fn unknown_variant(&mut self, def: Visible<EnumDef>, base: &Expr, name: &str) {
+        if let Some(location) = self.member_location(base, name) {
+            self.no_variant(def, name, location);
+        }
+    }
+
+    fn no_variant(&mut self, def: Visible<EnumDef>, name: &str, location: Location) {
+        let message = format!("`{}` has no variant `{}`", def.def.name, name);
+        let mut diagnostic = Diagnostic::error("NX0013", message, location);
+        let variants: Vec<&str> = def.def.variants.iter().map(|v| v.name.as_str()).collect();
+        diagnostic
+            .notes
+            .push(format!("variants: {}", variants.join(", ")));
+        if let Some(similar) = variants
+            .iter()
+            .filter(|variant| edit_distance(variant, name) <= 2)
+            .min_by_key(|variant| edit_distance(variant, name))
+        {
+            diagnostic.fixes.push(Fix {
+                message: format!("did you mean `{}`?", similar),
+                location,
+                replacement: similar.to_string(),
+            });
+        }
+        diagnostic.related.push(def.declared_here("enum"));
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Where `name` is written after `base.` in the current function
+    fn member_location(&self, base: &Expr, name: &str) -> Option<Location> {
+        let base = match base {
+            Expr::Identifier(base) => base,
+            Expr::MemberAccess(_, base) => base,
+            _ => return None,
+        };
+        let tokens: Vec<lexer::Token> =
+            lexer::tokenize(&self.source[self.scope.start..self.scope.end])
+                .into_iter()
+                .filter(|t| !t.is_trivia())
+                .collect();
+        let token = tokens
+            .windows(3)
+            .find(|w| w[0].text == base.as_str() && w[1].text == "." && w[2].text == name)?[2];
+        let span = Span {
+            start: self.scope.start + token.offset,
+            end: self.scope.start + token.end(),
+        };
+        Some(Location::new(self.source, span))
+    }
+
+    /// The next call to `name` in the current function: the span from the
+    /// name to the closing parenthesis, and each argument's span. Expressions
+    /// carry no spans, so calls are found by counting them in source order.
+    fn call_site(&mut self, name: &str) -> Option<(Location, Vec<Span>)> {
+        let count = self.calls.entry(name.to_string()).or_default();
+        let nth = *count;
+        *count += 1;
+
+        let base = self.scope.start;
+        let tokens: Vec<lexer::Token> =
+            lexer::tokenize(&self.source[self.scope.start..self.scope.end])
+                .into_iter()
+                .filter(|t| !t.is_trivia())
+                .collect();
+        let start = tokens
+            .windows(2)
+            .enumerate()
+            // Not the function's own header, which overrides share a name with
+            .filter(|(i, pair)| {
+                pair[0].kind == TokenKind::Ident
+                    && pair[0].text == name
+                    && pair[1].text == "("
+                    && (*i == 0 || tokens[i - 1].text != "fn")
+            })
+            .nth(nth)?
+            .0;
+
+        let mut args = Vec::new();
+        let mut depth = 0;
+        let mut arg_start: Option<usize> = None;
+        for (i, token) in tokens.iter().enumerate().skip(start + 1) {
+            match token.text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth -= 1,
+                _ => {}
+            }
+            let closes = depth == 0;
+            if (depth == 1 && token.text == ",") || closes {
+                if let Some(first) = arg_start.take() {
+                    let first = &tokens[first];
+                    let last = &tokens[i - 1];
+                    args.push(Span {
+                        start: base + first.offset,
+                        end: base + last.end(),
+                    });
+                }
+            } else if arg_start.is_none() && !(depth == 1 && token.text == "(") {
+                arg_start = Some(i);
+            }
+            if closes {
+                let span = Span {
+                    start: base + tokens[start].offset,
+                    end: base + token.end(),
+                };
+                return Some((Location::new(self.source, span), args));
+            }
+        }
+        None
+    }
+
+    /// Where `name` is written inside a construct, falling back to the whole construct
+    fn name_location(&self, span: Span, name: &str) -> Location {
+        name_location(self.source, span, name)
+    }
+}
+
+/// Where `name` is written inside the construct at `span`, falling back to
+/// the whole construct
+pub(crate) fn name_location(source: &str, span: Span, name: &str) -> Location {
+    let text = &source[span.start..span.end];
+    let span = lexer::tokenize(text)
+        .iter()
+        .find(|t| t.kind == TokenKind::Ident && t.text == name)
+        .map_or(span, |t| Span {
+            start: span.start + t.offset,
+            end: span.start + t.end(),
+        });
+    Location::new(source, span)
+}
+
+/// Value of an int or float literal, which may be negative
+fn number(value: &Expr) -> Option<f64> {
+    match value {
+        Expr::Int(n) => Some(*n as f64),
+        Expr::Float(n) => Some(*n),
+        Expr::UnaryOp(UnaryOp::Neg, value) => number(value).map(|n| -n),
+        _ => None,
+    }
+}
+
+/// The name an annotation problem is about, if any
+fn problem_name(problem: &Problem) -> &str {
+    match problem {
+        Problem::UnknownSystem(name, _)
+        | Problem::UnknownState(name, _)
+        | Problem::UnknownCondition(name, _) => name,
+        _ => "",
+    }
+}
+
+/// Whether a parameter of type `expected` accepts a value of type `found`.
+/// Only built-in types are compared; anything else is accepted.
+fn accepts(expected: &TypeExpr, found: &TypeExpr) -> bool {
+    let (TypeExpr::Simple(expected), TypeExpr::Simple(found)) = (expected, found) else {
+        return true;
+    };
+    expected == found
+        || (expected == "float" && found == "int")
+        || !BUILTIN_TYPES.contains(&expected.as_str())
+        || !BUILTIN_TYPES.contains(&found.as_str())
+}
+
+/// Why a `case` pattern can never match a value of the subject's type, if
+/// it can't
+fn pattern_mismatch(pattern: &Pattern, subject: &TypeExpr) -> Option<String> {
+    if matches!(subject, TypeExpr::Simple(t) if t == "Any") {
+        return None;
+    }
+    let found = match (pattern, subject) {
+        (Pattern::Tuple(items), TypeExpr::Generic { name, params })
+            if name == "Tuple" && items.len() == params.len() =>
+        {
+            return items
+                .iter()
+                .zip(params)
+                .find_map(|(item, t)| pattern_mismatch(item, t));
+        }
+        (Pattern::Tuple(items), _) => format!("a tuple of {}", plural(items.len(), "item")),
+        (Pattern::Literal(value) | Pattern::Range { start: value, .. }, _) => {
+            let found = infer_type(value)?;
+            if matches!(subject, TypeExpr::Simple(_)) && accepts(subject, &found) {
+                return None;
+            }
+            format!("`{}`", found)
+        }
+        (Pattern::Variant { path, .. }, _) => {
+            let enum_name = path.iter().rev().nth(1)?;
+            let same = match subject {
+                TypeExpr::Simple(t) => {
+                    !BUILTIN_TYPES.contains(&t.as_str())
+                        && t.rsplit('.').next() == Some(enum_name.as_str())
+                }
+                _ => false,
+            };
+            if same {
+                return None;
+            }
+            format!("`{}`", path.join("."))
+        }
+        _ => return None,
+    };
+    Some(format!("expected `{}`, found {}", subject, found))
+}
+
+/// Types whose values are checked against each other
+const BUILTIN_TYPES: &[&str] = &["int", "float", "str", "bool", "Vec2", "Vec3"];
+
+/// The `Fn` type `list.map(f)` and `list.filter(f)` expect, when the type of
+/// the list's items is known
+fn list_callback(list: &Expr, method: &str) -> Option<Vec<TypeExpr>> {
+    let returns = match method {
+        "map" => "Any",
+        "filter" => "bool",
+        _ => return None,
+    };
+    match infer_type(list)? {
+        TypeExpr::Generic { name, params } if name == "List" => {
+            let item = params.into_iter().next()?;
+            let any = matches!(&item, TypeExpr::Simple(t) if t == "Any");
+            (!any).then(|| vec![item, TypeExpr::Simple(returns.to_string())])
+        }
+        _ => None,
+    }
+}
+
+/// A struct or variant field: its name, type and whether it has a default
+type Field<'a> = (&'a str, &'a TypeExpr, bool);
+
+/// A query filter nested anywhere in a type
+fn misplaced_filter(type_expr: &TypeExpr) -> Option<&TypeExpr> {
+    match type_expr {
+        TypeExpr::Generic { name, .. } if is_filter(name) => Some(type_expr),
+        TypeExpr::Generic { params, .. } => params.iter().find_map(misplaced_filter),
+        TypeExpr::Simple(_) => None,
+    }
+}
+
+/// A function type nested anywhere in a type
+fn function_type(type_expr: &TypeExpr) -> Option<&TypeExpr> {
+    match type_expr {
+        TypeExpr::Generic { name, .. } if name == "Fn" => Some(type_expr),
+        TypeExpr::Generic { params, .. } => params.iter().find_map(function_type),
+        TypeExpr::Simple(_) => None,
+    }
+}
+
+/// `1 argument`, `2 arguments`
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// `signal died(cause: str)`, parameter names included since they are the
+/// event's fields
+fn signal_signature(signal: &SignalDef) -> String {
+    let params: Vec<String> = signal
+        .params
+        .iter()
+        .map(|p| format!("{}: {}", p.name, p.type_expr))
+        .collect();
+    format!("signal {}({})", signal.name, params.join(", "))
+}
+
+/// Whether a function has the parameter types, return type and `async`ness
+/// an interface or base entity requires; parameter names don't matter
+fn same_signature(func: &FnDef, required: &FnDef) -> bool {
+    func.is_async == required.is_async
+        && func.return_type == required.return_type
+        && func.params.len() == required.params.len()
+        && func
+            .params
+            .iter()
+            .zip(&required.params)
+            .all(|(param, required)| param.type_expr == required.type_expr)
+}
+
+/// Built-in functions the checker knows the argument count of, with the
+/// fewest and most arguments each takes
+const BUILTINS: &[(&str, usize, usize)] = &[("range", 1, 3), ("enumerate", 1, 1)];
+
+/// What looping over a built-in yields: how many names the loop takes, what
+/// they are and an example
+const LOOP_NAMES: &[(&str, usize, &str, &str)] = &[
+    ("range", 1, "a number", "for i in range(10):"),
+    (
+        "enumerate",
+        2,
+        "an index and an item",
+        "for i, item in enumerate(items):",
+    ),
+];
+
+/// `1 was`, `0 were`
+fn given(count: usize) -> String {
+    format!("{} {}", count, if count == 1 { "was" } else { "were" })
+}
+
+/// Names defined in one scope
+#[derive(Default)]
+struct Definitions {
+    first: HashMap<String, Location>,
+}
+
+impl Definitions {
+    fn define(&mut self, checker: &mut Checker, name: &str, span: Span) {
+        let location = checker.name_location(span, name);
+        match self.first.get(name) {
+            Some(first) => {
+                let message = format!("`{}` is defined more than once", name);
+                let mut diagnostic = Diagnostic::error("NX0003", message, location);
+                diagnostic.related.push(Related {
+                    message: "first defined here".to_string(),
+                    file: String::new(),
+                    location: *first,
+                });
+                checker.diagnostics.push(diagnostic);
+            }
+            None => {
+                self.first.insert(name.to_string(), location);
+            }
+        }
+    }
+}
+
+/// Levenshtein distance, for suggesting names
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitute.min(previous + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_codes_and_fixes() {
+        let source = "signal scored(points: int)\n\nentity Coin:\n    let value = 1\n    let value = 2\n\n    fn collect():\n        emit scord(value)\n        if value > 1:\n            emit scored()\n";
+        let (program, diagnostics) = check(source);
+        assert!(program.is_some());
+        let codes: Vec<&str> = diagnostics.iter().map(|d| d.code.as_str()).collect();
+        assert_eq!(codes, vec!["NX0003", "NX0004", "NX0005"]);
+
+        let duplicate = &diagnostics[0];
+        assert_eq!((duplicate.location.line, duplicate.location.column), (5, 9));
+        assert_eq!(duplicate.related[0].location.line, 4);
+
+        let unknown = &diagnostics[1];
+        assert_eq!(unknown.fixes[0].replacement, "scored");
+        let span = unknown.fixes[0].location.span;
+        assert_eq!(&source[span.start..span.end], "scord");
+
+        assert_eq!(
+            diagnostics[2].message,
+            "signal `scored` takes 1 argument but 0 were given"
+        );
+        assert_eq!(
+            diagnostics[2].clone().in_file("coin.nx").to_string(),
+            "coin.nx:10:13: error[NX0005]: signal `scored` takes 1 argument but 0 were given"
+        );
+    }
+
+    #[test]
+    fn test_check_constructors() {
+        let source = "struct Weapon:\n    name: str\n    damage: int = \"lots\"\n\nenum Shape:\n    Circle(radius: float)\n\nentity Player:\n    fn equip():\n        let a = Weapon(\"sword\", dammage: 5)\n        let b = Weapon(damage: 5)\n        let c = Weapon(1)\n        let d = Shape.Circle(1.0, 2.0)\n        let e = Shape.Circel(1.0)\n        let f = Weapon(\"axe\", 3)\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0010", "expected `int`, found `str`"),
+                ("NX0011", "`Weapon` has no field `dammage`"),
+                ("NX0012", "missing field `name` in `Weapon`"),
+                ("NX0010", "expected `str`, found `int`"),
+                ("NX0009", "`Shape.Circle` has 1 field but 2 were given"),
+                ("NX0013", "`Shape` has no variant `Circel`"),
+            ]
+        );
+
+        let unknown = &diagnostics[1];
+        assert_eq!((unknown.location.line, unknown.location.column), (10, 33));
+        assert_eq!(unknown.fixes[0].replacement, "damage");
+        assert_eq!(
+            diagnostics[3].notes,
+            vec!["field `name` of `Weapon` is `str`"]
+        );
+        assert_eq!(diagnostics[5].fixes[0].replacement, "Circle");
+        assert_eq!(diagnostics[5].location.line, 14);
+    }
+
+    #[test]
+    fn test_check_match() {
+        let source = "enum Shape:\n    Empty\n    Circle(radius: float)\n\nfn area(s: Shape) -> float:\n    match s:\n        case Shape.Circle(r, extra):\n            return r\n        case Shape.Circel(r):\n            return r\n        case Shape.Circle(_):\n            return 1.0\n        case Shape.Circle(r) if r > 1.0:\n            return r\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, Severity, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "NX0009",
+                    Severity::Error,
+                    "`Shape.Circle` has 1 field but the pattern has 2"
+                ),
+                ("NX0013", Severity::Error, "`Shape` has no variant `Circel`"),
+                ("NX0015", Severity::Warning, "unreachable `case`"),
+                ("NX0015", Severity::Warning, "unreachable `case`"),
+                (
+                    "NX0014",
+                    Severity::Error,
+                    "`match` doesn't cover `Shape.Empty`"
+                ),
+            ]
+        );
+        assert_eq!(diagnostics[1].fixes[0].replacement, "Circle");
+        // The guarded arm is covered by the first one too
+        assert_eq!(diagnostics[2].location.line, 11);
+        let unreachable = diagnostics[3].location;
+        assert_eq!((unreachable.line, unreachable.column), (13, 9));
+        assert_eq!(unreachable.end_column, 41);
+        assert_eq!(diagnostics[4].location.line, 6);
+        assert_eq!(
+            diagnostics[4].notes,
+            vec!["add a `case Shape.Empty:` arm, or a `case _:` arm to match everything else"]
+        );
+
+        // Any value can be missing, so only a catch-all arm helps
+        let (_, diagnostics) =
+            check("fn f(n: int):\n    match n:\n        case 1:\n            pass\n");
+        assert_eq!(diagnostics[0].message, "`match` doesn't cover `_`");
+        assert_eq!(
+            diagnostics[0].notes,
+            vec!["add a `case _:` arm to match everything else"]
+        );
+    }
+
+    #[test]
+    fn test_check_pattern_types() {
+        let source = "enum Shape:\n    Empty\n\nfn f(hp: int, pair: Tuple<int, bool>):\n    match hp:\n        case (0, true):\n            pass\n        case \"dead\":\n            pass\n        case Shape.Empty:\n            pass\n        case 1..5:\n            pass\n        case _:\n            pass\n    match pair:\n        case (0, 1):\n            pass\n        case (0, true, 2):\n            pass\n        case (n, true):\n            pass\n        case _:\n            pass\n    let name = \"a\"\n    match name:\n        case \"a\":\n            pass\n        case _:\n            pass\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(usize, &str)> = diagnostics
+            .iter()
+            .filter(|d| d.code == "NX0030")
+            .map(|d| (d.location.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (6, "expected `int`, found a tuple of 2 items"),
+                (8, "expected `int`, found `str`"),
+                (10, "expected `int`, found `Shape.Empty`"),
+                (17, "expected `bool`, found `int`"),
+                (19, "expected `Tuple<int, bool>`, found a tuple of 3 items"),
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].notes,
+            vec!["the `match` subject has type `int`"]
+        );
+    }
+
+    #[test]
+    fn test_check_empty_ranges() {
+        let source = "fn describe(n: int):\n    match n:\n        case 10..0:\n            pass\n        case 5..5:\n            pass\n        case 5..=5:\n            pass\n        case -1..-3:\n            pass\n        case _:\n            pass\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| (d.code.as_str(), d.location.line))
+            .collect();
+        assert_eq!(found, vec![("NX0025", 3), ("NX0025", 5), ("NX0025", 9)]);
+    }
+
+    #[test]
+    fn test_check_loops() {
+        let source = "fn tick(items: List<int>):\n    for i, item in enumerate(items):\n        if item < 0:\n            continue\n        break\n    for n in range(0, 10, 2, 1):\n        pass\n    break\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0009", "`range` takes 1 to 3 arguments but 4 were given"),
+                ("NX0016", "`break` outside of a loop"),
+            ]
+        );
+        let location = diagnostics[1].location;
+        assert_eq!((location.line, location.column), (8, 5));
+    }
+
+    #[test]
+    fn test_check_duplicate_interfaces() {
+        let source = "interface Damageable:\n    fn take_damage(amount: int)\n\nentity Crate implements Damageable, Damageable:\n    fn take_damage(amount: int):\n        pass\n";
+        let (_, diagnostics) = check(source);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.code, "NX0003");
+        assert_eq!(diagnostic.message, "`Damageable` is defined more than once");
+        assert_eq!(
+            (diagnostic.location.line, diagnostic.location.column),
+            (4, 37)
+        );
+        assert_eq!(diagnostic.related[0].location.column, 25);
+    }
+
+    #[test]
+    fn test_check_stored_functions() {
+        let source = "struct Button:\n    on_click: Fn<None>\n\nenum Rule:\n    Custom(check: Fn<int, bool>)\n\nsignal sorted(by: List<Fn<int, int>>)\n\nentity Spinner:\n    let ease = fn(t: float) -> float: t\n    signal spun(filter: Fn<int, bool>)\n\nfn apply(f: Fn<int, int>, x: int) -> Fn<int, int>:\n    return f\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, usize, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.location.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0026", 2, "`Fn<None>` can't be stored in a struct field"),
+                (
+                    "NX0026",
+                    5,
+                    "`Fn<int, bool>` can't be stored in an enum variant"
+                ),
+                ("NX0026", 7, "`Fn<int, int>` can't be stored in a signal"),
+                (
+                    "NX0026",
+                    10,
+                    "`Fn<float, float>` can't be stored in an entity variable"
+                ),
+                ("NX0026", 11, "`Fn<int, bool>` can't be stored in a signal"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_lambdas() {
+        let source = "fn apply(f: Fn<int, int>, x: int) -> int:\n    return f(x)\n\nfn tick():\n    let a = [1, 2].map(|x| x + \"a\")\n    let b = apply(|s| s + \"x\", 2)\n    let c = apply(|s| s > 1, 2)\n    let d = apply(|s, t| s, 2)\n    let e = apply(fn(s: str) -> int: 1, 2)\n    let f = [1, 2.5].filter(|x| x > 1)\n    let g = apply(|s| s * 2, 2)\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, usize, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.location.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0029", 5, "can't apply `+` to `int` and `str`"),
+                ("NX0029", 6, "can't apply `+` to `int` and `str`"),
+                ("NX0010", 7, "expected `int`, found `bool`"),
+                (
+                    "NX0009",
+                    8,
+                    "expected a function taking 1 argument, found one taking 2"
+                ),
+                ("NX0010", 9, "expected `int`, found `str`"),
+            ]
+        );
+        assert_eq!(
+            (
+                diagnostics[1].location.column,
+                diagnostics[1].location.end_column
+            ),
+            (19, 30)
+        );
+        assert_eq!(
+            diagnostics[2].notes,
+            vec!["the function must return `int` to be a `Fn<int, int>`"]
+        );
+    }
+
+    #[test]
+    fn test_check_loop_names() {
+        let source = "fn tick(xs: List<int>):\n    for a, b, c in enumerate(xs):\n        pass\n    for i, j in range(3):\n        pass\n    for x in enumerate(xs):\n        pass\n    for i, x in enumerate(xs):\n        pass\n    for i in range(3):\n        pass\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, usize, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.location.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "NX0028",
+                    2,
+                    "`enumerate` yields an index and an item but the loop has 3 names"
+                ),
+                (
+                    "NX0028",
+                    4,
+                    "`range` yields a number but the loop has 2 names"
+                ),
+                (
+                    "NX0028",
+                    6,
+                    "`enumerate` yields an index and an item but the loop has 1 name"
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].notes,
+            vec!["for example `for i, item in enumerate(items):`"]
+        );
+    }
+
+    #[test]
+    fn test_check_range_steps() {
+        let source = "fn tick():\n    for i in range(0, 10, 0):\n        pass\n    for i in range(0, 10, -1):\n        pass\n    for i in range(10, 0, -2):\n        pass\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0025", "`range` can't step by 0"),
+                ("NX0025", "`range` counts down but 0 is below 10"),
+            ]
+        );
+        let location = diagnostics[0].location;
+        assert_eq!((location.line, location.column), (2, 27));
+    }
+
+    #[test]
+    fn test_check_nested_bodies() {
+        let source = "fn f():\n    for i in range(3):\n        fn g():\n            break\n        continue\n\nstate_machine M:\n    state A:\n        emit nosuch(1)\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.location.line))
+            .collect();
+        assert_eq!(found, vec![("NX0016", 4), ("NX0004", 9)]);
+    }
+
+    #[test]
+    fn test_check_interfaces() {
+        let source = "interface Damageable:\n    signal died()\n    fn take_damage(amount: int)\n\nentity Crate implements Damageable:\n    fn take_damage(amount: float):\n        pass\n\nentity Enemy implements Damagable:\n    let hp = 1\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0018", "`Crate` is missing `signal died()`"),
+                (
+                    "NX0018",
+                    "`take_damage` doesn't match `fn take_damage(amount: int)`"
+                ),
+                ("NX0017", "unknown interface `Damagable`"),
+            ]
+        );
+        let missing = diagnostics[0].location;
+        assert_eq!(
+            (missing.line, missing.column, missing.end_column),
+            (5, 25, 35)
+        );
+        assert_eq!(diagnostics[1].location.line, 6);
+        assert_eq!(diagnostics[2].fixes[0].replacement, "Damageable");
+    }
+
+    #[test]
+    fn test_check_queries() {
+        let source = "system gravity(q: Query<Transform, Velocity, with Player>, e: Query<with Player>):\n    for v in q:\n        v.y -= 9.8\n    for t, v in q:\n        pass\n\nfn count(players: List<with Player>) -> int:\n    return 1\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("NX0022", "`e` doesn't yield any components"),
+                ("NX0022", "`q` yields 2 components but the loop has 1 name"),
+                ("NX0022", "`with Player` is only allowed in a `Query`"),
+            ]
+        );
+        assert_eq!(diagnostics[1].location.line, 2);
+        assert_eq!(diagnostics[1].notes, vec!["`q` yields Transform, Velocity"]);
+    }
+
+    #[test]
+    fn test_check_schedules() {
+        let source = "state_machine Game:\n    state Playing:\n        pass\n\n@schedule(FixedUpdat)\n@after(collide)\nsystem gravity():\n    pass\n\n@set(Physics)\n@after(gravity)\n@run_if(in_state(Playng))\nsystem collide():\n    pass\n\n@after(Physics, bounse)\nsystem bounce():\n    pass\n\nentity Enemy:\n    @after(gravty)\n    fn on_update(delta: float):\n        pass\n    @before(gravity)\n    fn hit():\n        pass\n\nentity Boss extends Enemy:\n    let hp = 1\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "NX0023",
+                    "`@before` can only annotate a system or `on_update`"
+                ),
+                ("NX0023", "unknown schedule `FixedUpdat`"),
+                ("NX0023", "unknown state `Playng`"),
+                ("NX0023", "unknown system or set `bounse`"),
+                ("NX0023", "unknown system or set `gravty`"),
+                ("NX0024", "`gravity` is ordered to run before itself"),
+            ]
+        );
+        assert_eq!(diagnostics[1].fixes[0].replacement, "FixedUpdate");
+        assert_eq!(diagnostics[2].location.line, 12);
+        assert_eq!(diagnostics[2].fixes[0].replacement, "Playing");
+        assert_eq!(diagnostics[3].fixes[0].replacement, "bounce");
+        assert_eq!(
+            diagnostics[5].notes,
+            vec!["each runs before the next: gravity -> collide -> gravity"]
+        );
+    }
+
+    #[test]
+    fn test_check_inheritance() {
+        let source = "interface Damageable:\n    fn take_damage(amount: int)\n\nentity Enemy:\n    signal died()\n    fn take_damage(amount: int):\n        pass\n\nentity Boss extends Enemy implements Damageable:\n    fn take_damage(amount: float):\n        super.take_damage(1, 2)\n        super.take_damag(1)\n        emit died()\n\nentity Crate extends Enemey:\n    fn open():\n        super.open()\n\nentity Door:\n    fn open():\n        super.open()\n\nentity A extends B:\n    let x = 1\n\nentity B extends A:\n    let x = 1\n\nentity C extends A:\n    let x = 1\n";
+        let (_, diagnostics) = check(source);
+        let found: Vec<(&str, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "NX0020",
+                    "`take_damage` doesn't match `fn take_damage(amount: int)` from `Enemy`"
+                ),
+                (
+                    "NX0018",
+                    "`take_damage` doesn't match `fn take_damage(amount: int)`"
+                ),
+                ("NX0009", "`take_damage` takes 1 argument but 2 were given"),
+                ("NX0021", "`Enemy` has no function `take_damag`"),
+                ("NX0019", "unknown base entity `Enemey`"),
+                (
+                    "NX0021",
+                    "`super` outside of an entity that extends another"
+                ),
+                ("NX0019", "`A` extends itself"),
+                ("NX0019", "`B` extends itself"),
+            ]
+        );
+        assert_eq!(diagnostics[0].location.line, 10);
+        assert_eq!(diagnostics[0].related[0].location.line, 4);
+        let call = diagnostics[2].location;
+        assert_eq!((call.line, call.column), (11, 15));
+        assert_eq!(diagnostics[3].fixes[0].replacement, "take_damage");
+        assert_eq!(diagnostics[4].fixes[0].replacement, "Enemy");
+        assert_eq!(diagnostics[6].notes, vec!["inheritance chain: A -> B -> A"]);
+    }
+
+    #[test]
+    fn test_syntax_error_suggests_colon() {
+        let (program, diagnostics) = check("entity Coin\n    let value = 1\n");
+        assert!(program.is_none());
+        assert_eq!(diagnostics[0].code, "NX0001");
+        assert_eq!(
+            diagnostics[0].message,
+            "expected `:` at the end of the block header"
+        );
+        let fix = &diagnostics[0].fixes[0];
+        assert_eq!((fix.location.line, fix.location.column), (1, 12));
+        assert_eq!(fix.replacement, ":");
+
+        let (_, diagnostics) = check("fn f(q: List<int>):\n    for (i, n) in q:\n        pass\n");
+        let fix = &diagnostics[0].fixes[0];
+        assert_eq!((fix.location.column, fix.location.end_column), (9, 15));
+        assert_eq!(fix.replacement, "i, n");
+    }
+}
@@ -0,0 +1,342 @@
+//! Exhaustiveness - Which `match` arms can run, and what no arm matches
+//!
+//! Uses the usual pattern-matrix algorithm: an arm is reachable when its
+//! pattern is useful against the unguarded arms before it, and a match is
+//! exhaustive when a `_` after the last arm would not be. Enums and `bool`
+//! have finitely many values; ints, floats and strings need a wildcard or a
+//! binding to be covered, and overlapping ranges are only compared as a
+//! whole, so a range split across several arms is never reported unreachable.
+
+use crate::{Expr, Pattern, UnaryOp};
+use std::collections::HashMap;
+
+/// Variants of each enum and how many fields each has, by the enum's name
+/// as written in patterns (`Shape` or `combat.Shape`)
+pub(crate) type Enums = HashMap<String, Vec<(String, usize)>>;
+
+/// What checking a match's arms found
+#[derive(Debug, Default)]
+pub(crate) struct Coverage {
+    /// Arms, by index, that can never run
+    pub unreachable: Vec<usize>,
+    /// A value no arm matches, as a pattern, if there is one
+    pub missing: Option<String>,
+}
+
+/// Check a match's arms, given as their pattern and whether they have a guard
+pub(crate) fn check(arms: &[(&Pattern, bool)], enums: &Enums) -> Coverage {
+    let mut coverage = Coverage::default();
+    let mut rows: Vec<Vec<Pat>> = Vec::new();
+    for (i, (pattern, guarded)) in arms.iter().enumerate() {
+        let row = vec![Pat::from(*pattern)];
+        if !useful(&rows, &row, enums) {
+            coverage.unreachable.push(i);
+        }
+        // A guarded arm may not match, so it covers nothing
+        if !guarded {
+            rows.push(row);
+        }
+    }
+    coverage.missing = witness(&rows, 1, enums).map(|w| w[0].to_string());
+    coverage
+}
+
+/// A pattern reduced to what matters for coverage
+#[derive(Debug, Clone)]
+enum Pat {
+    Any,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant {
+        enum_name: String,
+        name: String,
+    },
+    Bool(bool),
+    Tuple(usize),
+    /// Inclusive int range; a literal is a range of one value
+    Int(i64, i64),
+    /// Float and string literals and float ranges, compared as written
+    Other(String),
+}
+
+impl From<&Pattern> for Pat {
+    fn from(pattern: &Pattern) -> Self {
+        match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) | Pattern::Destructure { .. } => Pat::Any,
+            Pattern::Literal(Expr::Bool(b)) => Pat::Ctor(Ctor::Bool(*b), Vec::new()),
+            Pattern::Literal(value) => match int(value) {
+                Some(n) => Pat::Ctor(Ctor::Int(n, n), Vec::new()),
+                None => Pat::Ctor(Ctor::Other(format!("{:?}", value)), Vec::new()),
+            },
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => match (int(start), int(end)) {
+                (Some(start), Some(end)) => {
+                    let end = if *inclusive { end } else { end - 1 };
+                    Pat::Ctor(Ctor::Int(start, end), Vec::new())
+                }
+                _ => Pat::Ctor(Ctor::Other(format!("{:?}", pattern)), Vec::new()),
+            },
+            Pattern::Variant { path, fields } => {
+                let (name, enum_path) = path.split_last().expect("variant patterns have a path");
+                let ctor = Ctor::Variant {
+                    enum_name: enum_path.join("."),
+                    name: name.clone(),
+                };
+                Pat::Ctor(ctor, fields.iter().map(Pat::from).collect())
+            }
+            Pattern::Tuple(items) => Pat::Ctor(
+                Ctor::Tuple(items.len()),
+                items.iter().map(Pat::from).collect(),
+            ),
+        }
+    }
+}
+
+/// Value of an int literal, which may be negative
+fn int(value: &Expr) -> Option<i64> {
+    match value {
+        Expr::Int(n) => Some(*n),
+        Expr::UnaryOp(UnaryOp::Neg, value) => int(value).map(|n| -n),
+        _ => None,
+    }
+}
+
+/// `Shape.Circle(_)`, `(true, _)`
+impl std::fmt::Display for Pat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |args: &[Pat]| {
+            let args: Vec<String> = args.iter().map(Pat::to_string).collect();
+            args.join(", ")
+        };
+        match self {
+            Pat::Any => write!(f, "_"),
+            Pat::Ctor(Ctor::Variant { enum_name, name }, args) if args.is_empty() => {
+                write!(f, "{}.{}", enum_name, name)
+            }
+            Pat::Ctor(Ctor::Variant { enum_name, name }, args) => {
+                write!(f, "{}.{}({})", enum_name, name, join(args))
+            }
+            Pat::Ctor(Ctor::Bool(b), _) => write!(f, "{}", b),
+            Pat::Ctor(Ctor::Tuple(_), args) => write!(f, "({})", join(args)),
+            // Never part of a witness: these are only covered by `_`
+            Pat::Ctor(Ctor::Int(..) | Ctor::Other(_), _) => write!(f, "_"),
+        }
+    }
+}
+
+/// How many fields `ctor` has
+fn arity(ctor: &Ctor, enums: &Enums) -> Option<usize> {
+    match ctor {
+        Ctor::Variant { enum_name, name } => enums
+            .get(enum_name)?
+            .iter()
+            .find(|(variant, _)| variant == name)
+            .map(|(_, fields)| *fields),
+        Ctor::Tuple(n) => Some(*n),
+        _ => Some(0),
+    }
+}
+
+/// Whether every value `ctor` matches is matched by `by`
+fn covers(by: &Ctor, ctor: &Ctor) -> bool {
+    match (by, ctor) {
+        (Ctor::Int(lo, hi), Ctor::Int(start, end)) => lo <= start && end <= hi,
+        (Ctor::Tuple(_), Ctor::Tuple(_)) => true,
+        _ => by == ctor,
+    }
+}
+
+/// The rows that match `ctor`, with their first column replaced by its fields
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut fields = match &row[0] {
+                Pat::Any => vec![Pat::Any; arity],
+                Pat::Ctor(by, args) if covers(by, ctor) => args.clone(),
+                Pat::Ctor(..) => return None,
+            };
+            // Arities that don't match the declaration are reported elsewhere
+            fields.resize(arity, Pat::Any);
+            fields.extend(row[1..].iter().cloned());
+            Some(fields)
+        })
+        .collect()
+}
+
+/// The rows whose first column matches anything, without it
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Any))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// Every constructor of the first column's type if the rows' first column
+/// uses all of them, or else the ones that are missing (empty when the type
+/// has too many values to list)
+enum Signature {
+    Complete(Vec<Ctor>),
+    Incomplete(Vec<Ctor>),
+}
+
+fn signature(rows: &[Vec<Pat>], enums: &Enums) -> Signature {
+    let used: Vec<&Ctor> = rows
+        .iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Ctor(ctor, _) => Some(ctor),
+            Pat::Any => None,
+        })
+        .collect();
+    let Some(first) = used.first() else {
+        return Signature::Incomplete(Vec::new());
+    };
+
+    let all: Vec<Ctor> = match first {
+        Ctor::Variant { enum_name, .. } => match enums.get(enum_name) {
+            Some(variants) => variants
+                .iter()
+                .map(|(name, _)| Ctor::Variant {
+                    enum_name: enum_name.clone(),
+                    name: name.clone(),
+                })
+                .collect(),
+            // Nothing is known about the enum, so assume the arms name
+            // every variant rather than report ones that may not exist
+            None => {
+                let mut all: Vec<Ctor> = Vec::new();
+                for ctor in used {
+                    if !all.contains(ctor) {
+                        all.push(ctor.clone());
+                    }
+                }
+                return Signature::Complete(all);
+            }
+        },
+        Ctor::Bool(_) => vec![Ctor::Bool(true), Ctor::Bool(false)],
+        Ctor::Tuple(n) => vec![Ctor::Tuple(*n)],
+        Ctor::Int(..) | Ctor::Other(_) => return Signature::Incomplete(Vec::new()),
+    };
+    let missing: Vec<Ctor> = all
+        .iter()
+        .filter(|ctor| !used.iter().any(|by| covers(by, ctor)))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        Signature::Complete(all)
+    } else {
+        Signature::Incomplete(missing)
+    }
+}
+
+/// Whether some value matches `row` but none of `rows`
+fn useful(rows: &[Vec<Pat>], row: &[Pat], enums: &Enums) -> bool {
+    let Some(head) = row.first() else {
+        return rows.is_empty();
+    };
+    match head {
+        Pat::Ctor(ctor, args) => {
+            let arity = arity(ctor, enums).unwrap_or(args.len());
+            let mut specialized = args.clone();
+            specialized.resize(arity, Pat::Any);
+            specialized.extend(row[1..].iter().cloned());
+            useful(&specialize(rows, ctor, arity), &specialized, enums)
+        }
+        Pat::Any => match signature(rows, enums) {
+            Signature::Complete(all) => all.iter().any(|ctor| {
+                let arity = arity(ctor, enums).unwrap_or(0);
+                let mut specialized = vec![Pat::Any; arity];
+                specialized.extend(row[1..].iter().cloned());
+                useful(&specialize(rows, ctor, arity), &specialized, enums)
+            }),
+            Signature::Incomplete(_) => useful(&default(rows), &row[1..], enums),
+        },
+    }
+}
+
+/// Values of `width` columns that match none of `rows`, as patterns, if
+/// there are any
+fn witness(rows: &[Vec<Pat>], width: usize, enums: &Enums) -> Option<Vec<Pat>> {
+    if width == 0 {
+        return rows.is_empty().then(Vec::new);
+    }
+    match signature(rows, enums) {
+        Signature::Complete(all) => all.iter().find_map(|ctor| {
+            let arity = arity(ctor, enums).unwrap_or(0);
+            let mut found = witness(&specialize(rows, ctor, arity), arity + width - 1, enums)?;
+            let rest = found.split_off(arity);
+            let mut result = vec![Pat::Ctor(ctor.clone(), found)];
+            result.extend(rest);
+            Some(result)
+        }),
+        Signature::Incomplete(missing) => {
+            let rest = witness(&default(rows), width - 1, enums)?;
+            let head = match missing.into_iter().next() {
+                Some(ctor) => {
+                    let arity = arity(&ctor, enums).unwrap_or(0);
+                    Pat::Ctor(ctor, vec![Pat::Any; arity])
+                }
+                None => Pat::Any,
+            };
+            let mut result = vec![head];
+            result.extend(rest);
+            Some(result)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Statement};
+
+    fn arms(source: &str) -> Vec<(Pattern, bool)> {
+        let program = parse(source).unwrap();
+        let Statement::Match(match_stmt) = &program.statements[0] else {
+            panic!("expected match");
+        };
+        match_stmt
+            .arms
+            .iter()
+            .map(|arm| (arm.pattern.clone(), arm.guard.is_some()))
+            .collect()
+    }
+
+    fn coverage(source: &str) -> Coverage {
+        let enums: Enums = [(
+            "Shape".to_string(),
+            vec![
+                ("Circle".to_string(), 1),
+                ("Square".to_string(), 1),
+                ("Empty".to_string(), 0),
+            ],
+        )]
+        .into_iter()
+        .collect();
+        let arms = arms(source);
+        let arms: Vec<(&Pattern, bool)> = arms.iter().map(|(p, g)| (p, *g)).collect();
+        check(&arms, &enums)
+    }
+
+    #[test]
+    fn test_match_coverage() {
+        let found = coverage("match s:\n    case Shape.Circle(r) if r > 1.0:\n        pass\n    case Shape.Circle(_):\n        pass\n    case Shape.Empty:\n        pass\n");
+        assert!(found.unreachable.is_empty());
+        assert_eq!(found.missing.as_deref(), Some("Shape.Square(_)"));
+
+        let found = coverage("match (a, b):\n    case (true, _):\n        pass\n    case (_, false):\n        pass\n");
+        assert_eq!(found.missing.as_deref(), Some("(false, true)"));
+
+        let found = coverage("match n:\n    case 0..10:\n        pass\n    case 3:\n        pass\n    case -1..=1:\n        pass\n    case _:\n        pass\n    case x:\n        pass\n");
+        assert_eq!(found.unreachable, vec![1, 4]);
+        assert_eq!(found.missing, None);
+
+        let found = coverage("match n:\n    case 1:\n        pass\n");
+        assert_eq!(found.missing.as_deref(), Some("_"));
+    }
+}
@@ -0,0 +1,1200 @@
+//! Formatter - Canonical pretty-printer for NexScript source
+//!
+//! The AST does not keep comments or blank lines, so `format_source` also
+//! scans the source for its logical lines and lays those back onto the AST:
+//! comments stay attached to the statement or member that follows them, a
+//! comment at the end of a block stays at the end of the block whose
+//! indentation it matches, and trailing comments stay on their line.
+//! Formatting never changes the AST.
+
+use crate::lexer::{self, TokenKind};
+use crate::{
+    Annotation, AssignOp, BinaryOp, ComponentDef, EntityDef, Expr, FnDef, InterfaceDef, Param,
+    Pattern, Program, Result, SignalDef, StateMachine, Statement, UnaryOp, VarDecl,
+};
+use std::collections::VecDeque;
+
+/// One level of block indentation
+const INDENT: &str = "    ";
+
+/// Lines longer than this have their call arguments wrapped one per line
+pub const MAX_WIDTH: usize = 100;
+
+/// Format NexScript source, keeping its comments and its line endings
+pub fn format_source(source: &str) -> Result<String> {
+    let program = crate::parse(source)?;
+    let formatted = Formatter::default().program(&program, Layout::scan(source));
+    // Windows checkouts may have CRLF; judge by the first line ending
+    match source.find('\n') {
+        Some(end) if source[..end].ends_with('\r') => Ok(formatted.replace('\n', "\r\n")),
+        _ => Ok(formatted),
+    }
+}
+
+/// Format a program built without source (no comments to keep)
+pub fn format_program(program: &Program) -> String {
+    Formatter::default().program(program, Layout::default())
+}
+
+// ============================================================================
+// Source Layout
+// ============================================================================
+
+/// A logical source line (continuation lines inside brackets are joined)
+#[derive(Debug, Default)]
+struct Line {
+    /// First token, used to match lines with entity and state machine members
+    keyword: String,
+    /// Comment lines before this line; blank lines are kept as ""
+    leading: Vec<String>,
+    /// Comment at the end of the line
+    trailing: Option<String>,
+    /// Lines indented under this one
+    children: VecDeque<Line>,
+    /// Comment lines after the last line indented under this one
+    closing: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Layout {
+    lines: VecDeque<Line>,
+    /// Comments after the last statement
+    dangling: Vec<String>,
+}
+
+impl Layout {
+    fn scan(source: &str) -> Self {
+        let mut flat = Vec::new();
+        let mut pending = Vec::new();
+        let mut lines = source.lines();
+
+        while let Some(line) = lines.next() {
+            let trimmed = line.trim();
+            let indent = line.len() - line.trim_start().len();
+            if trimmed.is_empty() {
+                pending.push((None, String::new()));
+                continue;
+            }
+            if trimmed.starts_with('#') {
+                pending.push((Some(indent), trimmed.to_string()));
+                continue;
+            }
+
+            // Same joining rule as `preprocess_indentation`
+            let mut comments: Vec<&str> = comment(line).into_iter().collect();
+            let mut depth = crate::bracket_depth(line);
+            while depth > 0 {
+                let Some(next) = lines.next() else {
+                    break;
+                };
+                depth += crate::bracket_depth(next);
+                comments.extend(comment(next));
+            }
+
+            let keyword = lexer::tokenize(trimmed)
+                .into_iter()
+                .find(|t| !t.is_trivia())
+                .map(|t| t.text.to_string())
+                .unwrap_or_default();
+            let leading = close_blocks(&mut flat, std::mem::take(&mut pending), indent);
+            flat.push((
+                indent,
+                Line {
+                    keyword,
+                    leading,
+                    trailing: (!comments.is_empty()).then(|| comments.join(" ")),
+                    ..Line::default()
+                },
+            ));
+        }
+
+        let dangling = close_blocks(&mut flat, pending, 0);
+        let mut flat = flat.into_iter().peekable();
+        Layout {
+            lines: nest(&mut flat, 0),
+            dangling,
+        }
+    }
+}
+
+/// Split the comment lines (`None` for a blank line) before a line at
+/// `indent`: those indented deeper than it, up to the first that isn't, end
+/// the deepest block whose indentation they match; the rest lead the line
+fn close_blocks(
+    flat: &mut [(usize, Line)],
+    comments: Vec<(Option<usize>, String)>,
+    indent: usize,
+) -> Vec<String> {
+    let end = comments
+        .iter()
+        .position(|(i, _)| i.is_some_and(|i| i <= indent))
+        .unwrap_or(comments.len());
+    let mut comments = comments.into_iter();
+    let mut closing: Vec<_> = comments.by_ref().take(end).collect();
+    // Blank lines before the line stay with it
+    let mut blanks = Vec::new();
+    while closing.last().is_some_and(|(i, _)| i.is_none()) {
+        blanks.extend(closing.pop().map(|(_, text)| text));
+    }
+
+    let mut leading = Vec::new();
+    let mut pending = Vec::new();
+    // Never attach a later comment to a block printed before an earlier one
+    let mut max_indent = usize::MAX;
+    for (comment_indent, text) in closing {
+        let Some(comment_indent) = comment_indent else {
+            pending.push(text);
+            continue;
+        };
+        match enclosing_block(flat, comment_indent.min(max_indent), indent) {
+            Some((parent, block_indent)) => {
+                flat[parent].1.closing.append(&mut pending);
+                flat[parent].1.closing.push(text);
+                max_indent = block_indent;
+            }
+            None => {
+                leading.append(&mut pending);
+                leading.push(text);
+            }
+        }
+    }
+    leading.extend(pending);
+    leading.extend(blanks);
+    leading.extend(comments.map(|(_, text)| text));
+    leading
+}
+
+/// The deepest block still open after the last line that is indented at
+/// most `max` and more than `min`: the index of the line it is under, and
+/// its indentation
+fn enclosing_block(flat: &[(usize, Line)], max: usize, min: usize) -> Option<(usize, usize)> {
+    let mut block_indent = flat.last()?.0;
+    let mut end = flat.len() - 1;
+    while block_indent > min {
+        let parent = flat[..end].iter().rposition(|(i, _)| *i < block_indent)?;
+        if block_indent <= max {
+            return Some((parent, block_indent));
+        }
+        block_indent = flat[parent].0;
+        end = parent;
+    }
+    None
+}
+
+/// Group lines into blocks by indentation
+fn nest(
+    flat: &mut std::iter::Peekable<std::vec::IntoIter<(usize, Line)>>,
+    min_indent: usize,
+) -> VecDeque<Line> {
+    let mut lines = VecDeque::new();
+    while let Some((indent, mut line)) = flat.next_if(|(indent, _)| *indent >= min_indent) {
+        line.children = nest(flat, indent + 1);
+        lines.push_back(line);
+    }
+    lines
+}
+
+fn comment(line: &str) -> Option<&str> {
+    lexer::tokenize(line)
+        .into_iter()
+        .find(|t| t.kind == TokenKind::Comment)
+        .map(|t| t.text.trim_end())
+}
+
+/// Next line, or an empty one when formatting without source
+fn take(lines: &mut VecDeque<Line>) -> Line {
+    lines.pop_front().unwrap_or_default()
+}
+
+/// Next line if it starts with `keyword` (e.g. `elif` after an `if` block)
+fn take_clause(lines: &mut VecDeque<Line>, keyword: &str) -> Line {
+    if lines.front().is_some_and(|l| l.keyword == keyword) {
+        take(lines)
+    } else {
+        Line::default()
+    }
+}
+
+// ============================================================================
+// Printer
+// ============================================================================
+
+/// Blank line policy before an item
+#[derive(Debug, Clone, Copy)]
+enum Blank {
+    /// First item in a block: drop blank lines before it
+    None,
+    /// Keep at most one blank line from the source
+    Keep,
+    /// Always separate with exactly one blank line
+    Always,
+}
+
+/// A member of an entity or interface, to print them in source order
+enum Member<'a> {
+    Component(&'a ComponentDef),
+    Variable(&'a VarDecl),
+    Signal(&'a SignalDef),
+    Function(&'a FnDef),
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+}
+
+impl Formatter {
+    fn program(mut self, program: &Program, layout: Layout) -> String {
+        self.statements(&program.statements, layout.lines, 0);
+
+        let mut dangling = layout.dangling;
+        while dangling.last().is_some_and(|c| c.is_empty()) {
+            dangling.pop();
+        }
+        let blank = if self.out.is_empty() {
+            Blank::None
+        } else {
+            Blank::Keep
+        };
+        self.leading(&dangling, 0, blank);
+        self.out
+    }
+
+    fn line(&mut self, depth: usize, text: &str, trailing: Option<&str>) {
+        for _ in 0..depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        if let Some(comment) = trailing {
+            self.out.push_str("  ");
+            self.out.push_str(comment);
+        }
+        self.out.push('\n');
+    }
+
+    fn leading(&mut self, leading: &[String], depth: usize, blank: Blank) {
+        let mut pending = matches!(blank, Blank::Always);
+        let mut started = false;
+        for text in leading {
+            if text.is_empty() {
+                pending |= started || matches!(blank, Blank::Keep);
+                continue;
+            }
+            if pending {
+                self.out.push('\n');
+                pending = false;
+            }
+            started = true;
+            self.line(depth, text, None);
+        }
+        if pending {
+            self.out.push('\n');
+        }
+    }
+
+    /// Comments at the end of a block, after its last line
+    fn closing(&mut self, closing: &[String], depth: usize) {
+        self.leading(closing, depth, Blank::Keep);
+    }
+
+    fn statements(&mut self, stmts: &[Statement], mut lines: VecDeque<Line>, depth: usize) {
+        let mut prev_definition = false;
+        let mut stmts = stmts.iter().peekable();
+        let mut first = true;
+
+        while let Some(stmt) = stmts.next() {
+            let definition = matches!(
+                stmt,
+                Statement::EntityDef(_)
+                    | Statement::StructDef(_)
+                    | Statement::EnumDef(_)
+                    | Statement::FnDef(_)
+                    | Statement::SystemDef(_)
+                    | Statement::StateMachine(_)
+            );
+            let blank = if first {
+                Blank::None
+            } else if definition || prev_definition {
+                Blank::Always
+            } else {
+                Blank::Keep
+            };
+            let line = take(&mut lines);
+
+            // `await` is not in the grammar yet, so `await wait(1.0)` parses as
+            // two statements on one line; keep them together
+            if ends_with_await(stmt) {
+                if let Some(next) = stmts.next_if(|s| simple_text(s).is_some()) {
+                    let text = format!(
+                        "{} {}",
+                        simple_text(stmt).unwrap_or_default(),
+                        simple_text(next).unwrap_or_default()
+                    );
+                    self.leading(&line.leading, depth, blank);
+                    self.line(depth, &text, line.trailing.as_deref());
+                    prev_definition = false;
+                    first = false;
+                    continue;
+                }
+            }
+
+            self.statement(stmt, line, &mut lines, depth, blank);
+            prev_definition = definition;
+            first = false;
+        }
+    }
+
+    fn statement(
+        &mut self,
+        stmt: &Statement,
+        line: Line,
+        rest: &mut VecDeque<Line>,
+        depth: usize,
+        blank: Blank,
+    ) {
+        self.leading(&line.leading, depth, blank);
+        let trailing = line.trailing.as_deref();
+
+        match stmt {
+            Statement::EntityDef(entity) => {
+                let mut header = format!("entity {}", entity.name);
+                if let Some(base) = &entity.extends {
+                    header.push_str(&format!(" extends {}", base));
+                }
+                if !entity.implements.is_empty() {
+                    header.push_str(&format!(" implements {}", entity.implements.join(", ")));
+                }
+                header.push(':');
+                self.line(depth, &header, trailing);
+                self.entity(entity, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::StructDef(def) => {
+                self.line(depth, &format!("struct {}:", def.name), trailing);
+                let fields = def.fields.iter().map(|field| match &field.default {
+                    Some(default) => {
+                        format!("{}: {} = {}", field.name, field.type_expr, expr(default))
+                    }
+                    None => format!("{}: {}", field.name, field.type_expr),
+                });
+                self.members(fields, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::EnumDef(def) => {
+                self.line(depth, &format!("enum {}:", def.name), trailing);
+                let variants = def.variants.iter().map(|variant| {
+                    if variant.fields.is_empty() {
+                        variant.name.clone()
+                    } else {
+                        format!("{}({})", variant.name, params(&variant.fields))
+                    }
+                });
+                self.members(variants, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::InterfaceDef(def) => {
+                self.line(depth, &format!("interface {}:", def.name), trailing);
+                self.interface(def, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::FnDef(func) => {
+                let line = self.annotations(&func.annotations, line, rest, depth);
+                let trailing = line.trailing.as_deref();
+                self.line(depth, &format!("{}:", func.signature()), trailing);
+                self.statements(&func.body, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::SystemDef(system) => {
+                let line = self.annotations(&system.annotations, line, rest, depth);
+                let trailing = line.trailing.as_deref();
+                self.line(depth, &format!("{}:", system.signature()), trailing);
+                self.statements(&system.body, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::StateMachine(machine) => {
+                self.line(depth, &format!("state_machine {}:", machine.name), trailing);
+                self.state_machine(machine, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::If(if_stmt) => {
+                self.line(
+                    depth,
+                    &format!("if {}:", expr(&if_stmt.condition)),
+                    trailing,
+                );
+                self.statements(&if_stmt.then_body, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+
+                for (condition, body) in &if_stmt.elif_clauses {
+                    let clause = take_clause(rest, "elif");
+                    self.leading(&clause.leading, depth, Blank::None);
+                    self.line(
+                        depth,
+                        &format!("elif {}:", expr(condition)),
+                        clause.trailing.as_deref(),
+                    );
+                    self.statements(body, clause.children, depth + 1);
+                    self.closing(&clause.closing, depth + 1);
+                }
+
+                if let Some(body) = &if_stmt.else_body {
+                    let clause = take_clause(rest, "else");
+                    self.leading(&clause.leading, depth, Blank::None);
+                    self.line(depth, "else:", clause.trailing.as_deref());
+                    self.statements(body, clause.children, depth + 1);
+                    self.closing(&clause.closing, depth + 1);
+                }
+            }
+            Statement::Match(match_stmt) => {
+                let header = format!("match {}:", expr(&match_stmt.subject));
+                self.line(depth, &header, trailing);
+                let mut arms = line.children;
+                for arm in &match_stmt.arms {
+                    let clause = take_clause(&mut arms, "case");
+                    self.leading(&clause.leading, depth + 1, Blank::None);
+                    let guard = arm
+                        .guard
+                        .as_ref()
+                        .map(|guard| format!(" if {}", expr(guard)))
+                        .unwrap_or_default();
+                    let header = format!("case {}{}:", pattern(&arm.pattern), guard);
+                    self.line(depth + 1, &header, clause.trailing.as_deref());
+                    self.statements(&arm.body, clause.children, depth + 2);
+                    self.closing(&clause.closing, depth + 2);
+                }
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::While(while_stmt) => {
+                let header = format!("while {}:", expr(&while_stmt.condition));
+                self.line(depth, &header, trailing);
+                self.statements(&while_stmt.body, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            Statement::For(for_stmt) => {
+                let header = format!(
+                    "for {} in {}:",
+                    for_stmt.names.join(", "),
+                    expr(&for_stmt.iterable)
+                );
+                self.line(depth, &header, trailing);
+                self.statements(&for_stmt.body, line.children, depth + 1);
+                self.closing(&line.closing, depth + 1);
+            }
+            _ => self.simple(stmt, depth, trailing),
+        }
+    }
+
+    /// A single-line statement, wrapping its call arguments if it is too long
+    fn simple(&mut self, stmt: &Statement, depth: usize, trailing: Option<&str>) {
+        let text = simple_text(stmt).unwrap_or_default();
+        let width = depth * INDENT.len() + text.chars().count();
+        match wrap_parts(stmt) {
+            Some((open, args)) if width > MAX_WIDTH => {
+                self.line(depth, &open, None);
+                let last = args.len() - 1;
+                for (i, arg) in args.iter().enumerate() {
+                    let comma = if i == last { "" } else { "," };
+                    self.line(depth + 1, &format!("{}{}", arg, comma), None);
+                }
+                self.line(depth, ")", trailing);
+            }
+            _ => self.line(depth, &text, trailing),
+        }
+    }
+
+    /// Entity members in source order, with a blank line around components
+    /// and functions and between a run of variables or signals and the rest
+    fn entity(&mut self, entity: &EntityDef, lines: VecDeque<Line>, depth: usize) {
+        let mut components = VecDeque::new();
+        let mut variables = VecDeque::new();
+        let mut signals = VecDeque::new();
+        let mut functions = VecDeque::new();
+        for line in lines {
+            match line.keyword.as_str() {
+                "component" => components.push_back(line),
+                "let" => variables.push_back(line),
+                "signal" => signals.push_back(line),
+                "fn" | "async" | "@" => functions.push_back(line),
+                _ => {}
+            }
+        }
+
+        // Without source every span is empty, so the sort keeps the order
+        // of the lists
+        let mut members: Vec<(usize, Member)> = entity
+            .components
+            .iter()
+            .map(|c| (c.span.start, Member::Component(c)))
+            .chain(
+                entity
+                    .variables
+                    .iter()
+                    .map(|v| (v.span.start, Member::Variable(v))),
+            )
+            .chain(
+                entity
+                    .signals
+                    .iter()
+                    .map(|s| (s.span.start, Member::Signal(s))),
+            )
+            .chain(
+                entity
+                    .functions
+                    .iter()
+                    .map(|f| (f.span.start, Member::Function(f))),
+            )
+            .collect();
+        members.sort_by_key(|(start, _)| *start);
+
+        let mut previous: Option<std::mem::Discriminant<Member>> = None;
+        for (_, member) in members {
+            let kind = std::mem::discriminant(&member);
+            let blank = match (previous, &member) {
+                (None, _) => Blank::None,
+                (_, Member::Component(_) | Member::Function(_)) => Blank::Always,
+                (Some(previous), _) if previous != kind => Blank::Always,
+                _ => Blank::Keep,
+            };
+            previous = Some(kind);
+            match member {
+                Member::Component(component) => {
+                    let line = take(&mut components);
+                    self.component(component, line, depth, blank);
+                }
+                Member::Variable(var) => {
+                    let line = take(&mut variables);
+                    let stmt = Statement::VarDecl(var.clone());
+                    self.statement(&stmt, line, &mut VecDeque::new(), depth, blank);
+                }
+                Member::Signal(signal) => {
+                    let line = take(&mut signals);
+                    let stmt = Statement::SignalDef(signal.clone());
+                    self.statement(&stmt, line, &mut VecDeque::new(), depth, blank);
+                }
+                Member::Function(func) => {
+                    let line = take(&mut functions);
+                    let stmt = Statement::FnDef(func.clone());
+                    self.statement(&stmt, line, &mut functions, depth, blank);
+                }
+            }
+        }
+    }
+
+    /// One line per annotation, starting on `line`; returns the line of the
+    /// definition they annotate
+    fn annotations(
+        &mut self,
+        annotations: &[Annotation],
+        mut line: Line,
+        rest: &mut VecDeque<Line>,
+        depth: usize,
+    ) -> Line {
+        for annotation in annotations {
+            let mut text = format!("@{}", annotation.name);
+            if !annotation.args.is_empty() {
+                let args: Vec<String> = annotation.args.iter().map(expr).collect();
+                text.push_str(&format!("({})", args.join(", ")));
+            }
+            self.line(depth, &text, line.trailing.as_deref());
+            line = take(rest);
+            self.leading(&line.leading, depth, Blank::None);
+        }
+        line
+    }
+
+    /// Interface signals, then functions, like an entity's
+    fn interface(&mut self, def: &InterfaceDef, lines: VecDeque<Line>, depth: usize) {
+        let (mut signals, mut functions): (VecDeque<Line>, VecDeque<Line>) =
+            lines.into_iter().partition(|line| line.keyword == "signal");
+        // In source order, like an entity's members
+        let mut members: Vec<(usize, Member)> = def
+            .signals
+            .iter()
+            .map(|s| (s.span.start, Member::Signal(s)))
+            .chain(
+                def.functions
+                    .iter()
+                    .map(|f| (f.span.start, Member::Function(f))),
+            )
+            .collect();
+        members.sort_by_key(|(start, _)| *start);
+
+        let mut previous: Option<std::mem::Discriminant<Member>> = None;
+        for (_, member) in members {
+            let kind = std::mem::discriminant(&member);
+            let blank = match previous {
+                None => Blank::None,
+                Some(previous) if previous != kind => Blank::Always,
+                _ => Blank::Keep,
+            };
+            previous = Some(kind);
+            let (text, line) = match member {
+                Member::Signal(signal) => (
+                    simple_text(&Statement::SignalDef(signal.clone())),
+                    take(&mut signals),
+                ),
+                Member::Function(func) => (Some(func.signature()), take(&mut functions)),
+                Member::Component(_) | Member::Variable(_) => continue,
+            };
+            self.leading(&line.leading, depth, blank);
+            self.line(depth, &text.unwrap_or_default(), line.trailing.as_deref());
+        }
+    }
+
+    fn component(&mut self, component: &ComponentDef, line: Line, depth: usize, blank: Blank) {
+        self.leading(&line.leading, depth, blank);
+        let trailing = line.trailing.as_deref();
+        if component.fields.is_empty() {
+            self.line(depth, &format!("component {}", component.name), trailing);
+            return;
+        }
+
+        self.line(depth, &format!("component {}:", component.name), trailing);
+        let mut lines = line.children;
+        for (i, (name, value)) in component.fields.iter().enumerate() {
+            let field = take(&mut lines);
+            let blank = if i == 0 { Blank::None } else { Blank::Keep };
+            self.leading(&field.leading, depth + 1, blank);
+            let text = format!("{} = {}", name, expr(value));
+            self.line(depth + 1, &text, field.trailing.as_deref());
+        }
+        self.closing(&line.closing, depth + 1);
+    }
+
+    /// One line per struct field or enum variant, keeping their comments
+    fn members(
+        &mut self,
+        members: impl Iterator<Item = String>,
+        mut lines: VecDeque<Line>,
+        depth: usize,
+    ) {
+        for (i, text) in members.enumerate() {
+            let member = take(&mut lines);
+            let blank = if i == 0 { Blank::None } else { Blank::Keep };
+            self.leading(&member.leading, depth, blank);
+            self.line(depth, &text, member.trailing.as_deref());
+        }
+    }
+
+    fn state_machine(&mut self, machine: &StateMachine, mut lines: VecDeque<Line>, depth: usize) {
+        let mut first = true;
+        if let Some(initial) = &machine.initial_state {
+            let line = take_clause(&mut lines, "initial");
+            self.leading(&line.leading, depth, Blank::None);
+            let text = format!("initial = {}", initial);
+            self.line(depth, &text, line.trailing.as_deref());
+            first = false;
+        }
+
+        for state in &machine.states {
+            let line = take_clause(&mut lines, "state");
+            let blank = if first { Blank::None } else { Blank::Always };
+            self.leading(&line.leading, depth, blank);
+            self.line(
+                depth,
+                &format!("state {}:", state.name),
+                line.trailing.as_deref(),
+            );
+            self.statements(&state.body, line.children, depth + 1);
+            self.closing(&line.closing, depth + 1);
+            first = false;
+        }
+    }
+}
+
+/// Whether the statement ends with a bare `await` (see `Formatter::statements`)
+fn ends_with_await(stmt: &Statement) -> bool {
+    let await_kw = Expr::Identifier("await".to_string());
+    value_parts(stmt).is_some_and(|(_, value)| *value == await_kw)
+}
+
+/// Prefix and value of statements that end in an expression
+fn value_parts(stmt: &Statement) -> Option<(String, &Expr)> {
+    match stmt {
+        Statement::VarDecl(var) => {
+            let type_expr = var
+                .type_expr
+                .as_ref()
+                .map(|t| format!(": {}", t))
+                .unwrap_or_default();
+            Some((format!("let {}{} = ", var.name, type_expr), &var.value))
+        }
+        Statement::Assignment(assign) => {
+            let op = match assign.op {
+                AssignOp::Assign => "=",
+                AssignOp::AddAssign => "+=",
+                AssignOp::SubAssign => "-=",
+                AssignOp::MulAssign => "*=",
+                AssignOp::DivAssign => "/=",
+            };
+            Some((
+                format!("{} {} ", assign.target.parts.join("."), op),
+                &assign.value,
+            ))
+        }
+        Statement::Return(Some(value)) => Some(("return ".to_string(), value)),
+        Statement::Expr { expr: value, .. } => Some((String::new(), value)),
+        _ => None,
+    }
+}
+
+/// Text of a statement that fits on one line, `None` for block statements
+fn simple_text(stmt: &Statement) -> Option<String> {
+    if let Some((prefix, value)) = value_parts(stmt) {
+        return Some(format!("{}{}", prefix, expr(value)));
+    }
+    match stmt {
+        Statement::Import(import) if import.names.is_empty() => {
+            Some(format!("import {}", import.module.join(".")))
+        }
+        Statement::Import(import) => Some(format!(
+            "from {} import {}",
+            import.module.join("."),
+            import.names.join(", ")
+        )),
+        Statement::Return(None) => Some("return".to_string()),
+        Statement::Break { .. } => Some("break".to_string()),
+        Statement::Continue { .. } => Some("continue".to_string()),
+        Statement::Pass => Some("pass".to_string()),
+        Statement::Emit(emit) => {
+            let args: Vec<String> = emit.args.iter().map(expr).collect();
+            Some(format!("emit {}({})", emit.signal_name, args.join(", ")))
+        }
+        Statement::SignalDef(signal) => Some(format!(
+            "signal {}({})",
+            signal.name,
+            params(&signal.params)
+        )),
+        _ => None,
+    }
+}
+
+/// Opening line and arguments of a statement whose call can be wrapped
+fn wrap_parts(stmt: &Statement) -> Option<(String, Vec<String>)> {
+    if let Statement::Emit(emit) = stmt {
+        let args: Vec<String> = emit.args.iter().map(expr).collect();
+        return (!args.is_empty()).then(|| (format!("emit {}(", emit.signal_name), args));
+    }
+    match value_parts(stmt)? {
+        (prefix, Expr::Call { callee, args }) if !args.is_empty() => {
+            let open = format!("{}{}(", prefix, expr_prec(callee, PRIMARY));
+            Some((open, args.iter().map(arg).collect()))
+        }
+        _ => None,
+    }
+}
+
+fn params(params: &[Param]) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|p| format!("{}: {}", p.name, p.type_expr))
+        .collect();
+    params.join(", ")
+}
+
+// ============================================================================
+// Expressions
+// ============================================================================
+
+// Binding strength, mirroring the expression rules in grammar.pest. A
+// lambda's body takes everything after it, so it binds loosest of all.
+const LAMBDA: u8 = 0;
+const OR: u8 = 1;
+const AND: u8 = 2;
+const NOT: u8 = 3;
+const COMPARISON: u8 = 4;
+const ADDITIVE: u8 = 5;
+const MULTIPLICATIVE: u8 = 6;
+const NEG: u8 = 7;
+const PRIMARY: u8 = 8;
+
+/// Format an expression with only the parentheses its precedence needs
+fn expr(e: &Expr) -> String {
+    expr_prec(e, 0)
+}
+
+fn expr_prec(e: &Expr, min: u8) -> String {
+    let (prec, text) = match e {
+        Expr::Int(n) => (PRIMARY, n.to_string()),
+        Expr::Float(n) => {
+            let text = n.to_string();
+            if text.contains('.') {
+                (PRIMARY, text)
+            } else {
+                (PRIMARY, format!("{}.0", text))
+            }
+        }
+        Expr::String(s) => (PRIMARY, format!("\"{}\"", s)),
+        Expr::Bool(b) => (PRIMARY, b.to_string()),
+        Expr::Vec2(x, y) => (PRIMARY, format!("Vec2({}, {})", expr(x), expr(y))),
+        Expr::Vec3(x, y, z) => (
+            PRIMARY,
+            format!("Vec3({}, {}, {})", expr(x), expr(y), expr(z)),
+        ),
+        Expr::List(items) => {
+            let items: Vec<String> = items.iter().map(expr).collect();
+            (PRIMARY, format!("[{}]", items.join(", ")))
+        }
+        Expr::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", map_key(key), expr(value)))
+                .collect();
+            (PRIMARY, format!("{{{}}}", entries.join(", ")))
+        }
+        Expr::Tuple(items) => {
+            let items: Vec<String> = items.iter().map(expr).collect();
+            (PRIMARY, format!("({})", items.join(", ")))
+        }
+        Expr::Identifier(name) => (PRIMARY, name.clone()),
+        Expr::MemberAccess(base, member) => {
+            (PRIMARY, format!("{}.{}", expr_prec(base, PRIMARY), member))
+        }
+        Expr::Index(base, index) => (
+            PRIMARY,
+            format!("{}[{}]", expr_prec(base, PRIMARY), expr(index)),
+        ),
+        Expr::Call { callee, args } => {
+            let args: Vec<String> = args.iter().map(arg).collect();
+            (
+                PRIMARY,
+                format!("{}({})", expr_prec(callee, PRIMARY), args.join(", ")),
+            )
+        }
+        // Written back as the constructor call it came from
+        Expr::Construct { path, fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, expr(value)))
+                .collect();
+            (
+                PRIMARY,
+                format!("{}({})", path.replace("::", "."), fields.join(", ")),
+            )
+        }
+        // `fn(...)` is only needed to spell out the return type
+        Expr::Lambda {
+            params,
+            return_type,
+            body,
+        } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|p| match &p.type_expr {
+                    Some(t) => format!("{}: {}", p.name, t),
+                    None => p.name.clone(),
+                })
+                .collect();
+            let text = match return_type {
+                Some(t) => format!("fn({}) -> {}: {}", params.join(", "), t, expr(body)),
+                None => format!("|{}| {}", params.join(", "), expr(body)),
+            };
+            (LAMBDA, text)
+        }
+        Expr::UnaryOp(UnaryOp::Neg, operand) => (NEG, format!("-{}", expr_prec(operand, PRIMARY))),
+        Expr::UnaryOp(UnaryOp::Not, operand) => {
+            (NOT, format!("not {}", expr_prec(operand, COMPARISON)))
+        }
+        Expr::BinaryOp(left, op, right) => {
+            let (prec, symbol) = binary_op(*op);
+            // Operators chain to the left, so only the right side needs a
+            // tighter binding to keep its grouping
+            let text = format!(
+                "{} {} {}",
+                expr_prec(left, prec),
+                symbol,
+                expr_prec(right, prec + 1)
+            );
+            (prec, text)
+        }
+    };
+
+    if prec < min {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+fn pattern(p: &Pattern) -> String {
+    match p {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(name) => name.clone(),
+        Pattern::Literal(value) => expr(value),
+        Pattern::Range {
+            start,
+            end,
+            inclusive,
+        } => format!(
+            "{}{}{}",
+            expr(start),
+            if *inclusive { "..=" } else { ".." },
+            expr(end)
+        ),
+        Pattern::Variant { path, fields } if fields.is_empty() => path.join("."),
+        Pattern::Variant { path, fields } => {
+            let fields: Vec<String> = fields.iter().map(pattern).collect();
+            format!("{}({})", path.join("."), fields.join(", "))
+        }
+        Pattern::Tuple(items) => {
+            let items: Vec<String> = items.iter().map(pattern).collect();
+            format!("({})", items.join(", "))
+        }
+        // Written back as the variant pattern it came from
+        Pattern::Destructure { path, fields } => {
+            let path = path.replace("::", ".");
+            if fields.is_empty() {
+                return path;
+            }
+            let fields: Vec<String> = fields.iter().map(|(_, field)| pattern(field)).collect();
+            format!("{}({})", path, fields.join(", "))
+        }
+    }
+}
+
+fn arg(arg: &crate::Arg) -> String {
+    match &arg.name {
+        Some(name) => format!("{}: {}", name, expr(&arg.value)),
+        None => expr(&arg.value),
+    }
+}
+
+fn map_key(key: &str) -> String {
+    let is_identifier = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier {
+        key.to_string()
+    } else {
+        format!("\"{}\"", key)
+    }
+}
+
+fn binary_op(op: BinaryOp) -> (u8, &'static str) {
+    match op {
+        BinaryOp::Or => (OR, "or"),
+        BinaryOp::And => (AND, "and"),
+        BinaryOp::Eq => (COMPARISON, "=="),
+        BinaryOp::Ne => (COMPARISON, "!="),
+        BinaryOp::Lt => (COMPARISON, "<"),
+        BinaryOp::Le => (COMPARISON, "<="),
+        BinaryOp::Gt => (COMPARISON, ">"),
+        BinaryOp::Ge => (COMPARISON, ">="),
+        BinaryOp::Add => (ADDITIVE, "+"),
+        BinaryOp::Sub => (ADDITIVE, "-"),
+        BinaryOp::Mul => (MULTIPLICATIVE, "*"),
+        BinaryOp::Div => (MULTIPLICATIVE, "/"),
+        BinaryOp::Mod => (MULTIPLICATIVE, "%"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    const MESSY: &str = r#"# Player controller
+entity Player:
+  fn on_ready():
+      print( "hi" )   # greet
+  signal died()
+  let speed   =200.0
+  component Health:
+      current=100
+      # upper bound
+      max = 100
+
+  fn take_damage(amount:int):
+      Health.current-=amount
+
+
+      if Health.current<=0 and not(speed>1.5):
+          emit died()
+      elif Health.current < (10 - 2) * 3:
+          print("low")
+      else:
+          pass_turn(-(-amount))
+
+fn helper(x: float) -> float:
+    return (x + 1) * -x
+state_machine Movement:
+    initial = Idle
+    state Idle:
+        velocity = Vec2(0,0)
+    state Run:
+        velocity = Vec2( speed ,0)
+# trailing note
+"#;
+
+    /// The AST as JSON without spans, which move when layout changes
+    fn ast(source: &str) -> serde_json::Value {
+        fn strip(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.remove("span");
+                    map.values_mut().for_each(strip);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(parse(source).unwrap()).unwrap();
+        strip(&mut value);
+        value
+    }
+
+    fn assert_round_trip(source: &str) -> String {
+        let formatted = format_source(source).unwrap();
+        assert_eq!(ast(&formatted), ast(source), "{}", formatted);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let formatted = assert_round_trip(MESSY);
+        assert!(formatted.starts_with("# Player controller\nentity Player:\n    fn on_ready():\n"));
+        assert!(formatted.contains("        # upper bound\n        max = 100\n"));
+        // Members keep their order
+        assert!(formatted
+            .contains("    signal died()\n\n    let speed = 200.0\n\n    component Health:\n"));
+        assert!(formatted.contains("print(\"hi\")  # greet\n"));
+        assert!(formatted.contains("if Health.current <= 0 and not speed > 1.5:"));
+        assert!(formatted.contains("elif Health.current < (10 - 2) * 3:"));
+        assert!(formatted.contains("pass_turn(-(-amount))"));
+        assert!(formatted.contains("return (x + 1) * -x"));
+        assert!(formatted.ends_with("        velocity = Vec2(speed, 0)\n# trailing note\n"));
+
+        assert_round_trip(include_str!("../examples/player.nx"));
+    }
+
+    #[test]
+    fn test_format_structs_and_enums() {
+        let formatted = assert_round_trip(
+            "struct  Weapon:\n    name:str\n    damage : int=1\nenum Shape:\n    Empty\n    Circle( radius:float )\n",
+        );
+        assert_eq!(
+            formatted,
+            "struct Weapon:\n    name: str\n    damage: int = 1\n\nenum Shape:\n    Empty\n    Circle(radius: float)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_match() {
+        let formatted = assert_round_trip(
+            "match  (n,flag):\n    # small ones\n    case (0 ..10, true) if  n>1 :\n        pass\n    case Shape.Circle( r ):  # round\n        pass\n    case -1..=-1:\n        pass\n    case _:\n        pass\n",
+        );
+        assert_eq!(
+            formatted,
+            "match (n, flag):\n    # small ones\n    case (0..10, true) if n > 1:\n        pass\n    case Shape.Circle(r):  # round\n        pass\n    case -1..=-1:\n        pass\n    case _:\n        pass\n"
+        );
+    }
+
+    #[test]
+    fn test_format_loops() {
+        let formatted = assert_round_trip(
+            "for i ,item in enumerate( items ):\n    if item<0 :\n        continue\n    break\nwhile true:\n    pass\n",
+        );
+        assert_eq!(
+            formatted,
+            "for i, item in enumerate(items):\n    if item < 0:\n        continue\n    break\nwhile true:\n    pass\n"
+        );
+    }
+
+    #[test]
+    fn test_format_lambdas() {
+        let formatted = assert_round_trip(
+            "let f = fn( x:int )->int :x*2\nlet g = |a,b|a+b\nlet h = (||  1)() + (|x| x)(2)\n",
+        );
+        assert_eq!(
+            formatted,
+            "let f = fn(x: int) -> int: x * 2\nlet g = |a, b| a + b\nlet h = (|| 1)() + (|x| x)(2)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_interfaces() {
+        let formatted = assert_round_trip(
+            "interface Damageable:\n    fn take_damage( amount:int )\n    # dead\n    signal died()\nentity Crate implements Damageable ,combat.Targetable:\n    fn take_damage(amount: int):\n        pass\n",
+        );
+        assert_eq!(
+            formatted,
+            "interface Damageable:\n    fn take_damage(amount: int)\n\n    # dead\n    signal died()\n\nentity Crate implements Damageable, combat.Targetable:\n    fn take_damage(amount: int):\n        pass\n"
+        );
+    }
+
+    #[test]
+    fn test_format_inheritance() {
+        let formatted = assert_round_trip(
+            "entity Boss  extends enemies.Enemy implements Damageable:\n    fn take_damage(amount: int):\n        super.take_damage( amount )\n",
+        );
+        assert_eq!(
+            formatted,
+            "entity Boss extends enemies.Enemy implements Damageable:\n    fn take_damage(amount: int):\n        super.take_damage(amount)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_systems() {
+        let formatted = assert_round_trip(
+            "system gravity(q:Query<Transform,Velocity,with Player ,without Frozen>):\n    for t, v in q:\n        v.y -= 9.8\nsystem report(q: Query<Velocity>):\n    pass\n",
+        );
+        assert_eq!(
+            formatted,
+            "system gravity(q: Query<Transform, Velocity, with Player, without Frozen>):\n    for t, v in q:\n        v.y -= 9.8\n\nsystem report(q: Query<Velocity>):\n    pass\n"
+        );
+    }
+
+    #[test]
+    fn test_format_schedules() {
+        let formatted = assert_round_trip(
+            "@schedule( FixedUpdate )\n# before physics\n@after(spawn,Player.on_update)  # ordered\nsystem gravity():\n    pass\nentity Player:\n    let speed = 1.0\n    @run_if(in_state(Game.Playing))\n    fn on_update(delta: float):\n        pass\n",
+        );
+        assert_eq!(
+            formatted,
+            "@schedule(FixedUpdate)\n# before physics\n@after(spawn, Player.on_update)  # ordered\nsystem gravity():\n    pass\n\nentity Player:\n    let speed = 1.0\n\n    @run_if(in_state(Game.Playing))\n    fn on_update(delta: float):\n        pass\n"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_keyword_prefixed_names() {
+        let source = "fn check(notice: bool, order: int) -> bool:\n    important( order )\n    if not notice and order>0 :\n        return notice\n    return not  notice\n";
+        let formatted = assert_round_trip(source);
+        assert_eq!(
+            formatted,
+            "fn check(notice: bool, order: int) -> bool:\n    important(order)\n    if not notice and order > 0:\n        return notice\n    return not notice\n"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_comments_at_the_end_of_blocks() {
+        let source = "entity Player:\n    fn on_ready():\n        let x = 1\n        # end of on_ready\n\n    # before take_damage\n    fn take_damage(amount: int):\n        if amount > 0:\n            pass\n            # end of if\n        # end of take_damage\n# end of file\n";
+        assert_eq!(assert_round_trip(source), source);
+
+        // Over-indented comments go to the deepest block at or above them
+        let formatted =
+            assert_round_trip("fn helper():\n    pass\n      # helper\nfn other():\n    pass\n");
+        assert_eq!(
+            formatted,
+            "fn helper():\n    pass\n    # helper\n\nfn other():\n    pass\n"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_crlf_line_endings() {
+        let formatted = assert_round_trip("# hp\r\nentity Crate:\r\n    let  hp=3  # full\r\n");
+        assert_eq!(
+            formatted,
+            "# hp\r\nentity Crate:\r\n    let hp = 3  # full\r\n"
+        );
+    }
+
+    #[test]
+    fn test_format_wraps_long_calls() {
+        let source = "spawn_enemy(\"goblin\", Vec2(100.0, 200.0), health: 250, speed: 3.5, aggressive: true, loot: [1, 2, 3], boss: false)\n";
+        let formatted = assert_round_trip(source);
+        assert_eq!(
+            formatted,
+            "spawn_enemy(\n    \"goblin\",\n    Vec2(100.0, 200.0),\n    health: 250,\n    speed: 3.5,\n    aggressive: true,\n    loot: [1, 2, 3],\n    boss: false\n)\n"
+        );
+    }
+}
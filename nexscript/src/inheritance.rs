@@ -0,0 +1,518 @@
+//! Entity inheritance
+//!
+//! `entity Boss extends Enemy:` gets every variable, component, signal and
+//! function of `Enemy` it doesn't declare itself. Component fields are
+//! inherited one by one, so a derived entity can change a single default.
+//! Code generation and checking work on the flattened entity.
+//!
+//! `super.take_damage(amount)` calls the base's version of an overridden
+//! function. The base function is kept in the derived entity as
+//! `super_take_damage`, and its own `super_` functions gain another prefix.
+
+use crate::modules::{Imported, Imports, Item};
+use crate::{EntityDef, Expr, FnDef, Program, Span, Statement};
+use std::borrow::Cow;
+
+/// Prefix of base functions kept for `super` calls
+const SUPER: &str = "super_";
+
+/// Why an entity's base couldn't be inherited from
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Error {
+    /// The name after `extends` isn't an entity
+    Unknown(String),
+    /// The entity extends itself; the names from the entity back to itself
+    Cycle(Vec<String>),
+}
+
+/// An entity another one extends
+pub(crate) struct Base<'a> {
+    /// Already flattened when imported
+    pub entity: Cow<'a, EntityDef>,
+    /// Where it comes from, when declared in another file
+    pub imported: Option<&'a Imported<'a>>,
+}
+
+/// Finds base entities by the name `extends` uses: one of the file's own
+/// entities or an imported one
+pub(crate) struct Bases<'a> {
+    program: &'a Program,
+    imports: &'a Imports<'a>,
+}
+
+impl<'a> Bases<'a> {
+    pub fn new(program: &'a Program, imports: &'a Imports<'a>) -> Self {
+        Self { program, imports }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Base<'a>> {
+        let imported = match name.split_once('.') {
+            Some((module, member)) => self.imports.modules.get(module)?.get(member)?,
+            None => {
+                let local = self.program.statements.iter().find_map(|stmt| match stmt {
+                    Statement::EntityDef(entity) if entity.name == name => Some(entity),
+                    _ => None,
+                });
+                if let Some(entity) = local {
+                    return Some(Base {
+                        entity: Cow::Borrowed(entity),
+                        imported: None,
+                    });
+                }
+                self.imports
+                    .names
+                    .get(name)
+                    .or_else(|| self.imports.prelude.get(name))?
+            }
+        };
+        let Item::Entity(entity) = imported.item else {
+            return None;
+        };
+        let entity = match self.imports.bases.get(name) {
+            Some(flattened) => Cow::Borrowed(flattened),
+            None => Cow::Borrowed(entity),
+        };
+        Some(Base {
+            entity,
+            imported: Some(imported),
+        })
+    }
+
+    /// The entity with everything it inherits
+    pub fn flatten(&self, entity: &EntityDef) -> Result<EntityDef, Error> {
+        self.flatten_from(entity, &mut Vec::new())
+    }
+
+    /// The entity's base, flattened
+    pub fn base(&self, entity: &EntityDef) -> Result<Option<EntityDef>, Error> {
+        let Some(name) = &entity.extends else {
+            return Ok(None);
+        };
+        let base = self.get(name).ok_or_else(|| Error::Unknown(name.clone()))?;
+        match base.imported {
+            // Spans of another file would point at unrelated code here
+            Some(_) => {
+                let mut base = base.entity.into_owned();
+                respan(&mut base, entity.span);
+                Ok(Some(base))
+            }
+            None => {
+                let mut chain = vec![entity.name.clone()];
+                self.flatten_from(&base.entity, &mut chain).map(Some)
+            }
+        }
+    }
+
+    fn flatten_from(
+        &self,
+        entity: &EntityDef,
+        chain: &mut Vec<String>,
+    ) -> Result<EntityDef, Error> {
+        if chain.contains(&entity.name) {
+            chain.push(entity.name.clone());
+            return Err(Error::Cycle(chain.clone()));
+        }
+        let Some(name) = &entity.extends else {
+            return Ok(entity.clone());
+        };
+        let base = self.get(name).ok_or_else(|| Error::Unknown(name.clone()))?;
+        let base = match base.imported {
+            Some(_) => {
+                let mut base = base.entity.into_owned();
+                respan(&mut base, entity.span);
+                base
+            }
+            None => {
+                chain.push(entity.name.clone());
+                let base = self.flatten_from(&base.entity, chain)?;
+                chain.pop();
+                base
+            }
+        };
+        Ok(inherit(entity, base))
+    }
+}
+
+/// Every entity in the program flattened for code generation; entities
+/// whose base can't be found are left as they are
+pub(crate) fn flatten_program(program: &Program, imports: &Imports) -> Program {
+    let bases = Bases::new(program, imports);
+    let statements = program
+        .statements
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::EntityDef(entity) if entity.extends.is_some() => {
+                let Ok(mut flat) = bases.flatten(entity) else {
+                    return stmt.clone();
+                };
+                qualify(&mut flat);
+                Statement::EntityDef(flat)
+            }
+            stmt => stmt.clone(),
+        })
+        .collect();
+    Program { statements }
+}
+
+/// Prefix a derived entity's helper functions with its name, as its systems
+/// are (`boss_take_damage`), so they don't clash with the copies its base
+/// generates in the same module
+fn qualify(entity: &mut EntityDef) {
+    let prefix = format!("{}_", entity.name.to_lowercase());
+    let helpers: Vec<String> = entity
+        .functions
+        .iter()
+        .filter(|f| !matches!(f.name.as_str(), "on_update" | "on_ready"))
+        .map(|f| f.name.clone())
+        .collect();
+    for func in &mut entity.functions {
+        if helpers.contains(&func.name) {
+            func.name = format!("{}{}", prefix, func.name);
+        }
+        rename_calls(&mut func.body, &|name| {
+            helpers
+                .contains(&name.to_string())
+                .then(|| format!("{}{}", prefix, name))
+        });
+    }
+}
+
+/// `entity` with the members of its flattened `base` it doesn't override
+pub(crate) fn inherit(entity: &EntityDef, base: EntityDef) -> EntityDef {
+    let mut components = base.components;
+    for component in &entity.components {
+        match components.iter_mut().find(|c| c.name == component.name) {
+            Some(inherited) => {
+                for (name, value) in &component.fields {
+                    match inherited.fields.iter_mut().find(|(field, _)| field == name) {
+                        Some(field) => field.1 = value.clone(),
+                        None => inherited.fields.push((name.clone(), value.clone())),
+                    }
+                }
+            }
+            None => components.push(component.clone()),
+        }
+    }
+
+    let mut variables = base.variables;
+    for var in &entity.variables {
+        match variables.iter_mut().find(|v| v.name == var.name) {
+            Some(inherited) => *inherited = var.clone(),
+            None => variables.push(var.clone()),
+        }
+    }
+
+    let mut signals = base.signals;
+    for signal in &entity.signals {
+        match signals.iter_mut().find(|s| s.name == signal.name) {
+            Some(inherited) => *inherited = signal.clone(),
+            None => signals.push(signal.clone()),
+        }
+    }
+
+    let mut own = entity.functions.clone();
+    let mut called = Vec::new();
+    for func in &mut own {
+        called.extend(call_super(&mut func.body));
+    }
+    // `super` calls to functions the entity doesn't override call the
+    // inherited one
+    let overrides = |name: &str| own.iter().any(|f| f.name == name);
+    let direct: Vec<String> = called
+        .iter()
+        .filter(|name| !overrides(name))
+        .map(|name| format!("{}{}", SUPER, name))
+        .collect();
+    for func in &mut own {
+        rename_calls(&mut func.body, &|name| {
+            direct
+                .contains(&name.to_string())
+                .then(|| name[SUPER.len()..].to_string())
+        });
+    }
+
+    let mut functions = base.functions;
+    let mut supers = Vec::new();
+    for func in own {
+        let Some(inherited) = functions.iter_mut().find(|f| f.name == func.name) else {
+            functions.push(func);
+            continue;
+        };
+        let overridden = std::mem::replace(inherited, func);
+        if called.contains(&overridden.name) {
+            supers.push(overridden);
+        }
+    }
+    // The overridden versions move one step down the `super_` chain, with
+    // the base's own `super_` functions they call
+    let mut renamed: Vec<String> = Vec::new();
+    for func in &supers {
+        renamed.push(func.name.clone());
+        let mut name = format!("{}{}", SUPER, func.name);
+        while functions.iter().any(|f| f.name == name) {
+            renamed.push(name.clone());
+            name = format!("{}{}", SUPER, name);
+        }
+    }
+    let mut kept: Vec<FnDef> = Vec::new();
+    for func in supers.into_iter().chain(
+        functions
+            .iter()
+            .filter(|f| f.name.starts_with(SUPER) && renamed.contains(&f.name))
+            .cloned(),
+    ) {
+        let mut func = func;
+        func.name = format!("{}{}", SUPER, func.name);
+        rename_calls(&mut func.body, &|name| {
+            renamed
+                .contains(&name.to_string())
+                .then(|| format!("{}{}", SUPER, name))
+        });
+        kept.push(func);
+    }
+    functions.retain(|f| !(f.name.starts_with(SUPER) && renamed.contains(&f.name)));
+    functions.extend(kept);
+
+    let mut implements = base.implements;
+    for name in &entity.implements {
+        if !implements.contains(name) {
+            implements.push(name.clone());
+        }
+    }
+
+    EntityDef {
+        name: entity.name.clone(),
+        extends: entity.extends.clone(),
+        implements,
+        components,
+        functions,
+        signals,
+        variables,
+        span: entity.span,
+    }
+}
+
+/// Turn `super.name(...)` calls into calls to `super_name`, returning the
+/// names called
+fn call_super(body: &mut [Statement]) -> Vec<String> {
+    let mut called = Vec::new();
+    walk(body, &mut |stmt| {
+        exprs(stmt, &mut |expr| {
+            let Expr::Call { callee, .. } = expr else {
+                return;
+            };
+            let Expr::MemberAccess(base, name) = &**callee else {
+                return;
+            };
+            if **base == Expr::Identifier("super".to_string()) {
+                called.push(name.clone());
+                **callee = Expr::Identifier(format!("{}{}", SUPER, name));
+            }
+        })
+    });
+    called
+}
+
+/// Rename the functions called by name wherever `rename` gives a new name
+fn rename_calls(body: &mut [Statement], rename: &dyn Fn(&str) -> Option<String>) {
+    walk(body, &mut |stmt| {
+        exprs(stmt, &mut |expr| {
+            if let Expr::Call { callee, .. } = expr {
+                if let Expr::Identifier(name) = &mut **callee {
+                    if let Some(renamed) = rename(name) {
+                        *name = renamed;
+                    }
+                }
+            }
+        })
+    });
+}
+
+/// Point an entity's members at `span`
+fn respan(entity: &mut EntityDef, span: Span) {
+    for component in &mut entity.components {
+        component.span = span;
+    }
+    for var in &mut entity.variables {
+        var.span = span;
+    }
+    for signal in &mut entity.signals {
+        signal.span = span;
+        for param in &mut signal.params {
+            param.span = span;
+        }
+    }
+    for func in &mut entity.functions {
+        func.span = span;
+        for param in &mut func.params {
+            param.span = span;
+        }
+        for annotation in &mut func.annotations {
+            annotation.span = span;
+        }
+        walk(&mut func.body, &mut |stmt| match stmt {
+            Statement::VarDecl(var) => var.span = span,
+            Statement::Assignment(assign) => assign.span = span,
+            Statement::If(if_stmt) => if_stmt.span = span,
+            Statement::Match(match_stmt) => {
+                match_stmt.span = span;
+                for arm in &mut match_stmt.arms {
+                    arm.span = span;
+                }
+            }
+            Statement::While(while_stmt) => while_stmt.span = span,
+            Statement::For(for_stmt) => for_stmt.span = span,
+            Statement::Break { span: at } | Statement::Continue { span: at } => *at = span,
+            Statement::Emit(emit) => emit.span = span,
+            Statement::Expr { span: at, .. } => *at = span,
+            _ => {}
+        });
+    }
+}
+
+/// Visit every statement in a body, including those in nested blocks
+fn walk(body: &mut [Statement], f: &mut impl FnMut(&mut Statement)) {
+    for stmt in body {
+        f(stmt);
+        match stmt {
+            Statement::If(if_stmt) => {
+                walk(&mut if_stmt.then_body, f);
+                for (_, clause) in &mut if_stmt.elif_clauses {
+                    walk(clause, f);
+                }
+                if let Some(else_body) = &mut if_stmt.else_body {
+                    walk(else_body, f);
+                }
+            }
+            Statement::Match(match_stmt) => {
+                for arm in &mut match_stmt.arms {
+                    walk(&mut arm.body, f);
+                }
+            }
+            Statement::While(while_stmt) => walk(&mut while_stmt.body, f),
+            Statement::For(for_stmt) => walk(&mut for_stmt.body, f),
+            _ => {}
+        }
+    }
+}
+
+/// Visit every expression a statement holds directly, and their
+/// subexpressions
+fn exprs(stmt: &mut Statement, f: &mut impl FnMut(&mut Expr)) {
+    match stmt {
+        Statement::VarDecl(var) => expr(&mut var.value, f),
+        Statement::Assignment(assign) => expr(&mut assign.value, f),
+        Statement::Return(Some(value)) | Statement::Expr { expr: value, .. } => expr(value, f),
+        Statement::If(if_stmt) => {
+            expr(&mut if_stmt.condition, f);
+            for (condition, _) in &mut if_stmt.elif_clauses {
+                expr(condition, f);
+            }
+        }
+        Statement::Match(match_stmt) => {
+            expr(&mut match_stmt.subject, f);
+            for guard in match_stmt
+                .arms
+                .iter_mut()
+                .filter_map(|arm| arm.guard.as_mut())
+            {
+                expr(guard, f);
+            }
+        }
+        Statement::While(while_stmt) => expr(&mut while_stmt.condition, f),
+        Statement::For(for_stmt) => expr(&mut for_stmt.iterable, f),
+        Statement::Emit(emit) => {
+            for arg in &mut emit.args {
+                expr(arg, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn expr(e: &mut Expr, f: &mut impl FnMut(&mut Expr)) {
+    f(e);
+    match e {
+        Expr::Call { callee, args } => {
+            expr(callee, f);
+            for arg in args {
+                expr(&mut arg.value, f);
+            }
+        }
+        Expr::MemberAccess(base, _) | Expr::UnaryOp(_, base) => expr(base, f),
+        Expr::Index(left, right) | Expr::BinaryOp(left, _, right) | Expr::Vec2(left, right) => {
+            expr(left, f);
+            expr(right, f);
+        }
+        Expr::Vec3(x, y, z) => {
+            expr(x, f);
+            expr(y, f);
+            expr(z, f);
+        }
+        Expr::List(items) | Expr::Tuple(items) => {
+            for item in items {
+                expr(item, f);
+            }
+        }
+        Expr::Map(entries) => {
+            for (_, value) in entries {
+                expr(value, f);
+            }
+        }
+        Expr::Construct { fields, .. } => {
+            for (_, value) in fields {
+                expr(value, f);
+            }
+        }
+        Expr::Lambda { body, .. } => expr(body, f),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn entity<'a>(program: &'a Program, name: &str) -> &'a EntityDef {
+        program
+            .statements
+            .iter()
+            .find_map(|stmt| match stmt {
+                Statement::EntityDef(entity) if entity.name == name => Some(entity),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_flatten() {
+        let source = "entity Actor:\n    component Health:\n        current = 10\n        max = 10\n    signal died()\n    fn hit(amount: int):\n        print(amount)\n\nentity Enemy extends Actor:\n    let speed = 1.0\n    fn hit(amount: int):\n        super.hit(amount)\n\nentity Boss extends Enemy:\n    component Health:\n        max = 100\n    fn hit(amount: int):\n        super.hit(amount / 2)\n\nentity Loop extends Loop:\n    let x = 1\n";
+        let program = parse(source).unwrap();
+        let imports = Imports::default();
+        let bases = Bases::new(&program, &imports);
+
+        let boss = bases.flatten(entity(&program, "Boss")).unwrap();
+        let health = &boss.components[0].fields;
+        assert_eq!(health[0], ("current".to_string(), Expr::Int(10)));
+        assert_eq!(health[1], ("max".to_string(), Expr::Int(100)));
+        assert_eq!(boss.variables[0].name, "speed");
+        assert_eq!(boss.signals[0].name, "died");
+        let functions: Vec<&str> = boss.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(functions, vec!["hit", "super_hit", "super_super_hit"]);
+        // Each version calls the one below it
+        let calls: Vec<String> = boss
+            .functions
+            .iter()
+            .map(|f| format!("{:?}", f.body[0]))
+            .collect();
+        assert!(calls[0].contains("\"super_hit\""));
+        assert!(calls[1].contains("\"super_super_hit\""));
+        assert!(calls[2].contains("\"print\""));
+
+        assert_eq!(
+            bases.flatten(entity(&program, "Loop")),
+            Err(Error::Cycle(vec!["Loop".to_string(), "Loop".to_string()]))
+        );
+    }
+}
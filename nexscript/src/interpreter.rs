@@ -0,0 +1,1199 @@
+//! Interpreter - Evaluate NexScript directly, for `nexc repl`
+//!
+//! A tree-walking evaluator over the AST. It runs the language itself:
+//! values, operators, control flow, functions, lambdas, structs, enums and
+//! `match`. There is no engine behind it, so an entity is a mock instance
+//! whose variables and component fields (`Health.current`) live in the
+//! interpreter, `emit` records the signal, and `wait` returns at once.
+//!
+//! Ints follow the generated Rust: `7 / 2` is `3`. Lambdas capture the
+//! locals they can see by value when they are created.
+
+use crate::{
+    AssignOp, BinaryOp, EntityDef, EnumDef, Expr, FnDef, LValue, Pattern, Program, Statement,
+    StructDef, UnaryOp,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Calls nested deeper than this are reported rather than overflowing the
+/// stack
+const MAX_DEPTH: usize = 200;
+
+/// A runtime value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// What a function without a `return` gives back
+    None,
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Vec2(f64, f64),
+    Vec3(f64, f64, f64),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Map(Vec<(String, Value)>),
+    /// A struct, with its fields in declaration order
+    Struct {
+        name: String,
+        fields: Vec<(String, Value)>,
+    },
+    /// An enum variant, `Shape.Circle`, with its fields in declaration order
+    Variant {
+        path: String,
+        fields: Vec<(String, Value)>,
+    },
+    /// A named function, called by name
+    Function(String),
+    Lambda(Lambda),
+}
+
+/// A lambda and the locals it captured
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    params: Vec<String>,
+    body: Expr,
+    captured: HashMap<String, Value>,
+}
+
+impl Value {
+    /// Name of the value's type as NexScript writes it
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::None => "None".to_string(),
+            Value::Int(_) => "int".to_string(),
+            Value::Float(_) => "float".to_string(),
+            Value::Str(_) => "str".to_string(),
+            Value::Bool(_) => "bool".to_string(),
+            Value::Vec2(..) => "Vec2".to_string(),
+            Value::Vec3(..) => "Vec3".to_string(),
+            Value::List(_) => "List".to_string(),
+            Value::Tuple(_) => "Tuple".to_string(),
+            Value::Map(_) => "Map".to_string(),
+            Value::Struct { name, .. } => name.clone(),
+            Value::Variant { path, .. } => path.split('.').next().unwrap_or(path).to_string(),
+            Value::Function(_) | Value::Lambda(_) => "Fn".to_string(),
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Float(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value as `print` writes it: strings without quotes
+    pub fn to_text(&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// Formats the value as it would be written in NexScript
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join(values: impl Iterator<Item = String>) -> String {
+            values.collect::<Vec<_>>().join(", ")
+        }
+        fn fields(fields: &[(String, Value)]) -> String {
+            join(
+                fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value)),
+            )
+        }
+        match self {
+            Value::None => write!(f, "None"),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Vec2(x, y) => write!(f, "Vec2({:?}, {:?})", x, y),
+            Value::Vec3(x, y, z) => write!(f, "Vec3({:?}, {:?}, {:?})", x, y, z),
+            Value::List(items) => write!(f, "[{}]", join(items.iter().map(Value::to_string))),
+            Value::Tuple(items) => write!(f, "({})", join(items.iter().map(Value::to_string))),
+            Value::Map(entries) => write!(
+                f,
+                "{{{}}}",
+                join(entries.iter().map(|(k, v)| format!("{:?}: {}", k, v)))
+            ),
+            Value::Struct { name, fields: f_ } => write!(f, "{}({})", name, fields(f_)),
+            Value::Variant { path, fields: f_ } if f_.is_empty() => write!(f, "{}", path),
+            Value::Variant { path, fields: f_ } => write!(f, "{}({})", path, fields(f_)),
+            Value::Function(name) => write!(f, "<fn {}>", name),
+            Value::Lambda(_) => write!(f, "<lambda>"),
+        }
+    }
+}
+
+/// Why evaluation stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError(pub String);
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+pub type Eval<T> = std::result::Result<T, RuntimeError>;
+
+fn error<T>(message: impl Into<String>) -> Eval<T> {
+    Err(RuntimeError(message.into()))
+}
+
+/// How a statement finished
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// A mock instance of an entity: its variables, component fields as
+/// `Component.field`, and functions
+#[derive(Debug, Clone)]
+struct Instance {
+    name: String,
+    vars: HashMap<String, Value>,
+    functions: Vec<FnDef>,
+}
+
+/// Evaluates statements one at a time, keeping what they define
+#[derive(Debug, Default)]
+pub struct Interpreter {
+    globals: HashMap<String, Value>,
+    functions: HashMap<String, FnDef>,
+    structs: HashMap<String, StructDef>,
+    enums: HashMap<String, EnumDef>,
+    entities: Vec<Instance>,
+    /// Locals of the function being run, innermost block last
+    scopes: Vec<HashMap<String, Value>>,
+    /// The entity whose function is running
+    active: Option<usize>,
+    depth: usize,
+    /// Lines written by `print` and signals emitted, until taken
+    output: Vec<String>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What has been printed or emitted since the last call
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Run a whole program, giving the value of its last statement if that
+    /// is an expression
+    pub fn run(&mut self, program: &Program) -> Eval<Option<Value>> {
+        let mut last = None;
+        for stmt in &program.statements {
+            last = self.run_top(stmt)?;
+        }
+        Ok(last)
+    }
+
+    /// Bring a loaded file's definitions into scope, with a mock instance
+    /// of each entity; top-level statements other than definitions aren't run
+    pub fn load(&mut self, program: &Program) -> Eval<()> {
+        for stmt in &program.statements {
+            match stmt {
+                Statement::FnDef(_)
+                | Statement::StructDef(_)
+                | Statement::EnumDef(_)
+                | Statement::EntityDef(_) => {
+                    self.run_top(stmt)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Run one top-level statement, giving the value of an expression
+    pub fn run_top(&mut self, stmt: &Statement) -> Eval<Option<Value>> {
+        match stmt {
+            Statement::Expr { expr, .. } => return self.eval(expr).map(Some),
+            Statement::FnDef(func) => {
+                self.functions.insert(func.name.clone(), func.clone());
+            }
+            Statement::StructDef(def) => {
+                self.structs.insert(def.name.clone(), def.clone());
+            }
+            Statement::EnumDef(def) => {
+                self.enums.insert(def.name.clone(), def.clone());
+            }
+            Statement::EntityDef(entity) => self.spawn(entity)?,
+            Statement::SignalDef(_) | Statement::InterfaceDef(_) => {}
+            Statement::Import(_) => return error("imports can't be evaluated"),
+            Statement::SystemDef(_) | Statement::StateMachine(_) => {
+                return error("systems and state machines need the engine to run")
+            }
+            stmt => match self.exec(stmt)? {
+                Flow::Next => {}
+                Flow::Return(_) => return error("`return` outside of a function"),
+                Flow::Break | Flow::Continue => {
+                    return error("`break` or `continue` outside of a loop")
+                }
+            },
+        }
+        Ok(None)
+    }
+
+    /// The value of a variable, as a later line would see it
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /// Make a mock instance of an entity, replacing any earlier one
+    fn spawn(&mut self, entity: &EntityDef) -> Eval<()> {
+        self.entities
+            .retain(|instance| instance.name != entity.name);
+        self.entities.push(Instance {
+            name: entity.name.clone(),
+            vars: HashMap::new(),
+            functions: entity.functions.clone(),
+        });
+        let index = self.entities.len() - 1;
+        let previous = self.active.replace(index);
+        let result = (|| {
+            for component in &entity.components {
+                for (field, value) in &component.fields {
+                    let value = self.eval(value)?;
+                    let key = format!("{}.{}", component.name, field);
+                    self.entities[index].vars.insert(key, value);
+                }
+            }
+            for var in &entity.variables {
+                let value = self.eval(&var.value)?;
+                self.entities[index].vars.insert(var.name.clone(), value);
+            }
+            Ok(())
+        })();
+        self.active = previous;
+        result
+    }
+
+    fn block(&mut self, body: &[Statement]) -> Eval<Flow> {
+        self.scopes.push(HashMap::new());
+        let result = self.statements(body);
+        self.scopes.pop();
+        result
+    }
+
+    fn statements(&mut self, body: &[Statement]) -> Eval<Flow> {
+        for stmt in body {
+            match self.exec(stmt)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec(&mut self, stmt: &Statement) -> Eval<Flow> {
+        match stmt {
+            Statement::VarDecl(var) => {
+                let value = self.eval(&var.value)?;
+                self.define(&var.name, value);
+            }
+            Statement::Assignment(assign) => {
+                let value = self.eval(&assign.value)?;
+                let value = match assign.op {
+                    AssignOp::Assign => value,
+                    op => {
+                        let op = match op {
+                            AssignOp::AddAssign => BinaryOp::Add,
+                            AssignOp::SubAssign => BinaryOp::Sub,
+                            AssignOp::MulAssign => BinaryOp::Mul,
+                            _ => BinaryOp::Div,
+                        };
+                        binary(&self.read(&assign.target)?, op, &value)?
+                    }
+                };
+                self.write(&assign.target, value)?;
+            }
+            Statement::If(if_stmt) => {
+                if self.condition(&if_stmt.condition)? {
+                    return self.block(&if_stmt.then_body);
+                }
+                for (condition, body) in &if_stmt.elif_clauses {
+                    if self.condition(condition)? {
+                        return self.block(body);
+                    }
+                }
+                if let Some(body) = &if_stmt.else_body {
+                    return self.block(body);
+                }
+            }
+            Statement::Match(match_stmt) => {
+                let subject = self.eval(&match_stmt.subject)?;
+                for arm in &match_stmt.arms {
+                    let mut bound = HashMap::new();
+                    if !self.matches(&arm.pattern, &subject, &mut bound)? {
+                        continue;
+                    }
+                    self.scopes.push(bound);
+                    let guard = match &arm.guard {
+                        Some(guard) => self.condition(guard),
+                        None => Ok(true),
+                    };
+                    let result = match guard {
+                        Ok(true) => self.statements(&arm.body).map(Some),
+                        Ok(false) => Ok(None),
+                        Err(e) => Err(e),
+                    };
+                    self.scopes.pop();
+                    if let Some(flow) = result? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Statement::While(while_stmt) => {
+                while self.condition(&while_stmt.condition)? {
+                    match self.block(&while_stmt.body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            Statement::For(for_stmt) => {
+                let items = match self.eval(&for_stmt.iterable)? {
+                    Value::List(items) => items,
+                    Value::Map(entries) => {
+                        entries.into_iter().map(|(k, _)| Value::Str(k)).collect()
+                    }
+                    other => return error(format!("can't loop over a `{}`", other.type_name())),
+                };
+                for item in items {
+                    let mut scope = HashMap::new();
+                    match (&for_stmt.names[..], item) {
+                        ([name], item) => {
+                            scope.insert(name.clone(), item);
+                        }
+                        (names, Value::Tuple(values)) if names.len() == values.len() => {
+                            scope.extend(names.iter().cloned().zip(values));
+                        }
+                        (names, item) => {
+                            return error(format!(
+                                "can't unpack `{}` into {} names",
+                                item,
+                                names.len()
+                            ))
+                        }
+                    }
+                    self.scopes.push(scope);
+                    let flow = self.block(&for_stmt.body);
+                    self.scopes.pop();
+                    match flow? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            Statement::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::None,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Statement::Break { .. } => return Ok(Flow::Break),
+            Statement::Continue { .. } => return Ok(Flow::Continue),
+            Statement::Pass => {}
+            Statement::Emit(emit) => {
+                let args = emit
+                    .args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Eval<Vec<_>>>()?;
+                let args: Vec<String> = args.iter().map(Value::to_string).collect();
+                self.output
+                    .push(format!("emitted {}({})", emit.signal_name, args.join(", ")));
+            }
+            Statement::Expr { expr, .. } => {
+                self.eval(expr)?;
+            }
+            Statement::FnDef(func) => {
+                self.define(&func.name, Value::Function(func.name.clone()));
+                self.functions.insert(func.name.clone(), func.clone());
+            }
+            _ => return error("definitions can only be evaluated at the top level"),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn condition(&mut self, expr: &Expr) -> Eval<bool> {
+        match self.eval(expr)? {
+            Value::Bool(b) => Ok(b),
+            other => error(format!("expected a `bool`, found `{}`", other.type_name())),
+        }
+    }
+
+    /// Declare a variable in the innermost scope
+    fn define(&mut self, name: &str, value: Value) {
+        match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name.to_string(), value),
+            None => self.globals.insert(name.to_string(), value),
+        };
+    }
+
+    /// Where a name lives: a local, the active entity, a global, then any
+    /// loaded entity
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        if let Some(value) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Some(value);
+        }
+        if let Some(value) = self.active.and_then(|i| self.entities[i].vars.get(name)) {
+            return Some(value);
+        }
+        self.globals
+            .get(name)
+            .or_else(|| self.entities.iter().find_map(|e| e.vars.get(name)))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Value> {
+        if let Some(scope) = self.scopes.iter_mut().rev().find(|s| s.contains_key(name)) {
+            return scope.get_mut(name);
+        }
+        if let Some(i) = self
+            .active
+            .filter(|i| self.entities[*i].vars.contains_key(name))
+        {
+            return self.entities[i].vars.get_mut(name);
+        }
+        if self.globals.contains_key(name) {
+            return self.globals.get_mut(name);
+        }
+        self.entities.iter_mut().find_map(|e| e.vars.get_mut(name))
+    }
+
+    fn read(&self, target: &LValue) -> Eval<Value> {
+        let path = target.parts.join(".");
+        if let Some(value) = self.lookup(&path) {
+            return Ok(value.clone());
+        }
+        let (first, rest) = target
+            .parts
+            .split_first()
+            .expect("an assignment has a target");
+        let mut value = self
+            .lookup(first)
+            .ok_or_else(|| RuntimeError(format!("undefined variable `{}`", first)))?
+            .clone();
+        for field in rest {
+            value = member(&value, field)?;
+        }
+        Ok(value)
+    }
+
+    fn write(&mut self, target: &LValue, value: Value) -> Eval<()> {
+        // Component fields are stored under their dotted path
+        let path = target.parts.join(".");
+        if let Some(slot) = self.lookup_mut(&path) {
+            *slot = value;
+            return Ok(());
+        }
+        let (first, rest) = target
+            .parts
+            .split_first()
+            .expect("an assignment has a target");
+        let mut slot = self
+            .lookup_mut(first)
+            .ok_or_else(|| RuntimeError(format!("undefined variable `{}`", first)))?;
+        for field in rest {
+            slot = member_mut(slot, field)?;
+        }
+        *slot = value;
+        Ok(())
+    }
+
+    /// Whether `value` matches `pattern`, collecting the names it binds
+    fn matches(
+        &mut self,
+        pattern: &Pattern,
+        value: &Value,
+        bound: &mut HashMap<String, Value>,
+    ) -> Eval<bool> {
+        Ok(match pattern {
+            Pattern::Wildcard => true,
+            Pattern::Binding(name) => {
+                bound.insert(name.clone(), value.clone());
+                true
+            }
+            Pattern::Literal(literal) => equal(&self.eval(literal)?, value),
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let (start, end) = (self.eval(start)?, self.eval(end)?);
+                match (start.number(), end.number(), value.number()) {
+                    (Some(start), Some(end), Some(n)) => {
+                        n >= start && if *inclusive { n <= end } else { n < end }
+                    }
+                    _ => false,
+                }
+            }
+            Pattern::Variant { path, fields } => {
+                let wanted = path[path.len().saturating_sub(2)..].join(".");
+                let Value::Variant {
+                    path,
+                    fields: values,
+                } = value
+                else {
+                    return Ok(false);
+                };
+                if *path != wanted || fields.len() != values.len() {
+                    return Ok(false);
+                }
+                for (pattern, (_, value)) in fields.iter().zip(values) {
+                    if !self.matches(pattern, value, bound)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Pattern::Tuple(patterns) => {
+                let Value::Tuple(values) = value else {
+                    return Ok(false);
+                };
+                if patterns.len() != values.len() {
+                    return Ok(false);
+                }
+                for (pattern, value) in patterns.iter().zip(values) {
+                    if !self.matches(pattern, value, bound)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Pattern::Destructure { .. } => false,
+        })
+    }
+
+    /// Evaluate an expression
+    pub fn eval(&mut self, expr: &Expr) -> Eval<Value> {
+        match expr {
+            Expr::Int(n) => Ok(Value::Int(*n)),
+            Expr::Float(n) => Ok(Value::Float(*n)),
+            Expr::String(s) => Ok(Value::Str(s.clone())),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Vec2(x, y) => Ok(Value::Vec2(self.float(x)?, self.float(y)?)),
+            Expr::Vec3(x, y, z) => Ok(Value::Vec3(self.float(x)?, self.float(y)?, self.float(z)?)),
+            Expr::List(items) => Ok(Value::List(self.eval_all(items)?)),
+            Expr::Tuple(items) => Ok(Value::Tuple(self.eval_all(items)?)),
+            Expr::Map(entries) => entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), self.eval(value)?)))
+                .collect::<Eval<_>>()
+                .map(Value::Map),
+            Expr::Identifier(name) => {
+                if let Some(value) = self.lookup(name) {
+                    return Ok(value.clone());
+                }
+                if self.functions.contains_key(name) || self.entity_fn(name).is_some() {
+                    return Ok(Value::Function(name.clone()));
+                }
+                error(format!("undefined variable `{}`", name))
+            }
+            Expr::MemberAccess(base, name) => {
+                if let Some(path) = crate::type_checker::member_path(expr) {
+                    if let Some(value) = self.lookup(&path) {
+                        return Ok(value.clone());
+                    }
+                }
+                if let Expr::Identifier(base) = &**base {
+                    if let Some(variant) = self.variant(base, name, &[])? {
+                        return Ok(variant);
+                    }
+                    // `Player.speed` on a loaded entity
+                    if let Some(instance) = self.entities.iter().find(|e| e.name == *base) {
+                        if let Some(value) = instance.vars.get(name) {
+                            return Ok(value.clone());
+                        }
+                    }
+                }
+                let base = self.eval(base)?;
+                member(&base, name)
+            }
+            Expr::Index(base, index) => {
+                let (base, index) = (self.eval(base)?, self.eval(index)?);
+                match (&base, &index) {
+                    (Value::List(items) | Value::Tuple(items), Value::Int(i)) => {
+                        usize::try_from(*i)
+                            .ok()
+                            .and_then(|i| items.get(i))
+                            .cloned()
+                            .ok_or_else(|| {
+                                RuntimeError(format!(
+                                    "index {} is out of range for {} items",
+                                    i,
+                                    items.len()
+                                ))
+                            })
+                    }
+                    (Value::Map(entries), Value::Str(key)) => entries
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| v.clone())
+                        .ok_or_else(|| RuntimeError(format!("no key {:?}", key))),
+                    _ => error(format!(
+                        "can't index a `{}` with a `{}`",
+                        base.type_name(),
+                        index.type_name()
+                    )),
+                }
+            }
+            Expr::BinaryOp(left, BinaryOp::And, right) => {
+                Ok(Value::Bool(self.condition(left)? && self.condition(right)?))
+            }
+            Expr::BinaryOp(left, BinaryOp::Or, right) => {
+                Ok(Value::Bool(self.condition(left)? || self.condition(right)?))
+            }
+            Expr::BinaryOp(left, op, right) => {
+                let (left, right) = (self.eval(left)?, self.eval(right)?);
+                binary(&left, *op, &right)
+            }
+            Expr::UnaryOp(op, operand) => match (op, self.eval(operand)?) {
+                (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (UnaryOp::Neg, Value::Int(n)) => Ok(Value::Int(-n)),
+                (UnaryOp::Neg, Value::Float(n)) => Ok(Value::Float(-n)),
+                (UnaryOp::Neg, Value::Vec2(x, y)) => Ok(Value::Vec2(-x, -y)),
+                (UnaryOp::Neg, Value::Vec3(x, y, z)) => Ok(Value::Vec3(-x, -y, -z)),
+                (op, value) => error(format!(
+                    "can't apply `{}` to `{}`",
+                    if *op == UnaryOp::Not { "not" } else { "-" },
+                    value.type_name()
+                )),
+            },
+            Expr::Call { callee, args } => self.call(callee, args),
+            Expr::Lambda { params, body, .. } => {
+                let mut captured = self.globals.clone();
+                for scope in &self.scopes {
+                    captured.extend(scope.clone());
+                }
+                Ok(Value::Lambda(Lambda {
+                    params: params.iter().map(|p| p.name.clone()).collect(),
+                    body: (**body).clone(),
+                    captured,
+                }))
+            }
+            Expr::Construct { .. } => error("constructors are only built for code generation"),
+        }
+    }
+
+    fn eval_all(&mut self, exprs: &[Expr]) -> Eval<Vec<Value>> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    fn float(&mut self, expr: &Expr) -> Eval<f64> {
+        let value = self.eval(expr)?;
+        value
+            .number()
+            .ok_or_else(|| RuntimeError(format!("expected a number, found `{}`", value)))
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[crate::Arg]) -> Eval<Value> {
+        match callee {
+            Expr::Identifier(name) => {
+                if let Some(def) = self.structs.get(name).cloned() {
+                    let fields = def
+                        .fields
+                        .iter()
+                        .map(|f| (f.name.as_str(), f.default.as_ref()))
+                        .collect::<Vec<_>>();
+                    let fields = self.construct(name, &fields, args)?;
+                    return Ok(Value::Struct {
+                        name: name.clone(),
+                        fields,
+                    });
+                }
+                let values =
+                    self.eval_all(&args.iter().map(|a| a.value.clone()).collect::<Vec<_>>())?;
+                match self.lookup(name).cloned() {
+                    Some(value) => self.apply(&value, values),
+                    None => self.call_named(name, values),
+                }
+            }
+            Expr::MemberAccess(base, name) => {
+                if let Expr::Identifier(base) = &**base {
+                    if let Some(variant) = self.variant(base, name, args)? {
+                        return Ok(variant);
+                    }
+                    // `Player.take_damage(3)` on a loaded entity
+                    if let Some(index) = self.entities.iter().position(|e| e.name == *base) {
+                        if let Some(func) = self.entities[index]
+                            .functions
+                            .iter()
+                            .find(|f| f.name == *name)
+                        {
+                            let func = func.clone();
+                            let values = self.eval_args(args)?;
+                            return self.invoke(&func, values, Some(index));
+                        }
+                    }
+                }
+                let list = self.eval(base)?;
+                let values = self.eval_args(args)?;
+                match (list, name.as_str(), values.as_slice()) {
+                    (Value::List(items), "map", [f]) => items
+                        .into_iter()
+                        .map(|item| self.apply(f, vec![item]))
+                        .collect::<Eval<_>>()
+                        .map(Value::List),
+                    (Value::List(items), "filter", [f]) => {
+                        let mut kept = Vec::new();
+                        for item in items {
+                            match self.apply(f, vec![item.clone()])? {
+                                Value::Bool(true) => kept.push(item),
+                                Value::Bool(false) => {}
+                                other => {
+                                    return error(format!(
+                                        "a filter must return a `bool`, found `{}`",
+                                        other.type_name()
+                                    ))
+                                }
+                            }
+                        }
+                        Ok(Value::List(kept))
+                    }
+                    (value, name, _) => {
+                        error(format!("`{}` has no method `{}`", value.type_name(), name))
+                    }
+                }
+            }
+            callee => {
+                let f = self.eval(callee)?;
+                let values = self.eval_args(args)?;
+                self.apply(&f, values)
+            }
+        }
+    }
+
+    fn eval_args(&mut self, args: &[crate::Arg]) -> Eval<Vec<Value>> {
+        args.iter().map(|arg| self.eval(&arg.value)).collect()
+    }
+
+    /// `Enum.Variant`, or `Enum.Variant(...)` when called, if `base` is an enum
+    fn variant(&mut self, base: &str, name: &str, args: &[crate::Arg]) -> Eval<Option<Value>> {
+        let Some(def) = self.enums.get(base).cloned() else {
+            return Ok(None);
+        };
+        let Some(variant) = def.variants.iter().find(|v| v.name == name) else {
+            return error(format!("`{}` has no variant `{}`", base, name));
+        };
+        let fields: Vec<(&str, Option<&Expr>)> = variant
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), None))
+            .collect();
+        let path = format!("{}.{}", base, name);
+        let fields = self.construct(&path, &fields, args)?;
+        Ok(Some(Value::Variant { path, fields }))
+    }
+
+    /// Fields in declaration order from named and positional arguments and
+    /// defaults
+    fn construct(
+        &mut self,
+        what: &str,
+        fields: &[(&str, Option<&Expr>)],
+        args: &[crate::Arg],
+    ) -> Eval<Vec<(String, Value)>> {
+        let mut values: Vec<Option<Value>> = vec![None; fields.len()];
+        for (i, arg) in args.iter().enumerate() {
+            let index = match &arg.name {
+                Some(name) => fields.iter().position(|(field, _)| field == name),
+                None => Some(i).filter(|i| *i < fields.len()),
+            };
+            let Some(index) = index else {
+                return error(format!("`{}` has no field for argument {}", what, i + 1));
+            };
+            values[index] = Some(self.eval(&arg.value)?);
+        }
+        fields
+            .iter()
+            .zip(values)
+            .map(|((name, default), value)| {
+                let value = match (value, default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => self.eval(default)?,
+                    (None, None) => {
+                        return error(format!("missing field `{}` in `{}`", name, what))
+                    }
+                };
+                Ok((name.to_string(), value))
+            })
+            .collect()
+    }
+
+    /// Call a function value
+    fn apply(&mut self, f: &Value, args: Vec<Value>) -> Eval<Value> {
+        match f {
+            Value::Function(name) => self.call_named(name, args),
+            Value::Lambda(lambda) => {
+                if lambda.params.len() != args.len() {
+                    return error(format!(
+                        "the lambda takes {} arguments but {} were given",
+                        lambda.params.len(),
+                        args.len()
+                    ));
+                }
+                let mut scope = lambda.captured.clone();
+                scope.extend(lambda.params.iter().cloned().zip(args));
+                self.enter()?;
+                let saved = std::mem::replace(&mut self.scopes, vec![scope]);
+                let result = self.eval(&lambda.body);
+                self.scopes = saved;
+                self.depth -= 1;
+                result
+            }
+            other => error(format!("a `{}` can't be called", other.type_name())),
+        }
+    }
+
+    /// Call a function by name: the file's own, an entity's, or a built-in
+    fn call_named(&mut self, name: &str, args: Vec<Value>) -> Eval<Value> {
+        if let Some(func) = self.functions.get(name).cloned() {
+            return self.invoke(&func, args, self.active);
+        }
+        if let Some((index, func)) = self.entity_fn(name) {
+            return self.invoke(&func, args, Some(index));
+        }
+        self.builtin(name, args)
+    }
+
+    /// A function of the active entity, or else of any loaded entity
+    fn entity_fn(&self, name: &str) -> Option<(usize, FnDef)> {
+        let owns = |i: &usize| self.entities[*i].functions.iter().any(|f| f.name == name);
+        let index = self
+            .active
+            .filter(owns)
+            .or_else(|| (0..self.entities.len()).find(owns))?;
+        let func = self.entities[index]
+            .functions
+            .iter()
+            .find(|f| f.name == name)?;
+        Some((index, func.clone()))
+    }
+
+    fn invoke(&mut self, func: &FnDef, args: Vec<Value>, entity: Option<usize>) -> Eval<Value> {
+        if func.params.len() != args.len() {
+            return error(format!(
+                "`{}` takes {} arguments but {} were given",
+                func.name,
+                func.params.len(),
+                args.len()
+            ));
+        }
+        let scope = func
+            .params
+            .iter()
+            .map(|p| p.name.clone())
+            .zip(args)
+            .collect();
+        self.enter()?;
+        let saved = std::mem::replace(&mut self.scopes, vec![scope]);
+        let previous = std::mem::replace(&mut self.active, entity);
+        let result = self.statements(&func.body);
+        self.scopes = saved;
+        self.active = previous;
+        self.depth -= 1;
+        match result? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::None),
+        }
+    }
+
+    fn enter(&mut self) -> Eval<()> {
+        if self.depth >= MAX_DEPTH {
+            return error(format!("calls nested more than {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn builtin(&mut self, name: &str, args: Vec<Value>) -> Eval<Value> {
+        let number = |value: &Value| {
+            value
+                .number()
+                .ok_or_else(|| RuntimeError(format!("expected a number, found `{}`", value)))
+        };
+        match (name, args.as_slice()) {
+            ("print", values) => {
+                let text: Vec<String> = values.iter().map(Value::to_text).collect();
+                self.output.push(text.join(" "));
+                Ok(Value::None)
+            }
+            ("wait", [_]) | ("play_animation", [_]) => Ok(Value::None),
+            ("len", [Value::List(items) | Value::Tuple(items)]) => {
+                Ok(Value::Int(items.len() as i64))
+            }
+            ("len", [Value::Map(entries)]) => Ok(Value::Int(entries.len() as i64)),
+            ("len", [Value::Str(s)]) => Ok(Value::Int(s.chars().count() as i64)),
+            ("clamp", [value, min, max]) => Ok(Value::Float(
+                number(value)?.clamp(number(min)?, number(max)?),
+            )),
+            ("lerp", [from, to, t]) => {
+                let (from, to, t) = (number(from)?, number(to)?, number(t)?);
+                Ok(Value::Float(from + (to - from) * t))
+            }
+            ("range", args) if (1..=3).contains(&args.len()) => {
+                let ints = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Value::Int(n) => Ok(*n),
+                        other => error(format!("`range` takes ints, found `{}`", other)),
+                    })
+                    .collect::<Eval<Vec<i64>>>()?;
+                let (start, end, step) = match ints[..] {
+                    [end] => (0, end, 1),
+                    [start, end] => (start, end, 1),
+                    [start, end, step] => (start, end, step),
+                    _ => unreachable!(),
+                };
+                if step == 0 {
+                    return error("`range` step can't be 0");
+                }
+                let mut items = Vec::new();
+                let mut n = start;
+                while (step > 0 && n < end) || (step < 0 && n > end) {
+                    items.push(Value::Int(n));
+                    n += step;
+                }
+                Ok(Value::List(items))
+            }
+            ("enumerate", [Value::List(items)]) => Ok(Value::List(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| Value::Tuple(vec![Value::Int(i as i64), item.clone()]))
+                    .collect(),
+            )),
+            _ if crate::prelude_fn(name).is_some() => error(format!(
+                "wrong arguments to `{}`: {}",
+                name,
+                crate::prelude_fn(name)
+                    .map(|f| f.signature())
+                    .unwrap_or_default()
+            )),
+            _ => error(format!("undefined function `{}`", name)),
+        }
+    }
+}
+
+/// Field of a struct, variant or vector
+fn member(value: &Value, name: &str) -> Eval<Value> {
+    let found = match (value, name) {
+        (Value::Vec2(x, _) | Value::Vec3(x, _, _), "x") => Some(Value::Float(*x)),
+        (Value::Vec2(_, y) | Value::Vec3(_, y, _), "y") => Some(Value::Float(*y)),
+        (Value::Vec3(_, _, z), "z") => Some(Value::Float(*z)),
+        (Value::Struct { fields, .. } | Value::Variant { fields, .. }, name) => fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone()),
+        _ => None,
+    };
+    found.ok_or_else(|| RuntimeError(format!("`{}` has no field `{}`", value.type_name(), name)))
+}
+
+fn member_mut<'v>(value: &'v mut Value, name: &str) -> Eval<&'v mut Value> {
+    let type_name = value.type_name();
+    let found = match value {
+        Value::Struct { fields, .. } | Value::Variant { fields, .. } => fields
+            .iter_mut()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value),
+        _ => None,
+    };
+    found.ok_or_else(|| RuntimeError(format!("`{}` has no field `{}` to set", type_name, name)))
+}
+
+/// `==` across ints and floats
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left.number(), right.number()) {
+        (Some(l), Some(r)) => l == r,
+        _ => left == right,
+    }
+}
+
+fn binary(left: &Value, op: BinaryOp, right: &Value) -> Eval<Value> {
+    use Value::{Bool, Float, Int, Str, Vec2, Vec3};
+    let result = match (left, op, right) {
+        (_, BinaryOp::Eq, _) => Some(Bool(equal(left, right))),
+        (_, BinaryOp::Ne, _) => Some(Bool(!equal(left, right))),
+        (Int(_) | Float(_), _, Int(_) | Float(_)) | (Str(_), _, Str(_))
+            if matches!(
+                op,
+                BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+            ) =>
+        {
+            let ordering = match (left, right) {
+                (Str(l), Str(r)) => l.partial_cmp(r),
+                _ => left.number().partial_cmp(&right.number()),
+            };
+            ordering.map(|ordering| {
+                Bool(match op {
+                    BinaryOp::Lt => ordering.is_lt(),
+                    BinaryOp::Le => ordering.is_le(),
+                    BinaryOp::Gt => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                })
+            })
+        }
+        (Int(_), BinaryOp::Div | BinaryOp::Mod, Int(0)) => {
+            return error("division by zero");
+        }
+        (Int(l), op, Int(r)) => match op {
+            BinaryOp::Add => l.checked_add(*r).map(Int),
+            BinaryOp::Sub => l.checked_sub(*r).map(Int),
+            BinaryOp::Mul => l.checked_mul(*r).map(Int),
+            BinaryOp::Div => Some(Int(l / r)),
+            BinaryOp::Mod => Some(Int(l % r)),
+            _ => None,
+        },
+        (Int(_) | Float(_), op, Int(_) | Float(_)) => {
+            let (l, r) = (
+                left.number().unwrap_or_default(),
+                right.number().unwrap_or_default(),
+            );
+            match op {
+                BinaryOp::Add => Some(Float(l + r)),
+                BinaryOp::Sub => Some(Float(l - r)),
+                BinaryOp::Mul => Some(Float(l * r)),
+                BinaryOp::Div => Some(Float(l / r)),
+                BinaryOp::Mod => Some(Float(l % r)),
+                _ => None,
+            }
+        }
+        (Str(l), BinaryOp::Add, Str(r)) => Some(Str(format!("{}{}", l, r))),
+        (Vec2(a, b), BinaryOp::Add, Vec2(c, d)) => Some(Vec2(a + c, b + d)),
+        (Vec2(a, b), BinaryOp::Sub, Vec2(c, d)) => Some(Vec2(a - c, b - d)),
+        (Vec3(a, b, c), BinaryOp::Add, Vec3(d, e, f)) => Some(Vec3(a + d, b + e, c + f)),
+        (Vec3(a, b, c), BinaryOp::Sub, Vec3(d, e, f)) => Some(Vec3(a - d, b - e, c - f)),
+        (Vec2(a, b), BinaryOp::Mul | BinaryOp::Div, Int(_) | Float(_)) => {
+            let n = right.number().unwrap_or_default();
+            let n = if op == BinaryOp::Mul { n } else { 1.0 / n };
+            Some(Vec2(a * n, b * n))
+        }
+        (Vec3(a, b, c), BinaryOp::Mul | BinaryOp::Div, Int(_) | Float(_)) => {
+            let n = right.number().unwrap_or_default();
+            let n = if op == BinaryOp::Mul { n } else { 1.0 / n };
+            Some(Vec3(a * n, b * n, c * n))
+        }
+        _ => None,
+    };
+    result.ok_or_else(|| {
+        RuntimeError(format!(
+            "can't apply `{}` to `{}` and `{}`",
+            symbol(op),
+            left.type_name(),
+            right.type_name()
+        ))
+    })
+}
+
+fn symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Mod => "%",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::Ge => ">=",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Eval<Option<Value>> {
+        interpreter.run(&parse(source).unwrap())
+    }
+
+    #[test]
+    fn test_evaluates_statements_and_keeps_variables() {
+        let mut interpreter = Interpreter::new();
+        run(&mut interpreter, "let x = 7\n").unwrap();
+        assert_eq!(
+            run(&mut interpreter, "x / 2\n").unwrap(),
+            Some(Value::Int(3))
+        );
+        assert_eq!(
+            run(&mut interpreter, "x / 2.0\n").unwrap(),
+            Some(Value::Float(3.5))
+        );
+
+        let source = "fn fact(n: int) -> int:\n    if n <= 1:\n        return 1\n    return n * fact(n - 1)\n\nlet total = 0\nfor i, n in enumerate([1, 2, 3]):\n    if i == 1:\n        continue\n    total += n\nlet bonus = 10\nlet add = |x| x + bonus\nbonus = 0\n";
+        run(&mut interpreter, source).unwrap();
+        assert_eq!(interpreter.get("total"), Some(&Value::Int(4)));
+        assert_eq!(
+            run(&mut interpreter, "fact(5)\n").unwrap(),
+            Some(Value::Int(120))
+        );
+        // Captured by value when the lambda was made
+        assert_eq!(
+            run(&mut interpreter, "[1, 2].map(add)\n").unwrap(),
+            Some(Value::List(vec![Value::Int(11), Value::Int(12)]))
+        );
+
+        let source = "enum Shape:\n    Empty\n    Circle(radius: float)\n\nstruct Weapon:\n    name: str\n    damage: int = 1\n\nlet w = Weapon(\"axe\")\nw.damage += 2\nlet s = Shape.Circle(2.0)\nmatch s:\n    case Shape.Circle(r) if r > 1.0:\n        print(\"big\", r)\n    case _:\n        pass\n";
+        run(&mut interpreter, source).unwrap();
+        assert_eq!(interpreter.take_output(), vec!["big 2.0"]);
+        assert_eq!(
+            interpreter.get("w").unwrap().to_string(),
+            "Weapon(name: \"axe\", damage: 3)"
+        );
+
+        assert_eq!(
+            run(&mut interpreter, "1 + \"a\"\n")
+                .unwrap_err()
+                .to_string(),
+            "can't apply `+` to `int` and `str`"
+        );
+        assert_eq!(
+            run(&mut interpreter, "nope\n").unwrap_err().to_string(),
+            "undefined variable `nope`"
+        );
+    }
+
+    #[test]
+    fn test_calls_entity_functions_on_a_mock_instance() {
+        let source = "signal died()\n\nentity Player:\n    component Health:\n        current = 10\n    let speed = 2.0\n\n    fn take_damage(amount: int):\n        Health.current -= amount\n        if Health.current <= 0:\n            emit died()\n\n    fn run_speed() -> float:\n        return speed * 2\n";
+        let mut interpreter = Interpreter::new();
+        interpreter.load(&parse(source).unwrap()).unwrap();
+
+        run(&mut interpreter, "take_damage(4)\nPlayer.take_damage(6)\n").unwrap();
+        assert_eq!(
+            run(&mut interpreter, "Health.current\n").unwrap(),
+            Some(Value::Int(0))
+        );
+        assert_eq!(interpreter.take_output(), vec!["emitted died()"]);
+        assert_eq!(
+            run(&mut interpreter, "Player.run_speed()\n").unwrap(),
+            Some(Value::Float(4.0))
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Lexer - Lossless tokenizer for NexScript source
+//!
+//! Unlike the pest grammar, which skips whitespace and comments, this keeps
+//! every byte of the input so editor tooling can map tokens back to the
+//! exact text the user wrote.
+
+use serde::{Deserialize, Serialize};
+
+/// Kinds of tokens produced by [`tokenize`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenKind {
+    /// Spaces and tabs
+    Whitespace,
+    /// `\n`, `\r\n` or `\r`
+    Newline,
+    /// `#` up to the end of the line
+    Comment,
+    /// Identifiers and keywords
+    Ident,
+    Int,
+    Float,
+    /// String literal including its quotes (may be unterminated)
+    String,
+    /// Operators and delimiters
+    Punct,
+}
+
+/// A single token, borrowing its text from the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Byte offset of the token in the source
+    pub offset: usize,
+}
+
+impl Token<'_> {
+    /// Byte offset just past the end of the token
+    pub fn end(&self) -> usize {
+        self.offset + self.text.len()
+    }
+
+    /// Whitespace, newlines and comments
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment
+        )
+    }
+}
+
+/// Reserved words of the language
+pub const KEYWORDS: &[&str] = &[
+    "import",
+    "from",
+    "entity",
+    "component",
+    "struct",
+    "enum",
+    "interface",
+    "implements",
+    "extends",
+    "super",
+    "fn",
+    "system",
+    "async",
+    "await",
+    "signal",
+    "state_machine",
+    "state",
+    "initial",
+    "let",
+    "if",
+    "elif",
+    "else",
+    "match",
+    "case",
+    "while",
+    "for",
+    "in",
+    "with",
+    "without",
+    "return",
+    "break",
+    "continue",
+    "pass",
+    "emit",
+    "and",
+    "or",
+    "not",
+    "true",
+    "false",
+];
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word)
+}
+
+/// Operators that span more than one character, longest first
+const MULTI_CHAR_PUNCT: &[&str] = &[
+    "..=", "->", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "..",
+];
+
+/// Split source into tokens. Concatenating the token texts reproduces the
+/// input exactly; unknown characters become single-character `Punct` tokens.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let bytes = source.as_bytes();
+    let mut pos = 0;
+
+    while pos < source.len() {
+        let rest = &source[pos..];
+        let c = rest.chars().next().unwrap();
+
+        let (kind, len) = if c == ' ' || c == '\t' {
+            let len = rest
+                .find(|ch: char| ch != ' ' && ch != '\t')
+                .unwrap_or(rest.len());
+            (TokenKind::Whitespace, len)
+        } else if c == '\r' {
+            let len = if rest.starts_with("\r\n") { 2 } else { 1 };
+            (TokenKind::Newline, len)
+        } else if c == '\n' {
+            (TokenKind::Newline, 1)
+        } else if c == '#' {
+            let len = rest.find(['\r', '\n']).unwrap_or(rest.len());
+            (TokenKind::Comment, len)
+        } else if c == '"' {
+            let len = match rest[1..].find(['"', '\n', '\r']) {
+                Some(i) if rest.as_bytes()[1 + i] == b'"' => i + 2,
+                Some(i) => i + 1,
+                None => rest.len(),
+            };
+            (TokenKind::String, len)
+        } else if c.is_ascii_digit() {
+            let int_len = rest
+                .find(|ch: char| !ch.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let after = &bytes[pos + int_len..];
+            if after.len() >= 2 && after[0] == b'.' && after[1].is_ascii_digit() {
+                let frac_len = rest[int_len + 1..]
+                    .find(|ch: char| !ch.is_ascii_digit())
+                    .unwrap_or(rest.len() - int_len - 1);
+                (TokenKind::Float, int_len + 1 + frac_len)
+            } else {
+                (TokenKind::Int, int_len)
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+                .unwrap_or(rest.len());
+            (TokenKind::Ident, len)
+        } else if let Some(op) = MULTI_CHAR_PUNCT.iter().find(|op| rest.starts_with(**op)) {
+            (TokenKind::Punct, op.len())
+        } else {
+            (TokenKind::Punct, c.len_utf8())
+        };
+
+        tokens.push(Token {
+            kind,
+            text: &source[pos..pos + len],
+            offset: pos,
+        });
+        pos += len;
+    }
+
+    tokens
+}
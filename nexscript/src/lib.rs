@@ -2,14 +2,36 @@
 //!
 //! This crate provides parsing and transpilation of `.nx` files to Rust code.
 
+use cst::{OffsetMap, SyntaxTree};
+use modules::Imports;
+use pest::iterators::Pairs;
 use pest::Parser;
 use pest_derive::Parser;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use source_map::{mapped, SourceMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 
+pub mod analysis;
 mod ast_builder;
+pub mod build;
+pub mod cst;
+pub mod diagnostics;
+mod exhaustiveness;
+pub mod formatter;
+mod inheritance;
+pub mod interpreter;
+pub mod lexer;
+pub mod manifest;
+pub mod modules;
+mod queries;
+pub mod render;
+mod schedules;
+pub mod source_map;
 mod type_checker;
 
+pub use type_checker::{infer_type, infer_type_in, prelude_fn, PreludeFn, TypeEnv, PRELUDE};
+
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct NexScriptParser;
@@ -17,8 +39,12 @@ pub struct NexScriptParser;
 /// Errors that can occur during parsing or compilation
 #[derive(Debug, thiserror::Error)]
 pub enum NexScriptError {
-    #[error("Parse error at line {line}: {message}")]
-    ParseError { line: usize, message: String },
+    #[error("Parse error at line {line}, column {column}: {message}")]
+    ParseError {
+        line: usize,
+        column: usize,
+        message: String,
+    },
 
     #[error("Type error: {0}")]
     TypeError(String),
@@ -28,6 +54,15 @@ pub enum NexScriptError {
 
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+
+    #[error("{0} script(s) failed to compile")]
+    BuildFailed(usize),
+
+    #[error("Invalid manifest {path}: {message}")]
+    ManifestError { path: String, message: String },
+
+    #[error("The {0} target is not supported yet")]
+    UnsupportedTarget(String),
 }
 
 pub type Result<T> = std::result::Result<T, NexScriptError>;
@@ -36,109 +71,270 @@ pub type Result<T> = std::result::Result<T, NexScriptError>;
 // AST Node Definitions
 // ============================================================================
 
+/// Byte range in the source that a node was parsed from
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 /// The root of a NexScript program
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
 
 /// All possible statements in NexScript
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Statement {
+    Import(ImportStmt),
     EntityDef(EntityDef),
+    StructDef(StructDef),
+    EnumDef(EnumDef),
+    InterfaceDef(InterfaceDef),
     FnDef(FnDef),
+    SystemDef(SystemDef),
     SignalDef(SignalDef),
     StateMachine(StateMachine),
     VarDecl(VarDecl),
     Assignment(Assignment),
     If(IfStmt),
+    Match(MatchStmt),
     While(WhileStmt),
     For(ForStmt),
     Return(Option<Expr>),
+    Break {
+        span: Span,
+    },
+    Continue {
+        span: Span,
+    },
+    /// `pass`, which does nothing
+    Pass,
     Emit(EmitStmt),
-    Expr(Expr),
+    /// An expression evaluated for its effect, such as a call
+    Expr {
+        expr: Expr,
+        span: Span,
+    },
+}
+
+/// `import util.damage`, or `from util.damage import Damage, hit` when
+/// `names` is not empty
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ImportStmt {
+    /// Module path, e.g. `["util", "damage"]` for `util/damage.nx`
+    pub module: Vec<String>,
+    pub names: Vec<String>,
+    pub span: Span,
+}
+
+impl ImportStmt {
+    /// Name the module is used under and generated as: its last component
+    pub fn module_name(&self) -> &str {
+        self.module.last().map_or("", String::as_str)
+    }
 }
 
 /// Entity definition - the core game object type
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EntityDef {
     pub name: String,
+    /// Base entity from `extends`, as written (`Enemy`, `enemies.Enemy`)
+    pub extends: Option<String>,
+    /// Interfaces from `implements`, as written (`Damageable`,
+    /// `combat.Targetable`)
+    pub implements: Vec<String>,
     pub components: Vec<ComponentDef>,
     pub functions: Vec<FnDef>,
     pub signals: Vec<SignalDef>,
     pub variables: Vec<VarDecl>,
+    pub span: Span,
 }
 
 /// Component definition within an entity
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ComponentDef {
     pub name: String,
-    pub fields: HashMap<String, Expr>,
+    /// Fields in source order, so codegen and serialized ASTs are stable across runs
+    pub fields: Vec<(String, Expr)>,
+    pub span: Span,
+}
+
+/// Struct definition - a named record of typed fields
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<FieldDef>,
+    pub span: Span,
+}
+
+/// Field of a struct; fields with a default can be left out when constructing
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FieldDef {
+    pub name: String,
+    pub type_expr: TypeExpr,
+    pub default: Option<Expr>,
+    pub span: Span,
+}
+
+/// Enum definition
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<VariantDef>,
+    pub span: Span,
+}
+
+/// Interface definition - functions and signals that implementing entities
+/// must declare. Its functions have no body.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InterfaceDef {
+    pub name: String,
+    pub functions: Vec<FnDef>,
+    pub signals: Vec<SignalDef>,
+    pub span: Span,
+}
+
+/// Enum variant, with the fields it carries (none for a plain variant)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VariantDef {
+    pub name: String,
+    pub fields: Vec<Param>,
+    pub span: Span,
+}
+
+/// Standalone system, run every frame over the entities its `Query`
+/// parameters match
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SystemDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Vec<Statement>,
+    /// When it runs, see [`Annotation`]
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    pub span: Span,
+}
+
+/// `@after(spawn)` on the line before a system or `on_update`: its schedule,
+/// ordering, sets and run conditions
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Annotation {
+    pub name: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+impl SystemDef {
+    /// Signature as written in NexScript, e.g. `system gravity(q: Query<Velocity>)`
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| format!("{}: {}", p.name, p.type_expr))
+            .collect();
+        format!("system {}({})", self.name, params.join(", "))
+    }
 }
 
 /// Function definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FnDef {
     pub name: String,
     pub is_async: bool,
     pub params: Vec<Param>,
     pub return_type: Option<TypeExpr>,
     pub body: Vec<Statement>,
+    /// Only `on_update` can be annotated, see [`Annotation`]
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    pub span: Span,
+}
+
+impl FnDef {
+    /// Signature as written in NexScript, e.g. `fn take_damage(amount: int)`
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| format!("{}: {}", p.name, p.type_expr))
+            .collect();
+        let return_type = self
+            .return_type
+            .as_ref()
+            .map(|t| format!(" -> {}", t))
+            .unwrap_or_default();
+        let async_kw = if self.is_async { "async " } else { "" };
+        format!(
+            "{}fn {}({}){}",
+            async_kw,
+            self.name,
+            params.join(", "),
+            return_type
+        )
+    }
 }
 
 /// Function parameter
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Param {
     pub name: String,
     pub type_expr: TypeExpr,
+    pub span: Span,
 }
 
 /// Signal definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct SignalDef {
     pub name: String,
     pub params: Vec<Param>,
+    pub span: Span,
 }
 
 /// State machine definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StateMachine {
     pub name: String,
     pub initial_state: Option<String>,
     pub states: Vec<StateDef>,
+    pub span: Span,
 }
 
 /// Single state in a state machine
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StateDef {
     pub name: String,
     pub body: Vec<Statement>,
+    pub span: Span,
 }
 
 /// Variable declaration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct VarDecl {
     pub name: String,
     pub type_expr: Option<TypeExpr>,
     pub value: Expr,
+    pub span: Span,
 }
 
 /// Assignment statement
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Assignment {
     pub target: LValue,
     pub op: AssignOp,
     pub value: Expr,
+    pub span: Span,
 }
 
 /// Left-hand side of an assignment
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LValue {
     pub parts: Vec<String>,
 }
 
 /// Assignment operators
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum AssignOp {
     Assign,
     AddAssign,
@@ -148,45 +344,123 @@ pub enum AssignOp {
 }
 
 /// If statement
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct IfStmt {
     pub condition: Expr,
     pub then_body: Vec<Statement>,
     pub elif_clauses: Vec<(Expr, Vec<Statement>)>,
     pub else_body: Option<Vec<Statement>>,
+    pub span: Span,
+}
+
+/// Match statement: the first arm whose pattern matches and whose guard
+/// holds runs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MatchStmt {
+    pub subject: Expr,
+    pub arms: Vec<MatchArm>,
+    pub span: Span,
+}
+
+/// `case pattern if guard:` and its body
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Vec<Statement>,
+    pub span: Span,
+}
+
+/// Pattern in a `case`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Pattern {
+    /// `_`
+    Wildcard,
+    /// A name, which matches anything and binds it
+    Binding(String),
+    /// An int, float, string or bool literal
+    Literal(Expr),
+    /// `1..10`, or `1..=10` when `inclusive`
+    Range {
+        start: Expr,
+        end: Expr,
+        inclusive: bool,
+    },
+    /// An enum variant and patterns for its fields in order, e.g.
+    /// `Shape.Circle(r)`; `path` is as written, e.g. `["Shape", "Circle"]`
+    Variant {
+        path: Vec<String>,
+        fields: Vec<Pattern>,
+    },
+    /// `(a, _)`, matching a tuple
+    Tuple(Vec<Pattern>),
+    /// A variant with its field patterns by name, e.g.
+    /// `Shape::Circle { radius: r }`. Code generation builds these from
+    /// [`Pattern::Variant`]; the parser never does.
+    Destructure {
+        path: String,
+        fields: Vec<(String, Pattern)>,
+    },
 }
 
 /// While loop
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct WhileStmt {
     pub condition: Expr,
     pub body: Vec<Statement>,
+    pub span: Span,
 }
 
 /// For loop
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ForStmt {
-    pub var_name: String,
+    /// Loop variables; more than one unpacks each item, as in
+    /// `for i, item in enumerate(items)`
+    pub names: Vec<String>,
     pub iterable: Expr,
     pub body: Vec<Statement>,
+    pub span: Span,
 }
 
 /// Emit signal statement
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EmitStmt {
     pub signal_name: String,
     pub args: Vec<Expr>,
+    pub span: Span,
 }
 
 /// Type expression
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TypeExpr {
     Simple(String),
     Generic { name: String, params: Vec<TypeExpr> },
 }
 
+/// Formats the type as written in NexScript source (e.g. `List<int>`)
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeExpr::Simple(name) => write!(f, "{}", name),
+            // Query filters, `with Player`
+            TypeExpr::Generic { name, params } if params.len() == 1 && is_filter(name) => {
+                write!(f, "{} {}", name.to_lowercase(), params[0])
+            }
+            TypeExpr::Generic { name, params } => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "{}<{}>", name, params.join(", "))
+            }
+        }
+    }
+}
+
+/// Whether a type name is a query filter, `With` or `Without`
+pub(crate) fn is_filter(name: &str) -> bool {
+    name == "With" || name == "Without"
+}
+
 /// Expression node
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Expr {
     // Literals
     Int(i64),
@@ -197,6 +471,7 @@ pub enum Expr {
     Vec3(Box<Expr>, Box<Expr>, Box<Expr>),
     List(Vec<Expr>),
     Map(Vec<(String, Expr)>),
+    Tuple(Vec<Expr>),
 
     // References
     Identifier(String),
@@ -208,7 +483,26 @@ pub enum Expr {
     UnaryOp(UnaryOp, Box<Expr>),
 
     // Calls
-    Call { callee: Box<Expr>, args: Vec<Arg> },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Arg>,
+    },
+
+    /// An anonymous function: `fn(x: int) -> int: x * 2`, or `|x| x * 2`
+    /// with the parameter types left to inference
+    Lambda {
+        params: Vec<LambdaParam>,
+        return_type: Option<TypeExpr>,
+        body: Box<Expr>,
+    },
+
+    /// A struct or enum variant with its fields in declaration order, e.g.
+    /// `Element::Fire { damage: 3 }`. Code generation builds these from
+    /// constructor calls like `Element.Fire(damage: 3)`; the parser never does.
+    Construct {
+        path: String,
+        fields: Vec<(String, Expr)>,
+    },
 }
 
 /// Binary operators
@@ -240,49 +534,142 @@ pub enum UnaryOp {
 }
 
 /// Function argument (may be named)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Arg {
     pub name: Option<String>,
     pub value: Expr,
 }
 
+/// Lambda parameter, whose type may be left out
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LambdaParam {
+    pub name: String,
+    pub type_expr: Option<TypeExpr>,
+}
+
 // ============================================================================
 // Parser Implementation
 // ============================================================================
 
 /// Parse a NexScript source string into an AST
 pub fn parse(source: &str) -> Result<Program> {
-    // Preprocess to handle indentation
-    let preprocessed = preprocess_indentation(source);
+    let tree = SyntaxTree::parse(source)?;
+    Ok(ast_builder::build_ast(tree.root()))
+}
 
-    let pairs = NexScriptParser::parse(Rule::program, &preprocessed).map_err(|e| {
-        let line = match e.line_col {
-            pest::error::LineColLocation::Pos((line, _)) => line,
-            pest::error::LineColLocation::Span((line, _), _) => line,
+/// Run the grammar over preprocessed source, reporting errors against the original
+fn parse_preprocessed<'a>(
+    source: &str,
+    preprocessed: &'a str,
+    map: &OffsetMap,
+) -> Result<Pairs<'a, Rule>> {
+    NexScriptParser::parse(Rule::program, preprocessed).map_err(|e| {
+        let offset = match e.location {
+            pest::error::InputLocation::Pos(pos) => pos,
+            pest::error::InputLocation::Span((start, _)) => start,
         };
+        let (line, column) = source_map::line_col(source, map.start(offset));
         NexScriptError::ParseError {
             line,
-            message: e.to_string(),
+            column,
+            message: describe_error(&e.variant),
         }
-    })?;
+    })
+}
+
+/// Pest's message with grammar rule names replaced by what they mean to a
+/// script author, e.g. `expected an expression` rather than `unary_expr`
+fn describe_error(variant: &pest::error::ErrorVariant<Rule>) -> String {
+    let pest::error::ErrorVariant::ParsingError { positives, .. } = variant else {
+        return variant.message().to_string();
+    };
+    let mut expected: Vec<&str> = Vec::new();
+    for rule in positives {
+        let description = describe_rule(*rule);
+        if !expected.contains(&description) {
+            expected.push(description);
+        }
+    }
+    match expected.as_slice() {
+        [] => "unexpected input".to_string(),
+        [only] => format!("expected {}", only),
+        [rest @ .., last] => format!("expected {} or {}", rest.join(", "), last),
+    }
+}
 
-    // Build AST from pairs
-    Ok(ast_builder::build_ast(pairs))
+fn describe_rule(rule: Rule) -> &'static str {
+    match rule {
+        Rule::expression
+        | Rule::or_expr
+        | Rule::and_expr
+        | Rule::not_expr
+        | Rule::not_keyword
+        | Rule::comparison
+        | Rule::add_expr
+        | Rule::mul_expr
+        | Rule::unary_expr
+        | Rule::postfix_expr
+        | Rule::primary
+        | Rule::bool_literal
+        | Rule::list_literal
+        | Rule::map_literal => "an expression",
+        Rule::postfix | Rule::call | Rule::index | Rule::member_access => {
+            "a call, index or field access"
+        }
+        Rule::map_entry => "a map entry",
+        Rule::identifier => "a name",
+        Rule::module_path => "a module path",
+        Rule::import_keyword => "`import`",
+        Rule::type_expr | Rule::simple_type | Rule::generic_type => "a type",
+        Rule::int_literal | Rule::float_literal => "a number",
+        Rule::string_literal => "a string",
+        Rule::block | Rule::INDENT => "an indented block",
+        Rule::DEDENT => "the end of the block",
+        Rule::EOI => "the end of the file",
+        Rule::entity_member | Rule::component_field => "a member",
+        Rule::state_def => "a state",
+        Rule::param | Rule::param_list => "a parameter",
+        Rule::arg | Rule::arg_list => "an argument",
+        Rule::comp_op | Rule::add_op | Rule::mul_op | Rule::unary_op => "an operator",
+        Rule::assign_op => "`=`",
+        Rule::return_type => "`->`",
+        _ => "a statement",
+    }
 }
 
-/// Preprocess source to convert Python-style indentation to explicit tokens
-fn preprocess_indentation(source: &str) -> String {
+/// Preprocess source to convert Python-style indentation to explicit tokens.
+/// Also returns where each run of copied text came from in the source.
+fn preprocess_indentation(source: &str) -> (String, OffsetMap) {
     let mut result = String::new();
+    let mut map = OffsetMap::default();
     let mut indent_stack: Vec<usize> = vec![0];
+    let mut lines = source_lines(source);
 
-    for line in source.lines() {
+    while let Some((offset, line)) = lines.next() {
         if line.trim().is_empty() || line.trim().starts_with('#') {
+            map.push(result.len(), offset, line.len());
             result.push_str(line);
             result.push('\n');
             continue;
         }
 
+        // Lines inside open brackets continue the current one (e.g. a wrapped
+        // call). Join them, then pad with empty lines so line numbers still match.
         let indent = line.len() - line.trim_start().len();
+        let mut depth = bracket_depth(line);
+        let mut pieces = vec![(offset + indent, line.trim())];
+        if depth > 0 {
+            pieces[0].1 = strip_comment(line).trim();
+            while depth > 0 {
+                let Some((next_offset, next)) = lines.next() else {
+                    break;
+                };
+                depth += bracket_depth(next);
+                let next_indent = next.len() - next.trim_start().len();
+                pieces.push((next_offset + next_indent, strip_comment(next).trim()));
+            }
+        }
+
         let current_indent = *indent_stack.last().unwrap();
 
         if indent > current_indent {
@@ -295,8 +682,17 @@ fn preprocess_indentation(source: &str) -> String {
             }
         }
 
-        result.push_str(line.trim());
+        for (i, (piece_offset, piece)) in pieces.iter().enumerate() {
+            if i > 0 {
+                result.push(' ');
+            }
+            map.push(result.len(), *piece_offset, piece.len());
+            result.push_str(piece);
+        }
         result.push('\n');
+        for _ in 1..pieces.len() {
+            result.push('\n');
+        }
     }
 
     // Close any remaining indents
@@ -305,41 +701,507 @@ fn preprocess_indentation(source: &str) -> String {
         result.push_str("{{DEDENT}}");
     }
 
-    result
+    (result, map)
+}
+
+/// Lines with their byte offsets, split like `str::lines`
+fn source_lines(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    source.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        Some((start, line.strip_suffix('\r').unwrap_or(line)))
+    })
+}
+
+/// Net count of brackets opened on a line, ignoring strings and comments
+fn bracket_depth(line: &str) -> i32 {
+    lexer::tokenize(strip_comment(line))
+        .iter()
+        .filter(|t| t.kind == lexer::TokenKind::Punct)
+        .map(|t| match t.text {
+            "(" | "[" | "{" => 1,
+            ")" | "]" | "}" => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// The line without its trailing `#` comment
+fn strip_comment(line: &str) -> &str {
+    lexer::tokenize(line)
+        .iter()
+        .find(|t| t.kind == lexer::TokenKind::Comment)
+        .map_or(line, |comment| &line[..comment.offset])
 }
 
 // ============================================================================
 // Code Generation (Transpiler)
 // ============================================================================
 
+/// Bevy release the generated code is written against
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EngineApi {
+    #[default]
+    #[serde(rename = "0.14")]
+    V0_14,
+    #[serde(rename = "0.15")]
+    V0_15,
+}
+
+/// Bevy methods renamed in 0.15, as `(before, after)`
+const RENAMED_IN_0_15: &[(&str, &str)] = &[
+    ("delta_seconds", "delta_secs"),
+    ("elapsed_seconds", "elapsed_secs"),
+];
+
+impl EngineApi {
+    /// What an engine method the generated code calls is named in this
+    /// release, given its name in the oldest supported one
+    fn method(self, name: &'static str) -> &'static str {
+        if self < EngineApi::V0_15 {
+            return name;
+        }
+        RENAMED_IN_0_15
+            .iter()
+            .find(|(before, _)| *before == name)
+            .map_or(name, |(_, after)| after)
+    }
+}
+
+impl fmt::Display for EngineApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineApi::V0_14 => write!(f, "0.14"),
+            EngineApi::V0_15 => write!(f, "0.15"),
+        }
+    }
+}
+
+/// Project-wide code generation settings (see [`manifest::Manifest`])
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CodegenOptions {
+    pub engine_api: EngineApi,
+    /// Values of `features.<name>`; features not listed are off
+    pub features: BTreeMap<String, bool>,
+}
+
 /// Transpile NexScript AST to Rust code
 pub fn transpile(program: &Program) -> String {
+    source_map::strip(&transpile_marked(
+        program,
+        &Imports::default(),
+        &CodegenOptions::default(),
+    ))
+}
+
+/// Transpile, marking each construct with the `.nx` line it came from
+/// (`// nx:<line>`) and returning the matching source map
+pub fn transpile_with_source_map(program: &Program, source: &str) -> (String, SourceMap) {
+    transpile_module(
+        program,
+        source,
+        &Imports::default(),
+        &CodegenOptions::default(),
+    )
+}
+
+/// Transpile with the source map, naming imported items as they are
+/// generated in their own modules (see [`modules::ModuleGraph`])
+pub fn transpile_module(
+    program: &Program,
+    source: &str,
+    imports: &Imports,
+    options: &CodegenOptions,
+) -> (String, SourceMap) {
+    source_map::resolve(&transpile_marked(program, imports, options), source)
+}
+
+fn transpile_marked<'a>(
+    program: &'a Program,
+    imports: &'a Imports<'a>,
+    options: &'a CodegenOptions,
+) -> String {
     let mut output = String::new();
 
     output.push_str("// Generated by NexScript compiler\n");
     output.push_str("// Do not edit manually\n\n");
-    output.push_str("use bevy::prelude::*;\n\n");
+    output.push_str("use bevy::prelude::*;\n");
+
+    // `combat.damage(1)` and `util.combat.damage(1)` call into a module:
+    // `combat::damage(1)` in Rust; `features.debug_draw` is the flag's value
+    let mut paths = Paths {
+        modules: modules::plain_imports(program),
+        features: &options.features,
+        constructors: HashMap::new(),
+        enums: HashSet::new(),
+    };
+    for item in modules::Item::all(program) {
+        paths.declare(item.name().to_string(), item);
+    }
+    for (name, imported) in imports.names.iter().chain(&imports.prelude) {
+        paths.declare(name.clone(), imported.item);
+    }
+    for (module, items) in &imports.modules {
+        for (name, imported) in items {
+            paths.declare(format!("{}::{}", module, name), imported.item);
+        }
+    }
+    // Entities carry everything they inherit, so each generates on its own
+    let mut program = inheritance::flatten_program(program, imports);
+    for stmt in &mut program.statements {
+        resolve_paths(stmt, &paths);
+    }
+
+    let mut prelude: Vec<String> = imports
+        .prelude
+        .values()
+        .map(|imported| modules::module_name(std::path::Path::new(imported.file)))
+        .collect();
+    prelude.sort();
+    prelude.dedup();
+    for module in prelude {
+        output.push_str(&format!("use super::{}::*;\n", module));
+    }
+    for stmt in &program.statements {
+        if let Statement::Import(import) = stmt {
+            output.push_str(&transpile_import(import, imports));
+        }
+    }
+    output.push('\n');
 
+    let plan = schedules::Plan::new(&program, imports, |entity| entity);
+    for (set, span) in &plan.sets {
+        output.push_str(&mapped(
+            "",
+            *span,
+            format!(
+                "#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]\npub struct {};\n\n",
+                set
+            ),
+        ));
+    }
     for stmt in &program.statements {
-        output.push_str(&transpile_statement(stmt, 0));
+        match stmt {
+            Statement::EntityDef(entity) => {
+                output.push_str(&transpile_entity(entity, &plan, options.engine_api))
+            }
+            stmt => output.push_str(&transpile_statement(stmt, 0)),
+        }
+    }
+    let has_systems = program
+        .statements
+        .iter()
+        .any(|stmt| matches!(stmt, Statement::SystemDef(_)));
+    if has_systems {
+        output.push_str(&transpile_systems_plugin(&plan));
     }
 
     output
 }
 
+/// Generated modules are siblings, so imports go through `super`. Imported
+/// signals are their event types; names from modules that weren't resolved
+/// are used as written.
+fn transpile_import(import: &ImportStmt, imports: &Imports) -> String {
+    let module = modules::module_name(std::path::Path::new(import.module_name()));
+    if import.names.is_empty() {
+        return mapped("", import.span, format!("use super::{};\n", module));
+    }
+    let names: Vec<String> = import
+        .names
+        .iter()
+        .map(|name| {
+            imports
+                .names
+                .get(name)
+                .map_or_else(|| name.clone(), |imported| imported.item.rust_name())
+        })
+        .collect();
+    let names = match names.as_slice() {
+        [name] => name.clone(),
+        names => format!("{{{}}}", names.join(", ")),
+    };
+    mapped(
+        "",
+        import.span,
+        format!("use super::{}::{};\n", module, names),
+    )
+}
+
+/// Dotted names that aren't member accesses at runtime, and calls that
+/// aren't function calls
+struct Paths<'a> {
+    /// Modules imported with a plain `import`, by the name and the dotted
+    /// path they can be used under
+    modules: HashMap<String, &'a str>,
+    features: &'a BTreeMap<String, bool>,
+    /// Structs and enum variants by Rust path (`Weapon`, `Element::Fire`,
+    /// `combat::Weapon`), with each field's default
+    constructors: HashMap<String, Vec<(&'a str, Option<&'a Expr>)>>,
+    /// Enums by Rust path
+    enums: HashSet<String>,
+}
+
+impl<'a> Paths<'a> {
+    /// The module `expr` names, as `combat` or `util.combat`
+    fn module(&self, expr: &Expr) -> Option<&'a str> {
+        self.modules.get(&type_checker::member_path(expr)?).copied()
+    }
+
+    /// Make a struct or enum constructible under `path`
+    fn declare(&mut self, path: String, item: modules::Item<'a>) {
+        match item {
+            modules::Item::Struct(def) => {
+                let fields = def
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.as_str(), field.default.as_ref()))
+                    .collect();
+                self.constructors.insert(path, fields);
+            }
+            modules::Item::Enum(def) => {
+                for variant in &def.variants {
+                    let fields = variant
+                        .fields
+                        .iter()
+                        .map(|field| (field.name.as_str(), None))
+                        .collect();
+                    self.constructors
+                        .insert(format!("{}::{}", path, variant.name), fields);
+                }
+                self.enums.insert(path);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Rewrite `module.name` to the path `module::name`, `Enum.Variant` to
+/// `Enum::Variant`, `features.name` to the flag's value and constructor
+/// calls to [`Expr::Construct`] in every expression of a statement
+fn resolve_paths(stmt: &mut Statement, paths: &Paths) {
+    let body = |body: &mut Vec<Statement>| {
+        for stmt in body {
+            resolve_paths(stmt, paths);
+        }
+    };
+    match stmt {
+        Statement::EntityDef(entity) => {
+            for var in &mut entity.variables {
+                resolve_expr(&mut var.value, paths);
+            }
+            for component in &mut entity.components {
+                for (_, value) in &mut component.fields {
+                    resolve_expr(value, paths);
+                }
+            }
+            for func in &mut entity.functions {
+                body(&mut func.body);
+            }
+        }
+        Statement::StructDef(def) => {
+            for default in def
+                .fields
+                .iter_mut()
+                .filter_map(|field| field.default.as_mut())
+            {
+                resolve_expr(default, paths);
+            }
+        }
+        Statement::FnDef(func) => body(&mut func.body),
+        Statement::SystemDef(system) => body(&mut system.body),
+        Statement::StateMachine(machine) => {
+            for state in &mut machine.states {
+                body(&mut state.body);
+            }
+        }
+        Statement::VarDecl(var) => resolve_expr(&mut var.value, paths),
+        Statement::Assignment(assign) => resolve_expr(&mut assign.value, paths),
+        Statement::If(if_stmt) => {
+            resolve_expr(&mut if_stmt.condition, paths);
+            body(&mut if_stmt.then_body);
+            for (condition, clause) in &mut if_stmt.elif_clauses {
+                resolve_expr(condition, paths);
+                body(clause);
+            }
+            if let Some(else_body) = &mut if_stmt.else_body {
+                body(else_body);
+            }
+        }
+        Statement::Match(match_stmt) => {
+            resolve_expr(&mut match_stmt.subject, paths);
+            for arm in &mut match_stmt.arms {
+                resolve_pattern(&mut arm.pattern, paths);
+                if let Some(guard) = &mut arm.guard {
+                    resolve_expr(guard, paths);
+                }
+                body(&mut arm.body);
+            }
+        }
+        Statement::While(while_stmt) => {
+            resolve_expr(&mut while_stmt.condition, paths);
+            body(&mut while_stmt.body);
+        }
+        Statement::For(for_stmt) => {
+            resolve_expr(&mut for_stmt.iterable, paths);
+            body(&mut for_stmt.body);
+        }
+        Statement::Emit(emit) => {
+            for arg in &mut emit.args {
+                resolve_expr(arg, paths);
+            }
+        }
+        Statement::Return(Some(value)) | Statement::Expr { expr: value, .. } => {
+            resolve_expr(value, paths)
+        }
+        _ => {}
+    }
+}
+
+/// Rewrites inner expressions first, so `combat.Element.Fire(damage: 1)`
+/// becomes `combat::Element`, then `combat::Element::Fire`, then a constructor
+fn resolve_expr(expr: &mut Expr, paths: &Paths) {
+    resolve_children(expr, paths);
+    let resolved = match &*expr {
+        Expr::MemberAccess(base, member) => match (paths.module(base), &**base) {
+            (Some(module), _) => Some(Expr::Identifier(format!("{}::{}", module, member))),
+            (None, Expr::Identifier(base)) if paths.enums.contains(base) => {
+                Some(Expr::Identifier(format!("{}::{}", base, member)))
+            }
+            (None, Expr::Identifier(base)) if base == "features" => Some(Expr::Bool(
+                paths.features.get(member).copied().unwrap_or(false),
+            )),
+            _ => None,
+        },
+        Expr::Call { callee, args } => match &**callee {
+            Expr::Identifier(path) => paths.constructors.get(path).map(|fields| Expr::Construct {
+                path: path.clone(),
+                fields: construct_fields(fields, args),
+            }),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(mut resolved) = resolved {
+        // Defaults are copied in as written
+        resolve_children(&mut resolved, paths);
+        *expr = resolved;
+    }
+}
+
+/// Rewrite variant patterns of known enums to [`Pattern::Destructure`],
+/// pairing field patterns with the fields they are in the position of
+fn resolve_pattern(pattern: &mut Pattern, paths: &Paths) {
+    match pattern {
+        Pattern::Variant { path, fields } => {
+            for field in fields.iter_mut() {
+                resolve_pattern(field, paths);
+            }
+            let path = path.join("::");
+            if let Some(names) = paths.constructors.get(&path) {
+                let mut patterns = std::mem::take(fields).into_iter();
+                let fields = names
+                    .iter()
+                    .map(|(name, _)| {
+                        let field = patterns.next().unwrap_or(Pattern::Wildcard);
+                        (name.to_string(), field)
+                    })
+                    .collect();
+                *pattern = Pattern::Destructure { path, fields };
+            }
+        }
+        Pattern::Tuple(items) => {
+            for item in items {
+                resolve_pattern(item, paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Each field's value from a constructor call: the argument naming it, or
+/// in its position, or else its default. Fields with neither are left out
+/// (the checker reports them).
+fn construct_fields(fields: &[(&str, Option<&Expr>)], args: &[Arg]) -> Vec<(String, Expr)> {
+    fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, default))| {
+            let value = args
+                .iter()
+                .find(|arg| arg.name.as_deref() == Some(*name))
+                .or_else(|| args.get(i).filter(|arg| arg.name.is_none()))
+                .map(|arg| arg.value.clone())
+                .or_else(|| default.cloned())?;
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+fn resolve_children(expr: &mut Expr, paths: &Paths) {
+    match expr {
+        Expr::MemberAccess(base, _) | Expr::UnaryOp(_, base) => resolve_expr(base, paths),
+        Expr::Index(left, right) | Expr::BinaryOp(left, _, right) | Expr::Vec2(left, right) => {
+            resolve_expr(left, paths);
+            resolve_expr(right, paths);
+        }
+        Expr::Vec3(x, y, z) => {
+            resolve_expr(x, paths);
+            resolve_expr(y, paths);
+            resolve_expr(z, paths);
+        }
+        Expr::List(items) | Expr::Tuple(items) => {
+            for item in items {
+                resolve_expr(item, paths);
+            }
+        }
+        Expr::Map(entries) => {
+            for (_, value) in entries {
+                resolve_expr(value, paths);
+            }
+        }
+        Expr::Call { callee, args } => {
+            resolve_expr(callee, paths);
+            for arg in args {
+                resolve_expr(&mut arg.value, paths);
+            }
+        }
+        Expr::Construct { fields, .. } => {
+            for (_, value) in fields {
+                resolve_expr(value, paths);
+            }
+        }
+        Expr::Lambda { body, .. } => resolve_expr(body, paths),
+        _ => {}
+    }
+}
+
 fn transpile_statement(stmt: &Statement, indent: usize) -> String {
     let prefix = "    ".repeat(indent);
 
     match stmt {
-        Statement::EntityDef(entity) => transpile_entity(entity),
-        Statement::VarDecl(var) => {
+        // Emitted with the other `use`s at the top
+        Statement::Import(_) => String::new(),
+        // Emitted by `transpile_marked`, which knows when their systems run
+        Statement::EntityDef(_) => String::new(),
+        Statement::StructDef(def) => transpile_struct(def),
+        Statement::EnumDef(def) => transpile_enum(def),
+        Statement::InterfaceDef(def) => transpile_interface(def),
+        Statement::FnDef(func) => transpile_function(func, indent),
+        Statement::SystemDef(system) => transpile_system(system),
+        Statement::SignalDef(signal) => transpile_signal(signal, ""),
+        Statement::StateMachine(machine) => transpile_state_machine(machine),
+        Statement::VarDecl(var) => mapped(
+            &prefix,
+            var.span,
             format!(
                 "{}let {} = {};\n",
                 prefix,
                 var.name,
                 transpile_expr(&var.value)
-            )
-        }
+            ),
+        ),
         Statement::Assignment(assign) => {
             let op = match assign.op {
                 AssignOp::Assign => "=",
@@ -348,12 +1210,16 @@ fn transpile_statement(stmt: &Statement, indent: usize) -> String {
                 AssignOp::MulAssign => "*=",
                 AssignOp::DivAssign => "/=",
             };
-            format!(
-                "{}{}{} {};\n",
-                prefix,
-                transpile_lvalue(&assign.target),
-                op,
-                transpile_expr(&assign.value)
+            mapped(
+                &prefix,
+                assign.span,
+                format!(
+                    "{}{}{} {};\n",
+                    prefix,
+                    transpile_lvalue(&assign.target),
+                    op,
+                    transpile_expr(&assign.value)
+                ),
             )
         }
         Statement::If(if_stmt) => {
@@ -380,7 +1246,33 @@ fn transpile_statement(stmt: &Statement, indent: usize) -> String {
             }
 
             output.push_str("\n");
-            output
+            mapped(&prefix, if_stmt.span, output)
+        }
+        Statement::Match(match_stmt) => {
+            let mut output = format!(
+                "{}match {} {{\n",
+                prefix,
+                transpile_match_subject(match_stmt)
+            );
+            for arm in &match_stmt.arms {
+                let guard = arm
+                    .guard
+                    .as_ref()
+                    .map(|guard| format!(" if {}", transpile_expr(guard)))
+                    .unwrap_or_default();
+                output.push_str(&format!(
+                    "{}    {}{} => {{\n",
+                    prefix,
+                    transpile_pattern(&arm.pattern),
+                    guard
+                ));
+                for s in &arm.body {
+                    output.push_str(&transpile_statement(s, indent + 2));
+                }
+                output.push_str(&format!("{}    }}\n", prefix));
+            }
+            output.push_str(&format!("{}}}\n", prefix));
+            mapped(&prefix, match_stmt.span, output)
         }
         Statement::While(while_stmt) => {
             let mut output = format!(
@@ -392,29 +1284,54 @@ fn transpile_statement(stmt: &Statement, indent: usize) -> String {
                 output.push_str(&transpile_statement(s, indent + 1));
             }
             output.push_str(&format!("{}}}\n", prefix));
-            output
+            mapped(&prefix, while_stmt.span, output)
+        }
+        Statement::For(for_stmt) => {
+            let names = match for_stmt.names.as_slice() {
+                [name] => name.clone(),
+                names => format!("({})", names.join(", ")),
+            };
+            let mut output = format!(
+                "{}for {} in {} {{\n",
+                prefix,
+                names,
+                transpile_iterable(&for_stmt.iterable)
+            );
+            for s in &for_stmt.body {
+                output.push_str(&transpile_statement(s, indent + 1));
+            }
+            output.push_str(&format!("{}}}\n", prefix));
+            mapped(&prefix, for_stmt.span, output)
         }
+        Statement::Break { span } => mapped(&prefix, *span, format!("{}break;\n", prefix)),
+        Statement::Continue { span } => mapped(&prefix, *span, format!("{}continue;\n", prefix)),
+        Statement::Pass => String::new(),
         Statement::Emit(emit) => {
             let args: Vec<String> = emit.args.iter().map(transpile_expr).collect();
-            format!(
-                "{}// emit {}({});\n",
-                prefix,
-                emit.signal_name,
-                args.join(", ")
+            mapped(
+                &prefix,
+                emit.span,
+                format!(
+                    "{}// emit {}({});\n",
+                    prefix,
+                    emit.signal_name,
+                    args.join(", ")
+                ),
             )
         }
-        Statement::Expr(expr) => {
-            format!("{}{};\n", prefix, transpile_expr(expr))
-        }
+        Statement::Expr { expr, span } => mapped(
+            &prefix,
+            *span,
+            format!("{}{};\n", prefix, transpile_expr(expr)),
+        ),
         Statement::Return(expr) => match expr {
             Some(e) => format!("{}return {};\n", prefix, transpile_expr(e)),
             None => format!("{}return;\n", prefix),
         },
-        _ => format!("{}// TODO: transpile {:?}\n", prefix, stmt),
     }
 }
 
-fn transpile_entity(entity: &EntityDef) -> String {
+fn transpile_entity(entity: &EntityDef, plan: &schedules::Plan, engine_api: EngineApi) -> String {
     let mut output = String::new();
     let entity_name = &entity.name;
 
@@ -430,10 +1347,19 @@ fn transpile_entity(entity: &EntityDef) -> String {
         } else {
             "/* infer */".to_string()
         };
-        output.push_str(&format!("    pub {}: {},\n", var.name, type_str));
+        output.push_str(&mapped(
+            "    ",
+            var.span,
+            format!("    pub {}: {},\n", var.name, type_str),
+        ));
     }
     output.push_str("}\n\n");
 
+    // Signals become events, prefixed so two entities can share signal names
+    for signal in &entity.signals {
+        output.push_str(&transpile_signal(signal, entity_name));
+    }
+
     // 2. Generate Plugin to register systems
     output.push_str(&format!("pub struct {}Plugin;\n", entity_name));
     output.push_str(&format!("impl Plugin for {}Plugin {{\n", entity_name));
@@ -443,13 +1369,45 @@ fn transpile_entity(entity: &EntityDef) -> String {
         entity_name
     ));
 
+    // Entities are tagged with the marker of every interface they implement
+    if !entity.implements.is_empty() {
+        let markers: Vec<String> = entity
+            .implements
+            .iter()
+            .map(|name| name.replace('.', "::"))
+            .collect();
+        let markers = match markers.as_slice() {
+            [marker] => marker.clone(),
+            markers => format!("({})", markers.join(", ")),
+        };
+        output.push_str("        app.world_mut()\n");
+        output.push_str(&format!(
+            "            .register_component_hooks::<{}>()\n",
+            entity_name
+        ));
+        output.push_str("            .on_add(|mut world, entity, _| {\n");
+        output.push_str(&format!(
+            "                world.commands().entity(entity).insert({});\n",
+            markers
+        ));
+        output.push_str("            });\n");
+    }
+
     // Register lifecycle systems
     for func in &entity.functions {
         if func.name == "on_update" {
-            output.push_str(&format!(
-                "        app.add_systems(Update, {}_on_update);\n",
-                entity_name.to_lowercase()
-            ));
+            let label = format!("{}.on_update", entity_name);
+            if let Some(system) = plan.system(&label) {
+                output.push_str(&mapped(
+                    "        ",
+                    func.span,
+                    format!(
+                        "        app.add_systems({}, {});\n",
+                        system.schedule,
+                        plan.config(system)
+                    ),
+                ));
+            }
         } else if func.name == "on_ready" {
             output.push_str(&format!(
                 "        app.add_systems(Startup, {}_on_ready);\n",
@@ -463,7 +1421,7 @@ fn transpile_entity(entity: &EntityDef) -> String {
     // 3. Generate Systems
     for func in &entity.functions {
         if func.name == "on_update" {
-            output.push_str(&transpile_update_system(entity, func));
+            output.push_str(&transpile_update_system(entity, func, engine_api));
         } else if func.name == "on_ready" {
             output.push_str(&transpile_ready_system(entity, func)); // Placeholder
         } else {
@@ -471,10 +1429,241 @@ fn transpile_entity(entity: &EntityDef) -> String {
         }
     }
 
+    mapped("", entity.span, output)
+}
+
+/// Derives for plain data types, so they can be inspected, saved and loaded
+const DATA_DERIVES: &str =
+    "#[derive(Debug, Clone, PartialEq, Reflect, serde::Serialize, serde::Deserialize)]\n";
+
+/// A struct, with a `Default` impl when every field has a default
+fn transpile_struct(def: &StructDef) -> String {
+    let mut output = String::from(DATA_DERIVES);
+    output.push_str(&format!("pub struct {} {{\n", def.name));
+    for field in &def.fields {
+        output.push_str(&mapped(
+            "    ",
+            field.span,
+            format!(
+                "    pub {}: {},\n",
+                field.name,
+                transpile_type(&field.type_expr)
+            ),
+        ));
+    }
+    output.push_str("}\n\n");
+
+    let defaults: Option<Vec<(String, Expr)>> = def
+        .fields
+        .iter()
+        .map(|field| Some((field.name.clone(), field.default.clone()?)))
+        .collect();
+    if let Some(fields) = defaults.filter(|fields| !fields.is_empty()) {
+        let value = Expr::Construct {
+            path: "Self".to_string(),
+            fields,
+        };
+        output.push_str(&format!("impl Default for {} {{\n", def.name));
+        output.push_str("    fn default() -> Self {\n");
+        output.push_str(&format!("        {}\n", transpile_expr(&value)));
+        output.push_str("    }\n");
+        output.push_str("}\n\n");
+    }
+    mapped("", def.span, output)
+}
+
+/// An interface as a marker component, so every entity implementing it can
+/// be queried with `With<Damageable>`
+fn transpile_interface(def: &InterfaceDef) -> String {
+    let output = format!(
+        "#[derive(Component, Default)]\npub struct {};\n\n",
+        def.name
+    );
+    mapped("", def.span, output)
+}
+
+/// An enum; variants with fields become struct-like variants
+fn transpile_enum(def: &EnumDef) -> String {
+    let mut output = String::from(DATA_DERIVES);
+    output.push_str(&format!("pub enum {} {{\n", def.name));
+    for variant in &def.variants {
+        let text = if variant.fields.is_empty() {
+            format!("    {},\n", variant.name)
+        } else {
+            let fields: Vec<String> = variant
+                .fields
+                .iter()
+                .map(|field| format!("{}: {}", field.name, transpile_type(&field.type_expr)))
+                .collect();
+            format!("    {} {{ {} }},\n", variant.name, fields.join(", "))
+        };
+        output.push_str(&mapped("    ", variant.span, text));
+    }
+    output.push_str("}\n\n");
+    mapped("", def.span, output)
+}
+
+/// A signal as a Bevy event carrying its parameters
+fn transpile_signal(signal: &SignalDef, prefix: &str) -> String {
+    let mut output = String::from("#[derive(Event, Debug, Clone)]\n");
+    if signal.params.is_empty() {
+        output.push_str(&format!(
+            "pub struct {};\n\n",
+            event_name(prefix, &signal.name)
+        ));
+        return mapped("", signal.span, output);
+    }
+
+    output.push_str(&format!(
+        "pub struct {} {{\n",
+        event_name(prefix, &signal.name)
+    ));
+    for param in &signal.params {
+        output.push_str(&format!(
+            "    pub {}: {},\n",
+            param.name,
+            transpile_type(&param.type_expr)
+        ));
+    }
+    output.push_str("}\n\n");
+    mapped("", signal.span, output)
+}
+
+/// A state machine as a Bevy `States` enum starting in its initial state
+fn transpile_state_machine(machine: &StateMachine) -> String {
+    let initial = machine
+        .initial_state
+        .as_deref()
+        .or_else(|| machine.states.first().map(|s| s.name.as_str()));
+
+    let mut output =
+        String::from("#[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]\n");
+    output.push_str(&format!("pub enum {} {{\n", machine.name));
+    for state in &machine.states {
+        if Some(state.name.as_str()) == initial {
+            output.push_str("    #[default]\n");
+        }
+        output.push_str(&mapped(
+            "    ",
+            state.span,
+            format!("    {},\n", state.name),
+        ));
+    }
+    output.push_str("}\n\n");
+    mapped("", machine.span, output)
+}
+
+/// Event type for a signal, e.g. `health_changed` on `Player` -> `PlayerHealthChanged`
+pub(crate) fn event_name(prefix: &str, signal: &str) -> String {
+    let mut name = prefix.to_string();
+    for word in signal.split('_') {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
+
+/// Types a generated module contributes to the aggregate `NexScriptPlugin`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ModuleExports {
+    pub plugins: Vec<String>,
+    pub events: Vec<String>,
+    pub states: Vec<String>,
+    /// Structs and enums, registered for reflection
+    #[serde(default)]
+    pub types: Vec<String>,
+}
+
+/// Collect the plugins, events, states and types `transpile` generates for a
+/// program
+pub fn module_exports(program: &Program) -> ModuleExports {
+    let mut exports = ModuleExports::default();
+    for stmt in &program.statements {
+        match stmt {
+            Statement::EntityDef(entity) => {
+                exports.plugins.push(format!("{}Plugin", entity.name));
+                for signal in &entity.signals {
+                    exports.events.push(event_name(&entity.name, &signal.name));
+                }
+            }
+            Statement::SignalDef(signal) => exports.events.push(event_name("", &signal.name)),
+            Statement::SystemDef(_) if !exports.plugins.iter().any(|p| p == "SystemsPlugin") => {
+                exports.plugins.push("SystemsPlugin".to_string())
+            }
+            Statement::StateMachine(machine) => exports.states.push(machine.name.clone()),
+            Statement::StructDef(def) => exports.types.push(def.name.clone()),
+            Statement::EnumDef(def) => exports.types.push(def.name.clone()),
+            _ => {}
+        }
+    }
+    exports
+}
+
+/// Generate the `mod.rs` declaring every module and a `NexScriptPlugin`
+/// that registers all of their plugins, events, states and types. With
+/// `include_dir`, modules are pulled in from that directory under `OUT_DIR`
+/// so the file can be `include!`d from a build script's output.
+pub fn transpile_mod(modules: &[(String, ModuleExports)], include_dir: Option<&str>) -> String {
+    let mut modules: Vec<&(String, ModuleExports)> = modules.iter().collect();
+    modules.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut output = String::new();
+    output.push_str("// Generated by NexScript compiler\n");
+    output.push_str("// Do not edit manually\n\n");
+    output.push_str("use bevy::prelude::*;\n\n");
+
+    for (module, _) in &modules {
+        match include_dir {
+            Some(dir) => output.push_str(&format!(
+                "pub mod {} {{\n    include!(concat!(env!(\"OUT_DIR\"), \"/{}/{}.rs\"));\n}}\n",
+                module, dir, module
+            )),
+            None => output.push_str(&format!("pub mod {};\n", module)),
+        }
+    }
+    if !modules.is_empty() {
+        output.push('\n');
+    }
+
+    output.push_str("/// Adds every NexScript entity plugin, event, state and type\n");
+    output.push_str("pub struct NexScriptPlugin;\n");
+    output.push_str("impl Plugin for NexScriptPlugin {\n");
+    output.push_str("    fn build(&self, app: &mut App) {\n");
+    for (module, exports) in &modules {
+        for plugin in &exports.plugins {
+            output.push_str(&format!(
+                "        app.add_plugins({}::{});\n",
+                module, plugin
+            ));
+        }
+        for event in &exports.events {
+            output.push_str(&format!(
+                "        app.add_event::<{}::{}>();\n",
+                module, event
+            ));
+        }
+        for state in &exports.states {
+            output.push_str(&format!(
+                "        app.init_state::<{}::{}>();\n",
+                module, state
+            ));
+        }
+        for type_name in &exports.types {
+            output.push_str(&format!(
+                "        app.register_type::<{}::{}>();\n",
+                module, type_name
+            ));
+        }
+    }
+    output.push_str("    }\n");
+    output.push_str("}\n");
     output
 }
 
-fn transpile_update_system(entity: &EntityDef, func: &FnDef) -> String {
+fn transpile_update_system(entity: &EntityDef, func: &FnDef, engine_api: EngineApi) -> String {
     let mut output = String::new();
     let sys_name = format!("{}_on_update", entity.name.to_lowercase());
 
@@ -483,7 +1672,10 @@ fn transpile_update_system(entity: &EntityDef, func: &FnDef) -> String {
         "fn {}(time: Res<Time>, mut query: Query<(&mut {}, &mut Transform)>) {{\n",
         sys_name, entity.name
     ));
-    output.push_str("    let delta = time.delta_seconds();\n");
+    output.push_str(&format!(
+        "    let delta = time.{}();\n",
+        engine_api.method("delta_seconds")
+    ));
 
     // Iterate over entities
     output.push_str(&format!(
@@ -499,10 +1691,10 @@ fn transpile_update_system(entity: &EntityDef, func: &FnDef) -> String {
 
     output.push_str("    }\n");
     output.push_str("}\n\n");
-    output
+    mapped("", func.span, output)
 }
 
-fn transpile_ready_system(entity: &EntityDef, _func: &FnDef) -> String {
+fn transpile_ready_system(entity: &EntityDef, func: &FnDef) -> String {
     let mut output = String::new();
     let sys_name = format!("{}_on_ready", entity.name.to_lowercase());
     output.push_str(&format!("fn {}(mut commands: Commands) {{\n", sys_name));
@@ -511,7 +1703,7 @@ fn transpile_ready_system(entity: &EntityDef, _func: &FnDef) -> String {
         entity.name
     ));
     output.push_str("}\n\n");
-    output
+    mapped("", func.span, output)
 }
 
 fn transpile_statement_in_system(stmt: &Statement, indent: usize, entity_name: &str) -> String {
@@ -550,13 +1742,13 @@ fn transpile_function(func: &FnDef, indent: usize) -> String {
     let params: Vec<String> = func
         .params
         .iter()
-        .map(|p| format!("{}: {}", p.name, transpile_type(&p.type_expr)))
+        .map(|p| format!("{}: {}", p.name, transpile_signature_type(&p.type_expr)))
         .collect();
 
     let return_type = func
         .return_type
         .as_ref()
-        .map(|t| format!(" -> {}", transpile_type(t)))
+        .map(|t| format!(" -> {}", transpile_signature_type(t)))
         .unwrap_or_default();
 
     output.push_str(&format!(
@@ -574,6 +1766,88 @@ fn transpile_function(func: &FnDef, indent: usize) -> String {
 
     output.push_str(&format!("{}}}\n\n", prefix));
 
+    mapped(&prefix, func.span, output)
+}
+
+/// A standalone system. Each `Query` parameter becomes a Bevy query that
+/// borrows a component mutably only if the system assigns to it; other
+/// parameters (`time: Res<Time>`) are passed through.
+fn transpile_system(system: &SystemDef) -> String {
+    let access = queries::access(system);
+    let params: Vec<String> = system
+        .params
+        .iter()
+        .map(|p| match queries::Query::of(&p.type_expr) {
+            Some(query) => transpile_query(&p.name, &query, &access[p.name.as_str()]),
+            None => format!("{}: {}", p.name, transpile_type(&p.type_expr)),
+        })
+        .collect();
+
+    let mut output = format!("pub fn {}({}) {{\n", system.name, params.join(", "));
+    let mut body = system.body.clone();
+    queries::iterate(&mut body, &access);
+    for stmt in &body {
+        output.push_str(&transpile_statement(stmt, 1));
+    }
+    output.push_str("}\n\n");
+    mapped("", system.span, output)
+}
+
+/// `mut q: Query<(&Transform, &mut Velocity), (With<Player>, Without<Frozen>)>`
+fn transpile_query(name: &str, query: &queries::Query, mutable: &[bool]) -> String {
+    let data: Vec<String> = query
+        .components
+        .iter()
+        .zip(mutable)
+        .map(|(component, mutable)| {
+            let access = if *mutable { "&mut " } else { "&" };
+            format!("{}{}", access, transpile_type(component))
+        })
+        .collect();
+    let data = match data.as_slice() {
+        [component] => component.clone(),
+        data => format!("({})", data.join(", ")),
+    };
+    let filters: Vec<String> = query
+        .with
+        .iter()
+        .map(|component| format!("With<{}>", transpile_type(component)))
+        .chain(
+            query
+                .without
+                .iter()
+                .map(|component| format!("Without<{}>", transpile_type(component))),
+        )
+        .collect();
+    let query = match filters.as_slice() {
+        [] => format!("Query<{}>", data),
+        [filter] => format!("Query<{}, {}>", data, filter),
+        filters => format!("Query<{}, ({})>", data, filters.join(", ")),
+    };
+    let binding = if mutable.contains(&true) { "mut " } else { "" };
+    format!("{}{}: {}", binding, name, query)
+}
+
+/// The plugin adding a file's standalone systems to their schedules
+fn transpile_systems_plugin(plan: &schedules::Plan) -> String {
+    let mut output = String::new();
+    output.push_str("pub struct SystemsPlugin;\n");
+    output.push_str("impl Plugin for SystemsPlugin {\n");
+    output.push_str("    fn build(&self, app: &mut App) {\n");
+    // Entities add their own `on_update`
+    for system in plan.systems.iter().filter(|s| !s.label.contains('.')) {
+        output.push_str(&mapped(
+            "        ",
+            system.span,
+            format!(
+                "        app.add_systems({}, {});\n",
+                system.schedule,
+                plan.config(system)
+            ),
+        ));
+    }
+    output.push_str("    }\n");
+    output.push_str("}\n\n");
     output
 }
 
@@ -586,6 +1860,11 @@ fn transpile_type(type_expr: &TypeExpr) -> String {
             "str" => "String".to_string(),
             _ => name.clone(),
         },
+        // `Fn<int, bool>` takes an int and returns a bool. Anywhere but a
+        // function's signature it is boxed, so it can be stored.
+        TypeExpr::Generic { name, params } if name == "Fn" && !params.is_empty() => {
+            format!("Box<dyn {} + Send + Sync>", transpile_fn_trait(params))
+        }
         TypeExpr::Generic { name, params } => {
             let params_str: Vec<String> = params.iter().map(transpile_type).collect();
             format!("{}<{}>", name, params_str.join(", "))
@@ -593,10 +1872,151 @@ fn transpile_type(type_expr: &TypeExpr) -> String {
     }
 }
 
+/// A function parameter or return type, where `Fn<int, bool>` is an
+/// `impl Fn` so closures are passed through unboxed
+fn transpile_signature_type(type_expr: &TypeExpr) -> String {
+    match type_expr {
+        TypeExpr::Generic { name, params } if name == "Fn" && !params.is_empty() => {
+            format!("impl {}", transpile_fn_trait(params))
+        }
+        type_expr => transpile_type(type_expr),
+    }
+}
+
+/// `Fn(i32) -> bool` for `Fn<int, bool>`, the last type being the return
+/// type (`None` for nothing)
+fn transpile_fn_trait(params: &[TypeExpr]) -> String {
+    let (returns, params) = params.split_last().expect("checked non-empty");
+    let params: Vec<String> = params.iter().map(transpile_type).collect();
+    let returns = match returns {
+        TypeExpr::Simple(name) if name == "None" => String::new(),
+        returns => format!(" -> {}", transpile_type(returns)),
+    };
+    format!("Fn({}){}", params.join(", "), returns)
+}
+
 fn transpile_lvalue(lvalue: &LValue) -> String {
     lvalue.parts.join(".")
 }
 
+/// What a `for` loop iterates over: `range(...)` becomes a Rust range,
+/// `enumerate(items)` pairs each item with an `i32` index, and variables are
+/// cloned so the loop doesn't consume them
+fn transpile_iterable(iterable: &Expr) -> String {
+    match iterable {
+        Expr::Call { callee, args } => match &**callee {
+            Expr::Identifier(name) if name == "range" => transpile_range(args),
+            Expr::Identifier(name) if name == "enumerate" && args.len() == 1 => {
+                format!("(0..).zip({})", transpile_iterable(&args[0].value))
+            }
+            _ => transpile_expr(iterable),
+        },
+        Expr::Identifier(_) | Expr::MemberAccess(..) => {
+            format!("{}.clone()", transpile_expr(iterable))
+        }
+        _ => transpile_expr(iterable),
+    }
+}
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`; a
+/// negative step counts down from `start` to just above `end`
+fn transpile_range(args: &[Arg]) -> String {
+    let values: Vec<String> = args.iter().map(|arg| transpile_expr(&arg.value)).collect();
+    match (values.as_slice(), args.get(2).map(|arg| &arg.value)) {
+        ([end], _) => format!("0..{}", end),
+        ([start, end], _) | ([start, end, _], Some(Expr::Int(1))) => {
+            format!("{}..{}", start, end)
+        }
+        ([start, end, _], Some(Expr::UnaryOp(UnaryOp::Neg, step))) => format!(
+            "({} + 1..={}).rev().step_by({} as usize)",
+            end,
+            start,
+            transpile_expr(step)
+        ),
+        ([start, end, step], _) => format!("({}..{}).step_by({} as usize)", start, end, step),
+        // Reported by the checker
+        _ => "0..0".to_string(),
+    }
+}
+
+/// The value matched on: strings are matched as `&str` so string literal
+/// patterns apply, and variables holding enums are cloned rather than moved
+/// into the match
+fn transpile_match_subject(match_stmt: &MatchStmt) -> String {
+    let patterns: Vec<&Pattern> = match_stmt.arms.iter().map(|arm| &arm.pattern).collect();
+    match_value(&match_stmt.subject, &patterns)
+}
+
+fn match_value(value: &Expr, patterns: &[&Pattern]) -> String {
+    if let Expr::Tuple(items) = value {
+        let items: Vec<String> = items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let column: Vec<&Pattern> = patterns
+                    .iter()
+                    .filter_map(|pattern| match pattern {
+                        Pattern::Tuple(items) => items.get(i),
+                        _ => None,
+                    })
+                    .collect();
+                match_value(item, &column)
+            })
+            .collect();
+        return format!("({})", items.join(", "));
+    }
+
+    let text = transpile_expr(value);
+    let any = |f: fn(&Pattern) -> bool| patterns.iter().any(|pattern| f(pattern));
+    if any(|p| matches!(p, Pattern::Literal(Expr::String(_)))) {
+        format!("{}.as_str()", text)
+    } else if any(|p| matches!(p, Pattern::Variant { .. } | Pattern::Destructure { .. }))
+        && matches!(value, Expr::Identifier(_) | Expr::MemberAccess(..))
+    {
+        format!("{}.clone()", text)
+    } else {
+        text
+    }
+}
+
+fn transpile_pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(name) => name.clone(),
+        Pattern::Literal(value) => transpile_expr(value),
+        Pattern::Range {
+            start,
+            end,
+            inclusive,
+        } => format!(
+            "{}{}{}",
+            transpile_expr(start),
+            if *inclusive { "..=" } else { ".." },
+            transpile_expr(end)
+        ),
+        Pattern::Variant { path, fields } if fields.is_empty() => path.join("::"),
+        Pattern::Variant { path, fields } => {
+            let fields: Vec<String> = fields.iter().map(transpile_pattern).collect();
+            format!("{}({})", path.join("::"), fields.join(", "))
+        }
+        Pattern::Tuple(items) => {
+            let items: Vec<String> = items.iter().map(transpile_pattern).collect();
+            format!("({})", items.join(", "))
+        }
+        Pattern::Destructure { path, fields } if fields.is_empty() => path.clone(),
+        Pattern::Destructure { path, fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, field)| match field {
+                    Pattern::Binding(binding) if binding == name => name.clone(),
+                    field => format!("{}: {}", name, transpile_pattern(field)),
+                })
+                .collect();
+            format!("{} {{ {} }}", path, fields.join(", "))
+        }
+    }
+}
+
 fn transpile_expr(expr: &Expr) -> String {
     match expr {
         Expr::Int(n) => n.to_string(),
@@ -633,12 +2053,96 @@ fn transpile_expr(expr: &Expr) -> String {
                 transpile_expr(right)
             )
         }
+        // A nested operator is parenthesized so `-(-x)` isn't `--x`
+        Expr::UnaryOp(op, operand) => {
+            let op = match op {
+                UnaryOp::Neg => "-",
+                UnaryOp::Not => "!",
+            };
+            match &**operand {
+                Expr::UnaryOp(..) => format!("{}({})", op, transpile_expr(operand)),
+                operand => format!("{}{}", op, transpile_expr(operand)),
+            }
+        }
         Expr::MemberAccess(expr, member) => {
             format!("{}.{}", transpile_expr(expr), member)
         }
+        // Indices are `int`s, which Rust only indexes with as `usize`
+        Expr::Index(list, index) => match &**index {
+            Expr::Int(_) => format!("{}[{}]", transpile_expr(list), transpile_expr(index)),
+            index => format!(
+                "{}[{} as usize]",
+                transpile_expr(list),
+                transpile_expr(index)
+            ),
+        },
+        Expr::List(items) => {
+            let items: Vec<String> = items.iter().map(transpile_expr).collect();
+            format!("vec![{}]", items.join(", "))
+        }
         Expr::Call { callee, args } => {
             let args_str: Vec<String> = args.iter().map(|arg| transpile_expr(&arg.value)).collect();
-            format!("{}({})", transpile_expr(callee), args_str.join(", "))
+            match &**callee {
+                // Lists are mapped and filtered through iterators
+                Expr::MemberAccess(list, method)
+                    if matches!(method.as_str(), "map" | "filter") && args.len() == 1 =>
+                {
+                    let list = transpile_expr(list);
+                    let f = &args_str[0];
+                    let items = if method == "map" {
+                        format!("map({})", f)
+                    } else {
+                        format!("filter(|item| ({})(item.clone()))", f)
+                    };
+                    format!("{}.iter().cloned().{}.collect::<Vec<_>>()", list, items)
+                }
+                Expr::Lambda { .. } => {
+                    format!("({})({})", transpile_expr(callee), args_str.join(", "))
+                }
+                _ => format!("{}({})", transpile_expr(callee), args_str.join(", ")),
+            }
+        }
+        // `move`, so locals are captured by value
+        Expr::Lambda {
+            params,
+            return_type,
+            body,
+        } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|p| match &p.type_expr {
+                    Some(t) => format!("{}: {}", p.name, transpile_type(t)),
+                    None => p.name.clone(),
+                })
+                .collect();
+            match return_type {
+                Some(t) => format!(
+                    "move |{}| -> {} {{ {} }}",
+                    params.join(", "),
+                    transpile_type(t),
+                    transpile_expr(body)
+                ),
+                None => format!("move |{}| {}", params.join(", "), transpile_expr(body)),
+            }
+        }
+        Expr::Construct { path, fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| match value {
+                    // Fields hold owned strings
+                    Expr::String(_) => format!("{}: {}.to_string()", name, transpile_expr(value)),
+                    value => format!("{}: {}", name, transpile_expr(value)),
+                })
+                .collect();
+            if fields.is_empty() {
+                format!("{} {{}}", path)
+            } else {
+                format!("{} {{ {} }}", path, fields.join(", "))
+            }
+        }
+        Expr::Tuple(items) => {
+            let items: Vec<String> = items.iter().map(transpile_expr).collect();
+            format!("({})", items.join(", "))
         }
         Expr::Vec2(x, y) => format!("Vec2::new({}, {})", transpile_expr(x), transpile_expr(y)),
         Expr::Vec3(x, y, z) => format!(
@@ -662,7 +2166,7 @@ mod tests {
     #[test]
     fn test_preprocess_indentation() {
         let source = "entity Player:\n    let x = 1\n    let y = 2\n";
-        let result = preprocess_indentation(source);
+        let (result, _) = preprocess_indentation(source);
         assert!(result.contains("{{INDENT}}"));
         assert!(result.contains("{{DEDENT}}"));
     }
@@ -672,4 +2176,334 @@ mod tests {
         let result = parse("");
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_component_fields_keep_source_order() {
+        let source = "entity Player:\n    component Stats:\n        zeta = 1\n        alpha = 2\n        mid = 3\n";
+        let program = parse(source).unwrap();
+        let Statement::EntityDef(entity) = &program.statements[0] else {
+            panic!("expected entity");
+        };
+        let names: Vec<&str> = entity.components[0]
+            .fields
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, vec!["zeta", "alpha", "mid"]);
+
+        let again = parse(source).unwrap();
+        assert_eq!(
+            serde_json::to_string(&program).unwrap(),
+            serde_json::to_string(&again).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_postfix_chain() {
+        let program = parse("Health.current = max(Health.current - 1, 0)\n").unwrap();
+        let Statement::Assignment(assign) = &program.statements[0] else {
+            panic!("expected assignment");
+        };
+        let Expr::Call { callee, args } = &assign.value else {
+            panic!("expected call, got {:?}", assign.value);
+        };
+        assert!(matches!(&**callee, Expr::Identifier(name) if name == "max"));
+        assert_eq!(args.len(), 2);
+        assert!(matches!(
+            &args[0].value,
+            Expr::BinaryOp(left, BinaryOp::Sub, _)
+                if matches!(&**left, Expr::MemberAccess(_, field) if field == "current")
+        ));
+    }
+
+    #[test]
+    fn test_parse_keyword_prefixed_names() {
+        let program = parse("import util.damage\nfrom util import hit\nimportant()\nimport_all()\nimports = 5\nfromage = 1\n").unwrap();
+        let [Statement::Import(import), Statement::Import(from), rest @ ..] =
+            program.statements.as_slice()
+        else {
+            panic!("expected imports, got {:?}", program.statements);
+        };
+        assert_eq!(import.module, vec!["util", "damage"]);
+        assert_eq!(
+            (from.module.clone(), from.names.clone()),
+            (vec!["util".to_string()], vec!["hit".to_string()])
+        );
+        assert!(matches!(
+            rest,
+            [
+                Statement::Expr {
+                    expr: Expr::Call { .. },
+                    ..
+                },
+                Statement::Expr {
+                    expr: Expr::Call { .. },
+                    ..
+                },
+                Statement::Assignment(_),
+                Statement::Assignment(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parse_not_needs_a_word_boundary() {
+        let program =
+            parse("fn f():\n    return notice\n\nalive = not not_done\nspeed = -notable\n")
+                .unwrap();
+        let Statement::FnDef(func) = &program.statements[0] else {
+            panic!("expected function");
+        };
+        assert_eq!(
+            func.body,
+            vec![Statement::Return(Some(Expr::Identifier(
+                "notice".to_string()
+            )))]
+        );
+        let values: Vec<&Expr> = program.statements[1..]
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Assignment(assign) => &assign.value,
+                stmt => panic!("expected assignment, got {:?}", stmt),
+            })
+            .collect();
+        assert!(matches!(
+            values[0],
+            Expr::UnaryOp(UnaryOp::Not, operand) if **operand == Expr::Identifier("not_done".to_string())
+        ));
+        assert!(matches!(
+            values[1],
+            Expr::UnaryOp(UnaryOp::Neg, operand) if **operand == Expr::Identifier("notable".to_string())
+        ));
+    }
+
+    #[test]
+    fn test_transpile_mod_registers_everything() {
+        let program = parse(
+            "entity Player:\n    signal health_changed(old: int, new: int)\n\nsignal game_over()\n\nstate_machine Movement:\n    initial = Run\n    state Idle:\n        pass\n    state Run:\n        pass\n",
+        )
+        .unwrap();
+        let exports = module_exports(&program);
+        assert_eq!(exports.plugins, vec!["PlayerPlugin"]);
+        assert_eq!(exports.events, vec!["PlayerHealthChanged", "GameOver"]);
+        assert_eq!(exports.states, vec!["Movement"]);
+
+        let rust = transpile(&program);
+        assert!(rust
+            .contains("pub struct PlayerHealthChanged {\n    pub old: i32,\n    pub new: i32,\n}"));
+        assert!(rust.contains("pub struct GameOver;"));
+        assert!(rust.contains("    Idle,\n    #[default]\n    Run,\n"));
+
+        let included = transpile_mod(&[("player".to_string(), exports.clone())], Some("nx"));
+        assert!(included.contains(
+            "pub mod player {\n    include!(concat!(env!(\"OUT_DIR\"), \"/nx/player.rs\"));\n}\n"
+        ));
+
+        let module = transpile_mod(&[("player".to_string(), exports)], None);
+        assert!(module.contains("pub mod player;\n"));
+        assert!(module.contains("app.add_plugins(player::PlayerPlugin);"));
+        assert!(module.contains("app.add_event::<player::PlayerHealthChanged>();"));
+        assert!(module.contains("app.init_state::<player::Movement>();"));
+    }
+
+    #[test]
+    fn test_infer_type_with_env() {
+        let program = parse(
+            "entity Player:\n    component Health:\n        current = 100\n    let speed = 200.0\n",
+        )
+        .unwrap();
+        let Statement::EntityDef(entity) = &program.statements[0] else {
+            panic!("expected entity");
+        };
+        let mut env = TypeEnv::new();
+        env.declare_entity(entity);
+
+        let Statement::Expr { expr, .. } = &parse("Health.current * 2\n").unwrap().statements[0]
+        else {
+            panic!("expected expression");
+        };
+        assert_eq!(infer_type_in(expr, &env).unwrap().to_string(), "int");
+
+        let Statement::Expr { expr, .. } = &parse("speed * 2\n").unwrap().statements[0] else {
+            panic!("expected expression");
+        };
+        assert_eq!(infer_type_in(expr, &env).unwrap().to_string(), "float");
+    }
+
+    #[test]
+    fn test_codegen_options() {
+        let source = "entity Player:\n    fn on_update(delta: float):\n        if features.debug_draw or features.god_mode:\n            print(\"time.delta_seconds()\")\n";
+        let program = parse(source).unwrap();
+        let options = CodegenOptions {
+            engine_api: EngineApi::V0_15,
+            features: [("debug_draw".to_string(), true)].into_iter().collect(),
+        };
+        let (rust, _) = transpile_module(&program, source, &Imports::default(), &options);
+        assert!(rust.contains("let delta = time.delta_secs();"));
+        assert!(rust.contains("if (true || false)"), "{}", rust);
+        // Only the engine calls the compiler writes are renamed
+        assert!(rust.contains("print(\"time.delta_seconds()\")"));
+
+        let rust = transpile(&program);
+        assert!(rust.contains("let delta = time.delta_seconds();"));
+        assert!(rust.contains("if (false || false)"));
+    }
+
+    #[test]
+    fn test_structs_and_enums() {
+        let source = "struct Weapon:\n    name: str\n    damage: int = 1\n\nenum Shape:\n    Empty\n    Circle(radius: float)\n\nentity Player:\n    fn equip():\n        let w = Weapon(\"sword\", damage: 5)\n        let s = Shape.Circle(2.0)\n        let e = Shape.Empty\n";
+        let program = parse(source).unwrap();
+        let exports = module_exports(&program);
+        assert_eq!(exports.types, vec!["Weapon", "Shape"]);
+
+        let rust = transpile(&program);
+        assert!(
+            rust.contains("pub struct Weapon {\n    pub name: String,\n    pub damage: i32,\n}")
+        );
+        assert!(rust.contains("    Empty,\n    Circle { radius: f32 },\n"));
+        assert!(rust.contains("Weapon { name: \"sword\".to_string(), damage: 5 }"));
+        assert!(rust.contains("Shape::Circle { radius: 2.0 }"));
+        assert!(rust.contains("let e = Shape::Empty;"));
+
+        let module = transpile_mod(&[("items".to_string(), exports)], None);
+        assert!(module.contains("app.register_type::<items::Weapon>();"));
+    }
+
+    #[test]
+    fn test_transpile_match() {
+        let source = "enum Shape:\n    Empty\n    Circle(radius: float)\n\nfn area(s: Shape, n: int, name: str):\n    match s:\n        case Shape.Circle(r) if r > 1.0:\n            print(r)\n        case Shape.Circle(radius):\n            print(radius)\n        case _:\n            pass\n    match (n, name):\n        case (-1..=1, \"zero\"):\n            pass\n        case (x, _):\n            print(x)\n";
+        let rust = transpile(&parse(source).unwrap());
+        assert!(rust.contains("    match s.clone() {\n        Shape::Circle { radius: r } if (r > 1.0) => {\n            print(r);\n        }\n"));
+        assert!(rust.contains("        Shape::Circle { radius } => {\n"));
+        assert!(rust.contains("        _ => {\n"));
+        assert!(rust.contains("    match (n, name.as_str()) {\n        (-1..=1, \"zero\") => {\n"));
+    }
+
+    #[test]
+    fn test_transpile_unary_index_and_lists() {
+        let source = "fn tick(alive: bool, delta: float, xs: List<int>, i: int):\n    if not alive:\n        pass\n    let v = -delta\n    let w = -(-delta)\n    let first = xs[0]\n    let nth = xs[i + 1]\n    let ys = [1, 2]\n";
+        let rust = transpile(&parse(source).unwrap());
+        assert!(rust.contains("    if !alive {\n"));
+        assert!(rust.contains("    let v = -delta;\n"));
+        assert!(rust.contains("    let w = -(-delta);\n"));
+        assert!(rust.contains("    let first = xs[0];\n"));
+        assert!(rust.contains("    let nth = xs[(i + 1) as usize];\n"));
+        assert!(rust.contains("    let ys = vec![1, 2];\n"));
+        assert!(!rust.contains("/* expr */"));
+    }
+
+    #[test]
+    fn test_transpile_loops() {
+        let source = "fn tick(items: List<int>):\n    for i in range(5):\n        pass\n    for i in range(10, 0, -2):\n        continue\n    for i, item in enumerate(items):\n        if item < 0:\n            break\n";
+        let rust = transpile(&parse(source).unwrap());
+        assert!(rust.contains("    for i in 0..5 {\n"));
+        assert!(rust.contains(
+            "    for i in (0 + 1..=10).rev().step_by(2 as usize) {\n        continue;\n"
+        ));
+        assert!(rust.contains("    for (i, item) in (0..).zip(items.clone()) {\n"));
+        assert!(rust.contains("            break;\n"));
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let source = "fn apply(f: Fn<int, int>, x: int) -> int:\n    return f(x)\n\nfn make() -> Fn<int, None>:\n    return fn(x: int): print(x)\n\nfn tick(items: List<int>, bonus: int):\n    let double = fn(x: int) -> int: x * 2\n    let scaled = items.map(|x| x * bonus)\n    let n = apply(|x| x + 1, 3)\n";
+        let rust = transpile(&parse(source).unwrap());
+        assert!(rust.contains("pub fn apply(f: impl Fn(i32) -> i32, x: i32) -> i32 {\n"));
+        assert!(rust.contains("pub fn make() -> impl Fn(i32) {\n"));
+        assert!(rust.contains("let double = move |x: i32| -> i32 { (x * 2) };\n"));
+        assert!(rust.contains(
+            "let scaled = items.iter().cloned().map(move |x| (x * bonus)).collect::<Vec<_>>();\n"
+        ));
+        assert!(rust.contains("let n = apply(move |x| (x + 1), 3);\n"));
+
+        // Stored anywhere else, functions are boxed
+        let source = "struct Button:\n    on_click: Fn<None>\n\nentity Spinner:\n    let ease: Fn<float, float> = fn(t: float) -> float: t\n    signal spun(filter: Fn<int, bool>)\n";
+        let rust = transpile(&parse(source).unwrap());
+        assert!(rust.contains("    pub on_click: Box<dyn Fn() + Send + Sync>,\n"));
+        assert!(rust.contains("    pub ease: Box<dyn Fn(f32) -> f32 + Send + Sync>,\n"));
+        assert!(rust.contains("    pub filter: Box<dyn Fn(i32) -> bool + Send + Sync>,\n"));
+
+        let mut env = TypeEnv::new();
+        let simple = |name: &str| TypeExpr::Simple(name.to_string());
+        let generic = |name: &str, params| TypeExpr::Generic {
+            name: name.to_string(),
+            params,
+        };
+        env.declare("items", generic("List", vec![simple("int")]));
+        env.declare("scale", generic("Fn", vec![simple("int"), simple("float")]));
+        let infer = |source: &str| {
+            let Statement::Expr { expr, .. } = &parse(source).unwrap().statements[0] else {
+                panic!("expected expression");
+            };
+            infer_type_in(expr, &env).map(|t| t.to_string())
+        };
+        assert_eq!(infer("|x: int| x * 2.0\n").unwrap(), "Fn<int, float>");
+        assert_eq!(infer("items.map(|x| x > 1)\n").unwrap(), "List<bool>");
+        assert_eq!(infer("items.map(scale)\n").unwrap(), "List<float>");
+        assert_eq!(infer("items.filter(|x| x > 1)\n").unwrap(), "List<int>");
+        assert_eq!(infer("scale(3)\n").unwrap(), "float");
+        // List literals have the type their items share
+        assert_eq!(infer("[1, 2].map(|x| x * 2)\n").unwrap(), "List<int>");
+        assert_eq!(infer("[1, 2.5]\n").unwrap(), "List<float>");
+        assert_eq!(infer("[1, \"a\"]\n").unwrap(), "List<Any>");
+    }
+
+    #[test]
+    fn test_transpile_interfaces() {
+        let source = "interface Damageable:\n    fn take_damage(amount: int)\n\nentity Crate implements Damageable:\n    fn take_damage(amount: int):\n        pass\n";
+        let rust = transpile(&parse(source).unwrap());
+        assert!(rust.contains("#[derive(Component, Default)]\npub struct Damageable;\n"));
+        assert!(rust.contains(
+            "        app.world_mut()\n            .register_component_hooks::<Crate>()\n            .on_add(|mut world, entity, _| {\n                world.commands().entity(entity).insert(Damageable);\n            });\n"
+        ));
+    }
+
+    #[test]
+    fn test_transpile_systems() {
+        let source = "system gravity(time: Res<Time>, q: Query<Transform, Velocity, with Player, without Frozen>):\n    for t, v in q:\n        v.y -= 9.8 * time.delta_seconds()\n        t.translation.y += v.y\n\nsystem report(q: Query<Velocity, with Player>):\n    for v in q:\n        print(v.y)\n";
+        let program = parse(source).unwrap();
+        let rust = transpile(&program);
+        assert!(rust.contains(
+            "pub fn gravity(time: Res<Time>, mut q: Query<(&mut Transform, &mut Velocity), (With<Player>, Without<Frozen>)>) {\n    for (mut t, mut v) in q.iter_mut() {\n"
+        ));
+        assert!(rust.contains(
+            "pub fn report(q: Query<&Velocity, With<Player>>) {\n    for v in q.iter() {\n        print(v.y);\n"
+        ));
+        assert!(rust.contains(
+            "impl Plugin for SystemsPlugin {\n    fn build(&self, app: &mut App) {\n        app.add_systems(Update, gravity);\n        app.add_systems(Update, report);\n"
+        ));
+        assert_eq!(module_exports(&program).plugins, vec!["SystemsPlugin"]);
+    }
+
+    #[test]
+    fn test_transpile_schedules() {
+        let source = "state_machine Game:\n    state Menu:\n        pass\n    state Playing:\n        pass\n\n@schedule(FixedUpdate)\n@set(Physics)\n@run_if(in_state(Playing))\nsystem gravity():\n    pass\n\n@schedule(FixedUpdate)\n@after(Physics)\nsystem collide():\n    pass\n\nentity Player:\n    @before(spawn)\n    fn on_update(delta: float):\n        pass\n\nsystem spawn():\n    pass\n";
+        let program = parse(source).unwrap();
+        let rust = transpile(&program);
+        assert!(rust.contains(
+            "#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]\npub struct Physics;\n"
+        ));
+        assert!(rust.contains("app.add_systems(Update, player_on_update.before(spawn));"));
+        assert!(rust.contains(
+            "impl Plugin for SystemsPlugin {\n    fn build(&self, app: &mut App) {\n        app.add_systems(FixedUpdate, gravity.in_set(Physics).run_if(in_state(Game::Playing)));\n        app.add_systems(FixedUpdate, collide.after(Physics));\n        app.add_systems(Update, spawn);\n"
+        ));
+    }
+
+    #[test]
+    fn test_transpile_inheritance() {
+        let source = "entity Enemy:\n    let speed = 1.0\n    signal died()\n    fn take_damage(amount: int):\n        print(amount)\n    fn on_update(delta: float):\n        take_damage(1)\n\nentity Boss extends Enemy:\n    let speed = 2.0\n    fn take_damage(amount: int):\n        super.take_damage(amount / 2)\n";
+        let program = parse(source).unwrap();
+        let rust = transpile(&program);
+        assert!(rust.contains("pub struct Boss {\n    pub speed: f32,\n}"));
+        assert!(rust.contains("pub struct BossDied;"));
+        assert!(rust.contains("app.add_systems(Update, boss_on_update);"));
+        assert!(rust.contains(
+            "pub fn boss_take_damage(amount: i32) {\n    boss_super_take_damage((amount / 2));\n}"
+        ));
+        assert!(
+            rust.contains("pub fn boss_super_take_damage(amount: i32) {\n    print(amount);\n}")
+        );
+        // The base's own functions keep their names
+        assert!(rust.contains("pub fn take_damage(amount: i32) {\n    print(amount);\n}"));
+    }
 }
@@ -0,0 +1,352 @@
+//! Manifest - Project settings from `nexscript.toml`
+//!
+//! A project is the directory holding a `nexscript.toml`. Every `nexc`
+//! subcommand looks for one in the current directory and its parents, so it
+//! can be run from anywhere inside the project:
+//!
+//! ```toml
+//! [build]
+//! sources = ["scripts"]       # source roots, relative to the manifest
+//! output = "src/generated"
+//! target = "bevy"             # or "bytecode"
+//! engine-api = "0.15"         # Bevy version to generate against
+//! prelude = ["util.math"]     # modules every script sees without importing
+//!
+//! [lints]
+//! NX0015 = "allow"            # allow, warn or deny a warning by its code
+//!
+//! [features]
+//! debug_draw = true           # `features.debug_draw` in scripts
+//! ```
+//!
+//! Every section and key is optional.
+
+use crate::diagnostics::{self, Diagnostic, Severity};
+use crate::{CodegenOptions, EngineApi, NexScriptError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest
+pub const MANIFEST_FILE: &str = "nexscript.toml";
+
+/// Diagnostics reported as warnings, which lint levels can silence. Every
+/// other code is an error that stops the generated Rust from compiling, so
+/// it can only be set to `deny`.
+const WARNING_CODES: &[&str] = &["NX0015"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub build: BuildSettings,
+    #[serde(default)]
+    pub lints: Lints,
+    /// Feature flags, read in scripts as `features.<name>`
+    #[serde(default)]
+    pub features: BTreeMap<String, bool>,
+    /// Directory the manifest is in; the paths in it are relative to this
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuildSettings {
+    /// Directories searched for `.nx` files; imports resolve from the one a
+    /// file is in
+    pub sources: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub target: Target,
+    pub engine_api: EngineApi,
+    /// Modules, as written after `import`, whose items every other script
+    /// can use without importing them
+    pub prelude: Vec<String>,
+}
+
+impl Default for BuildSettings {
+    fn default() -> Self {
+        BuildSettings {
+            sources: vec![PathBuf::from(".")],
+            output: PathBuf::from("src/generated"),
+            target: Target::default(),
+            engine_api: EngineApi::default(),
+            prelude: Vec::new(),
+        }
+    }
+}
+
+/// What scripts are compiled to
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// Rust source for a Bevy app
+    #[default]
+    Bevy,
+    /// Bytecode for the NexScript VM
+    Bytecode,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Bevy => write!(f, "bevy"),
+            Target::Bytecode => write!(f, "bytecode"),
+        }
+    }
+}
+
+/// How a diagnostic is reported
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    /// Not reported at all
+    Allow,
+    /// Reported, but doesn't fail the build
+    Warn,
+    /// Fails the build
+    Deny,
+}
+
+impl LintLevel {
+    /// As written in the manifest
+    pub fn name(self) -> &'static str {
+        match self {
+            LintLevel::Allow => "allow",
+            LintLevel::Warn => "warn",
+            LintLevel::Deny => "deny",
+        }
+    }
+}
+
+/// Lint levels by diagnostic code; codes not listed keep their severity
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Lints(pub BTreeMap<String, LintLevel>);
+
+impl Lints {
+    pub fn level(&self, code: &str) -> Option<LintLevel> {
+        self.0.get(code).copied()
+    }
+
+    /// The severity set for `code`, `default` if none is, or `None` if the
+    /// code is allowed
+    pub fn severity(&self, code: &str, default: Severity) -> Option<Severity> {
+        match self.level(code) {
+            Some(LintLevel::Allow) => None,
+            Some(LintLevel::Warn) => Some(Severity::Warning),
+            Some(LintLevel::Deny) => Some(Severity::Error),
+            None => Some(default),
+        }
+    }
+
+    /// Drop allowed diagnostics and give the rest the severity set for them
+    pub fn apply(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        diagnostics
+            .into_iter()
+            .filter_map(|mut diagnostic| {
+                diagnostic.severity = self.severity(&diagnostic.code, diagnostic.severity)?;
+                Some(diagnostic)
+            })
+            .collect()
+    }
+}
+
+impl Manifest {
+    /// The nearest `nexscript.toml` in `start` or one of its parents
+    pub fn find(start: &Path) -> Option<PathBuf> {
+        let start = fs::canonicalize(start).unwrap_or_else(|_| start.to_path_buf());
+        start
+            .ancestors()
+            .map(|dir| dir.join(MANIFEST_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// Load the nearest manifest, if there is one
+    pub fn discover(start: &Path) -> crate::Result<Option<Manifest>> {
+        Manifest::find(start)
+            .map(|path| Manifest::load(&path))
+            .transpose()
+    }
+
+    pub fn load(path: &Path) -> crate::Result<Manifest> {
+        let text = fs::read_to_string(path)?;
+        let root = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Manifest::parse(&text, root).map_err(|message| NexScriptError::ManifestError {
+            path: path.display().to_string(),
+            message,
+        })
+    }
+
+    /// Parse a manifest's text, with `root` as the directory it is in
+    pub fn parse(text: &str, root: PathBuf) -> Result<Manifest, String> {
+        let mut manifest: Manifest = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        manifest.root = root;
+
+        for code in manifest.lints.0.keys() {
+            if diagnostics::explain(code).is_none() {
+                return Err(format!("unknown diagnostic code `{}` in [lints]", code));
+            }
+            let level = manifest.lints.0[code];
+            if level != LintLevel::Deny && !WARNING_CODES.contains(&code.as_str()) {
+                return Err(format!(
+                    "{} is always an error and can't be set to `{}`",
+                    code,
+                    level.name()
+                ));
+            }
+        }
+        if manifest.build.sources.is_empty() {
+            return Err("`sources` must list at least one directory".to_string());
+        }
+        for module in &manifest.build.prelude {
+            let path = module_file(module);
+            if !manifest
+                .sources()
+                .iter()
+                .any(|dir| dir.join(&path).is_file())
+            {
+                return Err(format!(
+                    "prelude module `{}` is not in any source directory",
+                    module
+                ));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Source directories, relative to where the manifest is
+    pub fn sources(&self) -> Vec<PathBuf> {
+        self.build
+            .sources
+            .iter()
+            .map(|dir| self.root.join(dir))
+            .collect()
+    }
+
+    pub fn output(&self) -> PathBuf {
+        self.root.join(&self.build.output)
+    }
+
+    /// The source directory `file` is in, which its imports resolve from
+    pub fn source_root(&self, file: &Path) -> Option<PathBuf> {
+        let file = fs::canonicalize(file).ok()?;
+        self.sources()
+            .into_iter()
+            .find(|dir| fs::canonicalize(dir).is_ok_and(|dir| file.starts_with(dir)))
+    }
+
+    /// Prelude modules as module paths, e.g. `["util", "math"]`
+    pub fn prelude(&self) -> Vec<Vec<String>> {
+        self.build
+            .prelude
+            .iter()
+            .map(|module| module.split('.').map(str::to_string).collect())
+            .collect()
+    }
+
+    pub fn codegen_options(&self) -> CodegenOptions {
+        CodegenOptions {
+            engine_api: self.build.engine_api,
+            features: self.features.clone(),
+        }
+    }
+}
+
+/// `util.math` -> `util/math.nx`
+fn module_file(module: &str) -> PathBuf {
+    let mut path: PathBuf = module.split('.').collect();
+    path.set_extension("nx");
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::{Diagnostic, Location};
+
+    #[test]
+    fn test_manifest_settings_and_lints() {
+        let root = std::env::temp_dir().join(format!("nexscript-manifest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("scripts/util")).unwrap();
+        fs::create_dir_all(root.join("scripts/enemies")).unwrap();
+        fs::write(
+            root.join("scripts/util/math.nx"),
+            "fn double(x: int) -> int:\n    return x * 2\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join(MANIFEST_FILE),
+            "[build]\nsources = [\"scripts\"]\noutput = \"gen\"\nengine-api = \"0.15\"\nprelude = [\"util.math\"]\n\n[lints]\nNX0015 = \"deny\"\nNX0003 = \"deny\"\n\n[features]\ndebug_draw = true\n",
+        )
+        .unwrap();
+
+        // Found from a directory inside the project
+        let manifest = Manifest::discover(&root.join("scripts/enemies"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            manifest.output(),
+            fs::canonicalize(&root).unwrap().join("gen")
+        );
+        assert_eq!(manifest.build.target, Target::Bevy);
+        assert_eq!(manifest.build.engine_api, EngineApi::V0_15);
+        assert_eq!(manifest.prelude(), vec![vec!["util", "math"]]);
+        assert!(manifest
+            .source_root(&root.join("scripts/util/math.nx"))
+            .is_some_and(|dir| dir.ends_with("scripts")));
+        assert!(manifest.codegen_options().features["debug_draw"]);
+
+        let found = vec![
+            Diagnostic::warning("NX0015", "unreachable", Location::default()),
+            Diagnostic::error("NX0003", "duplicate", Location::default()),
+            Diagnostic::error("NX0004", "unknown", Location::default()),
+        ];
+        let found = manifest.lints.apply(found);
+        let levels: Vec<(&str, Severity)> = found
+            .iter()
+            .map(|d| (d.code.as_str(), d.severity))
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                ("NX0015", Severity::Error),
+                ("NX0003", Severity::Error),
+                ("NX0004", Severity::Error)
+            ]
+        );
+        let allowed = Manifest::parse("[lints]\nNX0015 = \"allow\"\n", root.clone()).unwrap();
+        let found = vec![Diagnostic::warning(
+            "NX0015",
+            "unreachable",
+            Location::default(),
+        )];
+        assert!(allowed.lints.apply(found).is_empty());
+
+        for (text, error) in [
+            (
+                "[lints]\nNX9999 = \"warn\"\n",
+                "unknown diagnostic code `NX9999` in [lints]",
+            ),
+            (
+                "[lints]\nNX0001 = \"allow\"\n",
+                "NX0001 is always an error and can't be set to `allow`",
+            ),
+            (
+                "[lints]\nNX0009 = \"warn\"\n",
+                "NX0009 is always an error and can't be set to `warn`",
+            ),
+            (
+                "[build]\nprelude = [\"nope\"]\n",
+                "prelude module `nope` is not in any source directory",
+            ),
+        ] {
+            assert_eq!(Manifest::parse(text, root.clone()).unwrap_err(), error);
+        }
+        assert!(Manifest::parse("[build]\ntarget = \"wasm\"\n", root.clone()).is_err());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,701 @@
+//! Modules - Resolve `import`s across `.nx` files
+//!
+//! A module is a `.nx` file, named by its path from the project root with
+//! dots between directories: `import util.damage` loads `util/damage.nx`.
+//! [`ModuleGraph`] loads a file and everything it imports, parsing each file
+//! once, and resolves each file's imports into the [`Imports`] its checks and
+//! code generation see.
+//!
+//! Generated modules are flat siblings in the output directory, named after
+//! the file, so `util.damage` is `super::damage` in the generated Rust.
+//!
+//! Prelude modules (see [`crate::manifest`]) are imported into every other
+//! file as if with a glob: their items can be used by name, and a file's own
+//! definitions shadow them.
+
+use crate::diagnostics::{self, edit_distance, Diagnostic, Fix, Location};
+use crate::inheritance::{self, Bases};
+use crate::source_map::SourceMap;
+use crate::{
+    event_name, module_exports, transpile_module, CodegenOptions, EntityDef, EnumDef, FnDef,
+    ImportStmt, InterfaceDef, ModuleExports, Program, SignalDef, StateMachine, Statement,
+    StructDef,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A top-level definition one module can import from another
+#[derive(Debug, Clone, Copy)]
+pub enum Item<'a> {
+    Entity(&'a EntityDef),
+    Struct(&'a StructDef),
+    Enum(&'a EnumDef),
+    Interface(&'a InterfaceDef),
+    Function(&'a FnDef),
+    Signal(&'a SignalDef),
+    StateMachine(&'a StateMachine),
+}
+
+impl<'a> Item<'a> {
+    /// Every importable definition in a program
+    pub fn all(program: &'a Program) -> impl Iterator<Item = Item<'a>> {
+        program.statements.iter().filter_map(|stmt| match stmt {
+            Statement::EntityDef(entity) => Some(Item::Entity(entity)),
+            Statement::StructDef(def) => Some(Item::Struct(def)),
+            Statement::EnumDef(def) => Some(Item::Enum(def)),
+            Statement::InterfaceDef(def) => Some(Item::Interface(def)),
+            Statement::FnDef(func) => Some(Item::Function(func)),
+            Statement::SignalDef(signal) => Some(Item::Signal(signal)),
+            Statement::StateMachine(machine) => Some(Item::StateMachine(machine)),
+            _ => None,
+        })
+    }
+
+    pub fn name(&self) -> &'a str {
+        match self {
+            Item::Entity(entity) => &entity.name,
+            Item::Struct(def) => &def.name,
+            Item::Enum(def) => &def.name,
+            Item::Interface(def) => &def.name,
+            Item::Function(func) => &func.name,
+            Item::Signal(signal) => &signal.name,
+            Item::StateMachine(machine) => &machine.name,
+        }
+    }
+
+    /// Name of the generated Rust item: signals become event types
+    pub fn rust_name(&self) -> String {
+        match self {
+            Item::Signal(signal) => event_name("", &signal.name),
+            item => item.name().to_string(),
+        }
+    }
+}
+
+/// An item brought in by an import, with where it is defined
+#[derive(Debug, Clone, Copy)]
+pub struct Imported<'a> {
+    pub item: Item<'a>,
+    pub file: &'a str,
+    /// The item's name in its own file
+    pub location: Location,
+}
+
+/// What a file's imports bring into scope
+#[derive(Debug, Default)]
+pub struct Imports<'a> {
+    /// Names from `from ... import`
+    pub names: HashMap<String, Imported<'a>>,
+    /// Modules from `import`, by the name they are used under, with their items
+    pub modules: HashMap<String, HashMap<String, Imported<'a>>>,
+    /// Items of the prelude modules
+    pub prelude: HashMap<String, Imported<'a>>,
+    /// Imported entities that extend another, flattened in their own module,
+    /// by the name they are used under (`Enemy`, `enemies.Enemy`)
+    pub bases: HashMap<String, EntityDef>,
+}
+
+/// Every module reachable from the files loaded so far
+#[derive(Debug)]
+pub struct ModuleGraph {
+    root: PathBuf,
+    /// Files of the prelude modules that exist under `root`
+    prelude: Vec<PathBuf>,
+    modules: BTreeMap<PathBuf, Module>,
+}
+
+#[derive(Debug)]
+struct Module {
+    /// Path as it is shown in diagnostics
+    file: String,
+    source: Option<String>,
+    program: Option<Program>,
+    /// Syntax errors, or the read error
+    errors: Vec<Diagnostic>,
+    /// The file each import refers to, if it exists, in statement order
+    imports: Vec<(ImportStmt, Option<PathBuf>)>,
+}
+
+impl ModuleGraph {
+    /// Resolve imports relative to `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ModuleGraph {
+            root: root.into(),
+            prelude: Vec::new(),
+            modules: BTreeMap::new(),
+        }
+    }
+
+    /// Make the items of these modules visible in every other file. Modules
+    /// that aren't under the root are left out.
+    pub fn with_prelude(mut self, modules: &[Vec<String>]) -> Self {
+        self.prelude = modules
+            .iter()
+            .map(|module| self.module_path(module))
+            .filter(|path| path.is_file())
+            .collect();
+        self
+    }
+
+    /// The file a module path such as `util.damage` refers to
+    pub fn module_path(&self, module: &[String]) -> PathBuf {
+        let mut path = self.root.clone();
+        path.extend(module);
+        path.set_extension("nx");
+        path
+    }
+
+    /// Load a file and, transitively, everything it imports. Files already
+    /// loaded are not read again.
+    pub fn load(&mut self, path: &Path) {
+        let mut pending = vec![path.to_path_buf()];
+        pending.extend(self.prelude.iter().cloned());
+        while let Some(path) = pending.pop() {
+            let key = key(&path);
+            if self.modules.contains_key(&key) {
+                continue;
+            }
+            let module = self.read(&path);
+            pending.extend(module.imports.iter().filter_map(|(_, path)| path.clone()));
+            self.modules.insert(key, module);
+        }
+    }
+
+    fn read(&self, path: &Path) -> Module {
+        let file = path.display().to_string();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                let error = Diagnostic::error("NX0002", e.to_string(), Location::default());
+                return Module {
+                    file: file.clone(),
+                    source: None,
+                    program: None,
+                    errors: vec![error.in_file(file)],
+                    imports: Vec::new(),
+                };
+            }
+        };
+
+        let (program, errors) = match crate::parse(&source) {
+            Ok(program) => (Some(program), Vec::new()),
+            Err(e) => (
+                None,
+                vec![Diagnostic::from_error(&e, &source).in_file(&file)],
+            ),
+        };
+        let imports = program
+            .iter()
+            .flat_map(|program| &program.statements)
+            .filter_map(|stmt| match stmt {
+                Statement::Import(import) => {
+                    let path = self.module_path(&import.module);
+                    Some((import.clone(), path.is_file().then_some(path)))
+                }
+                _ => None,
+            })
+            .collect();
+        Module {
+            file,
+            source: Some(source),
+            program,
+            errors,
+            imports,
+        }
+    }
+
+    /// The syntax tree of a loaded file that parsed
+    pub fn program(&self, path: &Path) -> Option<&Program> {
+        self.module(path)?.program.as_ref()
+    }
+
+    fn module(&self, path: &Path) -> Option<&Module> {
+        self.modules.get(&key(path))
+    }
+
+    /// The prelude modules `path` sees: none if it is one itself
+    fn prelude_of(&self, path: &Path) -> &[PathBuf] {
+        let path = key(path);
+        if self.prelude.iter().any(|module| key(module) == path) {
+            return &[];
+        }
+        &self.prelude
+    }
+
+    /// Every file `path` imports, directly or not, and the prelude, excluding
+    /// itself
+    pub fn dependencies(&self, path: &Path) -> BTreeSet<PathBuf> {
+        let mut found: BTreeSet<PathBuf> = self.prelude_of(path).iter().cloned().collect();
+        let mut pending = vec![path.to_path_buf()];
+        pending.extend(found.iter().cloned());
+        while let Some(path) = pending.pop() {
+            let Some(module) = self.module(&path) else {
+                continue;
+            };
+            for (_, import) in &module.imports {
+                if let Some(import) = import {
+                    if found.insert(import.clone()) {
+                        pending.push(import.clone());
+                    }
+                }
+            }
+        }
+        found.remove(path);
+        found
+    }
+
+    /// Everything wrong with a loaded file: syntax errors, imports that don't
+    /// resolve or form a cycle, and the semantic checks with its imports in
+    /// scope
+    pub fn check(&self, path: &Path) -> Vec<Diagnostic> {
+        let Some(module) = self.module(path) else {
+            return Vec::new();
+        };
+        let (Some(program), Some(source)) = (&module.program, &module.source) else {
+            return module.errors.clone();
+        };
+
+        let mut found = Vec::new();
+        for (import, resolved) in &module.imports {
+            let location = Location::new(source, import.span);
+            let module_name = import.module.join(".");
+            let Some(resolved) = resolved else {
+                let message = format!("unresolved module `{}`", module_name);
+                let mut diagnostic = Diagnostic::error("NX0006", message, location);
+                diagnostic.notes.push(format!(
+                    "looked for {}",
+                    self.module_path(&import.module).display()
+                ));
+                found.push(diagnostic);
+                continue;
+            };
+
+            if let Some(cycle) = self.cycle(path, resolved) {
+                let message = format!("import cycle: {}", cycle.join(" -> "));
+                let mut diagnostic = Diagnostic::error("NX0007", message, location);
+                diagnostic
+                    .notes
+                    .push("modules can't import each other, directly or not".to_string());
+                found.push(diagnostic);
+            }
+
+            // A module with syntax errors reports them itself
+            let Some(imported) = self.module(resolved).and_then(|m| m.program.as_ref()) else {
+                continue;
+            };
+            for name in &import.names {
+                if Item::all(imported).any(|item| item.name() == name) {
+                    continue;
+                }
+                let location = diagnostics::name_location(source, import.span, name);
+                let message = format!("module `{}` has no `{}`", module_name, name);
+                let mut diagnostic = Diagnostic::error("NX0008", message, location);
+                if let Some(similar) = Item::all(imported)
+                    .map(|item| item.name())
+                    .filter(|other| edit_distance(other, name) <= 2)
+                    .min_by_key(|other| edit_distance(other, name))
+                {
+                    diagnostic.fixes.push(Fix {
+                        message: format!("did you mean `{}`?", similar),
+                        location,
+                        replacement: similar.to_string(),
+                    });
+                }
+                found.push(diagnostic);
+            }
+        }
+
+        found.extend(diagnostics::check_program_with(
+            program,
+            source,
+            &self.imports(path),
+        ));
+        found.into_iter().map(|d| d.in_file(&module.file)).collect()
+    }
+
+    /// The names a loaded file's imports bring into scope. Imports that don't
+    /// resolve contribute nothing.
+    pub fn imports(&self, path: &Path) -> Imports<'_> {
+        self.imports_of(path, &mut Vec::new())
+    }
+
+    /// [`Self::imports`], skipping the files in `visiting` when flattening
+    /// imported entities, so an import cycle can't recurse forever
+    fn imports_of(&self, path: &Path, visiting: &mut Vec<PathBuf>) -> Imports<'_> {
+        let mut imports = Imports::default();
+        let Some(module) = self.module(path) else {
+            return imports;
+        };
+        visiting.push(key(path));
+        for prelude in self.prelude_of(path) {
+            imports.prelude.extend(self.items(prelude));
+        }
+        for (import, resolved) in &module.imports {
+            // A module with syntax errors is treated as unresolved
+            let Some(resolved) = resolved
+                .as_deref()
+                .filter(|path| self.program(path).is_some())
+            else {
+                continue;
+            };
+            let items = self.items(resolved);
+            if import.names.is_empty() {
+                for (name, _) in &items {
+                    let used = format!("{}.{}", import.module_name(), name);
+                    if let Some(flattened) = self.flattened(resolved, name, visiting) {
+                        imports.bases.insert(used, flattened);
+                    }
+                }
+                imports.modules.insert(
+                    import.module_name().to_string(),
+                    items.into_iter().collect(),
+                );
+            } else {
+                for (name, item) in items {
+                    if import.names.contains(&name) {
+                        if let Some(flattened) = self.flattened(resolved, &name, visiting) {
+                            imports.bases.insert(name.clone(), flattened);
+                        }
+                        imports.names.insert(name, item);
+                    }
+                }
+            }
+        }
+        visiting.pop();
+        imports
+    }
+
+    /// An entity of a loaded file with what it inherits, if it extends
+    /// another
+    fn flattened(&self, path: &Path, name: &str, visiting: &mut Vec<PathBuf>) -> Option<EntityDef> {
+        if visiting.contains(&key(path)) {
+            return None;
+        }
+        let program = self.program(path)?;
+        let entity = program.statements.iter().find_map(|stmt| match stmt {
+            Statement::EntityDef(entity) if entity.name == name => Some(entity),
+            _ => None,
+        })?;
+        entity.extends.as_ref()?;
+        let imports = self.imports_of(path, visiting);
+        Bases::new(program, &imports).flatten(entity).ok()
+    }
+
+    /// What a loaded file that parsed contributes to the aggregate plugin,
+    /// counting the signals its entities inherit
+    pub fn exports(&self, path: &Path) -> Option<ModuleExports> {
+        let program = self.program(path)?;
+        let program = inheritance::flatten_program(program, &self.imports(path));
+        Some(module_exports(&program))
+    }
+
+    /// Every importable item of a loaded file that parsed, by name
+    fn items(&self, path: &Path) -> Vec<(String, Imported<'_>)> {
+        let Some(module) = self.module(path) else {
+            return Vec::new();
+        };
+        let (Some(program), Some(source)) = (&module.program, &module.source) else {
+            return Vec::new();
+        };
+        Item::all(program)
+            .map(|item| {
+                let span = match item {
+                    Item::Entity(entity) => entity.span,
+                    Item::Struct(def) => def.span,
+                    Item::Enum(def) => def.span,
+                    Item::Interface(def) => def.span,
+                    Item::Function(func) => func.span,
+                    Item::Signal(signal) => signal.span,
+                    Item::StateMachine(machine) => machine.span,
+                };
+                let imported = Imported {
+                    item,
+                    file: &module.file,
+                    location: diagnostics::name_location(source, span, item.name()),
+                };
+                (item.name().to_string(), imported)
+            })
+            .collect()
+    }
+
+    /// Generated Rust and source map for a loaded file that parsed
+    pub fn transpile(&self, path: &Path, options: &CodegenOptions) -> Option<(String, SourceMap)> {
+        let module = self.module(path)?;
+        let (program, source) = (module.program.as_ref()?, module.source.as_ref()?);
+        let (rust, mut source_map) =
+            transpile_module(program, source, &self.imports(path), options);
+        source_map.source = module.file.clone();
+        Some((rust, source_map))
+    }
+
+    /// The modules on a path from `import` back to `from`, if there is one,
+    /// as module paths starting and ending with `from`
+    fn cycle(&self, from: &Path, import: &Path) -> Option<Vec<String>> {
+        let target = key(from);
+        let mut trail = vec![import.to_path_buf()];
+        let mut visited = BTreeSet::new();
+        if !self.find(&target, &mut trail, &mut visited) {
+            return None;
+        }
+        let name = |path: &Path| {
+            let relative = path.strip_prefix(&self.root).unwrap_or(path);
+            let mut parts: Vec<String> = relative
+                .with_extension("")
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            parts.retain(|part| part != ".");
+            parts.join(".")
+        };
+        let mut cycle = vec![name(from)];
+        cycle.extend(trail.iter().map(|path| name(path)));
+        Some(cycle)
+    }
+
+    /// Depth-first search from the end of `trail` to `target`, leaving the
+    /// path taken in `trail`
+    fn find(
+        &self,
+        target: &Path,
+        trail: &mut Vec<PathBuf>,
+        visited: &mut BTreeSet<PathBuf>,
+    ) -> bool {
+        let current = trail.last().cloned().unwrap_or_default();
+        if key(&current) == target {
+            return true;
+        }
+        if !visited.insert(key(&current)) {
+            return false;
+        }
+        let Some(module) = self.module(&current) else {
+            return false;
+        };
+        for (_, import) in &module.imports {
+            let Some(import) = import else {
+                continue;
+            };
+            trail.push(import.clone());
+            if self.find(target, trail, visited) {
+                return true;
+            }
+            trail.pop();
+        }
+        false
+    }
+}
+
+/// Modules a program imports with a plain `import`, keyed by both names
+/// they can be used under: `combat` and `util.combat` for
+/// `import util.combat`. The value is the name [`Imports::modules`] uses.
+pub fn plain_imports(program: &Program) -> HashMap<String, &str> {
+    let mut modules = HashMap::new();
+    for stmt in &program.statements {
+        if let Statement::Import(import) = stmt {
+            if import.names.is_empty() {
+                modules.insert(import.module_name().to_string(), import.module_name());
+                modules.insert(import.module.join("."), import.module_name());
+            }
+        }
+    }
+    modules
+}
+
+/// Words Rust reserves, which a module can't be named
+const RUST_KEYWORDS: &[&str] = &[
+    "_", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if",
+    "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Rust module a `.nx` file is generated as: its lowercased file name made
+/// a valid identifier, e.g. `damage` for `util/Damage.nx`, `my_enemy` for
+/// `my-enemy.nx` and `type_` for `type.nx`
+pub fn module_name(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name: String = stem
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    if RUST_KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+/// The same file reached through different paths is one module
+fn key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "nexscript-modules-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&root);
+        for (path, source) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_module_name() {
+        let name = |path: &str| module_name(Path::new(path));
+        assert_eq!(name("util/Damage.nx"), "damage");
+        assert_eq!(name("my-enemy.nx"), "my_enemy");
+        assert_eq!(name("2d tiles.nx"), "_2d_tiles");
+        assert_eq!(name("mod.nx"), "mod_");
+        assert_eq!(name("Self.nx"), "self_");
+    }
+
+    #[test]
+    fn test_imports_resolve_across_files() {
+        let root = project("imports", &[
+            (
+                "util/combat.nx",
+                "signal hit(amount: int)\n\nfn damage(base: int, scale: float) -> int:\n    return base\n",
+            ),
+            (
+                "player.nx",
+                "from util.combat import hit, damage\nimport util.combat\n\nentity Player:\n    fn attack():\n        emit hit(1)\n        emit hit()\n        let d = damage(1, 2.0)\n        let e = combat.damage(\"x\", 1.0)\n",
+            ),
+        ]);
+        let player = root.join("player.nx");
+        let mut graph = ModuleGraph::new(&root);
+        graph.load(&player);
+
+        let deps: Vec<PathBuf> = graph.dependencies(&player).into_iter().collect();
+        assert_eq!(deps, vec![root.join("util/combat.nx")]);
+
+        let found = graph.check(&player);
+        let codes: Vec<&str> = found.iter().map(|d| d.code.as_str()).collect();
+        assert_eq!(codes, vec!["NX0005", "NX0010"]);
+        // The signal is declared in the other file
+        let related = &found[0].related[0];
+        assert_eq!(
+            related.file,
+            root.join("util/combat.nx").display().to_string()
+        );
+        assert_eq!(related.location.line, 1);
+        assert_eq!((found[1].location.line, found[1].location.column), (9, 31));
+
+        let (rust, _) = graph
+            .transpile(&player, &CodegenOptions::default())
+            .unwrap();
+        assert!(rust.contains("use super::combat::{Hit, damage};"));
+        assert!(rust.contains("use super::combat;"));
+        assert!(rust.contains("combat::damage("));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_qualified_names_resolve_through_module_paths() {
+        let root = project(
+            "qualified",
+            &[
+                (
+                    "util/combat.nx",
+                    "enum Element:\n    Fire\n\nfn damage(base: int) -> int:\n    return base\n",
+                ),
+                (
+                    "player.nx",
+                    "import util.combat\n\nfn attack() -> int:\n    let e = util.combat.Element.Fire\n    util.combat.damage(\"x\")\n    return combat.damag(1)\n",
+                ),
+            ],
+        );
+        let player = root.join("player.nx");
+        let mut graph = ModuleGraph::new(&root);
+        graph.load(&player);
+
+        let found = graph.check(&player);
+        let codes: Vec<&str> = found.iter().map(|d| d.code.as_str()).collect();
+        assert_eq!(codes, vec!["NX0010", "NX0008"]);
+        assert_eq!(found[1].message, "module `combat` has no `damag`");
+        assert_eq!((found[1].location.line, found[1].location.column), (6, 19));
+        assert_eq!(found[1].fixes[0].replacement, "damage");
+
+        let (rust, _) = graph
+            .transpile(&player, &CodegenOptions::default())
+            .unwrap();
+        assert!(rust.contains("combat::Element::Fire"));
+        assert!(rust.contains("combat::damage(\"x\""));
+        assert!(!rust.contains("util.combat"));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_unresolved_names_and_cycles() {
+        let root = project("unresolved", &[
+            ("a.nx", "import b\nfrom c import Enemyy\nimport missing\n"),
+            ("b.nx", "import a\n"),
+            ("c.nx", "entity Enemy:\n    let hp = 1\n"),
+        ]);
+        let a = root.join("a.nx");
+        let mut graph = ModuleGraph::new(&root);
+        graph.load(&a);
+
+        let found = graph.check(&a);
+        let messages: Vec<String> = found.iter().map(|d| d.message.clone()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "import cycle: a -> b -> a",
+                "module `c` has no `Enemyy`",
+                "unresolved module `missing`",
+            ]
+        );
+        assert_eq!(found[1].fixes[0].replacement, "Enemy");
+        assert_eq!(found[1].location.column, 15);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_prelude_is_visible_everywhere() {
+        let root = project("prelude", &[
+            ("util/math.nx", "fn double(x: int) -> int:\n    return x * 2\n"),
+            ("coin.nx", "fn double(x: float) -> float:\n    return x\n\nfn value() -> float:\n    return double(1.5)\n"),
+            ("player.nx", "entity Player:\n    fn on_ready():\n        let d = double(\"two\")\n"),
+        ]);
+        let player = root.join("player.nx");
+        let coin = root.join("coin.nx");
+        let mut graph =
+            ModuleGraph::new(&root).with_prelude(&[vec!["util".to_string(), "math".to_string()]]);
+        graph.load(&player);
+        graph.load(&coin);
+
+        assert_eq!(
+            graph.dependencies(&player).into_iter().collect::<Vec<_>>(),
+            vec![root.join("util/math.nx")]
+        );
+        let found = graph.check(&player);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].code, "NX0010");
+        // A file's own definitions shadow the prelude
+        assert!(graph.check(&coin).is_empty());
+
+        let (rust, _) = graph
+            .transpile(&player, &CodegenOptions::default())
+            .unwrap();
+        assert!(rust.contains("use super::math::*;"));
+        let (rust, _) = graph
+            .transpile(&root.join("util/math.nx"), &CodegenOptions::default())
+            .unwrap();
+        assert!(!rust.contains("use super::math::*;"));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
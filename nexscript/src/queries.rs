@@ -0,0 +1,287 @@
+//! Queries - What a system's `Query` parameters match and how it uses them
+//!
+//! `Query<Transform, Velocity, with Player, without Frozen>` matches the
+//! entities that have a `Transform`, a `Velocity` and a `Player` but no
+//! `Frozen`. A system iterates a query with `for t, v in q:`, one name per
+//! component. It only reads a component unless it assigns through that
+//! component's name (`v.y -= 1.0`) or calls a method on it
+//! (`t.rotate_z(0.1)`), so Bevy can run systems that read the same
+//! components in parallel.
+
+use crate::{is_filter, Expr, ForStmt, Statement, SystemDef, TypeExpr};
+use std::collections::HashMap;
+
+/// A `Query<...>` type, split into the components it yields and its filters
+#[derive(Debug, Default)]
+pub(crate) struct Query<'a> {
+    pub components: Vec<&'a TypeExpr>,
+    /// Components the entity must have, from `with`
+    pub with: Vec<&'a TypeExpr>,
+    /// Components the entity must not have, from `without`
+    pub without: Vec<&'a TypeExpr>,
+}
+
+impl<'a> Query<'a> {
+    /// The query a parameter's type is, if it is one
+    pub fn of(type_expr: &'a TypeExpr) -> Option<Self> {
+        let TypeExpr::Generic { name, params } = type_expr else {
+            return None;
+        };
+        if name != "Query" {
+            return None;
+        }
+        let mut query = Query::default();
+        for param in params {
+            match param {
+                TypeExpr::Generic { name, params } if is_filter(name) && params.len() == 1 => {
+                    match name.as_str() {
+                        "With" => query.with.push(&params[0]),
+                        _ => query.without.push(&params[0]),
+                    }
+                }
+                component => query.components.push(component),
+            }
+        }
+        Some(query)
+    }
+}
+
+/// The loops over a query: `for` statements anywhere in a body whose
+/// iterable is just a name
+pub(crate) fn loops(body: &[Statement]) -> Vec<(&str, &ForStmt)> {
+    statements(body)
+        .into_iter()
+        .filter_map(|stmt| match stmt {
+            Statement::For(for_stmt) => match &for_stmt.iterable {
+                Expr::Identifier(name) => Some((name.as_str(), for_stmt)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Which components of each of a system's queries it changes, by
+/// parameter name
+pub(crate) fn access(system: &SystemDef) -> HashMap<&str, Vec<bool>> {
+    let mut access: HashMap<&str, Vec<bool>> = system
+        .params
+        .iter()
+        .filter_map(|param| {
+            let query = Query::of(&param.type_expr)?;
+            Some((param.name.as_str(), vec![false; query.components.len()]))
+        })
+        .collect();
+    for (query, for_stmt) in loops(&system.body) {
+        let Some(mutable) = access.get_mut(query) else {
+            continue;
+        };
+        for (name, mutable) in for_stmt.names.iter().zip(mutable) {
+            *mutable |= mutates(&for_stmt.body, name);
+        }
+    }
+    access
+}
+
+/// Make each loop over a query iterate it the way Bevy does: `q.iter()`, or
+/// `q.iter_mut()` with the names of components it assigns to bound `mut`
+pub(crate) fn iterate(body: &mut [Statement], access: &HashMap<&str, Vec<bool>>) {
+    for stmt in body {
+        match stmt {
+            Statement::For(for_stmt) => {
+                iterate(&mut for_stmt.body, access);
+                let Expr::Identifier(query) = &for_stmt.iterable else {
+                    continue;
+                };
+                let Some(mutable) = access.get(query.as_str()) else {
+                    continue;
+                };
+                let method = if mutable.contains(&true) {
+                    "iter_mut"
+                } else {
+                    "iter"
+                };
+                for (name, mutable) in for_stmt.names.iter_mut().zip(mutable) {
+                    if *mutable {
+                        *name = format!("mut {}", name);
+                    }
+                }
+                for_stmt.iterable = Expr::Call {
+                    callee: Box::new(Expr::MemberAccess(
+                        Box::new(Expr::Identifier(query.clone())),
+                        method.to_string(),
+                    )),
+                    args: Vec::new(),
+                };
+            }
+            Statement::If(if_stmt) => {
+                iterate(&mut if_stmt.then_body, access);
+                for (_, clause) in &mut if_stmt.elif_clauses {
+                    iterate(clause, access);
+                }
+                if let Some(else_body) = &mut if_stmt.else_body {
+                    iterate(else_body, access);
+                }
+            }
+            Statement::Match(match_stmt) => {
+                for arm in &mut match_stmt.arms {
+                    iterate(&mut arm.body, access);
+                }
+            }
+            Statement::While(while_stmt) => iterate(&mut while_stmt.body, access),
+            _ => {}
+        }
+    }
+}
+
+/// Whether a body assigns to `name` or one of its fields, or calls a
+/// method on them, which may need to borrow it mutably
+fn mutates(body: &[Statement], name: &str) -> bool {
+    statements(body).into_iter().any(|stmt| {
+        let assigns = match stmt {
+            Statement::Assignment(assign) => {
+                assign.target.parts.first().map(String::as_str) == Some(name)
+            }
+            _ => false,
+        };
+        assigns
+            || expressions(stmt)
+                .into_iter()
+                .any(|expr| calls_method(expr, name))
+    })
+}
+
+/// The expressions a statement itself contains, not those of nested blocks
+fn expressions(stmt: &Statement) -> Vec<&Expr> {
+    match stmt {
+        Statement::VarDecl(var) => vec![&var.value],
+        Statement::Assignment(assign) => vec![&assign.value],
+        Statement::If(if_stmt) => std::iter::once(&if_stmt.condition)
+            .chain(if_stmt.elif_clauses.iter().map(|(condition, _)| condition))
+            .collect(),
+        Statement::Match(match_stmt) => std::iter::once(&match_stmt.subject)
+            .chain(match_stmt.arms.iter().filter_map(|arm| arm.guard.as_ref()))
+            .collect(),
+        Statement::While(while_stmt) => vec![&while_stmt.condition],
+        Statement::For(for_stmt) => vec![&for_stmt.iterable],
+        Statement::Return(Some(expr)) | Statement::Expr { expr, .. } => vec![expr],
+        Statement::Emit(emit) => emit.args.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether an expression calls a method on `name` or one of its fields
+/// (`t.rotate_z(0.1)`, `t.translation.normalize()`)
+fn calls_method(expr: &Expr, name: &str) -> bool {
+    if let Expr::Call { callee, .. } = expr {
+        if let Expr::MemberAccess(receiver, _) = &**callee {
+            if root(receiver) == Some(name) {
+                return true;
+            }
+        }
+    }
+    children(expr)
+        .into_iter()
+        .any(|child| calls_method(child, name))
+}
+
+/// The expressions directly inside an expression
+fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Call { callee, args } => std::iter::once(&**callee)
+            .chain(args.iter().map(|arg| &arg.value))
+            .collect(),
+        Expr::Vec2(x, y) => vec![x, y],
+        Expr::Vec3(x, y, z) => vec![x, y, z],
+        Expr::List(items) | Expr::Tuple(items) => items.iter().collect(),
+        Expr::Map(entries) => entries.iter().map(|(_, value)| value).collect(),
+        Expr::Construct { fields, .. } => fields.iter().map(|(_, value)| value).collect(),
+        Expr::MemberAccess(base, _) | Expr::UnaryOp(_, base) => vec![base],
+        Expr::Lambda { body, .. } => vec![body],
+        Expr::Index(a, b) | Expr::BinaryOp(a, _, b) => vec![a, b],
+        Expr::Int(_) | Expr::Float(_) | Expr::String(_) | Expr::Bool(_) | Expr::Identifier(_) => {
+            Vec::new()
+        }
+    }
+}
+
+/// The variable an access path starts from: `t` for `t.translation[0]`
+fn root(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Identifier(name) => Some(name),
+        Expr::MemberAccess(base, _) | Expr::Index(base, _) => root(base),
+        _ => None,
+    }
+}
+
+/// Every statement in a body, including those in nested blocks
+fn statements(body: &[Statement]) -> Vec<&Statement> {
+    let mut found = Vec::new();
+    for stmt in body {
+        found.push(stmt);
+        match stmt {
+            Statement::If(if_stmt) => {
+                found.extend(statements(&if_stmt.then_body));
+                for (_, clause) in &if_stmt.elif_clauses {
+                    found.extend(statements(clause));
+                }
+                if let Some(else_body) = &if_stmt.else_body {
+                    found.extend(statements(else_body));
+                }
+            }
+            Statement::Match(match_stmt) => {
+                for arm in &match_stmt.arms {
+                    found.extend(statements(&arm.body));
+                }
+            }
+            Statement::While(while_stmt) => found.extend(statements(&while_stmt.body)),
+            Statement::For(for_stmt) => found.extend(statements(&for_stmt.body)),
+            _ => {}
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn system(source: &str) -> SystemDef {
+        match parse(source).unwrap().statements.remove(0) {
+            Statement::SystemDef(system) => system,
+            stmt => panic!("expected a system, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_query_access() {
+        let system = system("system gravity(q: Query<Transform, Velocity, with Player, without Frozen>, r: Query<Velocity>):\n    for t, v in q:\n        if t.translation.y > 0.0:\n            v.y -= 9.8\n    for v in r:\n        print(v.y)\n");
+        let query = Query::of(&system.params[0].type_expr).unwrap();
+        assert_eq!(query.components.len(), 2);
+        assert_eq!(query.with[0], &TypeExpr::Simple("Player".to_string()));
+        assert_eq!(query.without[0], &TypeExpr::Simple("Frozen".to_string()));
+        assert_eq!(
+            system.params[0].type_expr.to_string(),
+            "Query<Transform, Velocity, with Player, without Frozen>"
+        );
+
+        let access = access(&system);
+        assert_eq!(access["q"], vec![false, true]);
+        assert_eq!(access["r"], vec![false]);
+    }
+
+    #[test]
+    fn test_query_method_access() {
+        // A method may change what it's called on, or the field it's called
+        // on, but passing a field to a function only reads it
+        let system = system("system spin(q: Query<Transform, Velocity, Sprite>):\n    for t, v, s in q:\n        t.rotate_z(0.1)\n        if v.linear.length() > 1.0:\n            print(s.color)\n");
+        assert_eq!(access(&system)["q"], vec![true, true, false]);
+        let rust = crate::transpile(
+            &parse("system spin(q: Query<Transform>):\n    for t in q:\n        t.rotate_z(0.1)\n")
+                .unwrap(),
+        );
+        assert!(rust.contains("mut q: Query<&mut Transform>"));
+        assert!(rust.contains("for mut t in q.iter_mut() {\n"));
+    }
+}
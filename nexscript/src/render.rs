@@ -0,0 +1,235 @@
+//! Render - Diagnostics as annotated source snippets
+//!
+//! Lays a diagnostic out the way rustc does: a header with its code, the
+//! lines it points at with the span underlined (`^` for the problem itself,
+//! `-` for related places such as an earlier definition) and its notes and
+//! help underneath. Colour is optional so the same output works in a
+//! terminal, a log file and a test.
+
+use crate::diagnostics::{Diagnostic, Location, Severity};
+
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+
+/// Render a diagnostic against the text of the file it is in. Without the
+/// source, only the header, location and notes are shown.
+pub fn render(diagnostic: &Diagnostic, source: Option<&str>, color: bool) -> String {
+    let paint = Paint(color);
+    let severity_color = match diagnostic.severity {
+        Severity::Error => RED,
+        Severity::Warning => YELLOW,
+    };
+
+    let mut labels: Vec<Label> = Vec::new();
+    if diagnostic.location.line > 0 {
+        labels.push(Label {
+            location: diagnostic.location,
+            message: "",
+            mark: '^',
+            color: severity_color,
+        });
+    }
+    // Places in other files are listed by location under the snippet
+    let (here, elsewhere): (Vec<_>, Vec<_>) = diagnostic
+        .related
+        .iter()
+        .partition(|related| related.file.is_empty() || related.file == diagnostic.file);
+    for related in here {
+        if related.location.line > 0 {
+            labels.push(Label {
+                location: related.location,
+                message: &related.message,
+                mark: '-',
+                color: BLUE,
+            });
+        }
+    }
+    labels.sort_by_key(|label| (label.location.line, label.location.column));
+
+    let width = labels
+        .iter()
+        .map(|label| label.location.line.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let gutter = paint.paint(BLUE, &format!("{:width$} |", ""));
+
+    let mut out = format!(
+        "{}{}\n",
+        paint.paint(
+            severity_color,
+            &format!("{}[{}]", diagnostic.severity, diagnostic.code)
+        ),
+        paint.paint(BOLD, &format!(": {}", diagnostic.message)),
+    );
+    if !diagnostic.file.is_empty() {
+        let mut location = diagnostic.file.clone();
+        if diagnostic.location.line > 0 {
+            location.push_str(&format!(
+                ":{}:{}",
+                diagnostic.location.line, diagnostic.location.column
+            ));
+        }
+        out.push_str(&format!(
+            "{}{}\n",
+            paint.paint(BLUE, &format!("{:width$}--> ", "")),
+            location
+        ));
+    }
+
+    if let Some(source) = source.filter(|_| !labels.is_empty()) {
+        out.push_str(&format!("{}\n", gutter));
+        let mut previous: Option<usize> = None;
+        for (i, label) in labels.iter().enumerate() {
+            let line = label.location.line;
+            let Some(text) = source.lines().nth(line - 1) else {
+                continue;
+            };
+            if previous != Some(line) {
+                if previous.is_some_and(|previous| line > previous + 1) {
+                    out.push_str(&format!("{}\n", paint.paint(BLUE, "...")));
+                }
+                let number = paint.paint(BLUE, &format!("{:>width$} |", line));
+                out.push_str(format!("{} {}", number, text).trim_end());
+                out.push('\n');
+            }
+            previous = Some(line);
+
+            // Line up with the source, keeping its tabs
+            let indent: String = text
+                .chars()
+                .take(label.location.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let end_column = if label.location.end_line == line {
+                label.location.end_column
+            } else {
+                text.chars().count() + 1
+            };
+            let marks = label
+                .mark
+                .to_string()
+                .repeat(end_column.saturating_sub(label.location.column).max(1));
+            let underline = format!("{} {}", marks, label.message);
+            out.push_str(
+                format!(
+                    "{} {}{}",
+                    gutter,
+                    indent,
+                    paint.paint(label.color, underline.trim_end())
+                )
+                .trim_end(),
+            );
+            out.push('\n');
+
+            if i + 1 == labels.len() {
+                out.push_str(&format!("{}\n", gutter));
+            }
+        }
+    }
+
+    for note in &diagnostic.notes {
+        out.push_str(&format!(
+            "{} {}: {}\n",
+            paint.paint(BLUE, &format!("{:width$} =", "")),
+            paint.paint(BOLD, "note"),
+            note
+        ));
+    }
+    for related in elsewhere {
+        let mut location = related.file.clone();
+        if related.location.line > 0 {
+            location.push_str(&format!(
+                ":{}:{}",
+                related.location.line, related.location.column
+            ));
+        }
+        out.push_str(&format!(
+            "{} {}: {} at {}\n",
+            paint.paint(BLUE, &format!("{:width$} =", "")),
+            paint.paint(BOLD, "note"),
+            related.message,
+            location
+        ));
+    }
+    for fix in &diagnostic.fixes {
+        out.push_str(&format!(
+            "{} {}: {}\n",
+            paint.paint(BLUE, &format!("{:width$} =", "")),
+            paint.paint(BOLD, "help"),
+            fix.message
+        ));
+    }
+    out
+}
+
+/// An underlined span
+struct Label<'a> {
+    location: Location,
+    message: &'a str,
+    mark: char,
+    color: &'static str,
+}
+
+/// ANSI colour, when enabled
+struct Paint(bool);
+
+impl Paint {
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.0 {
+            format!("\x1b[{}m{}\x1b[0m", color, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::{check, Related};
+
+    #[test]
+    fn test_render_underlines_spans() {
+        let source = "signal scored(points: int)\n\nentity Coin:\n    fn collect():\n        emit scored()\n";
+        let (_, diagnostics) = check(source);
+        let diagnostic = diagnostics[0].clone().in_file("coin.nx");
+        assert_eq!(
+            render(&diagnostic, Some(source), false),
+            "\
+error[NX0005]: signal `scored` takes 1 argument but 0 were given
+ --> coin.nx:5:9
+  |
+1 | signal scored(points: int)
+  |        ------ signal declared here
+...
+5 |         emit scored()
+  |         ^^^^^^^^^^^^^
+  |
+"
+        );
+
+        // Without the source there is nothing to underline
+        assert_eq!(
+            render(&diagnostic, None, false),
+            "error[NX0005]: signal `scored` takes 1 argument but 0 were given\n --> coin.nx:5:9\n"
+        );
+        assert!(render(&diagnostic, Some(source), true).contains("\x1b[1;31m^^^^^^^^^^^^^\x1b[0m"));
+    }
+
+    #[test]
+    fn test_render_related_file_without_location() {
+        let mut diagnostic = Diagnostic::error("NX0027", "generated twice", Location::default());
+        diagnostic.related.push(Related {
+            message: "also generated here".to_string(),
+            file: "src/enemy.nx".to_string(),
+            location: Location::default(),
+        });
+        let diagnostic = diagnostic.in_file("src/sub/enemy.nx");
+        assert_eq!(
+            render(&diagnostic, Some(""), false),
+            "error[NX0027]: generated twice\n --> src/sub/enemy.nx\n  = note: also generated here at src/enemy.nx\n"
+        );
+    }
+}
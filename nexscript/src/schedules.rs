@@ -0,0 +1,546 @@
+//! Schedules - When and in what order generated systems run
+//!
+//! Standalone systems and entities' `on_update` run every frame in `Update`,
+//! in no particular order. Annotations on the lines above them change that:
+//!
+//! ```text
+//! @schedule(FixedUpdate)
+//! @after(spawn, Player.on_update)
+//! @set(Physics)
+//! @run_if(in_state(Playing))
+//! system gravity(q: Query<Velocity>):
+//! ```
+//!
+//! `before` and `after` name systems of the same file or sets; `set` puts
+//! the system in a set, declared by using it. `run_if` takes a state of a
+//! state machine, or a function without parameters returning `bool`.
+
+use crate::modules::{Imports, Item};
+use crate::{Annotation, EntityDef, Expr, FnDef, Program, Span, StateMachine, Statement, TypeExpr};
+use std::collections::HashMap;
+
+/// Bevy schedules a system can run in
+pub(crate) const SCHEDULES: &[&str] = &[
+    "PreStartup",
+    "Startup",
+    "PostStartup",
+    "First",
+    "PreUpdate",
+    "Update",
+    "PostUpdate",
+    "Last",
+    "FixedFirst",
+    "FixedPreUpdate",
+    "FixedUpdate",
+    "FixedPostUpdate",
+    "FixedLast",
+];
+
+/// What systems are ordered against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Target<'a> {
+    /// Index into [`Plan::systems`]
+    System(usize),
+    Set(&'a str),
+}
+
+/// A system and when it runs
+#[derive(Debug)]
+pub(crate) struct System<'a> {
+    /// As scripts name it: `gravity`, `Player.on_update`
+    pub label: String,
+    /// The generated function: `gravity`, `player_on_update`
+    pub name: String,
+    pub schedule: &'a str,
+    pub before: Vec<Target<'a>>,
+    pub after: Vec<Target<'a>>,
+    pub sets: Vec<&'a str>,
+    /// Run conditions in Rust: `in_state(Game::Playing)`, `is_alive`
+    pub conditions: Vec<String>,
+    pub annotations: &'a [Annotation],
+    pub span: Span,
+}
+
+/// Why an annotation was ignored
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Problem {
+    /// On a function that isn't a system or `on_update`
+    NotASystem(String),
+    UnknownAnnotation,
+    /// Wrong arguments; what the annotation takes
+    Arguments(&'static str),
+    UnknownSchedule(String),
+    /// Not a system or set of the file; the names that are
+    UnknownSystem(String, Vec<String>),
+    /// Not a state of a state machine; the states there are
+    UnknownState(String, Vec<String>),
+    /// Not a function; the functions there are
+    UnknownCondition(String, Vec<String>),
+    /// A function that takes parameters or doesn't return `bool`
+    NotACondition(String),
+    /// Ordered against a system in another schedule: the system, the
+    /// other system and their schedules
+    Schedules(String, String, String, String),
+    /// Ordering that leads back to the system; the systems on the way
+    Cycle(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Error<'a> {
+    pub annotation: &'a Annotation,
+    pub problem: Problem,
+}
+
+/// Every system of a file with how it is scheduled
+#[derive(Debug, Default)]
+pub(crate) struct Plan<'a> {
+    pub systems: Vec<System<'a>>,
+    /// Sets systems are put in, in order of first use
+    pub sets: Vec<(&'a str, Span)>,
+    pub errors: Vec<Error<'a>>,
+}
+
+impl<'a> Plan<'a> {
+    /// Schedule a program's systems. `flattened` gives an entity with
+    /// everything it inherits.
+    pub fn new(
+        program: &'a Program,
+        imports: &'a Imports<'a>,
+        flattened: impl Fn(&'a EntityDef) -> &'a EntityDef,
+    ) -> Self {
+        let mut plan = Plan::default();
+        for stmt in &program.statements {
+            match stmt {
+                Statement::SystemDef(system) => plan.systems.push(System::new(
+                    system.name.clone(),
+                    system.name.clone(),
+                    &system.annotations,
+                    system.span,
+                )),
+                Statement::EntityDef(entity) => {
+                    let entity = flattened(entity);
+                    for func in &entity.functions {
+                        if func.name == "on_update" {
+                            plan.systems.push(System::new(
+                                format!("{}.on_update", entity.name),
+                                format!("{}_on_update", entity.name.to_lowercase()),
+                                &func.annotations,
+                                func.span,
+                            ));
+                        } else {
+                            plan.not_a_system(func);
+                        }
+                    }
+                }
+                Statement::FnDef(func) => plan.not_a_system(func),
+                _ => {}
+            }
+        }
+
+        for system in &plan.systems {
+            for annotation in system.annotations {
+                if annotation.name != "set" {
+                    continue;
+                }
+                for arg in &annotation.args {
+                    if let Expr::Identifier(set) = arg {
+                        if !plan.sets.iter().any(|(name, _)| name == set) {
+                            plan.sets.push((set.as_str(), annotation.span));
+                        }
+                    }
+                }
+            }
+        }
+
+        let names = Names::new(program, imports);
+        for i in 0..plan.systems.len() {
+            let annotations = plan.systems[i].annotations;
+            for annotation in annotations {
+                if let Err(problem) = plan.annotate(i, annotation, &names) {
+                    plan.errors.push(Error {
+                        annotation,
+                        problem,
+                    });
+                }
+            }
+        }
+        plan.cycles();
+        plan
+    }
+
+    /// The system scripts name `label`
+    pub fn system(&self, label: &str) -> Option<&System<'a>> {
+        self.systems.iter().find(|system| system.label == label)
+    }
+
+    /// What `add_systems` is given for a system:
+    /// `gravity.in_set(Physics).after(spawn).run_if(in_state(Game::Playing))`
+    pub fn config(&self, system: &System) -> String {
+        let mut config = system.name.clone();
+        for set in &system.sets {
+            config.push_str(&format!(".in_set({})", set));
+        }
+        for (method, targets) in [("before", &system.before), ("after", &system.after)] {
+            for target in targets {
+                config.push_str(&format!(".{}({})", method, self.rust_name(*target)));
+            }
+        }
+        for condition in &system.conditions {
+            config.push_str(&format!(".run_if({})", condition));
+        }
+        config
+    }
+
+    fn rust_name<'s>(&'s self, target: Target<'s>) -> &'s str {
+        match target {
+            Target::System(i) => &self.systems[i].name,
+            Target::Set(set) => set,
+        }
+    }
+
+    fn not_a_system(&mut self, func: &'a FnDef) {
+        if let Some(annotation) = func.annotations.first() {
+            self.errors.push(Error {
+                annotation,
+                problem: Problem::NotASystem(func.name.clone()),
+            });
+        }
+    }
+
+    /// Apply one of system `i`'s annotations
+    fn annotate(
+        &mut self,
+        i: usize,
+        annotation: &'a Annotation,
+        names: &Names<'a>,
+    ) -> Result<(), Problem> {
+        let args = &annotation.args;
+        match annotation.name.as_str() {
+            "schedule" => {
+                let [Expr::Identifier(schedule)] = args.as_slice() else {
+                    return Err(Problem::Arguments("a schedule, like `FixedUpdate`"));
+                };
+                let Some(schedule) = SCHEDULES.iter().find(|s| *s == schedule) else {
+                    return Err(Problem::UnknownSchedule(schedule.clone()));
+                };
+                self.systems[i].schedule = schedule;
+            }
+            "before" | "after" => {
+                if args.is_empty() {
+                    return Err(Problem::Arguments("the systems or sets to run it against"));
+                }
+                // Every unknown name is reported, not just the first
+                for arg in args {
+                    let target = match self.target(arg) {
+                        Ok(target) => target,
+                        Err(problem) => {
+                            self.errors.push(Error {
+                                annotation,
+                                problem,
+                            });
+                            continue;
+                        }
+                    };
+                    let system = &mut self.systems[i];
+                    match annotation.name.as_str() {
+                        "before" => system.before.push(target),
+                        _ => system.after.push(target),
+                    }
+                }
+            }
+            "set" => {
+                if args.is_empty() {
+                    return Err(Problem::Arguments("the names of the sets to put it in"));
+                }
+                for arg in args {
+                    let Expr::Identifier(set) = arg else {
+                        return Err(Problem::Arguments("the names of the sets to put it in"));
+                    };
+                    self.systems[i].sets.push(set);
+                }
+            }
+            "run_if" => {
+                let [condition] = args.as_slice() else {
+                    return Err(Problem::Arguments(
+                        "a condition, like `in_state(Playing)` or `is_alive`",
+                    ));
+                };
+                let condition = names.condition(condition)?;
+                self.systems[i].conditions.push(condition);
+            }
+            _ => return Err(Problem::UnknownAnnotation),
+        }
+        Ok(())
+    }
+
+    /// A system named like `spawn` or `Player.on_update`, or a set
+    fn target(&self, arg: &Expr) -> Result<Target<'a>, Problem> {
+        let label = match arg {
+            Expr::Identifier(name) => name.clone(),
+            Expr::MemberAccess(base, member) => match base.as_ref() {
+                Expr::Identifier(entity) => format!("{}.{}", entity, member),
+                _ => return Err(Problem::Arguments("the systems or sets to run it against")),
+            },
+            _ => return Err(Problem::Arguments("the systems or sets to run it against")),
+        };
+        if let Some(i) = self.systems.iter().position(|s| s.label == label) {
+            return Ok(Target::System(i));
+        }
+        if let Some((set, _)) = self.sets.iter().find(|(set, _)| *set == label) {
+            return Ok(Target::Set(set));
+        }
+        let known = self
+            .systems
+            .iter()
+            .map(|s| s.label.clone())
+            .chain(self.sets.iter().map(|(set, _)| set.to_string()))
+            .collect();
+        Err(Problem::UnknownSystem(label, known))
+    }
+
+    /// Report ordering against a system in another schedule, and orderings
+    /// that lead back to where they started
+    fn cycles(&mut self) {
+        // `edges[a]` are the systems that run after `a`, with the annotation
+        // ordering them
+        let mut edges: Vec<Vec<(usize, &'a Annotation)>> = vec![Vec::new(); self.systems.len()];
+        for (i, system) in self.systems.iter().enumerate() {
+            for annotation in system.annotations {
+                let before = match annotation.name.as_str() {
+                    "before" => true,
+                    "after" => false,
+                    _ => continue,
+                };
+                let targets: Vec<Target> = annotation
+                    .args
+                    .iter()
+                    .filter_map(|arg| self.target(arg).ok())
+                    .collect();
+                for target in targets {
+                    let others: Vec<usize> = match target {
+                        Target::System(j) => {
+                            let other = &self.systems[j];
+                            if other.schedule != system.schedule {
+                                self.errors.push(Error {
+                                    annotation,
+                                    problem: Problem::Schedules(
+                                        system.label.clone(),
+                                        other.label.clone(),
+                                        system.schedule.to_string(),
+                                        other.schedule.to_string(),
+                                    ),
+                                });
+                                continue;
+                            }
+                            vec![j]
+                        }
+                        // A set's members in other schedules aren't affected
+                        Target::Set(set) => (0..self.systems.len())
+                            .filter(|j| {
+                                let other = &self.systems[*j];
+                                other.sets.contains(&set) && other.schedule == system.schedule
+                            })
+                            .collect(),
+                    };
+                    for j in others {
+                        match before {
+                            true => edges[i].push((j, annotation)),
+                            false => edges[j].push((i, annotation)),
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut visited = vec![false; self.systems.len()];
+        for start in 0..self.systems.len() {
+            let mut path = Vec::new();
+            self.visit(start, &edges, &mut visited, &mut path);
+        }
+    }
+
+    fn visit(
+        &mut self,
+        system: usize,
+        edges: &[Vec<(usize, &'a Annotation)>],
+        visited: &mut [bool],
+        path: &mut Vec<usize>,
+    ) {
+        if visited[system] {
+            return;
+        }
+        path.push(system);
+        for &(next, annotation) in &edges[system] {
+            if let Some(at) = path.iter().position(|&s| s == next) {
+                let chain = path[at..]
+                    .iter()
+                    .chain([&next])
+                    .map(|&s| self.systems[s].label.clone())
+                    .collect();
+                self.errors.push(Error {
+                    annotation,
+                    problem: Problem::Cycle(chain),
+                });
+            } else {
+                self.visit(next, edges, visited, path);
+            }
+        }
+        path.pop();
+        visited[system] = true;
+    }
+}
+
+impl<'a> System<'a> {
+    fn new(label: String, name: String, annotations: &'a [Annotation], span: Span) -> Self {
+        System {
+            label,
+            name,
+            schedule: "Update",
+            before: Vec::new(),
+            after: Vec::new(),
+            sets: Vec::new(),
+            conditions: Vec::new(),
+            annotations,
+            span,
+        }
+    }
+}
+
+/// The state machines and functions run conditions can use: the file's
+/// own and those imported by name
+struct Names<'a> {
+    machines: Vec<&'a StateMachine>,
+    functions: HashMap<&'a str, &'a FnDef>,
+}
+
+impl<'a> Names<'a> {
+    fn new(program: &'a Program, imports: &'a Imports<'a>) -> Self {
+        let mut names = Names {
+            machines: Vec::new(),
+            functions: HashMap::new(),
+        };
+        let imported = imports
+            .prelude
+            .iter()
+            .chain(&imports.names)
+            .map(|(name, imported)| (name.as_str(), imported.item));
+        let local = Item::all(program).map(|item| (item.name(), item));
+        for (name, item) in imported.chain(local) {
+            match item {
+                Item::StateMachine(machine) => names.machines.push(machine),
+                Item::Function(func) => {
+                    names.functions.insert(name, func);
+                }
+                _ => {}
+            }
+        }
+        names
+    }
+
+    /// A run condition in Rust
+    fn condition(&self, condition: &Expr) -> Result<String, Problem> {
+        match condition {
+            Expr::Call { callee, args } if **callee == Expr::Identifier("in_state".into()) => {
+                let [arg] = args.as_slice() else {
+                    return Err(Problem::Arguments("a state, like `in_state(Playing)`"));
+                };
+                let state = self.state(&arg.value)?;
+                Ok(format!("in_state({})", state))
+            }
+            Expr::Identifier(name) => {
+                let Some(func) = self.functions.get(name.as_str()) else {
+                    let mut known: Vec<String> =
+                        self.functions.keys().map(|name| name.to_string()).collect();
+                    known.sort();
+                    return Err(Problem::UnknownCondition(name.clone(), known));
+                };
+                let returns_bool = func.return_type == Some(TypeExpr::Simple("bool".into()));
+                if !func.params.is_empty() || !returns_bool || func.is_async {
+                    return Err(Problem::NotACondition(name.clone()));
+                }
+                Ok(name.clone())
+            }
+            _ => Err(Problem::Arguments(
+                "a condition, like `in_state(Playing)` or `is_alive`",
+            )),
+        }
+    }
+
+    /// `Playing` or `Game.Playing` as `Game::Playing`
+    fn state(&self, state: &Expr) -> Result<String, Problem> {
+        let (machine, name) = match state {
+            Expr::Identifier(name) => (None, name),
+            Expr::MemberAccess(base, name) => match base.as_ref() {
+                Expr::Identifier(machine) => (Some(machine.as_str()), name),
+                _ => return Err(Problem::Arguments("a state, like `in_state(Playing)`")),
+            },
+            _ => return Err(Problem::Arguments("a state, like `in_state(Playing)`")),
+        };
+        let machines = self
+            .machines
+            .iter()
+            .filter(|m| machine.is_none_or(|machine| m.name == machine));
+        let mut known = Vec::new();
+        for m in machines {
+            if m.states.iter().any(|s| &s.name == name) {
+                return Ok(format!("{}::{}", m.name, name));
+            }
+            known.extend(m.states.iter().map(|s| s.name.clone()));
+        }
+        let name = match machine {
+            Some(machine) => format!("{}.{}", machine, name),
+            None => name.clone(),
+        };
+        Err(Problem::UnknownState(name, known))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_plan() {
+        let source = "state_machine Game:\n    state Menu:\n        pass\n    state Playing:\n        pass\n\nfn alive() -> bool:\n    return true\n\n@schedule(FixedUpdate)\n@set(Physics)\n@run_if(in_state(Playing))\nsystem gravity(q: Query<Velocity>):\n    pass\n\n@schedule(FixedUpdate)\n@after(Physics)\n@run_if(alive)\nsystem collide(q: Query<Velocity>):\n    pass\n\nentity Player:\n    @before(spawn)\n    fn on_update(delta: float):\n        pass\n\nsystem spawn():\n    pass\n";
+        let program = parse(source).unwrap();
+        let imports = Imports::default();
+        let plan = Plan::new(&program, &imports, |entity| entity);
+        assert!(plan.errors.is_empty(), "{:?}", plan.errors);
+        assert_eq!(plan.sets.len(), 1);
+
+        let gravity = plan.system("gravity").unwrap();
+        assert_eq!(gravity.schedule, "FixedUpdate");
+        assert_eq!(
+            plan.config(gravity),
+            "gravity.in_set(Physics).run_if(in_state(Game::Playing))"
+        );
+        let collide = plan.system("collide").unwrap();
+        assert_eq!(plan.config(collide), "collide.after(Physics).run_if(alive)");
+        let player = plan.system("Player.on_update").unwrap();
+        assert_eq!(player.schedule, "Update");
+        assert_eq!(plan.config(player), "player_on_update.before(spawn)");
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        let source = "@after(collide)\nsystem gravity():\n    pass\n\n@set(Physics)\n@after(gravity)\nsystem collide():\n    pass\n\n@after(Physics)\nsystem bounce():\n    pass\n\n@schedule(FixedUpdate)\n@before(gravity)\nsystem spawn():\n    pass\n\n@after(gravity)\nfn helper():\n    pass\n";
+        let program = parse(source).unwrap();
+        let imports = Imports::default();
+        let plan = Plan::new(&program, &imports, |entity| entity);
+        let problems: Vec<&Problem> = plan.errors.iter().map(|e| &e.problem).collect();
+        assert_eq!(
+            problems,
+            vec![
+                &Problem::NotASystem("helper".into()),
+                &Problem::Schedules(
+                    "spawn".into(),
+                    "gravity".into(),
+                    "FixedUpdate".into(),
+                    "Update".into()
+                ),
+                &Problem::Cycle(vec!["gravity".into(), "collide".into(), "gravity".into()]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Source Map - Trace generated Rust back to `.nx` source
+//!
+//! The transpiler wraps the code it generates for each construct in marker
+//! comments carrying the construct's span. `transpile_with_source_map` turns
+//! the opening markers into `// nx:<line>` comments, drops the closing ones
+//! and records which generated lines came from where. Constructs nest, so a
+//! line maps to the innermost construct it was generated for. The map is
+//! written next to the generated file as `<file>.rs.map`.
+
+use crate::Span;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Opens a construct: `// nx:@<start>..<end>`
+pub(crate) const OPEN: &str = "// nx:@";
+/// Closes the innermost open construct
+pub(crate) const CLOSE: &str = "// nx:end";
+
+/// Where the lines of a generated file came from
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SourceMap {
+    /// Path of the `.nx` file, as the build found it
+    pub source: String,
+    /// Non-overlapping runs of generated lines, in order
+    pub mappings: Vec<Mapping>,
+}
+
+/// A run of generated lines produced by one construct
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Mapping {
+    /// First and last generated line of the run, 1-based
+    pub generated_start: usize,
+    pub generated_end: usize,
+    /// 1-based line and column where the construct starts in the source
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+}
+
+impl SourceMap {
+    /// The construct a 1-based generated line came from
+    pub fn lookup(&self, generated_line: usize) -> Option<&Mapping> {
+        let i = self
+            .mappings
+            .partition_point(|m| m.generated_end < generated_line);
+        self.mappings
+            .get(i)
+            .filter(|m| m.generated_start <= generated_line)
+    }
+
+    /// Load the map written next to a generated file, if there is one
+    pub fn load(generated: &Path) -> Option<Self> {
+        let text = fs::read_to_string(map_path(generated)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Where the map for a generated file lives, e.g. `player.rs.map`
+pub fn map_path(generated: &Path) -> PathBuf {
+    let mut path = generated.as_os_str().to_owned();
+    path.push(".map");
+    PathBuf::from(path)
+}
+
+/// Wrap the code generated for a construct in markers
+pub(crate) fn mapped(prefix: &str, span: Span, code: String) -> String {
+    format!(
+        "{}{}{}..{}\n{}{}{}\n",
+        prefix, OPEN, span.start, span.end, code, prefix, CLOSE
+    )
+}
+
+/// Replace markers with `// nx:<line>` comments and build the map
+pub(crate) fn resolve(generated: &str, source: &str) -> (String, SourceMap) {
+    let mut output = String::new();
+    let mut map = SourceMap::default();
+    let mut open: Vec<Mapping> = Vec::new();
+    let mut line = 0;
+
+    for text in generated.lines() {
+        let trimmed = text.trim_start();
+        let indent = &text[..text.len() - trimmed.len()];
+
+        if trimmed == CLOSE {
+            open.pop();
+            continue;
+        }
+        if let Some(span) = trimmed.strip_prefix(OPEN).and_then(parse_span) {
+            let (source_line, column) = line_col(source, span.start);
+            output.push_str(&format!("{}// nx:{}\n", indent, source_line));
+            line += 1;
+            open.push(Mapping {
+                generated_start: line,
+                generated_end: line,
+                line: source_line,
+                column,
+                span,
+            });
+        } else {
+            output.push_str(text);
+            output.push('\n');
+            line += 1;
+        }
+
+        // Extend the innermost construct's run, or start a new one after a nested construct
+        if let Some(current) = open.last() {
+            match map.mappings.last_mut() {
+                Some(last) if last.span == current.span && last.generated_end + 1 == line => {
+                    last.generated_end = line
+                }
+                _ => map.mappings.push(Mapping {
+                    generated_start: line,
+                    generated_end: line,
+                    ..*current
+                }),
+            }
+        }
+    }
+
+    (output, map)
+}
+
+/// Drop every marker, for output without a map
+pub(crate) fn strip(generated: &str) -> String {
+    generated
+        .lines()
+        .filter(|line| {
+            let line = line.trim_start();
+            line != CLOSE && !line.starts_with(OPEN)
+        })
+        .flat_map(|line| [line, "\n"])
+        .collect()
+}
+
+fn parse_span(text: &str) -> Option<Span> {
+    let (start, end) = text.split_once("..")?;
+    Some(Span {
+        start: start.parse().ok()?,
+        end: end.parse().ok()?,
+    })
+}
+
+/// 1-based line and column of a byte offset
+pub(crate) fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse, transpile, transpile_with_source_map};
+
+    #[test]
+    fn test_source_map_points_at_nx_lines() {
+        let source = include_str!("../examples/player.nx");
+        let program = parse(source).unwrap();
+        let (rust, map) = transpile_with_source_map(&program, source);
+        let lines: Vec<&str> = rust.lines().collect();
+
+        // Every generated line inside `take_damage` maps into it
+        let start = lines
+            .iter()
+            .position(|l| l.contains("fn take_damage"))
+            .unwrap();
+        assert_eq!(lines[start - 1].trim(), "// nx:24");
+        let func = map.lookup(start + 1).unwrap();
+        assert_eq!((func.line, func.column), (24, 5));
+        assert_eq!(
+            &source[func.span.start..func.span.start + 15],
+            "fn take_damage("
+        );
+
+        let decl = lines
+            .iter()
+            .position(|l| l.contains("let old = Health.current"))
+            .unwrap();
+        assert_eq!(map.lookup(decl + 1).unwrap().line, 25);
+        // as do calls inside blocks
+        let call = lines.iter().position(|l| l.trim() == "die();").unwrap();
+        assert_eq!(lines[call - 1].trim(), "// nx:30");
+        assert_eq!(map.lookup(call + 1).unwrap().line, 30);
+        // The body's closing brace belongs to the function again
+        let end = start + lines[start..].iter().position(|l| *l == "}").unwrap();
+        assert_eq!(map.lookup(end + 1).unwrap().line, 24);
+
+        // Header lines come from nothing, and plain output has no markers
+        assert_eq!(map.lookup(1), None);
+        assert!(!transpile(&program).contains("// nx:"));
+    }
+}
@@ -1,9 +1,247 @@
 //! Type Checker & Inference Engine for NexScript
 
-use crate::{BinaryOp, Expr, TypeExpr, UnaryOp};
+use crate::queries::Query;
+use crate::{
+    BinaryOp, EntityDef, EnumDef, Expr, FnDef, ForStmt, LambdaParam, Pattern, StructDef, TypeExpr,
+    UnaryOp,
+};
+use std::collections::{HashMap, HashSet};
+
+/// A built-in function available to every script
+#[derive(Debug, Clone, Copy)]
+pub struct PreludeFn {
+    pub name: &'static str,
+    pub params: &'static [(&'static str, &'static str)],
+    pub return_type: Option<&'static str>,
+    pub doc: &'static str,
+}
+
+impl PreludeFn {
+    /// Signature as it would be written in NexScript
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(name, ty)| format!("{}: {}", name, ty))
+            .collect();
+        let return_type = self
+            .return_type
+            .map(|t| format!(" -> {}", t))
+            .unwrap_or_default();
+        format!("fn {}({}){}", self.name, params.join(", "), return_type)
+    }
+}
+
+/// Functions every script can call without declaring them
+pub const PRELUDE: &[PreludeFn] = &[
+    PreludeFn {
+        name: "print",
+        params: &[("value", "Any")],
+        return_type: None,
+        doc: "Write a value to the log",
+    },
+    PreludeFn {
+        name: "wait",
+        params: &[("seconds", "float")],
+        return_type: None,
+        doc: "Suspend an async fn for the given number of seconds",
+    },
+    PreludeFn {
+        name: "play_animation",
+        params: &[("name", "str")],
+        return_type: None,
+        doc: "Play a named animation on this entity",
+    },
+    PreludeFn {
+        name: "len",
+        params: &[("value", "Any")],
+        return_type: Some("int"),
+        doc: "Number of items in a list, map or string",
+    },
+    PreludeFn {
+        name: "clamp",
+        params: &[("value", "float"), ("min", "float"), ("max", "float")],
+        return_type: Some("float"),
+        doc: "Limit a value to the range [min, max]",
+    },
+    PreludeFn {
+        name: "lerp",
+        params: &[("from", "float"), ("to", "float"), ("t", "float")],
+        return_type: Some("float"),
+        doc: "Linear interpolation between two values",
+    },
+    PreludeFn {
+        name: "range",
+        params: &[("start", "int"), ("end", "int"), ("step", "int")],
+        return_type: None,
+        doc: "Ints from start up to (not including) end, step apart, for `for` loops. \
+              `range(end)` starts at 0 and step defaults to 1; a negative step counts down",
+    },
+    PreludeFn {
+        name: "enumerate",
+        params: &[("items", "List")],
+        return_type: None,
+        doc: "Each item with its index, for `for i, item in enumerate(items)`",
+    },
+];
+
+pub fn prelude_fn(name: &str) -> Option<&'static PreludeFn> {
+    PRELUDE.iter().find(|f| f.name == name)
+}
+
+/// Known names and their types, used to infer identifiers, members and calls
+#[derive(Debug, Clone, Default)]
+pub struct TypeEnv {
+    /// Variables, keyed by name or dotted path (e.g. `Health.current`)
+    vars: HashMap<String, TypeExpr>,
+    /// Functions and their return types (`None` for functions returning nothing)
+    functions: HashMap<String, Option<TypeExpr>>,
+    /// Struct fields and their types, by struct name
+    structs: HashMap<String, Vec<(String, TypeExpr)>>,
+    enums: HashSet<String>,
+    /// Enum variant field types, by `Enum.Variant`
+    variants: HashMap<String, Vec<TypeExpr>>,
+}
+
+impl TypeEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a variable (or dotted member path) with a known type
+    pub fn declare(&mut self, name: &str, type_expr: TypeExpr) {
+        self.vars.insert(name.to_string(), type_expr);
+    }
+
+    /// Declare a function so calls to it infer its return type
+    pub fn declare_fn(&mut self, func: &FnDef) {
+        self.functions
+            .insert(func.name.clone(), func.return_type.clone());
+    }
+
+    /// Declare a struct, so calls to it construct one and its fields have types
+    pub fn declare_struct(&mut self, def: &StructDef) {
+        let fields = def
+            .fields
+            .iter()
+            .map(|field| (field.name.clone(), field.type_expr.clone()))
+            .collect();
+        self.structs.insert(def.name.clone(), fields);
+    }
+
+    /// Declare an enum, so `Enum.Variant` and `Enum.Variant(...)` are its values
+    pub fn declare_enum(&mut self, def: &EnumDef) {
+        self.enums.insert(def.name.clone());
+        for variant in &def.variants {
+            let fields = variant.fields.iter().map(|f| f.type_expr.clone()).collect();
+            self.variants
+                .insert(format!("{}.{}", def.name, variant.name), fields);
+        }
+    }
+
+    /// Declare a `for` loop's variables: ints from `range`, an int index
+    /// and the item from `enumerate`, and the items of a typed list
+    pub fn declare_loop(&mut self, for_stmt: &ForStmt) {
+        let int = Some(TypeExpr::Simple("int".to_string()));
+        let types = match &for_stmt.iterable {
+            Expr::Call { callee, .. } if **callee == Expr::Identifier("range".to_string()) => {
+                vec![int]
+            }
+            Expr::Call { callee, args }
+                if **callee == Expr::Identifier("enumerate".to_string()) =>
+            {
+                let items = args.first().and_then(|arg| item_type(&arg.value, self));
+                vec![int, items]
+            }
+            // A query yields one of each of its components
+            iterable => match infer_type_in(iterable, self) {
+                Some(t) => match Query::of(&t) {
+                    Some(query) => query.components.into_iter().cloned().map(Some).collect(),
+                    None => vec![item_type(iterable, self)],
+                },
+                None => vec![None],
+            },
+        };
+        for (name, type_expr) in for_stmt.names.iter().zip(types) {
+            if let Some(t) = type_expr {
+                self.declare(name, t);
+            }
+        }
+    }
+
+    /// Declare the names a pattern binds, given the type of the value it
+    /// matches when known
+    pub fn declare_pattern(&mut self, pattern: &Pattern, subject: Option<&TypeExpr>) {
+        match pattern {
+            Pattern::Binding(name) => {
+                if let Some(t) = subject {
+                    self.declare(name, t.clone());
+                }
+            }
+            Pattern::Variant { path, fields } => {
+                let key = path[path.len().saturating_sub(2)..].join(".");
+                let types = self.variants.get(&key).cloned().unwrap_or_default();
+                for (i, field) in fields.iter().enumerate() {
+                    self.declare_pattern(field, types.get(i));
+                }
+            }
+            Pattern::Tuple(items) => {
+                let types = match subject {
+                    Some(TypeExpr::Generic { name, params }) if name == "Tuple" => {
+                        params.as_slice()
+                    }
+                    _ => &[],
+                };
+                for (i, item) in items.iter().enumerate() {
+                    self.declare_pattern(item, types.get(i));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether `name` is a declared struct or enum
+    pub fn is_declared_type(&self, name: &str) -> bool {
+        self.structs.contains_key(name) || self.enums.contains(name)
+    }
+
+    /// Declare everything an entity exposes to its own functions: variables,
+    /// component fields (as `Component.field`) and functions
+    pub fn declare_entity(&mut self, entity: &EntityDef) {
+        for var in &entity.variables {
+            let type_expr = var
+                .type_expr
+                .clone()
+                .or_else(|| infer_type_in(&var.value, self));
+            if let Some(t) = type_expr {
+                self.declare(&var.name, t);
+            }
+        }
+        for component in &entity.components {
+            self.declare(&component.name, TypeExpr::Simple(component.name.clone()));
+            for (field, value) in &component.fields {
+                if let Some(t) = infer_type_in(value, self) {
+                    self.declare(&format!("{}.{}", component.name, field), t);
+                }
+            }
+        }
+        for func in &entity.functions {
+            self.declare_fn(func);
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&TypeExpr> {
+        self.vars.get(name)
+    }
+}
 
 /// Infer the type of an expression
 pub fn infer_type(expr: &Expr) -> Option<TypeExpr> {
+    infer_type_in(expr, &TypeEnv::default())
+}
+
+/// Infer the type of an expression, resolving names against `env`
+pub fn infer_type_in(expr: &Expr, env: &TypeEnv) -> Option<TypeExpr> {
     match expr {
         Expr::Int(_) => Some(TypeExpr::Simple("int".to_string())),
         Expr::Float(_) => Some(TypeExpr::Simple("float".to_string())),
@@ -13,9 +251,19 @@ pub fn infer_type(expr: &Expr) -> Option<TypeExpr> {
         Expr::Vec2(_, _) => Some(TypeExpr::Simple("Vec2".to_string())),
         Expr::Vec3(_, _, _) => Some(TypeExpr::Simple("Vec3".to_string())),
 
-        Expr::List(_) => Some(TypeExpr::Generic {
+        Expr::List(items) => Some(TypeExpr::Generic {
             name: "List".to_string(),
-            params: vec![TypeExpr::Simple("Any".to_string())], // TODO: Infer inner type
+            params: vec![element_type(items, env)],
+        }),
+
+        Expr::Tuple(items) => Some(TypeExpr::Generic {
+            name: "Tuple".to_string(),
+            params: items
+                .iter()
+                .map(|item| {
+                    infer_type_in(item, env).unwrap_or_else(|| TypeExpr::Simple("Any".to_string()))
+                })
+                .collect(),
         }),
 
         Expr::Map(_) => Some(TypeExpr::Generic {
@@ -26,13 +274,9 @@ pub fn infer_type(expr: &Expr) -> Option<TypeExpr> {
             ],
         }),
 
-        Expr::UnaryOp(op, _) => match op {
+        Expr::UnaryOp(op, operand) => match op {
             UnaryOp::Not => Some(TypeExpr::Simple("bool".to_string())),
-            UnaryOp::Neg => {
-                // Return float/int based on operand? For now default to same logic as binary
-                // Actually we need to traverse down, but simplified:
-                Some(TypeExpr::Simple("float".to_string()))
-            }
+            UnaryOp::Neg => infer_type_in(operand, env),
         },
 
         Expr::BinaryOp(left, op, right) => {
@@ -49,8 +293,8 @@ pub fn infer_type(expr: &Expr) -> Option<TypeExpr> {
 
                 // Arithmetic depends on operands
                 BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
-                    let left_type = infer_type(left);
-                    let right_type = infer_type(right);
+                    let left_type = infer_type_in(left, env);
+                    let right_type = infer_type_in(right, env);
 
                     if let (Some(l), Some(r)) = (left_type, right_type) {
                         // Float valid if either is float
@@ -75,7 +319,47 @@ pub fn infer_type(expr: &Expr) -> Option<TypeExpr> {
             }
         }
 
-        Expr::Call { callee, .. } => {
+        Expr::Lambda {
+            params,
+            return_type,
+            body,
+        } => Some(lambda_type(params, return_type, body, &[], env)),
+
+        Expr::Call { callee, args } => {
+            // Calling a function value gives its return type
+            if let Some(TypeExpr::Generic { name, params }) = infer_type_in(callee, env) {
+                if name == "Fn" {
+                    return params.last().filter(|t| !is_type(t, "Any")).cloned();
+                }
+            }
+            // `items.map(f)` is a list of what `f` returns, `items.filter(f)`
+            // a list of the same items
+            if let (Expr::MemberAccess(list, method), [arg]) = (&**callee, args.as_slice()) {
+                if let Some(item) = item_type(list, env) {
+                    match method.as_str() {
+                        "map" => {
+                            let returns = match &arg.value {
+                                Expr::Lambda {
+                                    params,
+                                    return_type,
+                                    body,
+                                } => lambda_type(params, return_type, body, &[item], env),
+                                f => infer_type_in(f, env)?,
+                            };
+                            let TypeExpr::Generic { name, params } = returns else {
+                                return None;
+                            };
+                            let item = params.last().filter(|_| name == "Fn")?.clone();
+                            return Some(TypeExpr::Generic {
+                                name: "List".to_string(),
+                                params: vec![item],
+                            });
+                        }
+                        "filter" => return infer_type_in(list, env),
+                        _ => {}
+                    }
+                }
+            }
             // Very basic inference for constructor-like calls (e.g. Vec2(0,0))
             if let Expr::Identifier(name) = &**callee {
                 if name == "Vec2" {
@@ -87,16 +371,151 @@ pub fn infer_type(expr: &Expr) -> Option<TypeExpr> {
                 if name == "Color" {
                     return Some(TypeExpr::Simple("Color".to_string()));
                 }
+                if env.structs.contains_key(name) {
+                    return Some(TypeExpr::Simple(name.clone()));
+                }
+                if let Some(return_type) = env.functions.get(name) {
+                    return return_type.clone();
+                }
+                if let Some(prelude) = prelude_fn(name) {
+                    return prelude.return_type.map(|t| TypeExpr::Simple(t.to_string()));
+                }
+            }
+            // `Element.Fire(damage: 3)`
+            if let Expr::MemberAccess(base, _) = &**callee {
+                if let Expr::Identifier(name) = &**base {
+                    if env.enums.contains(name) {
+                        return Some(TypeExpr::Simple(name.clone()));
+                    }
+                }
+            }
+            None
+        }
+
+        // `Element::Fire` is an `Element`
+        Expr::Construct { path, .. } => {
+            let name = path.split("::").next().unwrap_or(path);
+            env.is_declared_type(name)
+                .then(|| TypeExpr::Simple(name.to_string()))
+        }
+
+        Expr::Identifier(name) => env.lookup(name).cloned(),
+
+        Expr::MemberAccess(base, member) => {
+            if let Some(path) = member_path(expr) {
+                if let Some(t) = env.lookup(&path) {
+                    return Some(t.clone());
+                }
+            }
+            if let Expr::Identifier(name) = &**base {
+                if env.enums.contains(name) {
+                    return Some(TypeExpr::Simple(name.clone()));
+                }
+            }
+            let base_type = infer_type_in(base, env)?;
+            if let TypeExpr::Simple(name) = &base_type {
+                if let Some(fields) = env.structs.get(name) {
+                    return fields
+                        .iter()
+                        .find(|(field, _)| field == member)
+                        .map(|(_, t)| t.clone());
+                }
+            }
+            // Vector components are always floats
+            if (is_type(&base_type, "Vec2") && matches!(member.as_str(), "x" | "y"))
+                || (is_type(&base_type, "Vec3") && matches!(member.as_str(), "x" | "y" | "z"))
+            {
+                return Some(TypeExpr::Simple("float".to_string()));
             }
             None
         }
 
-        // Identifiers and others are hard without context (symbol table)
+        // Everything else needs more context than we track
         // For now return None so transpiler prints /* infer */ or defaults
         _ => None,
     }
 }
 
+/// `Fn<params..., return>` for a lambda. Parameters without a type take
+/// theirs from `context` (such as the items of a list being mapped) and
+/// are `Any` otherwise; the return type is inferred from the body when not
+/// given.
+fn lambda_type(
+    params: &[LambdaParam],
+    return_type: &Option<TypeExpr>,
+    body: &Expr,
+    context: &[TypeExpr],
+    env: &TypeEnv,
+) -> TypeExpr {
+    let any = || TypeExpr::Simple("Any".to_string());
+    let mut scope = env.clone();
+    let mut types: Vec<TypeExpr> = params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let t = param
+                .type_expr
+                .clone()
+                .or_else(|| context.get(i).cloned())
+                .unwrap_or_else(any);
+            scope.declare(&param.name, t.clone());
+            t
+        })
+        .collect();
+    let returns = return_type
+        .clone()
+        .or_else(|| infer_type_in(body, &scope))
+        .unwrap_or_else(any);
+    types.push(returns);
+    TypeExpr::Generic {
+        name: "Fn".to_string(),
+        params: types,
+    }
+}
+
+/// The type every item of a list literal has, `float` for a mix of ints
+/// and floats, and `Any` when they disagree or aren't known
+fn element_type(items: &[Expr], env: &TypeEnv) -> TypeExpr {
+    let any = TypeExpr::Simple("Any".to_string());
+    let mut types = items.iter().map(|item| infer_type_in(item, env));
+    let Some(Some(mut unified)) = types.next() else {
+        return any;
+    };
+    for t in types {
+        match t {
+            Some(t) if t == unified => {}
+            Some(t) if is_number(&t) && is_number(&unified) => {
+                unified = TypeExpr::Simple("float".to_string());
+            }
+            _ => return any,
+        }
+    }
+    unified
+}
+
+fn is_number(t: &TypeExpr) -> bool {
+    is_type(t, "int") || is_type(t, "float")
+}
+
+/// Type of the items of a list, when known
+fn item_type(list: &Expr, env: &TypeEnv) -> Option<TypeExpr> {
+    match infer_type_in(list, env)? {
+        TypeExpr::Generic { name, params } if name == "List" => {
+            params.into_iter().next().filter(|t| !is_type(t, "Any"))
+        }
+        _ => None,
+    }
+}
+
+/// Dotted path for `a.b.c` style member access, if the chain is all identifiers
+pub(crate) fn member_path(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Identifier(name) => Some(name.clone()),
+        Expr::MemberAccess(base, member) => Some(format!("{}.{}", member_path(base)?, member)),
+        _ => None,
+    }
+}
+
 fn is_type(t: &TypeExpr, name: &str) -> bool {
     match t {
         TypeExpr::Simple(s) => s == name,